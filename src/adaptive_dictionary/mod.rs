@@ -1,24 +1,48 @@
 use std::collections::BTreeMap;
 
 
-pub struct AdaptiveDictionary {
-    pub frequencies: BTreeMap<u8, u32>,
+// Symbol frequencies, bytes unless coding wider symbols
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdaptiveDictionary<S = u8> {
+    pub frequencies: BTreeMap<S, u32>,
 }
 
-impl AdaptiveDictionary {
+impl<S: Copy + Ord> AdaptiveDictionary<S> {
     pub fn new() -> Self {
         AdaptiveDictionary {
             frequencies: BTreeMap::new(),
         }
     }
 
-    pub fn update(&mut self, data: &[u8]) {
-        for &byte in data {
-            *self.frequencies.entry(byte).or_insert(0) += 1;
+    pub fn update(&mut self, data: &[S]) {
+        for &symbol in data {
+            *self.frequencies.entry(symbol).or_insert(0) += 1;
         }
     }
 
-    pub fn get_frequencies(&self) -> &BTreeMap<u8, u32> {
+    pub fn get_frequencies(&self) -> &BTreeMap<S, u32> {
         &self.frequencies
     }
+
+    // Add the counts of `other`, e.g. to combine the statistics of several shards into
+    // one model before building a shared Huffman table. Counts saturate at u32::MAX.
+    pub fn merge(&mut self, other: &AdaptiveDictionary<S>) {
+        for (&symbol, &count) in &other.frequencies {
+            let total = self.frequencies.entry(symbol).or_insert(0);
+            *total = total.saturating_add(count);
+        }
+    }
+
+    // The counts this dictionary has beyond `other`, dropping symbols with none, e.g.
+    // what a worker saw since the snapshot it last sent. Merging the result into
+    // `other` gives this dictionary back when every count is at least the other's.
+    pub fn diff(&self, other: &AdaptiveDictionary<S>) -> AdaptiveDictionary<S> {
+        let frequencies = self
+            .frequencies
+            .iter()
+            .map(|(&symbol, &count)| (symbol, count.saturating_sub(other.frequencies.get(&symbol).copied().unwrap_or(0))))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        AdaptiveDictionary { frequencies }
+    }
 }
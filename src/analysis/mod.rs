@@ -0,0 +1,91 @@
+use crate::compressibility::{estimate, stored_size};
+use crate::compression::CompressionLevel;
+use crate::cost::entropy_of_counts;
+use crate::preprocessor::PatternUsage;
+use crate::sniff::{sniff, CompressedFormat, SNIFF_LEN};
+
+// Byte statistics for callers' own heuristics, e.g. skipping inputs that look
+// random or picking a codec per field, and the fuller report `qp analyze` prints.
+// Nothing here logs or prints.
+
+// How often each byte value occurs in `data`, indexed by the byte
+pub fn byte_histogram(data: &[u8]) -> [u64; 256] {
+    let mut histogram = [0u64; 256];
+    for &byte in data {
+        histogram[byte as usize] += 1;
+    }
+    histogram
+}
+
+// Shannon entropy of the bytes of `data` in bits per byte, from 0 for a single
+// repeated byte (or no bytes at all) to 8 for uniformly distributed bytes
+pub fn entropy(data: &[u8]) -> f64 {
+    entropy_of_counts(byte_histogram(data).iter().copied())
+}
+
+// Patterns have to shrink the Huffman-only prediction by this share to be worth
+// running the preprocessor at all
+const DEFAULT_GAIN: f64 = 0.05;
+// Beyond this share patterns dominate, and the longer ones Best mines tend to pay off
+const BEST_GAIN: f64 = 0.25;
+
+// How to compress an input, going by an Analysis
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recommendation {
+    // Coding will not make it smaller, keep the bytes as they are
+    Store,
+    Level(CompressionLevel),
+}
+
+// What the compressor would find in an input, without compressing it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    pub len: usize,
+    // Bits per byte of the whole input
+    pub entropy: f64,
+    pub compressed_format: Option<CompressedFormat>,
+    // Patterns of the dictionary fitted on a sample of `sample_len` bytes, most
+    // bytes saved first; occurrences are counted in the sample only
+    pub patterns: Vec<PatternUsage>,
+    pub sample_len: usize,
+    // Frame size predicted for the recommended level, see estimate_compressed_size
+    pub predicted_size: usize,
+    pub recommendation: Recommendation,
+}
+
+impl Analysis {
+    // Predicted frame size over input size
+    pub fn predicted_ratio(&self) -> f64 {
+        self.predicted_size as f64 / self.len.max(1) as f64
+    }
+}
+
+// Entropy, patterns, predicted size and the level that suits `data`
+pub fn analyze(data: &[u8]) -> Analysis {
+    let estimate = estimate(data);
+    let compressed_format = sniff(&data[..data.len().min(SNIFF_LEN)]);
+    let gain = 1.0 - estimate.size as f64 / estimate.huffman_size.max(1) as f64;
+    let recommendation = if compressed_format.is_some() || estimate.size.min(estimate.huffman_size) >= stored_size(data.len()) {
+        Recommendation::Store
+    } else if gain < DEFAULT_GAIN {
+        Recommendation::Level(CompressionLevel::Fast)
+    } else if gain > BEST_GAIN {
+        Recommendation::Level(CompressionLevel::Best)
+    } else {
+        Recommendation::Level(CompressionLevel::Default)
+    };
+    let predicted_size = match recommendation {
+        Recommendation::Store => stored_size(data.len()),
+        Recommendation::Level(CompressionLevel::Fast) => estimate.huffman_size,
+        Recommendation::Level(_) => estimate.size,
+    };
+    Analysis {
+        len: data.len(),
+        entropy: entropy(data),
+        compressed_format,
+        patterns: estimate.preprocessor.usage_report(),
+        sample_len: estimate.sample_len,
+        predicted_size,
+        recommendation,
+    }
+}
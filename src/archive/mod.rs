@@ -0,0 +1,475 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+
+use crate::compression::{frame_metadata, Compressor, Decompressor, FileMetadata};
+use crate::error::QuantumPackError;
+use crate::wire::{self, Reader};
+
+// Reading and writing files is the job of the outer layer
+pub use crate::file::{append_archive, create_archive, create_split_archive, extract_archive, extract_archive_entry, index_path, list_archive, reindex_archive};
+
+// A .qpa archive holds several files, each compressed into its own frame so any one
+// of them can be extracted without decoding the others:
+//
+//   "QPA1", u8 version
+//   the frames, back to back
+//   entry table: u32 entry count, then per entry varint path length, path,
+//                u64 size, u64 offset of its frame, u64 frame length
+//   u64 offset of the entry table, "QPAI"
+//
+// The table comes last so files can be appended by rewriting only the table.
+// Version 1 archives kept the same table in front, right after the header, and are
+// still read. Appending to one writes the table at the end and makes it version 2,
+// leaving the old table behind unused.
+//
+// Each frame records the file's modification time and permissions when the archive
+// was made from files on disk, see FileMetadata.
+//
+// Paths are relative, '/' separated and never contain "." or ".." components, so
+// extracting an archive cannot write outside the destination directory.
+//
+// A split archive keeps its entry table in a sidecar index file instead, so the
+// data file is only ever appended to, e.g. on log-structured storage:
+//
+//   data file:  "QPA1", u8 split version
+//               per entry varint path length, path, u64 size, u64 frame length,
+//               the frame
+//   index file: "QPI1", u8 index version, u64 length of the data file it covers,
+//               entry table as above
+//
+// Every record describes itself, so the index is only a cache: entries appended
+// after it was written are found by reading on from the length it covers, and a
+// lost index is rebuilt by reading the whole data file.
+const MAGIC: [u8; 4] = *b"QPA1";
+const LEGACY_VERSION: u8 = 1;
+const ARCHIVE_VERSION: u8 = 2;
+const SPLIT_VERSION: u8 = 3;
+const HEADER_LEN: u64 = 5;
+const INDEX_MARKER: [u8; 4] = *b"QPAI";
+const TRAILER_LEN: u64 = 12;
+const INDEX_MAGIC: [u8; 4] = *b"QPI1";
+const INDEX_VERSION: u8 = 1;
+// Extension of the index file next to a split data file
+pub const INDEX_EXTENSION: &str = "qpi";
+
+// One file in an archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    path: String,
+    size: u64,
+    offset: u64,
+    compressed_len: u64,
+}
+
+impl ArchiveEntry {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // Length of the file once extracted
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    // Position of the entry's frame in the archive
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn compressed_len(&self) -> u64 {
+        self.compressed_len
+    }
+
+    // Frame length relative to the file's length; above 1 when the entry expanded
+    pub fn ratio(&self) -> f64 {
+        if self.size == 0 {
+            return if self.compressed_len == 0 { 1.0 } else { f64::INFINITY };
+        }
+        self.compressed_len as f64 / self.size as f64
+    }
+
+    pub fn expanded(&self) -> bool {
+        self.compressed_len > self.size
+    }
+}
+
+pub const RATIO_BUCKETS: usize = 11;
+
+// Summary of how well the entries of an archive compressed, from the entry table alone
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveStats {
+    pub size: u64,
+    pub compressed_len: u64,
+    // buckets[i] counts the entries whose ratio lies in [i / 10, (i + 1) / 10); the
+    // last bucket holds every entry that did not shrink
+    pub buckets: [usize; RATIO_BUCKETS],
+    // Paths of the entries that are larger compressed than uncompressed, e.g.
+    // already compressed media that would be better left out
+    pub expanded: Vec<String>,
+}
+
+pub fn stats(entries: &[ArchiveEntry]) -> ArchiveStats {
+    let mut stats = ArchiveStats { size: 0, compressed_len: 0, buckets: [0; RATIO_BUCKETS], expanded: Vec::new() };
+    for entry in entries {
+        // The sizes come from the entry table, which may claim anything
+        stats.size = stats.size.saturating_add(entry.size);
+        stats.compressed_len = stats.compressed_len.saturating_add(entry.compressed_len);
+        let bucket = (entry.ratio() * 10.0) as usize;
+        stats.buckets[bucket.min(RATIO_BUCKETS - 1)] += 1;
+        if entry.expanded() {
+            stats.expanded.push(entry.path.clone());
+        }
+    }
+    stats
+}
+
+// Compress `files`, given as (path, contents) pairs, into an archive
+pub fn pack(files: &[(&str, &[u8])], compressor: &Compressor) -> Result<Vec<u8>, QuantumPackError> {
+    pack_with_metadata(files, &[], compressor)
+}
+
+// pack, recording metadata[i] in the frame of files[i]; `metadata` may be empty
+pub fn pack_with_metadata(files: &[(&str, &[u8])], metadata: &[FileMetadata], compressor: &Compressor) -> Result<Vec<u8>, QuantumPackError> {
+    let mut archive = MAGIC.to_vec();
+    archive.push(ARCHIVE_VERSION);
+    archive.extend_from_slice(&append_with_metadata(&[], files, metadata, compressor)?.1);
+    Ok(archive)
+}
+
+// Add `files` to an archive with the entry table `entries`. Returns the position of
+// the old entry table and the bytes that replace everything from there on: the new
+// frames, then the combined entry table. Existing frames are left untouched. A
+// version 1 archive also needs its version byte, after the magic, set to 2.
+pub fn append(entries: &[ArchiveEntry], files: &[(&str, &[u8])], compressor: &Compressor) -> Result<(u64, Vec<u8>), QuantumPackError> {
+    append_with_metadata(entries, files, &[], compressor)
+}
+
+// append, recording metadata[i] in the frame of files[i]; `metadata` may be empty
+pub fn append_with_metadata(entries: &[ArchiveEntry], files: &[(&str, &[u8])], metadata: &[FileMetadata], compressor: &Compressor) -> Result<(u64, Vec<u8>), QuantumPackError> {
+    let table_offset = entries.iter().map(|entry| entry.offset + entry.compressed_len).max().unwrap_or(HEADER_LEN);
+    let mut entries = entries.to_vec();
+    let mut paths: BTreeSet<String> = entries.iter().map(|entry| entry.path.clone()).collect();
+    let mut tail = Vec::new();
+    for (index, (path, contents)) in files.iter().enumerate() {
+        check_path(path)?;
+        if !paths.insert(path.to_string()) {
+            return Err(QuantumPackError::InvalidInput(format!("{} is in the archive twice", path)));
+        }
+        let start = tail.len();
+        compressor.compress_with_metadata(contents, metadata.get(index).copied(), &mut tail)?;
+        entries.push(ArchiveEntry {
+            path: path.to_string(),
+            size: contents.len() as u64,
+            offset: table_offset + start as u64,
+            compressed_len: (tail.len() - start) as u64,
+        });
+    }
+
+    let new_table_offset = table_offset + tail.len() as u64;
+    write_table(&mut tail, &entries);
+    wire::write_u64(&mut tail, new_table_offset);
+    tail.extend_from_slice(&INDEX_MARKER);
+    Ok((table_offset, tail))
+}
+
+fn write_table(output: &mut Vec<u8>, entries: &[ArchiveEntry]) {
+    wire::write_u32(output, entries.len() as u32);
+    for entry in entries {
+        wire::write_varint(output, entry.path.len() as u64);
+        output.extend_from_slice(entry.path.as_bytes());
+        wire::write_u64(output, entry.size);
+        wire::write_u64(output, entry.offset);
+        wire::write_u64(output, entry.compressed_len);
+    }
+}
+
+// The header of a split data file, before its first record
+pub fn split_header() -> Vec<u8> {
+    let mut header = MAGIC.to_vec();
+    header.push(SPLIT_VERSION);
+    header
+}
+
+// Compress `files` into records to append to a split data file that is `data_len`
+// bytes long and holds `entries`. Returns the records and their entries; the data
+// file is not otherwise changed.
+pub fn append_split(entries: &[ArchiveEntry], data_len: u64, files: &[(&str, &[u8])], metadata: &[FileMetadata], compressor: &Compressor) -> Result<(Vec<u8>, Vec<ArchiveEntry>), QuantumPackError> {
+    let mut paths: BTreeSet<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+    let mut records = Vec::new();
+    let mut added = Vec::new();
+    let mut frame = Vec::new();
+    for (index, &(path, contents)) in files.iter().enumerate() {
+        check_path(path)?;
+        if !paths.insert(path) {
+            return Err(QuantumPackError::InvalidInput(format!("{} is in the archive twice", path)));
+        }
+        frame.clear();
+        compressor.compress_with_metadata(contents, metadata.get(index).copied(), &mut frame)?;
+        wire::write_varint(&mut records, path.len() as u64);
+        records.extend_from_slice(path.as_bytes());
+        wire::write_u64(&mut records, contents.len() as u64);
+        wire::write_u64(&mut records, frame.len() as u64);
+        added.push(ArchiveEntry {
+            path: path.to_string(),
+            size: contents.len() as u64,
+            offset: data_len + records.len() as u64,
+            compressed_len: frame.len() as u64,
+        });
+        records.extend_from_slice(&frame);
+    }
+    Ok((records, added))
+}
+
+// The index file of a split data file that is `data_len` bytes long and holds `entries`
+pub fn write_index(entries: &[ArchiveEntry], data_len: u64) -> Vec<u8> {
+    let mut index = INDEX_MAGIC.to_vec();
+    index.push(INDEX_VERSION);
+    wire::write_u64(&mut index, data_len);
+    write_table(&mut index, entries);
+    index
+}
+
+// Parse an index file. Returns the length of the data file it covers and its entries.
+pub fn read_index(index: &[u8]) -> Result<(u64, Vec<ArchiveEntry>), QuantumPackError> {
+    let mut reader = Reader::new(index, "archive index");
+    if reader.bytes(INDEX_MAGIC.len())? != INDEX_MAGIC {
+        return Err(invalid_archive("missing QPI1 magic"));
+    }
+    let version = reader.u8()?;
+    if version != INDEX_VERSION {
+        return Err(QuantumPackError::UnsupportedVersion(version));
+    }
+    let data_len = reader.u64()?;
+    let entries = read_table(Cursor::new(reader.rest()), index.len() as u64, data_len)?;
+    Ok((data_len, entries))
+}
+
+// Whether `input` is a split data file. It is left just past the archive header.
+pub fn is_split<R: Read + Seek>(mut input: R) -> Result<bool, QuantumPackError> {
+    input.seek(SeekFrom::Start(0))?;
+    if read_bytes(&mut input, MAGIC.len())? != MAGIC {
+        return Err(invalid_archive("missing QPA1 magic"));
+    }
+    Ok(read_bytes(&mut input, 1)?[0] == SPLIT_VERSION)
+}
+
+// Read the records of a split data file from `offset`, the start of a record, to
+// the end, skipping over their frames. With an offset of 0 every record is read,
+// which rebuilds a lost index.
+pub fn read_records<R: Read + Seek>(mut input: R, offset: u64) -> Result<Vec<ArchiveEntry>, QuantumPackError> {
+    if !is_split(&mut input)? {
+        return Err(invalid_archive("not a split archive"));
+    }
+    let data_len = input.seek(SeekFrom::End(0))?;
+    let mut position = offset.max(HEADER_LEN);
+    if position > data_len {
+        return Err(invalid_archive("the index covers more than the data file holds"));
+    }
+    let mut input = BufReader::new(input);
+    input.seek(SeekFrom::Start(position))?;
+    let mut entries = Vec::new();
+    while position < data_len {
+        let mut record = (&mut input).take(data_len - position);
+        let path = read_path(&mut record, data_len)?;
+        let fields = read_bytes(&mut record, 16)?;
+        let mut reader = Reader::new(&fields, "archive");
+        let size = reader.u64()?;
+        let compressed_len = reader.u64()?;
+        let offset = input.stream_position()?;
+        let entry = ArchiveEntry { path, size, offset, compressed_len };
+        check_extent(&entry, data_len)?;
+        position = offset + compressed_len;
+        input.seek(SeekFrom::Start(position))?;
+        entries.push(entry);
+    }
+    check_unique(&entries)?;
+    Ok(entries)
+}
+
+// An archive held in memory. Parsing only reads the entry table; entries are
+// decoded when they are read.
+pub struct Archive<'a> {
+    data: &'a [u8],
+    entries: Vec<ArchiveEntry>,
+}
+
+impl<'a> Archive<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, QuantumPackError> {
+        let entries = read_entries(Cursor::new(data))?;
+        Ok(Archive { data, entries })
+    }
+
+    // The archive `data` with the entry table `entries`, e.g. read from the index
+    // of a split data file
+    pub fn with_entries(data: &'a [u8], entries: Vec<ArchiveEntry>) -> Result<Self, QuantumPackError> {
+        for entry in &entries {
+            check_extent(entry, data.len() as u64)?;
+        }
+        check_unique(&entries)?;
+        Ok(Archive { data, entries })
+    }
+
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    pub fn entry(&self, path: &str) -> Option<&ArchiveEntry> {
+        self.entries.iter().find(|entry| entry.path == path)
+    }
+
+    // Decode the contents of `entry`
+    pub fn read(&self, entry: &ArchiveEntry, decompressor: &Decompressor) -> Result<Vec<u8>, QuantumPackError> {
+        decode_entry(entry, self.frame(entry), decompressor)
+    }
+
+    // The modification time and permissions recorded for `entry`, if any
+    pub fn metadata(&self, entry: &ArchiveEntry) -> Result<Option<FileMetadata>, QuantumPackError> {
+        frame_metadata(self.frame(entry))
+    }
+
+    fn frame(&self, entry: &ArchiveEntry) -> &'a [u8] {
+        &self.data[entry.offset as usize..(entry.offset + entry.compressed_len) as usize]
+    }
+}
+
+// Decode the frame of `entry`, read from its offset in the archive
+pub(crate) fn decode_entry(entry: &ArchiveEntry, frame: &[u8], decompressor: &Decompressor) -> Result<Vec<u8>, QuantumPackError> {
+    let (contents, _) = decompressor.decompress(frame).map_err(|error| error.at(entry.offset))?;
+    if contents.len() as u64 != entry.size {
+        return Err(invalid_archive(format!("entry {} does not match its recorded size", entry.path)));
+    }
+    Ok(contents)
+}
+
+// Read the entry table of an archive without reading its frames, e.g. to list a
+// large archive file. The entries of a split data file are read from its records.
+pub fn read_entries<R: Read + Seek>(mut input: R) -> Result<Vec<ArchiveEntry>, QuantumPackError> {
+    if read_bytes(&mut input, MAGIC.len())? != MAGIC {
+        return Err(invalid_archive("missing QPA1 magic"));
+    }
+    let version = read_bytes(&mut input, 1)?[0];
+    if version == SPLIT_VERSION {
+        return read_records(input, HEADER_LEN);
+    }
+    let archive_len = input.seek(SeekFrom::End(0))?;
+    if version == LEGACY_VERSION {
+        input.seek(SeekFrom::Start(HEADER_LEN))?;
+        return read_table(input, archive_len, archive_len);
+    }
+    if version != ARCHIVE_VERSION {
+        return Err(QuantumPackError::UnsupportedVersion(version));
+    }
+    if archive_len < HEADER_LEN + TRAILER_LEN {
+        return Err(QuantumPackError::truncated("archive"));
+    }
+    input.seek(SeekFrom::End(-(TRAILER_LEN as i64)))?;
+    let trailer = read_bytes(&mut input, TRAILER_LEN as usize)?;
+    let mut reader = Reader::new(&trailer, "archive");
+    let table_offset = reader.u64()?;
+    if reader.rest() != INDEX_MARKER {
+        return Err(invalid_archive("missing entry table marker, the archive may be truncated"));
+    }
+    let table_end = archive_len - TRAILER_LEN;
+    if !(HEADER_LEN..=table_end).contains(&table_offset) {
+        return Err(invalid_archive("entry table lies outside the archive"));
+    }
+
+    input.seek(SeekFrom::Start(table_offset))?;
+    read_table(input.take(table_end - table_offset), table_end, table_offset)
+}
+
+// Set the version byte of a version 1 archive open as `archive` to 2, once append
+// has written the entry table at its end
+pub(crate) fn upgrade_legacy<F: Read + Write + Seek>(mut archive: F) -> Result<(), QuantumPackError> {
+    archive.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+    if read_bytes(&mut archive, 1)?[0] == LEGACY_VERSION {
+        archive.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+        archive.write_all(&[ARCHIVE_VERSION])?;
+    }
+    Ok(())
+}
+
+// Read an entry table with paths of at most `path_limit` bytes and frames within the
+// first `end` bytes of the archive
+fn read_table<R: Read>(mut input: R, path_limit: u64, end: u64) -> Result<Vec<ArchiveEntry>, QuantumPackError> {
+    let count = Reader::new(&read_bytes(&mut input, 4)?, "archive").u32()?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let path = read_path(&mut input, path_limit)?;
+        let fields = read_bytes(&mut input, 24)?;
+        let mut reader = Reader::new(&fields, "archive");
+        let entry = ArchiveEntry { path, size: reader.u64()?, offset: reader.u64()?, compressed_len: reader.u64()? };
+        check_extent(&entry, end)?;
+        entries.push(entry);
+    }
+    check_unique(&entries)?;
+    Ok(entries)
+}
+
+// Read an entry path of at most `limit` bytes
+fn read_path<R: Read>(input: &mut R, limit: u64) -> Result<String, QuantumPackError> {
+    let path_len = read_varint(input)?;
+    if path_len > limit {
+        return Err(QuantumPackError::truncated("archive"));
+    }
+    let path = String::from_utf8(read_bytes(input, path_len as usize)?).map_err(|_| invalid_archive("entry path is not UTF-8"))?;
+    check_path(&path)?;
+    Ok(path)
+}
+
+// Reject an entry whose frame does not lie within the first `end` bytes
+fn check_extent(entry: &ArchiveEntry, end: u64) -> Result<(), QuantumPackError> {
+    if entry.offset < HEADER_LEN || entry.offset.checked_add(entry.compressed_len).is_none_or(|frame_end| frame_end > end) {
+        return Err(invalid_archive(format!("entry {} lies outside the archive", entry.path)));
+    }
+    Ok(())
+}
+
+// Reject an entry table that holds a path twice, which append never writes; only one
+// of the entries could ever be extracted
+fn check_unique(entries: &[ArchiveEntry]) -> Result<(), QuantumPackError> {
+    let mut paths = BTreeSet::new();
+    match entries.iter().find(|entry| !paths.insert(entry.path.as_str())) {
+        Some(entry) => Err(invalid_archive(format!("entry {} is in the archive twice", entry.path))),
+        None => Ok(()),
+    }
+}
+
+fn read_bytes<R: Read>(input: &mut R, len: usize) -> Result<Vec<u8>, QuantumPackError> {
+    let mut bytes = vec![0; len];
+    input.read_exact(&mut bytes).map_err(|error| match error.kind() {
+        io::ErrorKind::UnexpectedEof => QuantumPackError::truncated("archive"),
+        _ => error.into(),
+    })?;
+    Ok(bytes)
+}
+
+fn read_varint<R: Read>(input: &mut R) -> Result<u64, QuantumPackError> {
+    let mut bytes = Vec::new();
+    while bytes.len() < 10 {
+        let byte = read_bytes(input, 1)?[0];
+        bytes.push(byte);
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    Reader::new(&bytes, "archive").varint()
+}
+
+// Reject paths that could escape the extraction directory
+fn check_path(path: &str) -> Result<(), QuantumPackError> {
+    let valid = !path.is_empty()
+        && !path.starts_with('/')
+        && !path.contains('\\')
+        && !path.contains('\0')
+        && path.split('/').all(|component| !matches!(component, "" | "." | ".."));
+    if valid {
+        Ok(())
+    } else {
+        Err(invalid_archive(format!("invalid entry path {:?}", path)))
+    }
+}
+
+fn invalid_archive<E: Into<String>>(message: E) -> QuantumPackError {
+    QuantumPackError::InvalidArchive(message.into())
+}
@@ -0,0 +1,93 @@
+use std::time::{Duration, Instant};
+
+use crate::compression::Compressor;
+use crate::error::QuantumPackError;
+
+// Size of the synthetic input compressed and decompressed over and over
+const BENCHMARK_LEN: usize = 256 * 1024;
+
+// How fast compression runs on this machine, in megabytes (10^6 bytes) of
+// uncompressed data per second, e.g. to decide at startup whether compressing
+// inline is affordable
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Throughput {
+    pub compress_mb_per_sec: f64,
+    pub decompress_mb_per_sec: f64,
+    // Compressed over uncompressed size of the synthetic input, for telling how
+    // representative it is of the caller's data
+    pub ratio: f64,
+}
+
+impl Compressor {
+    // Measure these settings on synthetic log-like records, spending about `duration`
+    // on it, half compressing and half decompressing. Each direction runs at least
+    // once, so a zero duration gives a quick, rough figure.
+    pub fn self_benchmark(&self, duration: Duration) -> Result<Throughput, QuantumPackError> {
+        let data = synthetic_records(BENCHMARK_LEN);
+        let mut frame = Vec::new();
+        let compress = repeat_for(duration / 2, || {
+            frame.clear();
+            self.compress_shared(&data, &mut frame)
+        })?;
+        let decompressor = self.decompressor();
+        let decompress = repeat_for(duration / 2, || decompressor.decompress(&frame).map(|_| ()))?;
+        Ok(Throughput {
+            compress_mb_per_sec: mb_per_sec(data.len(), compress),
+            decompress_mb_per_sec: mb_per_sec(data.len(), decompress),
+            ratio: frame.len() as f64 / data.len() as f64,
+        })
+    }
+}
+
+// Compressor::self_benchmark with the default settings
+pub fn self_benchmark(duration: Duration) -> Throughput {
+    Compressor::new().self_benchmark(duration).expect("the default settings compress any input")
+}
+
+// Run `operation` until `duration` is over, at least once. Returns the runs and
+// the time they took.
+fn repeat_for<F>(duration: Duration, mut operation: F) -> Result<(u32, Duration), QuantumPackError>
+where
+    F: FnMut() -> Result<(), QuantumPackError>,
+{
+    let start = Instant::now();
+    let mut runs = 0;
+    loop {
+        operation()?;
+        runs += 1;
+        if start.elapsed() >= duration {
+            return Ok((runs, start.elapsed()));
+        }
+    }
+}
+
+fn mb_per_sec(len: usize, (runs, elapsed): (u32, Duration)) -> f64 {
+    let bytes = len as f64 * runs as f64;
+    // A run can finish within the clock's resolution
+    bytes / elapsed.as_secs_f64().max(1e-9) / 1e6
+}
+
+// Records with repeated keys, a few distinct values and varying numbers, built
+// from a fixed seed so every machine measures the same input
+fn synthetic_records(len: usize) -> Vec<u8> {
+    const LEVELS: [&str; 4] = ["debug", "info", "info", "warn"];
+    const PATHS: [&str; 5] = ["/api/users", "/api/orders", "/health", "/api/orders/items", "/login"];
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    let mut data = Vec::with_capacity(len + 128);
+    while data.len() < len {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let record = format!(
+            "{{\"ts\":{},\"level\":\"{}\",\"path\":\"{}\",\"status\":{},\"ms\":{}}}\n",
+            1_700_000_000 + data.len() as u64 / 64,
+            LEVELS[(state % 4) as usize],
+            PATHS[(state >> 8) as usize % PATHS.len()],
+            if (state >> 16) & 15 == 0 { 500 } else { 200 },
+            (state >> 24) & 1023
+        );
+        data.extend_from_slice(record.as_bytes());
+    }
+    data.truncate(len);
+    data
+}
@@ -0,0 +1,242 @@
+// Shell completion scripts for the qp command line, generated from the table of
+// subcommands and options below. main.rs parses the same options by hand, so an
+// option added there belongs here too.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    PowerShell,
+}
+
+impl Shell {
+    pub const ALL: [Shell; 4] = [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::PowerShell];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+            Shell::PowerShell => "powershell",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Shell> {
+        Shell::ALL.iter().copied().find(|shell| shell.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Command {
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+// What follows an option on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    None,
+    Path,
+    Number,
+    Choice(&'static [&'static str]),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CliOption {
+    pub name: &'static str,
+    pub value: Value,
+    pub description: &'static str,
+}
+
+const fn command(name: &'static str, description: &'static str) -> Command {
+    Command { name, description }
+}
+
+const fn option(name: &'static str, value: Value, description: &'static str) -> CliOption {
+    CliOption { name, value, description }
+}
+
+pub const COMMANDS: &[Command] = &[
+    command("compress", "Compress a file"),
+    command("decompress", "Decompress a file"),
+    command("concat", "Join compressed files into one"),
+    command("recompress", "Compress a compressed file again with other settings"),
+    command("convert", "Convert to or from gzip and zstd"),
+    command("archive", "Pack files and directories into an archive"),
+    command("extract", "Extract an archive or one of its entries"),
+    command("list", "List the entries of an archive"),
+    command("reindex", "Rebuild the index file of a split archive"),
+    command("analyze", "Report entropy, repeated patterns and the level that suits a file"),
+    command("completions", "Print a shell completion script"),
+    #[cfg(feature = "trace")]
+    command("trace", "Compress a file and record the encoder decisions"),
+    #[cfg(feature = "trace")]
+    command("replay", "Check a compressed file against a recorded trace"),
+];
+
+pub const OPTIONS: &[CliOption] = &[
+    option("-o", Value::Path, "Output file or directory"),
+    option("-1", Value::None, "Fastest compression"),
+    option("-2", Value::None, "Compression level 2"),
+    option("-3", Value::None, "Compression level 3"),
+    option("-4", Value::None, "Compression level 4"),
+    option("-5", Value::None, "Compression level 5"),
+    option("-6", Value::None, "Compression level 6"),
+    option("-7", Value::None, "Compression level 7"),
+    option("-8", Value::None, "Compression level 8"),
+    option("-9", Value::None, "Best compression"),
+    option("--level", Value::Choice(&["fast", "default", "best"]), "Compression level"),
+    option("--bwlimit", Value::Number, "Limit throughput to this many bytes per second"),
+    option("--dict-file", Value::Path, "Read dictionary patterns from a file"),
+    option("--dict-replace", Value::None, "Use only the patterns of --dict-file"),
+    option("--allow-trailing", Value::None, "Ignore bytes after the last frame"),
+    option("--no-metadata", Value::None, "Do not record or restore modification time and permissions"),
+    option("--block-size", Value::Number, "Split the input into independently decodable blocks"),
+    option("--pad-to", Value::Number, "Pad the compressed output to exactly this many bytes"),
+    option("--salvage", Value::None, "Recover the blocks of a damaged file that still decode"),
+    option("--no-progress", Value::None, "Do not show progress on a terminal"),
+    option("-q", Value::None, "Print errors only"),
+    option("-v", Value::None, "Print a summary of each file; twice for encoder diagnostics"),
+    option("--error-format", Value::Choice(&["text", "json"]), "Report failures as text or JSON"),
+    option("--stats", Value::None, "Show a compression ratio histogram"),
+    option("--append", Value::None, "Add to an existing archive"),
+    option("--split-index", Value::None, "Keep the archive index in a separate .qpi file"),
+    option("--top", Value::Number, "Patterns analyze lists"),
+    option("--keep-going", Value::None, "Carry on past inputs that fail and report them at the end"),
+];
+
+// The completion script for `program` in `shell`
+pub fn script(shell: Shell, program: &str) -> String {
+    match shell {
+        Shell::Bash => bash(program),
+        Shell::Zsh => zsh(program),
+        Shell::Fish => fish(program),
+        Shell::PowerShell => powershell(program),
+    }
+}
+
+// Shell function names cannot contain every character a program name can
+fn function_name(program: &str) -> String {
+    program.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+}
+
+fn names<T>(items: &[T], name: impl Fn(&T) -> &'static str) -> String {
+    items.iter().map(name).collect::<Vec<_>>().join(" ")
+}
+
+fn shell_names() -> String {
+    names(&Shell::ALL, |shell| shell.name())
+}
+
+fn bash(program: &str) -> String {
+    let function = function_name(program);
+    let mut script = format!("_{}() {{\n", function);
+    script.push_str("    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n");
+    script.push_str("    case \"$prev\" in\n");
+    for option in OPTIONS {
+        match option.value {
+            Value::None => {}
+            Value::Path => script.push_str(&format!("        {}) COMPREPLY=($(compgen -f -- \"$cur\")); return ;;\n", option.name)),
+            Value::Number => script.push_str(&format!("        {}) return ;;\n", option.name)),
+            Value::Choice(choices) => script.push_str(&format!(
+                "        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;\n",
+                option.name,
+                choices.join(" ")
+            )),
+        }
+    }
+    script.push_str("    esac\n");
+    script.push_str("    if [[ \"$cur\" == -* ]]; then\n");
+    script.push_str(&format!("        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n", names(OPTIONS, |option| option.name)));
+    script.push_str("    elif [[ $COMP_CWORD -eq 1 ]]; then\n");
+    script.push_str(&format!("        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n", names(COMMANDS, |command| command.name)));
+    script.push_str("    elif [[ \"${COMP_WORDS[1]}\" == completions ]]; then\n");
+    script.push_str(&format!("        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n", shell_names()));
+    script.push_str("    else\n");
+    script.push_str("        COMPREPLY=($(compgen -f -- \"$cur\"))\n");
+    script.push_str("    fi\n");
+    script.push_str("}\n");
+    script.push_str(&format!("complete -o filenames -F _{} {}\n", function, program));
+    script
+}
+
+fn zsh(program: &str) -> String {
+    let function = function_name(program);
+    let mut script = format!("#compdef {}\n\n_{}() {{\n    _arguments -s \\\n", program, function);
+    for option in OPTIONS {
+        let action = match option.value {
+            Value::None => String::new(),
+            Value::Path => ":file:_files".to_string(),
+            Value::Number => ":bytes:".to_string(),
+            Value::Choice(choices) => format!(":value:({})", choices.join(" ")),
+        };
+        script.push_str(&format!("        '{}[{}]{}' \\\n", option.name, option.description, action));
+    }
+    let commands: Vec<String> = COMMANDS.iter().map(|command| format!("{}\\:\"{}\"", command.name, command.description)).collect();
+    script.push_str(&format!("        '1:command:(({}))' \\\n", commands.join(" ")));
+    script.push_str("        '*:file:_files'\n");
+    script.push_str("}\n\n");
+    script.push_str(&format!("_{} \"$@\"\n", function));
+    script
+}
+
+fn fish(program: &str) -> String {
+    let mut script = String::new();
+    for command in COMMANDS {
+        script.push_str(&format!(
+            "complete -c {} -n __fish_use_subcommand -f -a {} -d '{}'\n",
+            program, command.name, command.description
+        ));
+    }
+    script.push_str(&format!(
+        "complete -c {} -n '__fish_seen_subcommand_from completions' -f -a '{}'\n",
+        program,
+        shell_names()
+    ));
+    for option in OPTIONS {
+        let flag = match option.name.strip_prefix("--") {
+            Some(long) => format!("-l {}", long),
+            None => format!("-s {}", &option.name[1..]),
+        };
+        let value = match option.value {
+            Value::None => String::new(),
+            Value::Path => " -r -F".to_string(),
+            Value::Number => " -x".to_string(),
+            Value::Choice(choices) => format!(" -x -a '{}'", choices.join(" ")),
+        };
+        script.push_str(&format!("complete -c {} {}{} -d '{}'\n", program, flag, value, option.description));
+    }
+    script
+}
+
+fn powershell(program: &str) -> String {
+    let mut script = format!("Register-ArgumentCompleter -Native -CommandName '{}' -ScriptBlock {{\n", program);
+    script.push_str("    param($wordToComplete, $commandAst, $cursorPosition)\n");
+    script.push_str("    $words = @($commandAst.CommandElements | ForEach-Object { $_.ToString() })\n");
+    script.push_str("    $previous = if ($wordToComplete) { $words[-2] } else { $words[-1] }\n");
+    script.push_str("    $candidates = switch ($previous) {\n");
+    for option in OPTIONS {
+        if let Value::Choice(choices) = option.value {
+            let choices: Vec<String> = choices.iter().map(|choice| format!("@('{}', '{}')", choice, option.description)).collect();
+            script.push_str(&format!("        '{}' {{ @({}) }}\n", option.name, choices.join(", ")));
+        }
+    }
+    let shells: Vec<String> = Shell::ALL.iter().map(|shell| format!("@('{}', 'Shell')", shell.name())).collect();
+    script.push_str(&format!("        'completions' {{ @({}) }}\n", shells.join(", ")));
+    script.push_str("        default {\n");
+    script.push_str("            if ($wordToComplete -like '-*') {\n");
+    let options: Vec<String> = OPTIONS.iter().map(|option| format!("@('{}', '{}')", option.name, option.description)).collect();
+    script.push_str(&format!("                @({})\n", options.join(", ")));
+    script.push_str("            } elseif ($words.Count -le 2) {\n");
+    let commands: Vec<String> = COMMANDS.iter().map(|command| format!("@('{}', '{}')", command.name, command.description)).collect();
+    script.push_str(&format!("                @({})\n", commands.join(", ")));
+    script.push_str("            }\n");
+    script.push_str("        }\n");
+    script.push_str("    }\n");
+    script.push_str("    $candidates | Where-Object { $_[0] -like \"$wordToComplete*\" } | ForEach-Object {\n");
+    script.push_str("        [System.Management.Automation.CompletionResult]::new($_[0], $_[0], 'ParameterValue', $_[1])\n");
+    script.push_str("    }\n");
+    script.push_str("}\n");
+    script
+}
@@ -0,0 +1,97 @@
+use crate::compression::{encode_length_table, serialize_code_length_table, TableEncoding};
+use crate::cost::{entropy, estimate_encoded_bits, estimate_encoded_len, histogram, huffman_code_lengths};
+use crate::preprocessor::{take_spread, Preprocessor};
+
+// How compressible each region of an input is, for tools that draw a heatmap of a
+// file or send regions to different pipelines (e.g. store already compressed media
+// and compress the rest)
+
+// One window of the input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowProfile {
+    pub offset: usize,
+    pub len: usize,
+    // Shannon entropy of the window's bytes in bits per byte, 0 to 8
+    pub entropy: f64,
+    // Size of the window's Huffman data, coded on its own, over its size. Frame
+    // overhead and what the preprocessor's dictionary would save are not included,
+    // so this is what CompressionLevel::Fast gets on a large window.
+    pub predicted_ratio: f64,
+}
+
+impl WindowProfile {
+    // Whether compressing the window is likely to pay off, i.e. saves at least 5%
+    pub fn is_compressible(&self) -> bool {
+        self.predicted_ratio < 0.95
+    }
+}
+
+// Profile `data` in consecutive windows of `window` bytes, the last one possibly shorter
+pub fn compressibility_profile(data: &[u8], window: usize) -> Vec<WindowProfile> {
+    data.chunks(window.max(1))
+        .enumerate()
+        .map(|(index, chunk)| profile_window(index * window.max(1), chunk))
+        .collect()
+}
+
+fn profile_window(offset: usize, window: &[u8]) -> WindowProfile {
+    let histogram = histogram(window);
+    let predicted_ratio = estimate_encoded_len(&histogram) as f64 / window.len() as f64;
+    WindowProfile { offset, len: window.len(), entropy: entropy(&histogram), predicted_ratio }
+}
+
+// Bytes of the input estimate_compressed_size codes, drawn from all along it
+const ESTIMATE_SAMPLE_LEN: usize = 64 * 1024;
+// Header, padding bits, the three section sizes and the footer of a frame
+const FRAME_OVERHEAD: usize = 6 + 1 + 12 + 16;
+
+// Predict the size of the frame compress_shared writes for `data` with the default
+// settings, to decide whether compressing it is worth the CPU. Only a sample is
+// preprocessed: its tokens give the entropy after the patterns it covers, which is
+// scaled to the whole input. Inputs no larger than the sample are predicted exactly.
+pub fn estimate_compressed_size(data: &[u8]) -> usize {
+    estimate(data).size
+}
+
+// What estimate_compressed_size found out along the way
+pub(crate) struct Estimate {
+    pub(crate) size: usize,
+    // The same prediction for Huffman coding alone, as CompressionLevel::Fast does
+    pub(crate) huffman_size: usize,
+    // Fitted on the sample, its usage report tells what the patterns did
+    pub(crate) preprocessor: Preprocessor,
+    pub(crate) sample_len: usize,
+}
+
+pub(crate) fn estimate(data: &[u8]) -> Estimate {
+    let mut sample = Vec::new();
+    let sample = if data.len() > ESTIMATE_SAMPLE_LEN {
+        take_spread(data, ESTIMATE_SAMPLE_LEN, &mut sample);
+        &sample[..]
+    } else {
+        data
+    };
+    let scale = data.len() as f64 / sample.len().max(1) as f64;
+    let coded_size = |symbols: &[u8], dictionary_len: usize| {
+        let histogram = histogram(symbols);
+        let bits = estimate_encoded_bits(&histogram) as f64 * scale;
+        let dense = serialize_code_length_table(&huffman_code_lengths(&histogram));
+        let (table, sparse) = encode_length_table(&dense, TableEncoding::Smallest);
+        let table_len = table.len() + sparse as usize;
+        (FRAME_OVERHEAD + table_len + dictionary_len + (bits / 8.0).ceil() as usize).min(stored_size(data.len()))
+    };
+    let mut preprocessor = Preprocessor::new();
+    let tokens = preprocessor.preprocess(sample);
+    Estimate {
+        size: coded_size(&tokens, preprocessor.serialize_dictionary().len()),
+        huffman_size: coded_size(sample, 0),
+        preprocessor,
+        sample_len: sample.len(),
+    }
+}
+
+// Size of the frame holding `len` bytes stored as they are, which blocks that
+// would grow are, with a block type byte
+pub(crate) fn stored_size(len: usize) -> usize {
+    FRAME_OVERHEAD + 1 + len
+}
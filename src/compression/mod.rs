@@ -1,19 +1,31 @@
-use std::{collections::BTreeMap, fs::File, io::{self, Read, Write}};
-use crate::huffman::{HuffmanNode, build_huffman_tree_with_dictionary, generate_huffman_codes, huffman_decode, huffman_encode};
-use crate::preprocessor::Preprocessor;
+use std::{borrow::Cow, collections::BTreeMap, io::{self, Read, Seek, SeekFrom}, sync::Arc};
+use crate::huffman::{HuffmanNode, Symbol, adaptive_decode_bits, adaptive_encode_bits, adaptive_tree_size, build_huffman_tree_from_codes, build_huffman_tree_seeded, build_huffman_tree_with_dictionary, canonical_codes, code_lengths, generate_huffman_codes, huffman_decode_bits, huffman_decode_limited, huffman_encode, huffman_encode_bits, push_bit_count, split_bit_count};
+use crate::preprocessor::{Preprocessor, SharedDictionary, Token, Tokenization, TrainedDictionary};
 use crate::adaptive_dictionary::AdaptiveDictionary;
-use std::convert::TryInto;
-use std::str;
+use crate::checksum::crc32;
+use crate::stage::{max_encoded_len, Stage};
+use crate::error::QuantumPackError;
+use crate::file::OutputPolicy;
+use crate::hook::{FrameHook, FrameHookHandle};
+use crate::progress::{report, ProgressHandler};
+use crate::warning::{warn, Warning, WarningHandler};
+use crate::wire::{self, Reader};
 
 // This module handles the compression and decompression of data using Huffman coding
 // and an adaptive dictionary-based preprocessor. The key aspects that need to be consistent
 // across both compression and decompression processes are:
-// 1. Frequency Table and Huffman Tree: For consistent encoding/decoding rules.
+// 1. Code Lengths and Huffman Tree: For consistent encoding/decoding rules.
 // 2. Processed Data: Ensuring data integrity post preprocessing.
 // 3. Huffman Codes: Generated from the Huffman tree, crucial for encoding and decoding.
-// 4. Serialized Frequency Table: Format and content should match in both compression and decompression.
+// 4. Serialized Code Length Table: Canonical codes follow from the lengths alone, so both sides derive identical codes.
 // 5. Compressed Data: Output of compression and input for decompression.
 // 6. Decompressed Data: Should match the original input data for lossless handling.
+//
+// Two inputs have degenerate trees and are settled by the format itself. Empty input
+// has an empty code table, and its data section holds no code bits. Input of one
+// distinct symbol gives that symbol the one bit code 0, so the data holds exactly
+// one 0 bit per occurrence. Decoders reject data under an empty table and 1 bits
+// under a single symbol table.
 
 // Serialize the frequency table
 pub fn serialize_frequency_table(dictionary: &AdaptiveDictionary) -> Vec<u8> {
@@ -21,7 +33,7 @@ pub fn serialize_frequency_table(dictionary: &AdaptiveDictionary) -> Vec<u8> {
     for (&byte, &frequency) in dictionary.get_frequencies() {
         if frequency > 0 {
             serialized.push(byte); // Character byte
-            serialized.extend_from_slice(&frequency.to_be_bytes()); // Frequency bytes
+            wire::write_u32(&mut serialized, frequency); // Frequency bytes
         }
     }
     serialized
@@ -31,99 +43,1717 @@ pub fn serialize_frequency_table(dictionary: &AdaptiveDictionary) -> Vec<u8> {
 pub fn deserialize_frequency_table(serialized: &[u8]) -> AdaptiveDictionary {
     let mut dictionary = AdaptiveDictionary::new();
     for chunk in serialized.chunks_exact(5) {
-        let byte = chunk[0];
-        let frequency = u32::from_be_bytes([chunk[1], chunk[2], chunk[3], chunk[4]]);
+        let mut entry = Reader::new(chunk, "frequency table");
+        let (byte, frequency) = (entry.u8().unwrap(), entry.u32().unwrap());
         dictionary.frequencies.insert(byte, frequency);
     }
     dictionary
 }
 
-// Compress data
-pub fn compress(data: &[u8]) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
-    let mut preprocessor = Preprocessor::new();
-    let processed_data = preprocessor.preprocess(data);
+// Serialize canonical code lengths as (symbol, length) pairs
+pub fn serialize_code_lengths(lengths: &BTreeMap<u8, u8>) -> Vec<u8> {
+    let mut serialized = Vec::new();
+    for (&symbol, &length) in lengths {
+        serialized.push(symbol);
+        serialized.push(length);
+    }
+    serialized
+}
 
-    let mut dictionary = AdaptiveDictionary::new();
-    dictionary.update(&processed_data);
+// Deserialize canonical code lengths
+pub fn deserialize_code_lengths(serialized: &[u8]) -> BTreeMap<u8, u8> {
+    serialized.chunks_exact(2).map(|pair| (pair[0], pair[1])).collect()
+}
+
+// Serialize canonical code lengths as the frame header stores them: one byte per
+// symbol value from 0 up to the highest symbol used, 0 for symbols that do not occur
+pub fn serialize_code_length_table(lengths: &BTreeMap<u8, u8>) -> Vec<u8> {
+    let mut serialized = Vec::new();
+    for (&symbol, &length) in lengths {
+        serialized.resize(symbol as usize, 0);
+        serialized.push(length);
+    }
+    serialized
+}
+
+// Inverse of serialize_code_length_table. Rejects tables that cannot come from a
+// Huffman tree, i.e. where some code would be the prefix of another.
+pub fn deserialize_code_length_table(serialized: &[u8]) -> Result<BTreeMap<u8, u8>, QuantumPackError> {
+    if serialized.len() > 256 {
+        return Err(QuantumPackError::corrupt_header(format!("code length table has {} entries", serialized.len())));
+    }
+    let lengths: BTreeMap<u8, u8> = (0..=255u8).zip(serialized).filter(|&(_, &length)| length > 0).map(|(symbol, &length)| (symbol, length)).collect();
+    check_code_lengths(lengths.values(), 256)?;
+    Ok(lengths)
+}
+
+// Inverse of serialize_code_lengths for the table of a frame, where the pairs are in
+// symbol order and every listed symbol has a code
+fn deserialize_sparse_length_table(serialized: &[u8]) -> Result<BTreeMap<u8, u8>, QuantumPackError> {
+    if !serialized.len().is_multiple_of(2) || serialized.len() > 512 {
+        return Err(QuantumPackError::corrupt_header(format!("sparse code length table of {} bytes", serialized.len())));
+    }
+    let mut lengths = BTreeMap::new();
+    for pair in serialized.chunks_exact(2) {
+        if pair[1] == 0 || lengths.keys().next_back().is_some_and(|&last| last >= pair[0]) {
+            return Err(QuantumPackError::corrupt_header(format!("sparse code length table lists symbol {} out of order", pair[0])));
+        }
+        lengths.insert(pair[0], pair[1]);
+    }
+    check_code_lengths(lengths.values(), 256)?;
+    Ok(lengths)
+}
+
+// The code length table `dense`, as serialize_code_length_table writes it, in the
+// form `encoding` asks for. Returns whether that is the sparse form.
+pub(crate) fn encode_length_table(dense: &[u8], encoding: TableEncoding) -> (Cow<'_, [u8]>, bool) {
+    let symbols = dense.iter().filter(|&&length| length != 0).count();
+    // The sparse form costs a block type byte as well
+    let sparse = match encoding {
+        TableEncoding::Dense => false,
+        TableEncoding::Sparse => true,
+        TableEncoding::Smallest => 2 * symbols + 1 < dense.len(),
+    };
+    if !sparse {
+        return (Cow::Borrowed(dense), false);
+    }
+    let pairs = dense.iter().enumerate().filter(|&(_, &length)| length != 0).flat_map(|(symbol, &length)| [symbol as u8, length]);
+    (Cow::Owned(pairs.collect()), true)
+}
+
+// Code lengths of wide symbols, which are too sparse for a dense table: varint symbol
+// count, then per symbol the varint gap to the previous symbol and the code length
+pub fn serialize_symbol_length_table(lengths: &BTreeMap<u32, u8>) -> Vec<u8> {
+    let mut serialized = Vec::new();
+    wire::write_varint(&mut serialized, lengths.len() as u64);
+    let mut next = 0;
+    for (&symbol, &length) in lengths {
+        wire::write_varint(&mut serialized, symbol as u64 - next);
+        serialized.push(length);
+        next = symbol as u64 + 1;
+    }
+    serialized
+}
+
+pub fn deserialize_symbol_length_table(serialized: &[u8]) -> Result<BTreeMap<u32, u8>, QuantumPackError> {
+    let mut reader = Reader::new(serialized, "symbol length table");
+    let count = reader.varint()?;
+    let mut lengths = BTreeMap::new();
+    let mut next = 0u64;
+    for _ in 0..count {
+        let symbol = next.checked_add(reader.varint()?).filter(|&symbol| symbol <= u32::MAX as u64);
+        let symbol = symbol.ok_or_else(|| QuantumPackError::corrupt_header("symbol out of range"))?;
+        let length = reader.u8()?;
+        if length == 0 {
+            return Err(QuantumPackError::corrupt_header(format!("symbol {} has no code", symbol)));
+        }
+        lengths.insert(symbol as u32, length);
+        next = symbol + 1;
+    }
+    if !reader.is_empty() {
+        return Err(QuantumPackError::corrupt_header("symbol length table has trailing bytes"));
+    }
+    check_code_lengths(lengths.values(), lengths.len())?;
+    Ok(lengths)
+}
+
+// Reject lengths that cannot come from a Huffman tree over `symbols` symbols, i.e.
+// where some code would be the prefix of another
+fn check_code_lengths<'a, I: Iterator<Item = &'a u8>>(lengths: I, symbols: usize) -> Result<(), QuantumPackError> {
+    // Count the codes still available at each length, starting with the two one bit codes
+    let mut per_length = [0usize; 256];
+    lengths.for_each(|&length| per_length[length as usize] += 1);
+    let mut available = 2usize;
+    for &count in &per_length[1..] {
+        available = available.checked_sub(count).ok_or_else(|| QuantumPackError::corrupt_header("over-subscribed code lengths"))?;
+        // More free codes than symbols can never run out
+        available = available.saturating_mul(2).min(2 * symbols.max(1));
+    }
+    Ok(())
+}
+
+// Every frame starts with the magic, the format version and a flags byte. Version 3
+// records how many bits of the data section are padding in the header; version 2
+// ended the data with a count of the bits used in its last byte. Both store the
+// Huffman table as canonical code lengths. Version 1 stored symbol frequencies (or
+// code length pairs with CODE_LENGTHS_FLAG). Earlier versions are still decoded.
+pub(crate) const MAGIC: [u8; 4] = *b"QPK1";
+const FORMAT_VERSION: u8 = 3;
+// The first version with code length tables and the version 2 flags
+const CODE_LENGTH_VERSION: u8 = 2;
+const HEADER_LEN: usize = 6;
+// Version 1 only: the table holds (symbol, length) pairs instead of frequencies
+const CODE_LENGTHS_FLAG: u8 = 0x01;
+// Version 2 reuses the bit: the u32 seed the encoder broke ties with follows the
+// block type, see Compressor::tie_seed. Frames without it were written with seed 0.
+const TIE_SEED_FLAG: u8 = 0x01;
+// A stage descriptor follows the header
+const STAGE_FLAG: u8 = 0x02;
+// The frame codes symbols of the width in the byte after the header (and stage
+// descriptor), see Compressor::compress_symbols. The table is a symbol length table
+// and there is no dictionary.
+const SYMBOLS_FLAG: u8 = 0x04;
+// The frame records the input file's FileMetadata after the symbol width
+const METADATA_FLAG: u8 = 0x08;
+const METADATA_LEN: usize = 12;
+// Another block of the same input follows this frame, see Compressor::block_size
+const CONTINUED_FLAG: u8 = 0x10;
+// A u32-prefixed section of zero bytes follows the Huffman data, see Compressor::pad_to
+const PADDED_FLAG: u8 = 0x20;
+// The u32 ID of a preset dictionary follows the metadata and the dictionary section
+// is empty, see Compressor::preset_dictionary
+const DICTIONARY_ID_FLAG: u8 = 0x40;
+// A block type byte follows the dictionary ID; frames without it are BLOCK_HUFFMAN
+const BLOCK_TYPE_FLAG: u8 = 0x80;
+const KNOWN_FLAGS: u8 = TIE_SEED_FLAG | STAGE_FLAG | SYMBOLS_FLAG | METADATA_FLAG | CONTINUED_FLAG | PADDED_FLAG | DICTIONARY_ID_FLAG | BLOCK_TYPE_FLAG;
+// The data section holds Huffman codes, decoded with the table and dictionary
+const BLOCK_HUFFMAN: u8 = 0;
+// The data section holds the input as is and the table and dictionary are empty.
+// Written when coding would make the block larger, e.g. for compressed media.
+const BLOCK_STORED: u8 = 1;
+// The data section holds adaptive Huffman codes, see huffman::adaptive, and the table
+// is empty: the codes are learned from the data itself. The dictionary is as for
+// BLOCK_HUFFMAN. Byte frames only.
+const BLOCK_ADAPTIVE: u8 = 2;
+// Set in the block type byte when the sections went through a FrameHook: the data
+// section holds the sealed payload [u64 decoded length][u32 CRC-32][u32 table size]
+// [table][u32 dictionary size][dictionary][data], the table and dictionary sections
+// are empty and the footer's length and CRC are zero
+const SEALED: u8 = 0x80;
+// Set in the block type byte of a BLOCK_HUFFMAN byte frame whose table holds
+// (symbol, length) pairs, see serialize_code_lengths, instead of a length for every
+// symbol value up to the highest one used; see TableEncoding
+const SPARSE_TABLE: u8 = 0x40;
+const KNOWN_V1_FLAGS: u8 = CODE_LENGTHS_FLAG | STAGE_FLAG;
+// Starts the footer; a frame without it was cut short
+const END_MARKER: [u8; 4] = *b"QPND";
+// End marker, u64 decoded length and u32 CRC-32 of the decoded data
+const FOOTER_LEN: usize = 16;
+
+// Modification time and permissions of the file a frame was made from, restored by
+// the file helpers when the frame is decompressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMetadata {
+    // Seconds since the Unix epoch
+    pub mtime: u64,
+    // Unix permission bits, e.g. 0o644
+    pub mode: u32,
+}
+
+// Huffman data, Huffman table and serialized dictionary, as returned by compress
+type Parts = (Vec<u8>, Vec<u8>, Vec<u8>);
+
+// How a byte frame stores its code length table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TableEncoding {
+    // Whichever of the two is smaller for the frame
+    #[default]
+    Smallest,
+    // A length byte for every symbol value up to the highest one used, see
+    // serialize_code_length_table; best when most byte values occur
+    Dense,
+    // A (symbol, length) pair for every symbol used, see serialize_code_lengths;
+    // best for text, where few byte values occur and the highest is large
+    Sparse,
+}
+
+// Trades compression ratio for speed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionLevel {
+    // Huffman coding only, the preprocessor does not run
+    Fast,
+    // The preprocessor template as configured, by default up to 254 dictionary
+    // entries fitted to the first DEFAULT_SAMPLE_SIZE bytes
+    #[default]
+    Default,
+    // The template plus variants with longer patterns, one of them with up to 1024
+    // dictionary entries, mined from the whole input rather than its first
+    // DEFAULT_SAMPLE_SIZE bytes, keeping whichever gives the smallest output
+    Best,
+}
+
+// Dictionary entries of the largest variant Best tries; codes past the 254th take
+// 4 bytes, which only its longer patterns can afford
+const BEST_MAX_ENTRIES: usize = 1024;
+
+impl CompressionLevel {
+    // Fastest first
+    pub const ALL: [CompressionLevel; 3] = [CompressionLevel::Fast, CompressionLevel::Default, CompressionLevel::Best];
+
+    // Map a gzip style level from 1 (fastest) to 9 (smallest)
+    pub fn from_number(level: u8) -> Option<Self> {
+        match level {
+            1..=3 => Some(CompressionLevel::Fast),
+            4..=6 => Some(CompressionLevel::Default),
+            7..=9 => Some(CompressionLevel::Best),
+            _ => None,
+        }
+    }
+
+    // The name `--level` and the level key of a profile take
+    pub fn name(self) -> &'static str {
+        match self {
+            CompressionLevel::Fast => "fast",
+            CompressionLevel::Default => "default",
+            CompressionLevel::Best => "best",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        CompressionLevel::ALL.iter().copied().find(|level| level.name() == name)
+    }
+
+    // Preprocessors to try on each input; none means no preprocessing
+    fn preprocessors(self, template: &Preprocessor) -> Vec<Preprocessor> {
+        match self {
+            CompressionLevel::Fast => Vec::new(),
+            CompressionLevel::Default => vec![template.clone()],
+            CompressionLevel::Best => {
+                let wide = template.to_builder().max_pattern_length(8).sample_size(usize::MAX);
+                let entries = template.max_entries().max(BEST_MAX_ENTRIES);
+                vec![
+                    template.clone(),
+                    wide.clone().tokenization(Tokenization::Greedy).build(),
+                    wide.clone().tokenization(Tokenization::Optimal).build(),
+                    wide.max_entries(entries).tokenization(Tokenization::Greedy).build(),
+                ]
+            }
+        }
+    }
+}
+
+// Compression settings shared by the in-memory and file helpers
+#[derive(Clone, Default)]
+pub struct Compressor {
+    preprocessor: Preprocessor,
+    level: CompressionLevel,
+    pub(crate) bwlimit: Option<u64>,
+    pub(crate) stage: Option<Stage>,
+    dictionary: Option<Arc<SharedDictionary>>,
+    preset: Option<Arc<Preset>>,
+    tie_seed: u32,
+    pub(crate) output_policy: OutputPolicy,
+    pub(crate) record_metadata: bool,
+    pub(crate) skip_detection: bool,
+    adaptive: bool,
+    // Write stored frames without trying to compress
+    pub(crate) store: bool,
+    pub(crate) block_size: Option<usize>,
+    pub(crate) pad_to: Option<usize>,
+    table_encoding: TableEncoding,
+    pub(crate) warnings: Option<WarningHandler>,
+    pub(crate) progress: Option<ProgressHandler>,
+    pub(crate) hook: Option<FrameHookHandle>,
+}
+
+impl Compressor {
+    pub fn new() -> Self {
+        Compressor::default()
+    }
+
+    // Template that is cloned and fitted for every input, e.g. one built with user patterns
+    pub fn preprocessor(mut self, preprocessor: Preprocessor) -> Self {
+        self.preprocessor = preprocessor;
+        self
+    }
+
+    // Limit file reads and writes to `bytes_per_sec`
+    pub fn bwlimit(mut self, bytes_per_sec: u64) -> Self {
+        self.bwlimit = Some(bytes_per_sec);
+        self
+    }
+
+    // How the file helpers create output files, see OutputPolicy
+    pub fn output_policy(mut self, policy: OutputPolicy) -> Self {
+        self.output_policy = policy;
+        self
+    }
+
+    // Whether the file helpers record the input's modification time and permissions
+    // in the frame. Off by default, so that compressing the same contents twice gives
+    // the same bytes whenever the file was touched.
+    pub fn preserve_metadata(mut self, enabled: bool) -> Self {
+        self.record_metadata = enabled;
+        self
+    }
+
+    // Report non-fatal conditions such as blocks that expanded to `handler`
+    pub fn on_warning(mut self, handler: WarningHandler) -> Self {
+        self.warnings = Some(handler);
+        self
+    }
+
+    // Whether compress_file stores inputs that are compressed already, e.g. gzip or
+    // JPEG files, without trying to compress them (the default). See sniff.
+    pub fn detect_compressed(mut self, enabled: bool) -> Self {
+        self.skip_detection = !enabled;
+        self
+    }
+
+    // Report the progress of compress_file and compress_to to `handler`, see ProgressHandler
+    pub fn on_progress(mut self, handler: ProgressHandler) -> Self {
+        self.progress = Some(handler);
+        self
+    }
+
+    // Split frame output into blocks of `bytes` input bytes, each a frame with its own
+    // dictionary and Huffman table. Huge inputs then compress block by block, and a
+    // damaged block does not take the others with it (see stream::salvage). The
+    // blocks decode as one input.
+    pub fn block_size(mut self, bytes: usize) -> Self {
+        self.block_size = Some(bytes.max(1));
+        self
+    }
+
+    // Pad the compressed output of every input (each archive member, each QpEncoder
+    // block) to exactly `bytes`, for fixed-size storage slots or encryption blocks.
+    // The padding is recorded in the last frame and skipped by the decoder. Output
+    // that does not fit, i.e. is longer than `bytes` minus the 4 byte size of the
+    // padding section, fails with InvalidInput.
+    pub fn pad_to(mut self, bytes: usize) -> Self {
+        self.pad_to = Some(bytes);
+        self
+    }
+
+    // How frames store their code length table. compress always returns the dense
+    // form, the one deserialize_code_length_table reads.
+    pub fn table_encoding(mut self, encoding: TableEncoding) -> Self {
+        self.table_encoding = encoding;
+        self
+    }
+
+    // Best also tries the template with longer patterns, a full sample and both tokenizations
+    pub fn level(mut self, level: CompressionLevel) -> Self {
+        self.level = level;
+        self
+    }
+
+    // Transform applied to file and frame input before the preprocessor; the
+    // decoder learns about it from the frame header
+    pub fn stage(mut self, stage: Stage) -> Self {
+        self.stage = Some(stage);
+        self
+    }
+
+    // Use the current dictionary of `dictionary` for every frame instead of fitting
+    // one to each input. The preprocessor template's other settings still apply.
+    pub fn shared_dictionary(mut self, dictionary: Arc<SharedDictionary>) -> Self {
+        self.dictionary = Some(dictionary);
+        self
+    }
+
+    // Use `dictionary` for every frame and record only its preset_id in the frame, for
+    // many small messages that would otherwise each carry the same dictionary. The
+    // decoder needs the same dictionary, see Decompressor::preset_dictionary. Takes
+    // precedence over shared_dictionary.
+    pub fn preset_dictionary(mut self, dictionary: TrainedDictionary) -> Self {
+        self.preset = Some(Arc::new(Preset::new(dictionary)));
+        self
+    }
+
+    // Seal every frame with `hook`, e.g. to encrypt it at rest; see FrameHook
+    pub fn frame_hook(mut self, hook: FrameHookHandle) -> Self {
+        self.hook = Some(hook);
+        self
+    }
+
+    // Code byte frames with adaptive Huffman coding, which learns the codes while it
+    // codes instead of counting the tokens first, and stores no table. Costs a little
+    // ratio on most inputs and decodes slower. Symbol frames keep a static table.
+    pub fn adaptive_huffman(mut self, enabled: bool) -> Self {
+        self.adaptive = enabled;
+        self
+    }
+
+    // Seed for breaking ties where the order of equal candidates shapes the output:
+    // symbols of equal frequency in the Huffman heap (see huffman::tie_rank) and
+    // mined patterns of equal frequency (see preprocessor::pattern_rank). The seed is
+    // recorded in every coded frame, so another implementation following the same
+    // rules can reproduce the frame exactly. 0, the default, is not recorded.
+    pub fn tie_seed(mut self, seed: u32) -> Self {
+        self.tie_seed = seed;
+        self
+    }
+
+    // The preprocessor template with this compressor's tie seed
+    fn template(&self) -> Preprocessor {
+        let mut template = self.preprocessor.clone();
+        template.set_tie_seed(self.tie_seed);
+        template
+    }
+
+    // Compress data. The second element is the Huffman table as canonical code lengths,
+    // see serialize_code_length_table, or empty with adaptive_huffman, whose parts
+    // decompress_adaptive decodes.
+    pub fn compress(&self, data: &[u8]) -> Result<Parts, QuantumPackError> {
+        Ok(self.compress_tokens(data)?.0)
+    }
+
+    // The transform tokens behind the frame compress_shared would write for `data`,
+    // e.g. to try other entropy coders on real data, with the dictionary that gives
+    // each pattern code its bytes. A stage runs first, so the tokens cover its
+    // output. The input is treated as one block whatever the block size.
+    pub fn tokens(&self, data: &[u8]) -> Result<(Vec<Token>, TrainedDictionary), QuantumPackError> {
+        let staged = self.stage.map(|stage| stage.encode(data));
+        let data = staged.as_deref().unwrap_or(data);
+        let (_, _, preprocessor) = self.compress_tokens(data)?;
+        Ok((preprocessor.tokenize(data), preprocessor.dictionary().clone()))
+    }
+
+    // Compress data, also returning the preprocessed tokens that were Huffman coded
+    // and the preprocessor that produced them
+    fn compress_tokens(&self, data: &[u8]) -> Result<(Parts, Vec<u8>, Preprocessor), QuantumPackError> {
+        let fixed = match (&self.preset, &self.dictionary) {
+            (Some(preset), _) => Some(preset.dictionary.clone()),
+            (None, Some(shared)) => Some(TrainedDictionary::clone(&shared.load())),
+            (None, None) => None,
+        };
+        if let Some(dictionary) = fixed {
+            let mut preprocessor = self.template();
+            preprocessor.set_dictionary(dictionary);
+            let tokens = preprocessor.apply_recorded(data);
+            if preprocessor.patterns_used() == 0 && !preprocessor.dictionary().is_empty() && !data.is_empty() {
+                warn(&self.warnings, Warning::DictionaryUnused);
+            }
+            return Ok((encode(&preprocessor, &tokens, self.adaptive)?, tokens, preprocessor));
+        }
+
+        let mut best: Option<(Parts, Vec<u8>, Preprocessor)> = None;
+        for mut preprocessor in self.level.preprocessors(&self.template()) {
+            let processed_data = preprocessor.preprocess(data);
+            let parts = encode(&preprocessor, &processed_data, self.adaptive)?;
+            let size = |(data, table, dictionary): &Parts| data.len() + table.len() + dictionary.len();
+            trace!("candidate preprocessor with {} patterns codes to {} bytes", preprocessor.dictionary().len(), size(&parts));
+            if best.as_ref().is_none_or(|(best, _, _)| size(&parts) < size(best)) {
+                best = Some((parts, processed_data, preprocessor));
+            }
+        }
+        match best {
+            Some((parts, tokens, preprocessor)) => {
+                if !preprocessor.dictionary().is_empty() && preprocessor.patterns_used() == 0 {
+                    warn(&self.warnings, Warning::DictionaryUnused);
+                }
+                Ok((parts, tokens, preprocessor))
+            }
+            // An empty dictionary only escapes the bytes the decoder would read as codes
+            None => {
+                let mut preprocessor = Preprocessor::new();
+                preprocessor.set_tie_seed(self.tie_seed);
+                preprocessor.set_dictionary(TrainedDictionary::new());
+                let tokens = preprocessor.apply(data);
+                Ok((encode(&preprocessor, &tokens, self.adaptive)?, tokens, preprocessor))
+            }
+        }
+    }
+
+    // Compress `region`, e.g. a memory-mapped file or a database page owned by the
+    // caller, and append the frame to `output` without copying the input. See
+    // write_frame for the layout.
+    pub fn compress_shared(&self, region: &[u8], output: &mut Vec<u8>) -> Result<(), QuantumPackError> {
+        self.compress_with_metadata(region, None, output).map(|_| ())
+    }
+
+    // compress_shared, recording `metadata` in the first frame. Returns how many
+    // dictionary patterns the frames use, counted per block.
+    pub(crate) fn compress_with_metadata(&self, region: &[u8], metadata: Option<FileMetadata>, output: &mut Vec<u8>) -> Result<usize, QuantumPackError> {
+        let output_start = output.len();
+        let block_size = match self.block_size {
+            Some(block_size) if region.len() > block_size => block_size,
+            _ => {
+                let patterns_used = self.compress_block(region, metadata, false, output)?;
+                self.check_expansion(0, region.len(), output.len() - output_start);
+                return self.pad_output(output, output_start, 0).map(|()| patterns_used);
+            }
+        };
+        let mut blocks = region.chunks(block_size).enumerate().peekable();
+        let mut metadata = metadata;
+        let mut start = output_start;
+        let mut patterns_used = 0;
+        while let Some((index, block)) = blocks.next() {
+            start = output.len();
+            patterns_used += self.compress_block(block, metadata.take(), blocks.peek().is_some(), output)?;
+            self.check_expansion(index, block.len(), output.len() - start);
+        }
+        self.pad_output(output, start, start - output_start).map(|()| patterns_used)
+    }
+
+    // With pad_to, grow the last frame of an output, the one at `frame_start` in
+    // `output` after `earlier` bytes of other frames, to give the output the size asked for
+    pub(crate) fn pad_output(&self, output: &mut Vec<u8>, frame_start: usize, earlier: usize) -> Result<(), QuantumPackError> {
+        let size = match self.pad_to {
+            Some(size) => size,
+            None => return Ok(()),
+        };
+        let output_len = earlier + output.len() - frame_start;
+        if output_len == size {
+            return Ok(());
+        }
+        let padding = size
+            .checked_sub(output_len + 4)
+            .filter(|&padding| padding <= u32::MAX as usize)
+            .ok_or_else(|| QuantumPackError::InvalidInput(format!("{} bytes of compressed output cannot be padded to {} bytes", output_len, size)))?;
+        let footer = output.split_off(output.len() - FOOTER_LEN);
+        output[frame_start + HEADER_LEN - 1] |= PADDED_FLAG;
+        wire::write_u32(output, padding as u32);
+        output.resize(output.len() + padding, 0);
+        output.extend_from_slice(&footer);
+        Ok(())
+    }
+
+    // Stored inputs were reported when they were detected
+    pub(crate) fn check_expansion(&self, index: usize, input_len: usize, frame_len: usize) {
+        if frame_len > input_len && !self.store {
+            warn(&self.warnings, Warning::BlockExpanded { index, input_len, frame_len });
+        }
+    }
+
+    // Compress `block` into a single frame, marked as continued when another block of
+    // the same input follows it. Blocks that coding would make larger are stored.
+    // Returns how many dictionary patterns the frame uses.
+    pub(crate) fn compress_block(&self, block: &[u8], metadata: Option<FileMetadata>, continued: bool, output: &mut Vec<u8>) -> Result<usize, QuantumPackError> {
+        if self.store {
+            return store_block(block, metadata, continued, self.hook.as_deref(), output).map(|()| 0);
+        }
+        let staged = self.stage.map(|stage| stage.encode(block));
+        let ((compressed, code_length_table, serialized_dictionary), _, preprocessor) = self.compress_tokens(staged.as_deref().unwrap_or(block))?;
+        let dictionary = self.frame_dictionary(&serialized_dictionary);
+        let (table, block_type) = self.frame_table(&code_length_table);
+        // A stored frame spends one byte on its block type and none on the stage
+        if block.len() + 1 < table.len() + dictionary.len() + compressed.len() {
+            debug!("storing a block of {} bytes that codes to {}", block.len(), table.len() + dictionary.len() + compressed.len());
+            return store_block(block, metadata, continued, self.hook.as_deref(), output).map(|()| 0);
+        }
+        let (code, padding_bits) = code_section(&compressed)?;
+        let header = FrameHeader { stage: self.stage.as_ref(), symbol_width: None, metadata, continued, dictionary_id: self.preset_id(), block_type, tie_seed: self.tie_seed, padding_bits, hook: self.hook.as_deref() };
+        write_frame(output, &header, &table, dictionary, code, block)?;
+        Ok(preprocessor.patterns_used())
+    }
+
+    // The code length table as a coded frame stores it, and the frame's block type
+    fn frame_table<'a>(&self, code_length_table: &'a [u8]) -> (Cow<'a, [u8]>, u8) {
+        if self.adaptive {
+            return (Cow::Borrowed(code_length_table), BLOCK_ADAPTIVE);
+        }
+        match encode_length_table(code_length_table, self.table_encoding) {
+            (table, true) => (table, BLOCK_HUFFMAN | SPARSE_TABLE),
+            (table, false) => (table, BLOCK_HUFFMAN),
+        }
+    }
+
+    fn preset_id(&self) -> Option<u32> {
+        self.preset.as_ref().map(|preset| preset.id)
+    }
+
+    // A decompressor for the frames these settings write, sharing the preset dictionary
+    pub(crate) fn decompressor(&self) -> Decompressor {
+        Decompressor { preset: self.preset.clone(), hook: self.hook.clone(), ..Decompressor::default() }
+    }
+
+    // What the frame stores of the dictionary: nothing for a preset dictionary
+    fn frame_dictionary<'a>(&self, serialized_dictionary: &'a [u8]) -> &'a [u8] {
+        if self.preset.is_some() {
+            &[]
+        } else {
+            serialized_dictionary
+        }
+    }
+
+    // compress_shared, also returning what the encoder chose for the frame
+    #[cfg(feature = "trace")]
+    pub(crate) fn compress_traced(&self, region: &[u8], output: &mut Vec<u8>) -> Result<Decisions, QuantumPackError> {
+        if self.adaptive {
+            return Err(QuantumPackError::InvalidInput("adaptive Huffman frames have no code lengths to trace".to_string()));
+        }
+        if self.hook.is_some() {
+            return Err(QuantumPackError::InvalidInput("sealed frames cannot be traced".to_string()));
+        }
+        let staged = self.stage.map(|stage| stage.encode(region));
+        let ((compressed, code_length_table, serialized_dictionary), tokens, _) = self.compress_tokens(staged.as_deref().unwrap_or(region))?;
+        let (code, padding_bits) = code_section(&compressed)?;
+        let (table, block_type) = self.frame_table(&code_length_table);
+        let header = FrameHeader { stage: self.stage.as_ref(), symbol_width: None, metadata: None, continued: false, dictionary_id: self.preset_id(), block_type, tie_seed: self.tie_seed, padding_bits, hook: self.hook.as_deref() };
+        let dictionary = self.frame_dictionary(&serialized_dictionary).to_vec();
+        write_frame(output, &header, &table, &dictionary, code, region)?;
+        Ok(Decisions { code_lengths: deserialize_code_length_table(&code_length_table)?, dictionary, tokens })
+    }
+
+    // Huffman code pre-tokenized data, e.g. 16 or 32 bit token ids, as symbols of
+    // their own. Neither the preprocessor nor a stage runs. The frame decodes to the
+    // symbols in little-endian byte order, or back to symbols with
+    // Decompressor::decompress_symbols.
+    pub fn compress_symbols<S: Symbol>(&self, symbols: &[S], output: &mut Vec<u8>) -> Result<(), QuantumPackError> {
+        let mut frequencies = AdaptiveDictionary::new();
+        frequencies.update(symbols);
+        let mut tree_codes = BTreeMap::new();
+        if let Some(huffman_tree) = build_huffman_tree_seeded(&frequencies, self.tie_seed) {
+            generate_huffman_codes(huffman_tree.as_ref(), &mut vec![], &mut tree_codes);
+        }
+        let lengths = code_lengths(&tree_codes);
+        let table = serialize_symbol_length_table(&lengths.iter().map(|(symbol, &length)| (symbol.to_u32(), length)).collect());
+        let (code, code_bits) = huffman_encode_bits(symbols, &canonical_codes(&lengths));
+        if code.len() > u32::MAX as usize {
+            return Err(QuantumPackError::InputTooLarge);
+        }
+
+        let mut decoded = Vec::with_capacity(symbols.len() * S::WIDTH);
+        for symbol in symbols {
+            decoded.extend_from_slice(&symbol.to_u32().to_le_bytes()[..S::WIDTH]);
+        }
+        let padding_bits = (code.len() * 8 - code_bits) as u8;
+        let header = FrameHeader { stage: None, symbol_width: Some(S::WIDTH as u8), metadata: None, continued: false, dictionary_id: None, block_type: BLOCK_HUFFMAN, tie_seed: self.tie_seed, padding_bits, hook: self.hook.as_deref() };
+        write_frame(output, &header, &table, &[], &code, &decoded)
+    }
+}
 
-    let huffman_tree = build_huffman_tree_with_dictionary(&dictionary).unwrap();
+// The choices the encoder made for one byte frame: the Huffman code lengths, the
+// preprocessor dictionary and the tokens that were Huffman coded
+#[cfg(feature = "trace")]
+pub(crate) struct Decisions {
+    pub(crate) code_lengths: BTreeMap<u8, u8>,
+    pub(crate) dictionary: Vec<u8>,
+    pub(crate) tokens: Vec<u8>,
+}
 
-    let mut codes = BTreeMap::new();
-    generate_huffman_codes(huffman_tree.as_ref(), &mut vec![], &mut codes);
+// Write `block` as it is into a stored frame
+fn store_block(block: &[u8], metadata: Option<FileMetadata>, continued: bool, hook: Option<&dyn FrameHook>, output: &mut Vec<u8>) -> Result<(), QuantumPackError> {
+    let header = FrameHeader { stage: None, symbol_width: None, metadata, continued, dictionary_id: None, block_type: BLOCK_STORED, tie_seed: 0, padding_bits: 0, hook };
+    write_frame(output, &header, &[], &[], block, block)
+}
 
-    let huffman_encoded_data = huffman_encode(&processed_data, &codes);
+// Split Huffman data from encode into the code bytes a frame stores and the
+// padding bits of their last byte, which the frame header records
+fn code_section(compressed: &[u8]) -> Result<(&[u8], u8), QuantumPackError> {
+    let (code, code_bits) = split_bit_count(compressed)?;
+    Ok((code, (code.len() * 8 - code_bits) as u8))
+}
 
-    let frequency_table = serialize_frequency_table(&dictionary);
+// The header fields of a frame, the optional ones each announced by a flag
+struct FrameHeader<'a> {
+    stage: Option<&'a Stage>,
+    symbol_width: Option<u8>,
+    metadata: Option<FileMetadata>,
+    continued: bool,
+    dictionary_id: Option<u32>,
+    // Written only when it is not BLOCK_HUFFMAN
+    block_type: u8,
+    // Only coded frames have ties to break
+    tie_seed: u32,
+    // 0 bits after the last code, less than a byte
+    padding_bits: u8,
+    // Seals the sections, see SEALED
+    hook: Option<&'a dyn FrameHook>,
+}
+
+// Layout: ["QPK1"][u8 version][u8 flags][u8 stage size][stage][u8 symbol width]
+// [u64 mtime][u32 mode][u32 dictionary ID][u8 block type][u32 tie seed][u8 padding bits]
+// [u32 table size][table][u32 dictionary size][dictionary][u32 data size][data][u32 padding size][padding]
+// [footer], the stage only with STAGE_FLAG, the width only with SYMBOLS_FLAG, the
+// metadata only with METADATA_FLAG, the dictionary ID only with DICTIONARY_ID_FLAG,
+// the block type only with BLOCK_TYPE_FLAG, the tie seed only with TIE_SEED_FLAG
+// and the padding, added by pad_output, only with PADDED_FLAG. `compressed` is the
+// input itself for a stored frame.
+fn write_frame(output: &mut Vec<u8>, header: &FrameHeader, table: &[u8], dictionary: &[u8], compressed: &[u8], decoded: &[u8]) -> Result<(), QuantumPackError> {
+    let sealed_payload;
+    let (decoded_len, crc) = (decoded.len() as u64, crc32(decoded));
+    let (block_type, table, dictionary, compressed) = match header.hook {
+        Some(hook) => {
+            let mut payload = Vec::with_capacity(20 + table.len() + dictionary.len() + compressed.len());
+            wire::write_u64(&mut payload, decoded_len);
+            wire::write_u32(&mut payload, crc);
+            wire::write_u32(&mut payload, table.len() as u32);
+            payload.extend_from_slice(table);
+            wire::write_u32(&mut payload, dictionary.len() as u32);
+            payload.extend_from_slice(dictionary);
+            payload.extend_from_slice(compressed);
+            sealed_payload = hook.before_write(&payload)?;
+            if sealed_payload.len() > u32::MAX as usize {
+                return Err(QuantumPackError::InputTooLarge);
+            }
+            (header.block_type | SEALED, &[][..], &[][..], &sealed_payload[..])
+        }
+        None => (header.block_type, table, dictionary, compressed),
+    };
+
+    let mut flags = 0;
+    if header.stage.is_some() {
+        flags |= STAGE_FLAG;
+    }
+    if header.symbol_width.is_some() {
+        flags |= SYMBOLS_FLAG;
+    }
+    if header.metadata.is_some() {
+        flags |= METADATA_FLAG;
+    }
+    if header.continued {
+        flags |= CONTINUED_FLAG;
+    }
+    if header.dictionary_id.is_some() {
+        flags |= DICTIONARY_ID_FLAG;
+    }
+    if block_type != BLOCK_HUFFMAN {
+        flags |= BLOCK_TYPE_FLAG;
+    }
+    if header.tie_seed != 0 {
+        flags |= TIE_SEED_FLAG;
+    }
+
+    output.reserve(HEADER_LEN + 24 + METADATA_LEN + table.len() + dictionary.len() + compressed.len() + FOOTER_LEN);
+    output.extend_from_slice(&MAGIC);
+    output.push(FORMAT_VERSION);
+    output.push(flags);
+    if let Some(stage) = header.stage {
+        let descriptor = stage.serialize();
+        output.push(descriptor.len() as u8);
+        output.extend_from_slice(&descriptor);
+    }
+    if let Some(width) = header.symbol_width {
+        output.push(width);
+    }
+    if let Some(metadata) = header.metadata {
+        wire::write_u64(output, metadata.mtime);
+        wire::write_u32(output, metadata.mode);
+    }
+    if let Some(id) = header.dictionary_id {
+        wire::write_u32(output, id);
+    }
+    if block_type != BLOCK_HUFFMAN {
+        output.push(block_type);
+    }
+    if header.tie_seed != 0 {
+        wire::write_u32(output, header.tie_seed);
+    }
+    output.push(header.padding_bits);
+    wire::write_u32(output, table.len() as u32);
+    output.extend_from_slice(table);
+    wire::write_u32(output, dictionary.len() as u32);
+    output.extend_from_slice(dictionary);
+    wire::write_u32(output, compressed.len() as u32);
+    output.extend_from_slice(compressed);
+    output.extend_from_slice(&END_MARKER);
+    // The footer of a sealed frame is zero, its length and CRC are in the payload
+    let sealed = header.hook.is_some();
+    wire::write_u64(output, if sealed { 0 } else { decoded_len });
+    wire::write_u32(output, if sealed { 0 } else { crc });
+    Ok(())
+}
+
+// Huffman code the preprocessed data and serialize the dictionary it was produced with.
+// Adaptive coding leaves the table empty.
+fn encode(preprocessor: &Preprocessor, processed_data: &[u8], adaptive: bool) -> Result<Parts, QuantumPackError> {
+    if adaptive {
+        let (mut encoded_data, bit_len) = adaptive_encode_bits(processed_data);
+        push_bit_count(&mut encoded_data, bit_len);
+        if encoded_data.len() > u32::MAX as usize {
+            return Err(QuantumPackError::InputTooLarge);
+        }
+        debug!("adaptively coded {} tokens into {} bytes", processed_data.len(), encoded_data.len());
+        return Ok((encoded_data, Vec::new(), preprocessor.serialize_dictionary()));
+    }
+
+    let mut dictionary = AdaptiveDictionary::new();
+    dictionary.update(processed_data);
+
+    // Empty input has no tree and no codes. Only the code lengths of the tree are
+    // kept; the codes themselves are the canonical ones, which the decoder derives
+    // from the lengths alone.
+    let mut tree_codes = BTreeMap::new();
+    if let Some(huffman_tree) = build_huffman_tree_seeded(&dictionary, preprocessor.tie_seed()) {
+        generate_huffman_codes(huffman_tree.as_ref(), &mut vec![], &mut tree_codes);
+    }
+    let lengths = code_lengths(&tree_codes);
+    let codes = canonical_codes(&lengths);
+    let code_length_table = serialize_code_length_table(&lengths);
+
+    let huffman_encoded_data = huffman_encode(processed_data, &codes);
+    // The frame stores the size in a u32
+    if huffman_encoded_data.len() > u32::MAX as usize {
+        return Err(QuantumPackError::InputTooLarge);
+    }
 
     let serialized_dictionary = preprocessor.serialize_dictionary();
+    debug!(
+        "coded {} tokens into {} bytes with a {} byte table and a {} byte dictionary",
+        processed_data.len(),
+        huffman_encoded_data.len(),
+        code_length_table.len(),
+        serialized_dictionary.len()
+    );
+
+    Ok((huffman_encoded_data, code_length_table, serialized_dictionary))
+}
+
+// Compress `data` into a frame that refers to `dictionary` by its preset_id, see
+// Compressor::preset_dictionary
+pub fn compress_with_dictionary(data: &[u8], dictionary: &TrainedDictionary) -> Result<Vec<u8>, QuantumPackError> {
+    let mut frame = Vec::new();
+    Compressor::new().preset_dictionary(dictionary.clone()).compress_shared(data, &mut frame)?;
+    Ok(frame)
+}
+
+// Decode a frame from compress_with_dictionary. Fails with DictionaryMismatch when
+// it was compressed with another dictionary.
+pub fn decompress_with_dictionary(input: &[u8], dictionary: &TrainedDictionary) -> Result<Vec<u8>, QuantumPackError> {
+    Ok(Decompressor::new().preset_dictionary(dictionary.clone()).decompress(input)?.0)
+}
+
+// Compress data
+pub fn compress(data: &[u8]) -> Result<Parts, QuantumPackError> {
+    Compressor::new().compress(data)
+}
 
-    (huffman_encoded_data, frequency_table, serialized_dictionary)
+// Compress a caller owned buffer into a complete frame, as written by compress_file
+pub fn compress_shared(region: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+    let mut frame = Vec::new();
+    Compressor::new().compress_shared(region, &mut frame)?;
+    Ok(frame)
 }
 
 // Decompress data
-pub fn decompress(encoded_data: &[u8], frequency_table: &[u8], serialized_dictionary: &[u8], huffman_tree: &HuffmanNode) -> Vec<u8> {
-    let huffman_decoded_data = huffman_decode(encoded_data, huffman_tree);
+pub fn decompress(encoded_data: &[u8], frequency_table: &[u8], serialized_dictionary: &[u8], huffman_tree: &HuffmanNode) -> Result<Vec<u8>, QuantumPackError> {
+    reverse_serialized(serialized_dictionary, &huffman_decode_limited(encoded_data, huffman_tree, usize::MAX)?, usize::MAX)
+}
+
+// Decompress the parts Compressor::compress returns with adaptive_huffman, which
+// need no table or tree
+pub fn decompress_adaptive(encoded_data: &[u8], serialized_dictionary: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+    let (code, code_bits) = split_bit_count(encoded_data)?;
+    reverse_serialized(serialized_dictionary, &adaptive_decode_bits(code, code_bits, usize::MAX)?, usize::MAX)
+}
 
+// Undo the preprocessor with the dictionary serialized in the frame, producing at
+// most `max_output` bytes
+fn reverse_serialized(serialized_dictionary: &[u8], huffman_decoded_data: &[u8], max_output: usize) -> Result<Vec<u8>, QuantumPackError> {
     let mut preprocessor = Preprocessor::new();
-    preprocessor.deserialize_dictionary(serialized_dictionary);
+    preprocessor.deserialize_dictionary(serialized_dictionary)?;
 
-    preprocessor.reverse_transform_data(&huffman_decoded_data)
+    preprocessor.reverse_transform_limited(huffman_decoded_data, max_output)
 }
 
-// Compress a file
-pub fn compress_file(input_path: &str, output_path: &str) -> io::Result<()> {
-    let mut file = File::open(input_path)?;
-    let mut contents = Vec::new();
-    file.read_to_end(&mut contents)?;
+// Undo the preprocessor with a dictionary the frame does not carry
+fn reverse_with(dictionary: &TrainedDictionary, huffman_decoded_data: &[u8], max_output: usize) -> Result<Vec<u8>, QuantumPackError> {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.set_dictionary(dictionary.clone());
+    preprocessor.reverse_transform_limited(huffman_decoded_data, max_output)
+}
 
-    let (compressed, frequency_table, serialized_dictionary) = compress(&contents);
+// Bytes found after the end of a frame, e.g. padding from a container the file was embedded in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrailingData {
+    // Position of the first trailing byte, i.e. the length of the frame
+    pub offset: u64,
+    pub len: u64,
+}
 
-    let mut output_file = File::create(output_path)?;
+// What decompression does when a frame is followed by more bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrailingDataPolicy {
+    // Fail with QuantumPackError::TrailingData
+    #[default]
+    Strict,
+    // Decode the frame and report the trailing bytes to the caller
+    Permissive,
+}
 
-    output_file.write_all(&(frequency_table.len() as u32).to_be_bytes())?;
-    output_file.write_all(&frequency_table)?;
-    output_file.write_all(&(serialized_dictionary.len() as u32).to_be_bytes())?;
-    output_file.write_all(&serialized_dictionary)?;
-    output_file.write_all(&compressed)?;
+// A preset dictionary with its ID, which takes serializing the whole dictionary to
+// work out and is needed for every frame
+pub(crate) struct Preset {
+    dictionary: TrainedDictionary,
+    id: u32,
+}
 
-    Ok(())
+impl Preset {
+    fn new(dictionary: TrainedDictionary) -> Self {
+        let id = dictionary.preset_id();
+        Preset { dictionary, id }
+    }
+}
+
+// Decompression settings shared by the in-memory and file helpers
+#[derive(Clone, Default)]
+pub struct Decompressor {
+    pub(crate) bwlimit: Option<u64>,
+    trailing_data: TrailingDataPolicy,
+    max_output_size: Option<usize>,
+    concatenated: bool,
+    preset: Option<Arc<Preset>>,
+    hook: Option<FrameHookHandle>,
+    pub(crate) output_policy: OutputPolicy,
+    pub(crate) skip_metadata: bool,
+    pub(crate) warnings: Option<WarningHandler>,
+    pub(crate) progress: Option<ProgressHandler>,
+}
+
+impl Decompressor {
+    pub fn new() -> Self {
+        Decompressor::default()
+    }
+
+    // Limit file reads and writes to `bytes_per_sec`
+    pub fn bwlimit(mut self, bytes_per_sec: u64) -> Self {
+        self.bwlimit = Some(bytes_per_sec);
+        self
+    }
+
+    // How the file helpers create output files, see OutputPolicy
+    pub fn output_policy(mut self, policy: OutputPolicy) -> Self {
+        self.output_policy = policy;
+        self
+    }
+
+    // Report non-fatal conditions such as metadata that could not be restored to `handler`
+    pub fn on_warning(mut self, handler: WarningHandler) -> Self {
+        self.warnings = Some(handler);
+        self
+    }
+
+    // Report the progress of decompress_file and decompress_to to `handler`, see ProgressHandler
+    pub fn on_progress(mut self, handler: ProgressHandler) -> Self {
+        self.progress = Some(handler);
+        self
+    }
+
+    // Whether the file helpers give their output the modification time and
+    // permissions recorded in the frame (the default) or leave the defaults
+    pub fn preserve_metadata(mut self, enabled: bool) -> Self {
+        self.skip_metadata = !enabled;
+        self
+    }
+
+    pub fn trailing_data(mut self, policy: TrailingDataPolicy) -> Self {
+        self.trailing_data = policy;
+        self
+    }
+
+    // Refuse frames that decode to more than `bytes`, and stop decoding as soon as the
+    // output, or an intermediate buffer, would grow past what a frame of that length
+    // can need, so untrusted input cannot exhaust memory
+    pub fn max_output_size(mut self, bytes: usize) -> Self {
+        self.max_output_size = Some(bytes);
+        self
+    }
+
+    // Decode frames written back to back, e.g. by stream::concat or QpEncoder, as one
+    // stream. Only bytes after the last frame that do not start another frame are
+    // trailing data.
+    pub fn concatenated(mut self, enabled: bool) -> Self {
+        self.concatenated = enabled;
+        self
+    }
+
+    // The dictionary frames compressed with Compressor::preset_dictionary refer to.
+    // Frames that name another one fail with DictionaryMismatch; frames that carry
+    // their own dictionary decode as before.
+    pub fn preset_dictionary(mut self, dictionary: TrainedDictionary) -> Self {
+        self.preset = Some(Arc::new(Preset::new(dictionary)));
+        self
+    }
+
+    // Open the frames a Compressor with the same hook sealed, see FrameHook. Frames
+    // that are not sealed are refused.
+    pub fn frame_hook(mut self, hook: FrameHookHandle) -> Self {
+        self.hook = Some(hook);
+        self
+    }
+
+    // Decode a frame written by Compressor::compress_symbols back into its symbols.
+    // The frame's symbol width has to match `S`; byte frames decode as u8 symbols.
+    pub fn decompress_symbols<S: Symbol>(&self, input: &[u8]) -> Result<(Vec<S>, Option<TrailingData>), QuantumPackError> {
+        let width = frame_symbol_width(input)?.unwrap_or(1);
+        if width != S::WIDTH {
+            return Err(QuantumPackError::InvalidInput(format!("frame holds {} byte symbols, not {} byte ones", width, S::WIDTH)));
+        }
+        let (decoded, trailing) = self.decompress(input)?;
+        Ok((symbols_from_le(&decoded), trailing))
+    }
+
+    // Decode a complete compressed file held in memory
+    pub fn decompress(&self, input: &[u8]) -> Result<(Vec<u8>, Option<TrailingData>), QuantumPackError> {
+        self.decompress_reporting(input, &None)
+    }
+
+    // Inverse of Compressor::compress_shared for one frame, using the preset dictionary
+    // but not the hook. Returns the decoded data and the length of the frame, which
+    // may be followed by unrelated bytes.
+    pub(crate) fn decode_frame(&self, frame: &[u8], max_output: usize) -> Result<(Vec<u8>, usize), QuantumPackError> {
+        decode_frame_with(frame, max_output, self.preset.as_deref(), None)
+    }
+
+    // How much all the frames of one input may decode to, see max_output_size
+    pub(crate) fn max_output(&self) -> usize {
+        self.max_output_size.unwrap_or(usize::MAX)
+    }
+
+    // decompress, reporting to `progress` after each frame
+    pub(crate) fn decompress_reporting(&self, input: &[u8], progress: &Option<ProgressHandler>) -> Result<(Vec<u8>, Option<TrailingData>), QuantumPackError> {
+        let max_output = self.max_output();
+        let preset = self.preset.as_deref();
+        let hook = self.hook.as_deref();
+        let (mut decompressed, mut frame_len) = decode_frame_with(input, max_output, preset, hook)?;
+        report(progress, frame_len as u64, decompressed.len() as u64);
+        let mut continued = frame_continues(input)?;
+        // The blocks of one input always decode together
+        while continued || (self.concatenated && input[frame_len..].starts_with(&MAGIC)) {
+            if continued && frame_len == input.len() {
+                return Err(QuantumPackError::truncated("compressed data").at(frame_len as u64));
+            }
+            let rest = &input[frame_len..];
+            let at = |error: QuantumPackError| error.at(frame_len as u64);
+            let (block, len) = decode_frame_with(rest, max_output - decompressed.len(), preset, hook).map_err(at)?;
+            continued = frame_continues(rest).map_err(at)?;
+            decompressed.extend_from_slice(&block);
+            frame_len += len;
+            report(progress, frame_len as u64, decompressed.len() as u64);
+        }
+        if frame_len == input.len() {
+            return Ok((decompressed, None));
+        }
+        let trailing = TrailingData { offset: frame_len as u64, len: (input.len() - frame_len) as u64 };
+        match self.trailing_data {
+            TrailingDataPolicy::Permissive => Ok((decompressed, Some(trailing))),
+            TrailingDataPolicy::Strict => Err(QuantumPackError::TrailingData(trailing)),
+        }
+    }
+
+}
+
+// decode_frame, with the preset dictionary frames may refer to and the hook that
+// opens sealed frames
+fn decode_frame_with(frame: &[u8], max_output: usize, preset: Option<&Preset>, hook: Option<&dyn FrameHook>) -> Result<(Vec<u8>, usize), QuantumPackError> {
+    let parts = frame_parts(frame)?;
+    let opened;
+    let parts = match (parts.sealed, hook) {
+        (false, None) => parts,
+        (true, Some(hook)) => {
+            opened = hook.after_read(parts.data)?;
+            parts.open(&opened)?
+        }
+        (true, None) => return Err(QuantumPackError::InvalidInput("the frame is sealed and no FrameHook is set to open it".to_string())),
+        (false, Some(_)) => return Err(QuantumPackError::InvalidInput("the frame is not sealed by the FrameHook that is set".to_string())),
+    };
+    if let Some(expected) = parts.dictionary_id {
+        let found = preset.map(|preset| preset.id);
+        if found != Some(expected) {
+            return Err(QuantumPackError::DictionaryMismatch { expected, found });
+        }
+    }
+    if parts.decoded_len > max_output as u64 {
+        return Err(QuantumPackError::OutputLimitExceeded { limit: max_output });
+    }
+
+    // Patterns and stages both expand their input, so every step is held to the
+    // length the footer claims, which is within the limit, instead of only comparing
+    // the result with it afterwards. This is what makes decode_memory a bound. A
+    // staged frame goes through the preprocessor in its encoded form, which may be
+    // longer than the output.
+    let output_limit = parts.decoded_len as usize;
+    let tokens_limit = match parts.stage {
+        Some(_) => max_encoded_len(output_limit),
+        None => output_limit,
+    };
+    // A literal escape spends three tokens on one byte
+    let symbols_limit = tokens_limit.saturating_mul(3);
+    let reverse = |tokens: &[u8]| match parts.dictionary_id.and(preset) {
+        Some(preset) => reverse_with(&preset.dictionary, tokens, tokens_limit),
+        None => reverse_serialized(parts.dictionary, tokens, tokens_limit),
+    };
+    let decode = || -> Result<Vec<u8>, QuantumPackError> {
+        if parts.block_type == BLOCK_STORED {
+            return Ok(parts.data.to_vec());
+        }
+        let (code, code_bits) = parts.code_bits()?;
+        if parts.block_type == BLOCK_ADAPTIVE {
+            reverse(&adaptive_decode_bits(code, code_bits, symbols_limit)?)
+        } else if parts.is_symbols() {
+            decode_symbols(parts.table, code, code_bits, parts.symbol_width, output_limit)
+        } else {
+            match parts.huffman_tree()? {
+                Some(huffman_tree) => reverse(&huffman_decode_bits(code, code_bits, &huffman_tree, symbols_limit)?),
+                None => {
+                    check_no_symbols(code_bits)?;
+                    Ok(Vec::new())
+                }
+            }
+        }
+    };
+    // A step that outgrows its bound decodes to more than the footer says
+    let budget = |error| match error {
+        QuantumPackError::OutputLimitExceeded { .. } => QuantumPackError::ChecksumMismatch { offset: 0 },
+        error => error,
+    };
+    let mut decompressed = decode().map_err(budget)?;
+    if let Some(stage) = parts.stage {
+        // A column claiming more values than fit the output is rejected before they
+        // are allocated
+        decompressed = stage.decode_limited(&decompressed, output_limit).map_err(budget)?;
+    }
+    if decompressed.len() as u64 != parts.decoded_len || crc32(&decompressed) != parts.crc {
+        return Err(QuantumPackError::ChecksumMismatch { offset: 0 });
+    }
+    Ok((decompressed, parts.frame_len))
+}
+
+// The fields of a frame, not decoded yet
+pub(crate) struct FrameParts<'a> {
+    pub(crate) version: u8,
+    pub(crate) flags: u8,
+    pub(crate) stage: Option<Stage>,
+    pub(crate) symbol_width: usize,
+    pub(crate) dictionary_id: Option<u32>,
+    pub(crate) block_type: u8,
+    // The sections are still sealed, see SEALED
+    pub(crate) sealed: bool,
+    // The table lists (symbol, length) pairs, see SPARSE_TABLE
+    pub(crate) sparse_table: bool,
+    // None before version 3, where the data ends in a bit count byte instead
+    pub(crate) padding_bits: Option<u8>,
+    pub(crate) table: &'a [u8],
+    pub(crate) dictionary: &'a [u8],
+    pub(crate) data: &'a [u8],
+    pub(crate) decoded_len: u64,
+    pub(crate) crc: u32,
+    pub(crate) frame_len: usize,
 }
-// Decompress a file
-pub fn decompress_file(input_path: &str, output_path: &str) -> io::Result<()> {
-    let mut file = File::open(input_path)?;
-    let mut combined_contents = Vec::new();
-    file.read_to_end(&mut combined_contents)?;
 
-    // Read frequency table size and content
-    let (size_bytes, rest) = combined_contents.split_at(4);
-    let frequency_table_size = u32::from_be_bytes(size_bytes.try_into().unwrap()) as usize;
-    let (frequency_table, rest) = rest.split_at(frequency_table_size);
+impl<'a> FrameParts<'a> {
+    pub(crate) fn is_symbols(&self) -> bool {
+        self.flags & SYMBOLS_FLAG != 0
+    }
+
+    // The parts with the sections of `opened`, the payload of a sealed frame as
+    // FrameHook::after_read returned it
+    fn open<'b>(self, opened: &'b [u8]) -> Result<FrameParts<'b>, QuantumPackError>
+    where
+        'a: 'b,
+    {
+        let mut reader = Reader::new(opened, "sealed payload");
+        let decoded_len = reader.u64()?;
+        let crc = reader.u32()?;
+        let table_size = reader.u32()?;
+        let table = reader.bytes(table_size as usize)?;
+        let dictionary_size = reader.u32()?;
+        let dictionary = reader.bytes(dictionary_size as usize)?;
+        let data = reader.bytes(reader.remaining())?;
+        check_sections(self.block_type, self.flags, table)?;
+        Ok(FrameParts { sealed: false, table, dictionary, data, decoded_len, crc, ..self })
+    }
+
+    // The code lengths of a byte frame; earlier versions stored other tables
+    #[cfg(feature = "trace")]
+    pub(crate) fn code_lengths(&self) -> Result<BTreeMap<u8, u8>, QuantumPackError> {
+        if self.version < CODE_LENGTH_VERSION || self.is_symbols() || self.block_type == BLOCK_ADAPTIVE || self.sealed {
+            return Err(QuantumPackError::InvalidInput("only static byte frames of the current version carry code lengths".to_string()));
+        }
+        self.length_table()
+    }
+
+    // The code length table of a byte frame from version 2 on
+    fn length_table(&self) -> Result<BTreeMap<u8, u8>, QuantumPackError> {
+        if self.sparse_table {
+            deserialize_sparse_length_table(self.table)
+        } else {
+            deserialize_code_length_table(self.table)
+        }
+    }
+
+    // The code bytes of the data section and how many of their bits are codes. The
+    // padding bits that fill up the last byte must be zero.
+    pub(crate) fn code_bits(&self) -> Result<(&[u8], usize), QuantumPackError> {
+        let padding_bits = match self.padding_bits {
+            Some(padding_bits) => padding_bits as usize,
+            None => return Ok(split_bit_count(self.data)?),
+        };
+        match self.data.last() {
+            None if padding_bits == 0 => Ok((self.data, 0)),
+            Some(&last) if last & ((1 << padding_bits) - 1) == 0 => Ok((self.data, self.data.len() * 8 - padding_bits)),
+            _ => Err(QuantumPackError::corrupt_header(format!("{} padding bits do not fit the data section", padding_bits))),
+        }
+    }
+
+    // Decoding tree of a byte frame, None for empty input
+    pub(crate) fn huffman_tree(&self) -> Result<Option<Box<HuffmanNode>>, QuantumPackError> {
+        Ok(if self.version >= CODE_LENGTH_VERSION {
+            build_huffman_tree_from_codes(&canonical_codes(&self.length_table()?))
+        } else if self.flags & CODE_LENGTHS_FLAG != 0 {
+            build_huffman_tree_from_codes(&canonical_codes(&deserialize_code_lengths(self.table)))
+        } else {
+            build_huffman_tree_with_dictionary(&deserialize_frequency_table(self.table))
+        })
+    }
+}
+
+// Split the frame at the start of `frame` into its fields
+pub(crate) fn frame_parts(frame: &[u8]) -> Result<FrameParts<'_>, QuantumPackError> {
+    let mut reader = Reader::new(frame, "compressed data");
+    let (version, flags) = read_header(&mut reader)?;
+    let stage = read_stage(&mut reader, flags)?;
+    let symbol_width = read_symbol_width(&mut reader, flags)?;
+    read_metadata(&mut reader, flags)?;
+    let dictionary_id = read_dictionary_id(&mut reader, flags)?;
+    let block_type = read_block_type(&mut reader, flags)?;
+    let (sealed, sparse_table) = (block_type & SEALED != 0, block_type & SPARSE_TABLE != 0);
+    let block_type = block_type & !(SEALED | SPARSE_TABLE);
+    if sparse_table && flags & SYMBOLS_FLAG != 0 {
+        return Err(QuantumPackError::corrupt_header("symbol frame with a sparse code length table"));
+    }
+    read_tie_seed(&mut reader, version, flags)?;
+    let padding_bits = read_padding_bits(&mut reader, version)?;
+
+    // Read Huffman table size and content
+    let table_size = reader.u32()?;
+    let table = reader.bytes(table_size as usize)?;
+    if sealed && table_size != 0 {
+        return Err(QuantumPackError::corrupt_header("sealed frame with a table outside its payload"));
+    }
+    check_sections(block_type, flags, table)?;
 
     // Read serialized dictionary size and content
-    let (size_bytes, rest) = rest.split_at(4);
-    let dictionary_size = u32::from_be_bytes(size_bytes.try_into().unwrap()) as usize;
-    let (serialized_dictionary, compressed_data) = rest.split_at(dictionary_size);
+    let dictionary_size = reader.u32()?;
+    let dictionary = reader.bytes(dictionary_size as usize)?;
 
-    let dictionary = deserialize_frequency_table(frequency_table);
-    let huffman_tree = build_huffman_tree_with_dictionary(&dictionary).unwrap();
+    let data_size = reader.u32()?;
+    let data = reader.bytes(data_size as usize)?;
+    skip_padding(&mut reader, flags)?;
+    if reader.bytes(END_MARKER.len())? != END_MARKER {
+        return Err(QuantumPackError::corrupt_header("missing end-of-stream marker"));
+    }
+    let decoded_len = reader.u64()?;
+    let crc = reader.u32()?;
+    let frame_len = frame.len() - reader.remaining();
+    if sealed && dictionary_size != 0 {
+        return Err(QuantumPackError::corrupt_header("sealed frame with a dictionary outside its payload"));
+    }
+    if sealed && (decoded_len != 0 || crc != 0) {
+        return Err(QuantumPackError::corrupt_header("sealed frame with a length or CRC outside its payload"));
+    }
+    Ok(FrameParts { version, flags, stage, symbol_width, dictionary_id, block_type, sealed, sparse_table, padding_bits, table, dictionary, data, decoded_len, crc, frame_len })
+}
 
-    let decompressed = decompress(compressed_data, frequency_table, serialized_dictionary, &huffman_tree);
+// Adaptive blocks learn their code from the data, so they have no table to store
+fn check_sections(block_type: u8, flags: u8, table: &[u8]) -> Result<(), QuantumPackError> {
+    if block_type == BLOCK_ADAPTIVE && (!table.is_empty() || flags & SYMBOLS_FLAG != 0) {
+        return Err(QuantumPackError::corrupt_header("adaptive block with a code table"));
+    }
+    Ok(())
+}
 
+// Only empty input has an empty code table, so the data section must not hold any
+// codes
+pub(crate) fn check_no_symbols(code_bits: usize) -> Result<(), QuantumPackError> {
+    if code_bits != 0 {
+        return Err(QuantumPackError::corrupt_header("data section without a code table"));
+    }
+    Ok(())
+}
 
-    // Convert decompressed data to a string
-    let decompressed_str = match str::from_utf8(&decompressed) {
-        Ok(s) => s,
-        Err(e) => {
-            //println!("UTF-8 error at byte index: {}", e.valid_up_to());
-            return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+// Huffman decode a symbol frame's data into little-endian symbols of `width` bytes
+fn decode_symbols(table: &[u8], code: &[u8], code_bits: usize, width: usize, max_output: usize) -> Result<Vec<u8>, QuantumPackError> {
+    let lengths = deserialize_symbol_length_table(table)?;
+    if let Some(&symbol) = lengths.keys().find(|&&symbol| width < 4 && symbol >> (8 * width) != 0) {
+        return Err(QuantumPackError::corrupt_header(format!("symbol {} does not fit in {} bytes", symbol, width)));
+    }
+    let huffman_tree = match build_huffman_tree_from_codes(&canonical_codes(&lengths)) {
+        Some(huffman_tree) => huffman_tree,
+        None => {
+            check_no_symbols(code_bits)?;
+            return Ok(Vec::new());
         }
     };
-    
-    //println!("Final decompressed string: {:?}", decompressed_str);
+    let symbols: Vec<u32> = huffman_decode_bits(code, code_bits, &huffman_tree, max_output / width)?;
+    let mut decoded = Vec::with_capacity(symbols.len() * width);
+    for symbol in symbols {
+        decoded.extend_from_slice(&symbol.to_le_bytes()[..width]);
+    }
+    Ok(decoded)
+}
+
+// Symbols from their little-endian bytes, as a symbol frame decodes to
+pub(crate) fn symbols_from_le<S: Symbol>(bytes: &[u8]) -> Vec<S> {
+    bytes
+        .chunks_exact(S::WIDTH)
+        .map(|chunk| {
+            let mut value = [0; 4];
+            value[..S::WIDTH].copy_from_slice(chunk);
+            S::from_u32(u32::from_le_bytes(value)).expect("chunk has the symbol's width")
+        })
+        .collect()
+}
 
-    // Write the string to the output file
-    let mut output_file = File::create(output_path)?;
-    output_file.write_all(decompressed_str.as_bytes())?;
+// Bytes per symbol of the frame at the start of `input` if it is a symbol frame
+pub(crate) fn frame_symbol_width(input: &[u8]) -> Result<Option<usize>, QuantumPackError> {
+    let mut reader = Reader::new(input, "compressed data");
+    let (_, flags) = read_header(&mut reader)?;
+    read_stage(&mut reader, flags)?;
+    let width = read_symbol_width(&mut reader, flags)?;
+    Ok(Some(width).filter(|_| flags & SYMBOLS_FLAG != 0))
+}
 
+fn read_symbol_width(reader: &mut Reader, flags: u8) -> Result<usize, QuantumPackError> {
+    if flags & SYMBOLS_FLAG == 0 {
+        return Ok(1);
+    }
+    match reader.u8()? {
+        width @ (1 | 2 | 4) => Ok(width as usize),
+        width => Err(QuantumPackError::corrupt_header(format!("unsupported symbol width {}", width))),
+    }
+}
+
+// Whether another block of the same input follows the frame at the start of `input`
+pub(crate) fn frame_continues(input: &[u8]) -> Result<bool, QuantumPackError> {
+    let (_, flags) = read_header(&mut Reader::new(input, "compressed data"))?;
+    Ok(flags & CONTINUED_FLAG != 0)
+}
+
+// The file metadata recorded in the frame at the start of `input`, if any
+pub fn frame_metadata(input: &[u8]) -> Result<Option<FileMetadata>, QuantumPackError> {
+    let mut reader = Reader::new(input, "compressed data");
+    let (_, flags) = read_header(&mut reader)?;
+    read_stage(&mut reader, flags)?;
+    read_symbol_width(&mut reader, flags)?;
+    read_metadata(&mut reader, flags)
+}
+
+fn read_metadata(reader: &mut Reader, flags: u8) -> Result<Option<FileMetadata>, QuantumPackError> {
+    if flags & METADATA_FLAG == 0 {
+        return Ok(None);
+    }
+    Ok(Some(FileMetadata { mtime: reader.u64()?, mode: reader.u32()? }))
+}
+
+// The preset dictionary the frame at the start of `input` was compressed with, if
+// any, e.g. to look it up before decompressing
+pub fn frame_dictionary_id(input: &[u8]) -> Result<Option<u32>, QuantumPackError> {
+    let mut reader = Reader::new(input, "compressed data");
+    let (_, flags) = read_header(&mut reader)?;
+    read_stage(&mut reader, flags)?;
+    read_symbol_width(&mut reader, flags)?;
+    read_metadata(&mut reader, flags)?;
+    read_dictionary_id(&mut reader, flags)
+}
+
+fn read_dictionary_id(reader: &mut Reader, flags: u8) -> Result<Option<u32>, QuantumPackError> {
+    if flags & DICTIONARY_ID_FLAG == 0 {
+        return Ok(None);
+    }
+    Ok(Some(reader.u32()?))
+}
+
+// The seed the frame at the start of `input` broke ties with, to compress the same
+// data into the same frame again with Compressor::tie_seed
+pub fn frame_tie_seed(input: &[u8]) -> Result<u32, QuantumPackError> {
+    let mut reader = Reader::new(input, "compressed data");
+    let (version, flags) = read_header(&mut reader)?;
+    read_stage(&mut reader, flags)?;
+    read_symbol_width(&mut reader, flags)?;
+    read_metadata(&mut reader, flags)?;
+    read_dictionary_id(&mut reader, flags)?;
+    read_block_type(&mut reader, flags)?;
+    read_tie_seed(&mut reader, version, flags)
+}
+
+fn has_tie_seed(version: u8, flags: u8) -> bool {
+    version >= CODE_LENGTH_VERSION && flags & TIE_SEED_FLAG != 0
+}
+
+fn read_tie_seed(reader: &mut Reader, version: u8, flags: u8) -> Result<u32, QuantumPackError> {
+    if !has_tie_seed(version, flags) {
+        return Ok(0);
+    }
+    reader.u32()
+}
+
+// The number of 0 bits that fill up the last byte of the data section, None for
+// versions that end the data with a bit count byte instead
+fn read_padding_bits(reader: &mut Reader, version: u8) -> Result<Option<u8>, QuantumPackError> {
+    if version < FORMAT_VERSION {
+        return Ok(None);
+    }
+    match reader.u8()? {
+        padding_bits @ 0..=7 => Ok(Some(padding_bits)),
+        padding_bits => Err(QuantumPackError::corrupt_header(format!("{} padding bits in the last byte", padding_bits))),
+    }
+}
+
+fn read_block_type(reader: &mut Reader, flags: u8) -> Result<u8, QuantumPackError> {
+    if flags & BLOCK_TYPE_FLAG == 0 {
+        return Ok(BLOCK_HUFFMAN);
+    }
+    let block_type = reader.u8()?;
+    match block_type & !SEALED {
+        BLOCK_HUFFMAN | BLOCK_STORED | BLOCK_ADAPTIVE => Ok(block_type),
+        unsealed if unsealed == BLOCK_HUFFMAN | SPARSE_TABLE => Ok(block_type),
+        _ => Err(QuantumPackError::corrupt_header(format!("unknown block type {}", block_type))),
+    }
+}
+
+// The stage the frame at the start of `input` was written with
+pub(crate) fn frame_stage(input: &[u8]) -> Result<Option<Stage>, QuantumPackError> {
+    let mut reader = Reader::new(input, "compressed data");
+    let (_, flags) = read_header(&mut reader)?;
+    read_stage(&mut reader, flags)
+}
+
+fn read_stage(reader: &mut Reader, flags: u8) -> Result<Option<Stage>, QuantumPackError> {
+    if flags & STAGE_FLAG == 0 {
+        return Ok(None);
+    }
+    let descriptor_size = reader.u8()?;
+    Ok(Some(Stage::deserialize(reader.bytes(descriptor_size as usize)?)?))
+}
+
+// Length of the frame at the start of `input` and the length it decodes to, read
+// from the size fields and the footer without decoding anything
+pub(crate) fn frame_extent(input: &[u8]) -> Result<(usize, u64), QuantumPackError> {
+    let mut reader = Reader::new(input, "compressed data");
+    let (version, flags) = read_header(&mut reader)?;
+    if flags & STAGE_FLAG != 0 {
+        let descriptor_size = reader.u8()?;
+        reader.bytes(descriptor_size as usize)?;
+    }
+    read_symbol_width(&mut reader, flags)?;
+    read_metadata(&mut reader, flags)?;
+    read_dictionary_id(&mut reader, flags)?;
+    read_block_type(&mut reader, flags)?;
+    read_tie_seed(&mut reader, version, flags)?;
+    read_padding_bits(&mut reader, version)?;
+    for _ in 0..section_count(flags) {
+        let size = reader.u32()?;
+        reader.bytes(size as usize)?;
+    }
+    if reader.bytes(END_MARKER.len())? != END_MARKER {
+        return Err(QuantumPackError::corrupt_header("missing end-of-stream marker"));
+    }
+    let decoded_len = reader.u64()?;
+    reader.u32()?;
+    Ok((input.len() - reader.remaining(), decoded_len))
+}
+
+// What decoding a frame allocates at most, worked out from its size fields and
+// footer. Decoding stops as soon as any buffer outgrows what the length in the
+// footer allows, so a frame that lies about it cannot take more. The format has no
+// sliding window; the dictionary takes its place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeMemory {
+    // Huffman tree and deserialized dictionary
+    pub tables: usize,
+    // Huffman-decoded tokens, before the dictionary is reversed
+    pub block_buffer: usize,
+    // The decoded data, plus the stage's input when the frame has a stage
+    pub output: usize,
+}
+
+impl DecodeMemory {
+    pub fn total(&self) -> usize {
+        self.tables.saturating_add(self.block_buffer).saturating_add(self.output)
+    }
+}
+
+// The memory needed to decode the frame at the start of `input`, found without
+// decoding or allocating anything, so a constrained decoder can refuse it up front
+pub fn decode_memory(input: &[u8]) -> Result<DecodeMemory, QuantumPackError> {
+    let mut reader = Reader::new(input, "compressed data");
+    let (version, flags) = read_header(&mut reader)?;
+    if flags & STAGE_FLAG != 0 {
+        let descriptor_size = reader.u8()?;
+        reader.bytes(descriptor_size as usize)?;
+    }
+    let symbol_width = read_symbol_width(&mut reader, flags)?;
+    read_metadata(&mut reader, flags)?;
+    read_dictionary_id(&mut reader, flags)?;
+    let block_type = read_block_type(&mut reader, flags)?;
+    if block_type & SEALED != 0 {
+        return Err(QuantumPackError::InvalidInput("a sealed frame keeps its decoded length in the sealed payload".to_string()));
+    }
+    let (block_type, sparse_table) = (block_type & !SPARSE_TABLE, block_type & SPARSE_TABLE != 0);
+    read_tie_seed(&mut reader, version, flags)?;
+    read_padding_bits(&mut reader, version)?;
+    let table_size = reader.u32()?;
+    let table = reader.bytes(table_size as usize)?;
+    let dictionary_size = reader.u32()? as usize;
+    reader.bytes(dictionary_size)?;
+    let data_size = reader.u32()? as usize;
+    reader.bytes(data_size)?;
+    skip_padding(&mut reader, flags)?;
+    if reader.bytes(END_MARKER.len())? != END_MARKER {
+        return Err(QuantumPackError::corrupt_header("missing end-of-stream marker"));
+    }
+    let decoded_len = reader.u64()?.min(usize::MAX as u64) as usize;
+    reader.u32()?;
+
+    if block_type == BLOCK_STORED {
+        return Ok(DecodeMemory { tables: 0, block_buffer: 0, output: decoded_len });
+    }
+    if flags & SYMBOLS_FLAG != 0 {
+        // Every entry of the table takes at least two bytes and symbols decode as u32
+        let tree = table.len() * std::mem::size_of::<HuffmanNode<u32>>();
+        let block_buffer = data_size.saturating_mul(8).min(decoded_len / symbol_width).saturating_mul(4);
+        return Ok(DecodeMemory { tables: tree, block_buffer, output: decoded_len });
+    }
+
+    // Version 1 tables are not dense, assume every byte value has a code
+    let symbols = match version {
+        CODE_LENGTH_VERSION.. if sparse_table => table.len() / 2,
+        CODE_LENGTH_VERSION.. => table.iter().filter(|&&length| length != 0).count(),
+        _ => 256,
+    };
+    let tree = if block_type == BLOCK_ADAPTIVE {
+        adaptive_tree_size()
+    } else {
+        (2 * symbols).saturating_sub(1) * std::mem::size_of::<HuffmanNode>()
+    };
+    // Every pattern is held by three maps of the dictionary
+    let tables = tree + 3 * dictionary_size;
+
+    // Codes are at least one bit long, and a literal escape spends three tokens on one byte
+    let mut block_buffer = data_size.saturating_mul(8);
+    let output = if flags & STAGE_FLAG != 0 {
+        // Decoding holds the stage's input to max_encoded_len, and its tokens to
+        // three per byte of it
+        let stage_input = max_encoded_len(decoded_len);
+        block_buffer = block_buffer.min(stage_input.saturating_mul(3));
+        decoded_len.saturating_add(stage_input)
+    } else {
+        block_buffer = block_buffer.min(decoded_len.saturating_mul(3));
+        decoded_len
+    };
+    Ok(DecodeMemory { tables, block_buffer, output })
+}
+
+// Read one frame from `input` without reading past its end, so frames written back
+// to back can be taken one at a time. Returns None at a clean end of input.
+pub(crate) fn read_frame<R: Read>(input: &mut R) -> Result<Option<Vec<u8>>, QuantumPackError> {
+    let mut frame = Vec::new();
+    let first = read_chunk(input, &mut frame, HEADER_LEN)?;
+    if first == 0 {
+        return Ok(None);
+    }
+    let (version, flags) = read_header(&mut Reader::new(&frame, "compressed data"))?;
+    if flags & STAGE_FLAG != 0 {
+        read_exact_chunk(input, &mut frame, 1)?;
+        let descriptor_size = frame[frame.len() - 1] as usize;
+        read_exact_chunk(input, &mut frame, descriptor_size)?;
+    }
+    if flags & SYMBOLS_FLAG != 0 {
+        read_exact_chunk(input, &mut frame, 1)?;
+    }
+    if flags & METADATA_FLAG != 0 {
+        read_exact_chunk(input, &mut frame, METADATA_LEN)?;
+    }
+    if flags & DICTIONARY_ID_FLAG != 0 {
+        read_exact_chunk(input, &mut frame, 4)?;
+    }
+    if flags & BLOCK_TYPE_FLAG != 0 {
+        read_exact_chunk(input, &mut frame, 1)?;
+    }
+    if has_tie_seed(version, flags) {
+        read_exact_chunk(input, &mut frame, 4)?;
+    }
+    if version >= FORMAT_VERSION {
+        read_exact_chunk(input, &mut frame, 1)?;
+    }
+    for _ in 0..section_count(flags) {
+        read_exact_chunk(input, &mut frame, 4)?;
+        let size = Reader::new(&frame[frame.len() - 4..], "compressed data").u32()? as usize;
+        read_exact_chunk(input, &mut frame, size)?;
+    }
+    read_exact_chunk(input, &mut frame, FOOTER_LEN)?;
+    Ok(Some(frame))
+}
+
+// Length and decoded length of the frame at the current position of `input`, found
+// by seeking over its sections instead of reading them. Leaves `input` at the end
+// of the frame. Returns None at a clean end of input.
+pub(crate) fn seek_frame<R: Read + Seek>(input: &mut R) -> Result<Option<(u64, u64)>, QuantumPackError> {
+    let start = input.stream_position()?;
+    let mut header = Vec::new();
+    if read_chunk(input, &mut header, HEADER_LEN)? == 0 {
+        return Ok(None);
+    }
+    let (version, flags) = read_header(&mut Reader::new(&header, "compressed data"))?;
+    if flags & STAGE_FLAG != 0 {
+        read_exact_chunk(input, &mut header, 1)?;
+        let descriptor_size = header[header.len() - 1] as usize;
+        read_exact_chunk(input, &mut header, descriptor_size)?;
+    }
+    if flags & SYMBOLS_FLAG != 0 {
+        read_exact_chunk(input, &mut header, 1)?;
+    }
+    if flags & METADATA_FLAG != 0 {
+        read_exact_chunk(input, &mut header, METADATA_LEN)?;
+    }
+    if flags & DICTIONARY_ID_FLAG != 0 {
+        read_exact_chunk(input, &mut header, 4)?;
+    }
+    if flags & BLOCK_TYPE_FLAG != 0 {
+        read_exact_chunk(input, &mut header, 1)?;
+    }
+    if has_tie_seed(version, flags) {
+        read_exact_chunk(input, &mut header, 4)?;
+    }
+    if version >= FORMAT_VERSION {
+        read_exact_chunk(input, &mut header, 1)?;
+    }
+    for _ in 0..section_count(flags) {
+        read_exact_chunk(input, &mut header, 4)?;
+        let size = Reader::new(&header[header.len() - 4..], "compressed data").u32()?;
+        input.seek(SeekFrom::Current(size as i64))?;
+    }
+    let mut footer = Vec::new();
+    read_exact_chunk(input, &mut footer, FOOTER_LEN)?;
+    let mut reader = Reader::new(&footer, "compressed data");
+    if reader.bytes(END_MARKER.len())? != END_MARKER {
+        return Err(QuantumPackError::corrupt_header("missing end-of-stream marker"));
+    }
+    let decoded_len = reader.u64()?;
+    Ok(Some((input.stream_position()? - start, decoded_len)))
+}
+
+// Number of u32-prefixed sections after the header: the table, the dictionary, the
+// Huffman data and with PADDED_FLAG the padding
+fn section_count(flags: u8) -> usize {
+    if flags & PADDED_FLAG != 0 {
+        4
+    } else {
+        3
+    }
+}
+
+fn skip_padding(reader: &mut Reader, flags: u8) -> Result<(), QuantumPackError> {
+    if flags & PADDED_FLAG != 0 {
+        let size = reader.u32()?;
+        reader.bytes(size as usize)?;
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+// Check the magic and return the version and flags. Input that does not start with
+// the magic is not a frame at all; a newer version is reported as such.
+fn read_header(reader: &mut Reader) -> Result<(u8, u8), QuantumPackError> {
+    let magic = reader.bytes(MAGIC.len().min(reader.remaining()))?;
+    if magic != &MAGIC[..magic.len()] {
+        return Err(QuantumPackError::NotAFrame);
+    }
+    reader.bytes(MAGIC.len() - magic.len())?;
+    let version = reader.u8()?;
+    let known_flags = match version {
+        1 => KNOWN_V1_FLAGS,
+        CODE_LENGTH_VERSION | FORMAT_VERSION => KNOWN_FLAGS,
+        _ => return Err(QuantumPackError::UnsupportedVersion(version)),
+    };
+    let flags = reader.u8()?;
+    if flags & !known_flags != 0 {
+        return Err(QuantumPackError::corrupt_header(format!("unknown flags {:#04x}", flags)));
+    }
+    Ok((version, flags))
+}
+
+// Append up to `len` bytes, returning how many were available
+fn read_chunk<R: Read>(input: &mut R, buffer: &mut Vec<u8>, len: usize) -> io::Result<usize> {
+    input.take(len as u64).read_to_end(buffer)
+}
+
+fn read_exact_chunk<R: Read>(input: &mut R, buffer: &mut Vec<u8>, len: usize) -> Result<(), QuantumPackError> {
+    if read_chunk(input, buffer, len)? < len {
+        return Err(QuantumPackError::truncated("compressed data"));
+    }
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use crate::compression::{Compressor, Decompressor};
+use crate::error::QuantumPackError;
+
+// Formats `qp convert` reads and writes, for migrating data to and from
+// quantum-pack. Gzip and zstd need the `gzip` and `zstd` features; without them
+// those formats are still recognized but converting them is an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    QuantumPack,
+    Gzip,
+    Zstd,
+}
+
+impl Format {
+    // Recognize the format from the magic bytes at the start of `data`
+    pub fn detect(data: &[u8]) -> Option<Format> {
+        if data.starts_with(b"QPK1") {
+            Some(Format::QuantumPack)
+        } else if data.starts_with(&[0x1F, 0x8B]) {
+            Some(Format::Gzip)
+        } else if data.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+            Some(Format::Zstd)
+        } else {
+            None
+        }
+    }
+
+    // The format a file name implies: .gz, .zst, and quantum-pack for anything else
+    pub fn from_path(path: &str) -> Format {
+        if path.ends_with(".gz") {
+            Format::Gzip
+        } else if path.ends_with(".zst") {
+            Format::Zstd
+        } else {
+            Format::QuantumPack
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::QuantumPack => "quantum-pack",
+            Format::Gzip => "gzip",
+            Format::Zstd => "zstd",
+        }
+    }
+}
+
+// Decode `data`, whose format is detected from its magic bytes, and encode it as
+// `to`. Quantum-pack output uses `compressor`.
+pub fn convert(data: &[u8], to: Format, compressor: &Compressor) -> Result<Vec<u8>, QuantumPackError> {
+    let from = Format::detect(data).ok_or_else(|| QuantumPackError::InvalidInput("unrecognized input format".to_string()))?;
+    encode(&decode(data, from)?, to, compressor)
+}
+
+pub fn decode(data: &[u8], format: Format) -> Result<Vec<u8>, QuantumPackError> {
+    match format {
+        Format::QuantumPack => Ok(Decompressor::new().concatenated(true).decompress(data)?.0),
+        Format::Gzip => gzip::decode(data),
+        Format::Zstd => zstd::decode(data),
+    }
+}
+
+pub fn encode(data: &[u8], format: Format, compressor: &Compressor) -> Result<Vec<u8>, QuantumPackError> {
+    match format {
+        Format::QuantumPack => {
+            let mut frame = Vec::new();
+            compressor.compress_shared(data, &mut frame)?;
+            Ok(frame)
+        }
+        Format::Gzip => gzip::encode(data),
+        Format::Zstd => zstd::encode(data),
+    }
+}
+
+#[cfg(not(all(feature = "gzip", feature = "zstd")))]
+fn unsupported(format: Format) -> QuantumPackError {
+    QuantumPackError::InvalidInput(format!("{} support requires the `{}` feature", format.name(), format.name()))
+}
+
+#[cfg(feature = "gzip")]
+mod gzip {
+    use std::io::{Read, Write};
+
+    use flate2::read::MultiGzDecoder;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use crate::error::QuantumPackError;
+
+    // Concatenated gzip members decode as one stream, like gunzip does
+    pub(super) fn decode(data: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+        let mut decoded = Vec::new();
+        MultiGzDecoder::new(data).read_to_end(&mut decoded)?;
+        Ok(decoded)
+    }
+
+    pub(super) fn encode(data: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        Ok(encoder.finish()?)
+    }
+}
+
+#[cfg(not(feature = "gzip"))]
+mod gzip {
+    use super::{unsupported, Format};
+    use crate::error::QuantumPackError;
+
+    pub(super) fn decode(_: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+        Err(unsupported(Format::Gzip))
+    }
+
+    pub(super) fn encode(_: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+        Err(unsupported(Format::Gzip))
+    }
+}
+
+#[cfg(feature = "zstd")]
+mod zstd {
+    use std::io::Read;
+
+    use ruzstd::decoding::StreamingDecoder;
+    use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+
+    use crate::error::QuantumPackError;
+
+    // Frames written back to back decode as one stream
+    pub(super) fn decode(mut data: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+        let mut decoded = Vec::new();
+        while !data.is_empty() {
+            let mut decoder = StreamingDecoder::new(&mut data)
+                .map_err(|error| QuantumPackError::InvalidInput(format!("invalid zstd frame: {}", error)))?;
+            decoder.read_to_end(&mut decoded)?;
+        }
+        Ok(decoded)
+    }
+
+    // The pure Rust encoder only implements its fastest level
+    pub(super) fn encode(data: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+        Ok(compress_to_vec(data, CompressionLevel::Fastest))
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+mod zstd {
+    use super::{unsupported, Format};
+    use crate::error::QuantumPackError;
+
+    pub(super) fn decode(_: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+        Err(unsupported(Format::Zstd))
+    }
+
+    pub(super) fn encode(_: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+        Err(unsupported(Format::Zstd))
+    }
+}
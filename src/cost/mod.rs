@@ -0,0 +1,60 @@
+use std::collections::BTreeMap;
+
+use crate::adaptive_dictionary::AdaptiveDictionary;
+use crate::huffman::{build_huffman_tree_seeded, code_lengths, count_symbols, generate_huffman_codes, Symbol};
+
+// The size estimates the encoder and the optimal parser work with. Tools that weigh
+// dictionaries, tokenizations or other entropy coders against this one should use
+// these rather than their own formulas, so their numbers agree with the encoder's.
+// A histogram maps each symbol to how often it occurs; symbols with a count of 0
+// are ignored.
+
+// Count the symbols of `data`
+pub fn histogram<S: Symbol>(data: &[S]) -> BTreeMap<S, u32> {
+    count_symbols(data).frequencies
+}
+
+// The Huffman code length the encoder gives each symbol of `histogram` with the default tie seed
+pub fn huffman_code_lengths<S: Symbol>(histogram: &BTreeMap<S, u32>) -> BTreeMap<S, u8> {
+    let frequencies = AdaptiveDictionary { frequencies: histogram.iter().filter(|&(_, &count)| count > 0).map(|(&symbol, &count)| (symbol, count)).collect() };
+    let mut codes = BTreeMap::new();
+    if let Some(tree) = build_huffman_tree_seeded(&frequencies, 0) {
+        generate_huffman_codes(tree.as_ref(), &mut vec![], &mut codes);
+    }
+    code_lengths(&codes)
+}
+
+// Bits of Huffman code the encoder writes for symbols with `histogram`. A lone
+// symbol still costs one bit per occurrence.
+pub fn estimate_encoded_bits<S: Symbol>(histogram: &BTreeMap<S, u32>) -> u64 {
+    huffman_code_lengths(histogram).iter().map(|(symbol, &length)| histogram[symbol] as u64 * length as u64).sum()
+}
+
+// Size of the Huffman data compress returns for symbols with `histogram`: the bits
+// rounded up to whole bytes and the byte recording how many bits of the last one
+// are used, which a frame keeps in its header instead. Header, table, dictionary
+// and footer come on top.
+pub fn estimate_encoded_len<S: Symbol>(histogram: &BTreeMap<S, u32>) -> usize {
+    estimate_encoded_bits(histogram).div_ceil(8) as usize + 1
+}
+
+// Shannon entropy of `histogram` in bits per symbol, the least any code can spend
+pub fn entropy<S: Symbol>(histogram: &BTreeMap<S, u32>) -> f64 {
+    entropy_of_counts(histogram.values().map(|&count| count as u64))
+}
+
+// Shannon entropy of the distribution with these counts, which analysis::entropy
+// takes from a byte histogram
+pub(crate) fn entropy_of_counts<I: Iterator<Item = u64> + Clone>(counts: I) -> f64 {
+    let total: u64 = counts.clone().sum();
+    counts.filter(|&count| count > 0).fold(0.0, |entropy, count| {
+        let probability = count as f64 / total as f64;
+        entropy - probability * probability.log2()
+    })
+}
+
+// Bits an ideal entropy coder spends on a symbol seen `count` times among `total`.
+// The optimal parser prices literals and pattern codes with this.
+pub fn symbol_bits(count: u64, total: u64) -> f64 {
+    (total.max(1) as f64 / count.max(1) as f64).log2()
+}
@@ -0,0 +1,212 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::compression::TrailingData;
+use crate::huffman::DecodeError;
+
+// Everything that can go wrong while compressing or decoding. Malformed input is
+// always reported through one of these, never by panicking, so the decoder can be
+// pointed at untrusted files.
+#[derive(Debug)]
+pub enum QuantumPackError {
+    // Reading the input or writing the output failed
+    Io(io::Error),
+    // The input ends inside the named structure, which starts at `offset`
+    Truncated { what: &'static str, offset: u64 },
+    // A length-prefixed field in the named structure has a varint longer than 10 bytes
+    MalformedVarint(&'static str),
+    // The input does not start with the QPK1 magic
+    NotAFrame,
+    // The frame was written by a newer format version
+    UnsupportedVersion(u8),
+    // The header or footer of the frame at `offset` is not one this version writes
+    CorruptHeader { message: String, offset: u64 },
+    InvalidDictionary(String),
+    // The frame was compressed with the preset dictionary `expected` (see
+    // TrainedDictionary::preset_id) and the decoder was given `found` or none
+    DictionaryMismatch { expected: u32, found: Option<u32> },
+    InvalidStage(String),
+    InvalidPage(String),
+    InvalidArchive(String),
+    // The archive has no entry with this path
+    EntryNotFound(String),
+    // A profile definition could not be parsed, with the 1-based line number
+    InvalidProfile { line: usize, message: String },
+    // A message catalog could not be parsed, with the 1-based line number
+    InvalidCatalog { line: usize, message: String },
+    // The Huffman data is malformed
+    Huffman(DecodeError),
+    // The frame decodes to more than Decompressor::max_output_size allows
+    OutputLimitExceeded { limit: usize },
+    // The data decoded from the frame at `offset` does not match the length and
+    // CRC-32 in its footer
+    ChecksumMismatch { offset: u64 },
+    // The frame is followed by more bytes and the policy is TrailingDataPolicy::Strict
+    TrailingData(TrailingData),
+    // The compressed data does not fit the u32 size fields of a frame
+    InputTooLarge,
+    // The caller passed something the operation cannot accept, e.g. an oversized page
+    InvalidInput(String),
+}
+
+impl fmt::Display for QuantumPackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QuantumPackError::Io(error) => write!(f, "{}", error),
+            QuantumPackError::Truncated { what, .. } => write!(f, "{} is truncated", what),
+            QuantumPackError::MalformedVarint(what) => write!(f, "{} has a malformed varint", what),
+            QuantumPackError::NotAFrame => write!(f, "not a quantum-pack frame (missing QPK1 magic)"),
+            QuantumPackError::UnsupportedVersion(version) => write!(f, "unsupported format version {}", version),
+            QuantumPackError::CorruptHeader { message, .. } => write!(f, "corrupt frame header: {}", message),
+            QuantumPackError::InvalidDictionary(message) => write!(f, "invalid dictionary: {}", message),
+            QuantumPackError::DictionaryMismatch { expected, found: Some(found) } => {
+                write!(f, "frame needs preset dictionary {:08x}, not {:08x}", expected, found)
+            }
+            QuantumPackError::DictionaryMismatch { expected, found: None } => write!(f, "frame needs preset dictionary {:08x}", expected),
+            QuantumPackError::InvalidStage(message) => write!(f, "invalid stage: {}", message),
+            QuantumPackError::InvalidPage(message) => write!(f, "invalid page: {}", message),
+            QuantumPackError::InvalidArchive(message) => write!(f, "invalid archive: {}", message),
+            QuantumPackError::EntryNotFound(path) => write!(f, "{} is not in the archive", path),
+            QuantumPackError::InvalidProfile { line, message } => write!(f, "invalid profile on line {}: {}", line, message),
+            QuantumPackError::InvalidCatalog { line, message } => write!(f, "invalid message catalog on line {}: {}", line, message),
+            QuantumPackError::Huffman(error) => write!(f, "invalid Huffman data: {}", error),
+            QuantumPackError::OutputLimitExceeded { limit } => write!(f, "decoded data exceeds the limit of {} bytes", limit),
+            QuantumPackError::ChecksumMismatch { .. } => write!(f, "decoded data does not match the checksum in the footer"),
+            QuantumPackError::TrailingData(trailing) => write!(
+                f,
+                "{} unexpected bytes after the end of the frame at offset {}",
+                trailing.len, trailing.offset
+            ),
+            QuantumPackError::InputTooLarge => write!(f, "input is too large for a single frame"),
+            QuantumPackError::InvalidInput(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl QuantumPackError {
+    // Stable name of the variant, for tools that match on failures instead of the message
+    pub fn kind(&self) -> &'static str {
+        match self {
+            QuantumPackError::Io(_) => "io",
+            QuantumPackError::Truncated { .. } => "truncated",
+            QuantumPackError::MalformedVarint(_) => "malformed_varint",
+            QuantumPackError::NotAFrame => "not_a_frame",
+            QuantumPackError::UnsupportedVersion(_) => "unsupported_version",
+            QuantumPackError::CorruptHeader { .. } => "corrupt_header",
+            QuantumPackError::InvalidDictionary(_) => "invalid_dictionary",
+            QuantumPackError::DictionaryMismatch { .. } => "dictionary_mismatch",
+            QuantumPackError::InvalidStage(_) => "invalid_stage",
+            QuantumPackError::InvalidPage(_) => "invalid_page",
+            QuantumPackError::InvalidArchive(_) => "invalid_archive",
+            QuantumPackError::EntryNotFound(_) => "entry_not_found",
+            QuantumPackError::InvalidProfile { .. } => "invalid_profile",
+            QuantumPackError::InvalidCatalog { .. } => "invalid_catalog",
+            QuantumPackError::Huffman(_) => "huffman",
+            QuantumPackError::OutputLimitExceeded { .. } => "output_limit_exceeded",
+            QuantumPackError::ChecksumMismatch { .. } => "checksum_mismatch",
+            QuantumPackError::TrailingData(_) => "trailing_data",
+            QuantumPackError::InputTooLarge => "input_too_large",
+            QuantumPackError::InvalidInput(_) => "invalid_input",
+        }
+    }
+
+    // Byte offset in the input the error points at, where one is known: the start of
+    // the frame or other structure at fault, or the first trailing byte
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            QuantumPackError::Truncated { offset, .. } | QuantumPackError::CorruptHeader { offset, .. } | QuantumPackError::ChecksumMismatch { offset } => Some(*offset),
+            QuantumPackError::TrailingData(trailing) => Some(trailing.offset),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn truncated(what: &'static str) -> Self {
+        QuantumPackError::Truncated { what, offset: 0 }
+    }
+
+    pub(crate) fn corrupt_header(message: impl Into<String>) -> Self {
+        QuantumPackError::CorruptHeader { message: message.into(), offset: 0 }
+    }
+
+    // The error for a structure found `start` bytes into the input, e.g. the second
+    // frame of a concatenated file, whose offset was counted from the structure
+    pub(crate) fn at(mut self, start: u64) -> Self {
+        match &mut self {
+            QuantumPackError::Truncated { offset, .. } | QuantumPackError::CorruptHeader { offset, .. } | QuantumPackError::ChecksumMismatch { offset } => {
+                *offset = offset.saturating_add(start);
+            }
+            QuantumPackError::TrailingData(trailing) => trailing.offset = trailing.offset.saturating_add(start),
+            _ => {}
+        }
+        self
+    }
+
+    // The error as one line of JSON with the fields code, kind, message, path and
+    // offset. `code` names the operation that failed and `path` the file it was
+    // working on; both are up to the caller, e.g. `qp --error-format json`.
+    pub fn to_json(&self, code: &str, path: Option<&str>) -> String {
+        let path = path.map_or_else(|| "null".to_string(), |path| format!("\"{}\"", escape_json(path)));
+        let offset = self.offset().map_or_else(|| "null".to_string(), |offset| offset.to_string());
+        format!(
+            "{{\"code\":\"{}\",\"kind\":\"{}\",\"message\":\"{}\",\"path\":{},\"offset\":{}}}",
+            escape_json(code),
+            self.kind(),
+            escape_json(&self.to_string()),
+            path,
+            offset,
+        )
+    }
+}
+
+impl Error for QuantumPackError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            QuantumPackError::Io(error) => Some(error),
+            QuantumPackError::Huffman(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for QuantumPackError {
+    fn from(error: io::Error) -> Self {
+        QuantumPackError::Io(error)
+    }
+}
+
+impl From<DecodeError> for QuantumPackError {
+    fn from(error: DecodeError) -> Self {
+        match error {
+            DecodeError::OutputLimitExceeded { limit } => QuantumPackError::OutputLimitExceeded { limit },
+            error => QuantumPackError::Huffman(error),
+        }
+    }
+}
+
+// For the Read and Write adapters, which have to report io::Error
+impl From<QuantumPackError> for io::Error {
+    fn from(error: QuantumPackError) -> Self {
+        match error {
+            QuantumPackError::Io(error) => error,
+            QuantumPackError::InvalidInput(_) | QuantumPackError::InputTooLarge => io::Error::new(io::ErrorKind::InvalidInput, error),
+            error => io::Error::new(io::ErrorKind::InvalidData, error),
+        }
+    }
+}
+
+pub(crate) fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,58 @@
+use crate::compression::{frame_extent, frame_parts, Compressor};
+use crate::error::QuantumPackError;
+use crate::preprocessor::TrainedDictionary;
+
+// Comparing candidate dictionaries on held-out samples before rolling one out. Each
+// sample is compressed on its own with the dictionary as a preset, the way a
+// service compressing message by message would use it.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalReport {
+    // Compressed over original size per sample, in the order given
+    pub ratios: Vec<f64>,
+    pub mean_ratio: f64,
+    // Population variance of the ratios; high when the dictionary suits some
+    // samples much better than others
+    pub ratio_variance: f64,
+    // Mean bytes per sample besides the coded data: header, Huffman table, padding and
+    // footer of each of its frames
+    pub mean_overhead: f64,
+    // What a frame carrying the dictionary instead of its preset ID would add
+    pub dictionary_len: usize,
+}
+
+impl Compressor {
+    // Compress every holdout sample with `dictionary` as a preset and these settings
+    pub fn evaluate_dictionary(&self, dictionary: &TrainedDictionary, holdout: &[&[u8]]) -> Result<EvalReport, QuantumPackError> {
+        let compressor = self.clone().preset_dictionary(dictionary.clone());
+        let mut ratios = Vec::with_capacity(holdout.len());
+        let mut overhead = 0;
+        let mut frame = Vec::new();
+        for sample in holdout {
+            frame.clear();
+            compressor.compress_shared(sample, &mut frame)?;
+            overhead += frame.len() - data_len(&frame)?;
+            ratios.push(frame.len() as f64 / sample.len().max(1) as f64);
+        }
+        let count = ratios.len().max(1) as f64;
+        let mean_ratio = ratios.iter().sum::<f64>() / count;
+        let ratio_variance = ratios.iter().map(|ratio| (ratio - mean_ratio).powi(2)).sum::<f64>() / count;
+        Ok(EvalReport { ratios, mean_ratio, ratio_variance, mean_overhead: overhead as f64 / count, dictionary_len: dictionary.serialize().len() })
+    }
+}
+
+// Length of the coded data in `frames`, more than one with a block size
+fn data_len(mut frames: &[u8]) -> Result<usize, QuantumPackError> {
+    let mut len = 0;
+    while !frames.is_empty() {
+        let (frame_len, _) = frame_extent(frames)?;
+        len += frame_parts(&frames[..frame_len])?.data.len();
+        frames = &frames[frame_len..];
+    }
+    Ok(len)
+}
+
+// evaluate_dictionary with the default settings
+pub fn evaluate_dictionary(dictionary: &TrainedDictionary, holdout: &[&[u8]]) -> Result<EvalReport, QuantumPackError> {
+    Compressor::new().evaluate_dictionary(dictionary, holdout)
+}
@@ -0,0 +1,51 @@
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::compression::FileMetadata;
+
+// The metadata recorded for a file. Times before the epoch are recorded as the epoch.
+pub(crate) fn of(metadata: &fs::Metadata) -> io::Result<FileMetadata> {
+    let mtime = metadata.modified()?.duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0);
+    Ok(FileMetadata { mtime, mode: mode(&metadata.permissions()) })
+}
+
+#[cfg(unix)]
+fn mode(permissions: &fs::Permissions) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    permissions.mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode(permissions: &fs::Permissions) -> u32 {
+    if permissions.readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+// Give the output file at `path` the recorded metadata
+pub(crate) fn restore(path: &str, metadata: &FileMetadata) -> io::Result<()> {
+    // The time first, the file may not be writable once its mode is restored
+    let file = OpenOptions::new().write(true).open(path)?;
+    file.set_modified(UNIX_EPOCH + Duration::from_secs(metadata.mtime))?;
+    let mut permissions = file.metadata()?.permissions();
+    set_mode(&mut permissions, metadata.mode);
+    drop(file);
+    fs::set_permissions(Path::new(path), permissions)
+}
+
+// Only the permission bits: setuid, setgid and sticky bits from an archive someone
+// else made are not restored
+#[cfg(unix)]
+fn set_mode(permissions: &mut fs::Permissions, mode: u32) {
+    use std::os::unix::fs::PermissionsExt;
+    permissions.set_mode(mode & 0o777);
+}
+
+#[cfg(not(unix))]
+fn set_mode(permissions: &mut fs::Permissions, mode: u32) {
+    permissions.set_readonly(mode & 0o222 == 0);
+}
@@ -0,0 +1,625 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::archive::{self, Archive, ArchiveEntry};
+use crate::compression::{frame_metadata, Compressor, Decompressor, FileMetadata, TrailingData};
+use crate::convert::{self, Format};
+use crate::error::QuantumPackError;
+use crate::messages::Catalog;
+use crate::profile::Profiles;
+use crate::progress::report;
+use crate::sniff::{sniff, CompressedFormat, SNIFF_LEN};
+use crate::stream;
+use crate::throttle::Throttled;
+use crate::warning::{warn, Warning};
+
+mod metadata;
+mod output;
+
+pub use output::{OutputFactory, OutputPolicy};
+use output::PendingOutput;
+
+// Filesystem helpers. Everything below this layer works on byte slices and never
+// opens files, prints or sleeps, so the core also runs where there is no
+// filesystem (e.g. WASM) and can be tested without touching the disk.
+//
+// Output files are created under an OutputPolicy and removed again when producing
+// them fails, so an error never leaves a truncated file at the output path.
+//
+// With Compressor::preserve_metadata, compressing a file records its modification
+// time and permissions in the frame, and decompressing restores them unless turned
+// off with Decompressor::preserve_metadata.
+
+impl Compressor {
+    // Compress a file. With a block size the file is read and compressed a block at a
+    // time, so it may be larger than memory; see compress_to. Files that are
+    // compressed already are stored, see detect_compressed.
+    pub fn compress_file(&self, input_path: &str, output_path: &str) -> Result<(), QuantumPackError> {
+        let mut input = File::open(input_path)?;
+        if !self.skip_detection && !self.store {
+            if let Some(format) = sniff_file(&mut input)? {
+                warn(&self.warnings, Warning::AlreadyCompressed { format });
+                let mut compressor = self.clone();
+                compressor.store = true;
+                return compressor.compress_opened(input, output_path);
+            }
+        }
+        self.compress_opened(input, output_path)
+    }
+
+    fn compress_opened(&self, input: File, output_path: &str) -> Result<(), QuantumPackError> {
+        let metadata = if self.record_metadata { Some(metadata::of(&input.metadata()?)?) } else { None };
+        #[cfg(feature = "mmap")]
+        {
+            if let Some(frame) = self.compress_mapped(&input, metadata)? {
+                return write_output(output_path, &self.output_policy, None, &frame);
+            }
+        }
+        let mut output = PendingOutput::create(output_path, &self.output_policy)?;
+        match self.bwlimit {
+            Some(limit) => self.compress_stream(Throttled::new(input, limit), metadata, Throttled::new(&mut output, limit))?,
+            None => self.compress_stream(input, metadata, &mut output)?,
+        }
+        output.commit()?;
+        Ok(())
+    }
+
+    // Compress a file straight from a memory map, so a multi-gigabyte input is never
+    // copied into a Vec before pattern identification and transformation. The file
+    // must not be truncated while it is being compressed: on Unix that ends the
+    // process with SIGBUS. None when the file is better read, see below.
+    #[cfg(feature = "mmap")]
+    fn compress_mapped(&self, input: &File, metadata: Option<FileMetadata>) -> Result<Option<Vec<u8>>, QuantumPackError> {
+        // Blocks are already read one at a time, throttled input has to go through
+        // Read, empty files cannot be mapped, and devices or FIFOs have no length
+        let info = input.metadata()?;
+        if self.block_size.is_some() || self.bwlimit.is_some() || !info.is_file() || info.len() == 0 {
+            return Ok(None);
+        }
+        // Safety: see above. Writes by other processes only change what gets compressed.
+        let map = unsafe { memmap2::Mmap::map(input)? };
+        let mut frame = Vec::new();
+        self.compress_with_metadata(&map, metadata, &mut frame)?;
+        report(&self.progress, map.len() as u64, frame.len() as u64);
+        Ok(Some(frame))
+    }
+
+    // Compress everything read from `input` to `output`, e.g. stdin to stdout. With a
+    // block size each block is written as soon as it is compressed and memory use
+    // stays at about two blocks however long the input is; if compression fails,
+    // the frames of the earlier blocks have already been written. Without one the
+    // whole input is read into a single frame before anything is written.
+    pub fn compress_to<R: Read, W: Write>(&self, input: R, output: W) -> Result<(), QuantumPackError> {
+        match self.bwlimit {
+            Some(limit) => self.compress_stream(Throttled::new(input, limit), None, Throttled::new(output, limit)),
+            None => self.compress_stream(input, None, output),
+        }
+    }
+
+    // The frames compress_with_metadata would produce, one block in memory at a time.
+    // The next block is read before a frame is written to know whether it continues.
+    fn compress_stream<R: Read, W: Write>(&self, mut input: R, mut metadata: Option<FileMetadata>, mut output: W) -> Result<(), QuantumPackError> {
+        let block_size = self.block_size.unwrap_or(usize::MAX) as u64;
+        let mut block = Vec::new();
+        let mut next = Vec::new();
+        let mut frame = Vec::new();
+        let mut written = 0;
+        let mut consumed = 0;
+        (&mut input).take(block_size).read_to_end(&mut block)?;
+        for index in 0.. {
+            next.clear();
+            (&mut input).take(block_size).read_to_end(&mut next)?;
+            frame.clear();
+            self.compress_block(&block, metadata.take(), !next.is_empty(), &mut frame)?;
+            self.check_expansion(index, block.len(), frame.len());
+            if next.is_empty() {
+                self.pad_output(&mut frame, 0, written)?;
+            }
+            output.write_all(&frame)?;
+            written += frame.len();
+            consumed += block.len();
+            report(&self.progress, consumed as u64, written as u64);
+            if next.is_empty() {
+                break;
+            }
+            std::mem::swap(&mut block, &mut next);
+        }
+        output.flush()?;
+        Ok(())
+    }
+
+    // Re-encode a compressed file with these settings, see stream::recompress. The
+    // output is removed again if the input turns out to be corrupt. The input is read
+    // as the output is written, so with OutputPolicy::InPlace they must differ.
+    pub fn recompress_file(&self, input_path: &str, output_path: &str) -> Result<(), QuantumPackError> {
+        self.recompress_file_with(input_path, output_path, &self.decompressor())
+    }
+
+    // recompress_file, decoding with `decompressor`, see stream::recompress_with
+    pub fn recompress_file_with(&self, input_path: &str, output_path: &str, decompressor: &Decompressor) -> Result<(), QuantumPackError> {
+        if matches!(self.output_policy, OutputPolicy::InPlace) && same_file(input_path, output_path) {
+            return Err(QuantumPackError::InvalidInput(format!("{} cannot be recompressed in place onto itself", input_path)));
+        }
+        let input = BufReader::new(File::open(input_path)?);
+        let mut output = PendingOutput::create(output_path, &self.output_policy)?;
+        stream::recompress_with(input, &mut output, self, decompressor)?;
+        output.commit()?;
+        Ok(())
+    }
+}
+
+// Whether both paths exist and name the same file
+fn same_file(first: &str, second: &str) -> bool {
+    match (fs::canonicalize(first), fs::canonicalize(second)) {
+        (Ok(first), Ok(second)) => first == second,
+        _ => false,
+    }
+}
+
+// The compressed format of a regular file, read from its start. Leaves the file at
+// its start again.
+fn sniff_file(input: &mut File) -> Result<Option<CompressedFormat>, QuantumPackError> {
+    if !input.metadata()?.is_file() {
+        return Ok(None);
+    }
+    let mut head = Vec::new();
+    (&mut *input).take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+    input.seek(SeekFrom::Start(0))?;
+    Ok(sniff(&head))
+}
+
+// Compress a file
+pub fn compress_file(input_path: &str, output_path: &str) -> Result<(), QuantumPackError> {
+    Compressor::new().compress_file(input_path, output_path)
+}
+
+// Compress a file, limiting both reading and writing to `bytes_per_sec`
+pub fn compress_file_throttled(input_path: &str, output_path: &str, bytes_per_sec: u64) -> Result<(), QuantumPackError> {
+    Compressor::new().bwlimit(bytes_per_sec).compress_file(input_path, output_path)
+}
+
+// Decoded data, trailing data and the metadata of the first frame, as read by decompress_input
+type Decoded = (Vec<u8>, Option<TrailingData>, Option<FileMetadata>);
+
+impl Decompressor {
+    pub fn decompress_file(&self, input_path: &str, output_path: &str) -> Result<Option<TrailingData>, QuantumPackError> {
+        let input = File::open(input_path)?;
+        let (decompressed, trailing, metadata) = match self.bwlimit {
+            Some(limit) => self.decompress_input(Throttled::new(input, limit))?,
+            None => self.decompress_input(input)?,
+        };
+        write_output(output_path, &self.output_policy, self.bwlimit, &decompressed)?;
+        self.restore_metadata(output_path, metadata);
+        Ok(trailing)
+    }
+
+    // Recover what can be decoded of a damaged file, see stream::salvage. The output
+    // file is written even if every frame is damaged.
+    pub fn salvage_file(&self, input_path: &str, output_path: &str) -> Result<stream::Salvaged, QuantumPackError> {
+        let mut contents = Vec::new();
+        File::open(input_path)?.read_to_end(&mut contents)?;
+        let salvaged = stream::salvage_with(&contents, self);
+        write_output(output_path, &self.output_policy, self.bwlimit, &salvaged.data)?;
+        Ok(salvaged)
+    }
+
+    // Decode the frame read from `input` into `output`, e.g. stdin to stdout. Nothing
+    // is written unless the whole frame decodes.
+    pub fn decompress_to<R: Read, W: Write>(&self, input: R, output: W) -> Result<Option<TrailingData>, QuantumPackError> {
+        let (decompressed, trailing, _) = match self.bwlimit {
+            Some(limit) => self.decompress_input(Throttled::new(input, limit))?,
+            None => self.decompress_input(input)?,
+        };
+        match self.bwlimit {
+            Some(limit) => write_all(Throttled::new(output, limit), &decompressed)?,
+            None => write_all(output, &decompressed)?,
+        }
+        Ok(trailing)
+    }
+
+    fn decompress_input<R: Read>(&self, mut input: R) -> Result<Decoded, QuantumPackError> {
+        let mut combined_contents = Vec::new();
+        input.read_to_end(&mut combined_contents)?;
+        let (decompressed, trailing) = self.decompress_reporting(&combined_contents, &self.progress)?;
+        Ok((decompressed, trailing, frame_metadata(&combined_contents)?))
+    }
+
+    // Give the output the recorded metadata. Failing to do so only warrants a
+    // warning; the data itself was written.
+    fn restore_metadata(&self, output_path: &str, metadata: Option<FileMetadata>) {
+        let metadata = match metadata {
+            Some(metadata) if !self.skip_metadata => metadata,
+            _ => return,
+        };
+        let result = match self.output_policy {
+            OutputPolicy::Custom(_) => Err("the output is not a file".to_string()),
+            _ => metadata::restore(output_path, &metadata).map_err(|error| error.to_string()),
+        };
+        if let Err(reason) = result {
+            warn(&self.warnings, Warning::MetadataNotRestored { path: output_path.to_string(), reason });
+        }
+    }
+}
+
+// Join compressed files into one that decodes to their concatenated contents,
+// without decompressing and recompressing them
+pub fn concat_files(input_paths: &[&str], output_path: &str) -> Result<(), QuantumPackError> {
+    let mut inputs = Vec::new();
+    for path in input_paths {
+        let mut contents = Vec::new();
+        File::open(path)?.read_to_end(&mut contents)?;
+        inputs.push(contents);
+    }
+    let mut output = Vec::new();
+    stream::concat(&inputs.iter().map(Vec::as_slice).collect::<Vec<_>>(), &mut output)?;
+    write_output(output_path, &OutputPolicy::default(), None, &output)
+}
+
+// Convert a gzip, zstd or quantum-pack file to the format its output name implies,
+// see convert::convert. Quantum-pack output uses `compressor`, and the output file
+// is created under its output policy.
+pub fn convert_file(input_path: &str, output_path: &str, compressor: &Compressor) -> Result<(), QuantumPackError> {
+    let mut contents = Vec::new();
+    File::open(input_path)?.read_to_end(&mut contents)?;
+    let converted = convert::convert(&contents, Format::from_path(output_path), compressor)?;
+    write_output(output_path, &compressor.output_policy, None, &converted)
+}
+
+// Pack files into a .qpa archive, see archive::pack. Directories are added with
+// everything below them. Entries are named after the input paths, without any
+// leading '/' or "./", so "/var/log" extracts to "<output dir>/var/log".
+pub fn create_archive(input_paths: &[&str], output_path: &str, compressor: &Compressor) -> Result<(), QuantumPackError> {
+    let files = read_inputs(input_paths, compressor.bwlimit, None)?;
+    let archive = archive::pack_with_metadata(&entry_list(&files), &entry_metadata(&files, compressor), compressor)?;
+    write_output(output_path, &compressor.output_policy, compressor.bwlimit, &archive)
+}
+
+// Like create_archive, but inputs that cannot be read are left out and reported
+// instead of failing the whole archive
+pub fn create_archive_keep_going(input_paths: &[&str], output_path: &str, compressor: &Compressor) -> Result<BatchReport, QuantumPackError> {
+    let mut report = BatchReport::default();
+    let files = read_inputs(input_paths, compressor.bwlimit, Some(&mut report.failed))?;
+    let archive = archive::pack_with_metadata(&entry_list(&files), &entry_metadata(&files, compressor), compressor)?;
+    write_output(output_path, &compressor.output_policy, compressor.bwlimit, &archive)?;
+    report.succeeded = files.into_iter().map(|(name, _, _)| name).collect();
+    Ok(report)
+}
+
+// Pack files into a split archive: `output_path` gets the frames, which later
+// appends only ever add to, and the entry table goes to the index file next to it,
+// see index_path. Entries are named like create_archive names them.
+pub fn create_split_archive(input_paths: &[&str], output_path: &str, compressor: &Compressor) -> Result<(), QuantumPackError> {
+    let files = read_inputs(input_paths, compressor.bwlimit, None)?;
+    let mut data = archive::split_header();
+    let (records, entries) = archive::append_split(&[], data.len() as u64, &entry_list(&files), &entry_metadata(&files, compressor), compressor)?;
+    data.extend_from_slice(&records);
+    write_output(output_path, &compressor.output_policy, compressor.bwlimit, &data)?;
+    write_output(&index_path(output_path), &compressor.output_policy, None, &archive::write_index(&entries, data.len() as u64))
+}
+
+// The index file of the split archive at `archive_path`: the same path with a .qpi
+// extension, e.g. logs.qpa has its entry table in logs.qpi
+pub fn index_path(archive_path: &str) -> String {
+    Path::new(archive_path).with_extension(archive::INDEX_EXTENSION).to_string_lossy().into_owned()
+}
+
+// Rebuild the index file of a split archive from its data file, e.g. after it was
+// lost or damaged, or when it was not copied along. Returns the entries.
+pub fn reindex_archive(archive_path: &str) -> Result<Vec<ArchiveEntry>, QuantumPackError> {
+    let entries = archive::read_records(BufReader::new(File::open(archive_path)?), 0)?;
+    let data_len = fs::metadata(archive_path)?.len();
+    write_output(&index_path(archive_path), &OutputPolicy::default(), None, &archive::write_index(&entries, data_len))?;
+    Ok(entries)
+}
+
+// Outcome of an operation on many files that carries on past failures. Errors that
+// stop the whole operation, e.g. an unreadable archive, are still returned as Err.
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    // Entry names that were processed, in order
+    pub succeeded: Vec<String>,
+    pub failed: Vec<BatchFailure>,
+}
+
+#[derive(Debug)]
+pub struct BatchFailure {
+    // The input path or entry name that failed
+    pub path: String,
+    pub error: QuantumPackError,
+}
+
+impl BatchReport {
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+// Add files to an existing .qpa archive, naming them like create_archive does. Only
+// the entry table at the end is rewritten, so the archive is always modified in
+// place whatever the output policy; if writing fails the old table is put back.
+// A split archive's data file is only appended to, and its index rewritten.
+pub fn append_archive(archive_path: &str, input_paths: &[&str], compressor: &Compressor) -> Result<(), QuantumPackError> {
+    let mut archive = fs::OpenOptions::new().read(true).write(true).open(archive_path)?;
+    if archive::is_split(&mut archive)? {
+        return append_split_archive(archive_path, archive, input_paths, compressor);
+    }
+    archive.seek(SeekFrom::Start(0))?;
+    let entries = archive::read_entries(BufReader::new(&mut archive))?;
+    let files = read_inputs(input_paths, compressor.bwlimit, None)?;
+    let (offset, tail) = archive::append_with_metadata(&entries, &entry_list(&files), &entry_metadata(&files, compressor), compressor)?;
+
+    let mut old_tail = Vec::new();
+    archive.seek(SeekFrom::Start(offset))?;
+    archive.read_to_end(&mut old_tail)?;
+    let result = replace_tail(&mut archive, offset, &tail).map_err(QuantumPackError::from).and_then(|()| archive::upgrade_legacy(&mut archive));
+    if result.is_err() {
+        let _ = replace_tail(&mut archive, offset, &old_tail);
+    }
+    result
+}
+
+// If writing the new records fails, the data file is cut back to its old length.
+// The index is written under the output policy once they are safely on disk;
+// should that fail, the new entries are still found when the archive is read.
+fn append_split_archive(archive_path: &str, mut archive: File, input_paths: &[&str], compressor: &Compressor) -> Result<(), QuantumPackError> {
+    let mut entries = split_entries(archive_path, &mut archive)?;
+    let data_len = archive.seek(SeekFrom::End(0))?;
+    let files = read_inputs(input_paths, compressor.bwlimit, None)?;
+    let (records, added) = archive::append_split(&entries, data_len, &entry_list(&files), &entry_metadata(&files, compressor), compressor)?;
+    let result = archive.write_all(&records).and_then(|()| archive.sync_all());
+    if result.is_err() {
+        let _ = archive.set_len(data_len);
+    }
+    result?;
+    entries.extend(added);
+    write_output(&index_path(archive_path), &compressor.output_policy, None, &archive::write_index(&entries, data_len + records.len() as u64))
+}
+
+// The entries of the archive open as `input`, from its index if it is split
+fn archive_entries<R: Read + Seek>(archive_path: &str, mut input: R) -> Result<Vec<ArchiveEntry>, QuantumPackError> {
+    if archive::is_split(&mut input)? {
+        return split_entries(archive_path, input);
+    }
+    input.seek(SeekFrom::Start(0))?;
+    archive::read_entries(input)
+}
+
+// The entries of a split archive. The index covers the data file as it was when the
+// index was written; records appended since are read from the data file. Without an
+// index, or with one that is damaged or belongs to another data file, every record
+// is.
+fn split_entries<R: Read + Seek>(archive_path: &str, mut input: R) -> Result<Vec<ArchiveEntry>, QuantumPackError> {
+    let index = match fs::read(index_path(archive_path)) {
+        Ok(index) => Some(index),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+        Err(error) => return Err(error.into()),
+    };
+    if let Some(index) = index {
+        let indexed = archive::read_index(&index).and_then(|(covered, mut entries)| {
+            entries.extend(archive::read_records(&mut input, covered)?);
+            Ok(entries)
+        });
+        if let Ok(entries) = indexed {
+            return Ok(entries);
+        }
+    }
+    archive::read_records(input, 0)
+}
+
+fn replace_tail(file: &mut File, offset: u64, tail: &[u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(tail)?;
+    file.set_len(offset + tail.len() as u64)?;
+    file.sync_all()
+}
+
+type InputFile = (String, Vec<u8>, FileMetadata);
+
+// Entry names, contents and metadata of the files at `input_paths`, see collect_files.
+// With `failed`, inputs that cannot be read are recorded there and skipped. With
+// `bwlimit`, each file is read at most that many bytes per second.
+fn read_inputs(input_paths: &[&str], bwlimit: Option<u64>, mut failed: Option<&mut Vec<BatchFailure>>) -> Result<Vec<InputFile>, QuantumPackError> {
+    let mut skip = |path: &Path, error: QuantumPackError| match failed.as_mut() {
+        Some(failed) => {
+            failed.push(BatchFailure { path: path.display().to_string(), error });
+            Ok(())
+        }
+        None => Err(error),
+    };
+    let mut paths = Vec::new();
+    for path in input_paths {
+        let mut found = Vec::new();
+        match collect_files(Path::new(path), &mut found) {
+            Ok(()) => paths.extend(found),
+            Err(error) => skip(Path::new(path), error)?,
+        }
+    }
+    let mut files = Vec::new();
+    for (name, path) in paths {
+        let read = || -> Result<InputFile, QuantumPackError> {
+            let file = File::open(&path)?;
+            let metadata = metadata::of(&file.metadata()?)?;
+            let mut contents = Vec::new();
+            match bwlimit {
+                Some(limit) => Throttled::new(file, limit).read_to_end(&mut contents)?,
+                None => (&file).read_to_end(&mut contents)?,
+            };
+            Ok((name.clone(), contents, metadata))
+        };
+        match read() {
+            Ok(file) => files.push(file),
+            Err(error) => skip(&path, error)?,
+        }
+    }
+    Ok(files)
+}
+
+fn entry_list(files: &[InputFile]) -> Vec<(&str, &[u8])> {
+    files.iter().map(|(name, contents, _)| (name.as_str(), contents.as_slice())).collect()
+}
+
+fn entry_metadata(files: &[InputFile], compressor: &Compressor) -> Vec<FileMetadata> {
+    if !compressor.record_metadata {
+        return Vec::new();
+    }
+    files.iter().map(|(_, _, metadata)| *metadata).collect()
+}
+
+// Entry names and paths of `path` and, for a directory, the files below it in name
+// order. Symbolic links to directories below `path` are skipped, so a link back up
+// the tree cannot make the walk go on forever; links to files are followed.
+fn collect_files(path: &Path, files: &mut Vec<(String, PathBuf)>) -> Result<(), QuantumPackError> {
+    if path.is_dir() {
+        let mut children = fs::read_dir(path)?.map(|entry| entry.map(|entry| entry.path())).collect::<io::Result<Vec<_>>>()?;
+        children.sort();
+        for child in children {
+            if fs::symlink_metadata(&child)?.file_type().is_symlink() && child.is_dir() {
+                continue;
+            }
+            collect_files(&child, files)?;
+        }
+        return Ok(());
+    }
+    let mut components = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => components.push(name.to_str().ok_or_else(|| {
+                QuantumPackError::InvalidInput(format!("{} is not valid UTF-8", path.display()))
+            })?),
+            Component::ParentDir => {
+                return Err(QuantumPackError::InvalidInput(format!("{} refers to a parent directory", path.display())));
+            }
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+        }
+    }
+    files.push((components.join("/"), path.to_path_buf()));
+    Ok(())
+}
+
+// Extract every file of a .qpa archive below `output_dir`, creating directories as
+// needed. Returns the entry paths in archive order.
+pub fn extract_archive(archive_path: &str, output_dir: &str, decompressor: &Decompressor) -> Result<Vec<String>, QuantumPackError> {
+    let data = fs::read(archive_path)?;
+    let archive = read_archive(archive_path, &data)?;
+    let mut extracted = Vec::new();
+    for entry in archive.entries() {
+        extract_entry(&archive, entry, output_dir, decompressor)?;
+        extracted.push(entry.path().to_string());
+    }
+    Ok(extracted)
+}
+
+// Like extract_archive, but entries that fail to decode or write are reported and
+// the rest are still extracted
+pub fn extract_archive_keep_going(archive_path: &str, output_dir: &str, decompressor: &Decompressor) -> Result<BatchReport, QuantumPackError> {
+    let data = fs::read(archive_path)?;
+    let archive = read_archive(archive_path, &data)?;
+    let mut report = BatchReport::default();
+    for entry in archive.entries() {
+        match extract_entry(&archive, entry, output_dir, decompressor) {
+            Ok(()) => report.succeeded.push(entry.path().to_string()),
+            Err(error) => report.failed.push(BatchFailure { path: entry.path().to_string(), error }),
+        }
+    }
+    Ok(report)
+}
+
+// The archive read from `archive_path` into `data`, with the entries of its index if
+// it is split
+fn read_archive<'a>(archive_path: &str, data: &'a [u8]) -> Result<Archive<'a>, QuantumPackError> {
+    Archive::with_entries(data, archive_entries(archive_path, io::Cursor::new(data))?)
+}
+
+fn extract_entry(archive: &Archive, entry: &ArchiveEntry, output_dir: &str, decompressor: &Decompressor) -> Result<(), QuantumPackError> {
+    let contents = archive.read(entry, decompressor)?;
+    let path = Path::new(output_dir).join(entry.path());
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let path = path.to_str().ok_or_else(|| QuantumPackError::InvalidInput(format!("{} is not valid UTF-8", path.display())))?;
+    write_output(path, &decompressor.output_policy, decompressor.bwlimit, &contents)?;
+    decompressor.restore_metadata(path, archive.metadata(entry)?);
+    Ok(())
+}
+
+// Extract the single entry `entry_path` of a .qpa archive to `output_path`. Only the
+// entry table and that entry's frame are read.
+pub fn extract_archive_entry(archive_path: &str, entry_path: &str, output_path: &str, decompressor: &Decompressor) -> Result<(), QuantumPackError> {
+    let mut input = BufReader::new(File::open(archive_path)?);
+    let entries = archive_entries(archive_path, &mut input)?;
+    let entry = entries
+        .iter()
+        .find(|entry| entry.path() == entry_path)
+        .ok_or_else(|| QuantumPackError::EntryNotFound(entry_path.to_string()))?;
+
+    input.seek(SeekFrom::Start(entry.offset()))?;
+    let mut frame = Vec::new();
+    input.take(entry.compressed_len()).read_to_end(&mut frame)?;
+    let contents = archive::decode_entry(entry, &frame, decompressor)?;
+    write_output(output_path, &decompressor.output_policy, decompressor.bwlimit, &contents)?;
+    decompressor.restore_metadata(output_path, frame_metadata(&frame)?);
+    Ok(())
+}
+
+// The entry table of a .qpa archive. Only the table is read, not the frames.
+pub fn list_archive(archive_path: &str) -> Result<Vec<ArchiveEntry>, QuantumPackError> {
+    archive_entries(archive_path, BufReader::new(File::open(archive_path)?))
+}
+
+// Write `data` to `path` under `policy`, removing the output again if that fails
+fn write_output(path: &str, policy: &OutputPolicy, bwlimit: Option<u64>, data: &[u8]) -> Result<(), QuantumPackError> {
+    let mut output = PendingOutput::create(path, policy)?;
+    match bwlimit {
+        Some(limit) => write_all(Throttled::new(&mut output, limit), data)?,
+        None => write_all(&mut output, data)?,
+    }
+    output.commit()?;
+    Ok(())
+}
+
+fn write_all<W: Write>(mut output: W, data: &[u8]) -> Result<(), QuantumPackError> {
+    output.write_all(data)?;
+    output.flush()?;
+    Ok(())
+}
+
+// Decompress a file
+pub fn decompress_file(input_path: &str, output_path: &str) -> Result<(), QuantumPackError> {
+    Decompressor::new().decompress_file(input_path, output_path).map(|_| ())
+}
+
+// Decompress a file, limiting both reading and writing to `bytes_per_sec`
+pub fn decompress_file_throttled(input_path: &str, output_path: &str, bytes_per_sec: u64) -> Result<(), QuantumPackError> {
+    Decompressor::new().bwlimit(bytes_per_sec).decompress_file(input_path, output_path).map(|_| ())
+}
+
+// Read a user pattern list: one pattern per line, blank lines are ignored
+pub fn read_pattern_file(path: &str) -> io::Result<Vec<Vec<u8>>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut patterns = Vec::new();
+    for line in reader.split(b'\n') {
+        let mut line = line?;
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        if !line.is_empty() {
+            patterns.push(line);
+        }
+    }
+    Ok(patterns)
+}
+
+// Parse a message catalog file, see Catalog::parse for the format
+pub fn load_catalog(path: &str) -> Result<Catalog, QuantumPackError> {
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+    Catalog::parse(&text)
+}
+
+// Parse a profile file, see Profiles::parse for the format
+pub fn load_profiles(path: &str) -> Result<Profiles, QuantumPackError> {
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+    Profiles::parse(&text)
+}
@@ -0,0 +1,126 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// Opens the writer for an output path, for OutputPolicy::Custom
+pub type OutputFactory = Arc<dyn Fn(&Path) -> io::Result<Box<dyn Write + Send>> + Send + Sync>;
+
+// How the file helpers create their output files. With either file policy an
+// output that fails, or is abandoned by a panic, is removed again, so a partial
+// file is never left behind.
+#[derive(Clone, Default)]
+pub enum OutputPolicy {
+    // Write a temporary file next to the output, sync it and rename it over the
+    // output once complete. Readers see the old file or the whole new one.
+    #[default]
+    TempAndRename,
+    // Write the output file directly. Cheaper, but it exists half-written while
+    // the output is produced.
+    InPlace,
+    // Hand the output to a writer opened by the factory, e.g. for object storage.
+    // Cleaning up after a failure is up to the writer.
+    Custom(OutputFactory),
+}
+
+impl fmt::Debug for OutputPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputPolicy::TempAndRename => write!(f, "TempAndRename"),
+            OutputPolicy::InPlace => write!(f, "InPlace"),
+            OutputPolicy::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+// Distinguishes temporary files of threads writing the same output
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+enum Sink {
+    File {
+        // Taken by commit, so the handle is closed before the file is renamed
+        file: Option<BufWriter<File>>,
+        // The file being written, removed unless committed
+        written: PathBuf,
+        rename_to: Option<PathBuf>,
+    },
+    Custom(Box<dyn Write + Send>),
+}
+
+// An output being written under an OutputPolicy. It only becomes the output once
+// `commit` succeeds; dropping it before then removes what was written.
+pub(crate) struct PendingOutput {
+    sink: Sink,
+    committed: bool,
+}
+
+impl PendingOutput {
+    pub(crate) fn create(path: &str, policy: &OutputPolicy) -> io::Result<Self> {
+        let path = Path::new(path);
+        let sink = match policy {
+            OutputPolicy::TempAndRename => {
+                let temp = temp_path(path);
+                let file = File::create(&temp)?;
+                Sink::File { file: Some(BufWriter::new(file)), written: temp, rename_to: Some(path.to_path_buf()) }
+            }
+            OutputPolicy::InPlace => {
+                let file = File::create(path)?;
+                Sink::File { file: Some(BufWriter::new(file)), written: path.to_path_buf(), rename_to: None }
+            }
+            OutputPolicy::Custom(factory) => Sink::Custom(factory(path)?),
+        };
+        Ok(PendingOutput { sink, committed: false })
+    }
+
+    pub(crate) fn commit(mut self) -> io::Result<()> {
+        match &mut self.sink {
+            Sink::File { file, written, rename_to } => {
+                let file = file.take().expect("commit is only called once");
+                let file = file.into_inner().map_err(|error| error.into_error())?;
+                if let Some(target) = rename_to {
+                    file.sync_all()?;
+                    drop(file);
+                    fs::rename(&*written, &*target)?;
+                }
+            }
+            Sink::Custom(writer) => writer.flush()?,
+        }
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Write for PendingOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.sink {
+            Sink::File { file, .. } => file.as_mut().expect("not committed").write(buf),
+            Sink::Custom(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.sink {
+            Sink::File { file, .. } => file.as_mut().expect("not committed").flush(),
+            Sink::Custom(writer) => writer.flush(),
+        }
+    }
+}
+
+impl Drop for PendingOutput {
+    fn drop(&mut self) {
+        if let (false, Sink::File { file, written, .. }) = (self.committed, &mut self.sink) {
+            drop(file.take());
+            let _ = fs::remove_file(&*written);
+        }
+    }
+}
+
+// ".<name>.<pid>-<n>.tmp" in the output's directory, so the rename stays on one filesystem
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let unique = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{}.{}-{}.tmp", name, process::id(), unique))
+}
@@ -0,0 +1,30 @@
+use std::sync::Arc;
+
+use crate::error::QuantumPackError;
+
+// Lets an application seal frames with its own encryption, e.g. an envelope key
+// from its KMS, without changing the format code. Set with Compressor::frame_hook
+// and Decompressor::frame_hook.
+//
+// The table, dictionary and data sections of a frame are handed to before_write as
+// one payload, together with the decoded length and CRC-32 of the decoded data, and
+// what it returns is stored in their place; the footer holds zeros instead. That
+// leaves readable:
+//   - the flags, stage descriptor, symbol width, dictionary ID and tie seed
+//   - the file's modification time and permissions, if preserve_metadata is on
+//   - the block type, which tells an incompressible (stored) block from the rest
+//   - the length of the sealed payload, and so roughly the compressed length
+// Frames can still be walked, but nothing that needs the decoded length, e.g.
+// decode_memory or a seek table, works on them. A sealed frame only decodes
+// through a Decompressor with a hook, and a Decompressor with a hook only decodes
+// sealed frames.
+pub trait FrameHook: Send + Sync {
+    // Seal the payload of a frame about to be written
+    fn before_write(&self, payload: &[u8]) -> Result<Vec<u8>, QuantumPackError>;
+
+    // Undo before_write on the payload read from a frame. Payloads the hook did not
+    // seal, or that were tampered with, should fail rather than decode to garbage.
+    fn after_read(&self, sealed: &[u8]) -> Result<Vec<u8>, QuantumPackError>;
+}
+
+pub type FrameHookHandle = Arc<dyn FrameHook>;
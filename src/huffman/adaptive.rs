@@ -0,0 +1,190 @@
+use super::DecodeError;
+
+// Adaptive Huffman coding (FGK): encoder and decoder start from the same empty tree
+// and update it after every byte, so the code follows the data as it goes and no
+// table is stored. The tree begins as a single NYT ("not yet transmitted") leaf. A
+// byte seen before is sent as the path to its leaf; a new byte as the path to the
+// NYT leaf followed by its 8 bits, after which the NYT leaf splits into a new NYT
+// leaf (left, 0) and the byte's leaf (right, 1).
+//
+// Nodes are numbered so that weights never decrease with the number and siblings
+// are neighbours; the root has the highest number. Before a node's weight goes up it
+// trades places with the highest numbered node of its weight, unless that is its
+// parent, which keeps the numbering valid.
+
+const SYMBOLS: usize = 256;
+// 256 leaves, the NYT leaf and the 256 internal nodes above them
+const NODES: usize = 2 * SYMBOLS + 1;
+const ROOT: usize = NODES - 1;
+const NONE: u16 = u16::MAX;
+
+struct AdaptiveTree {
+    weight: [u64; NODES],
+    parent: [u16; NODES],
+    // 0 and 1 child of internal nodes, NONE for leaves
+    children: [[u16; 2]; NODES],
+    // Byte of each leaf, NONE for internal nodes and the NYT leaf
+    symbol: [u16; NODES],
+    // Leaf of each byte, NONE until it is first seen
+    leaf: [u16; SYMBOLS],
+    nyt: usize,
+}
+
+impl AdaptiveTree {
+    fn new() -> Self {
+        AdaptiveTree {
+            weight: [0; NODES],
+            parent: [NONE; NODES],
+            children: [[NONE; 2]; NODES],
+            symbol: [NONE; NODES],
+            leaf: [NONE; SYMBOLS],
+            nyt: ROOT,
+        }
+    }
+
+    fn is_leaf(&self, node: usize) -> bool {
+        self.children[node][0] == NONE
+    }
+
+    // Bits from the root down to `node`, appended to `path`
+    fn path(&self, mut node: usize, path: &mut Vec<u8>) {
+        let start = path.len();
+        while node != ROOT {
+            let parent = self.parent[node] as usize;
+            path.push((self.children[parent][1] as usize == node) as u8);
+            node = parent;
+        }
+        path[start..].reverse();
+    }
+
+    // Count one more `symbol`, giving it a leaf if it is new
+    fn update(&mut self, symbol: u8) {
+        let mut node = match self.leaf[symbol as usize] {
+            NONE => {
+                let (parent, nyt, leaf) = (self.nyt, self.nyt - 2, self.nyt - 1);
+                self.children[parent] = [nyt as u16, leaf as u16];
+                self.parent[nyt] = parent as u16;
+                self.parent[leaf] = parent as u16;
+                self.symbol[leaf] = symbol as u16;
+                self.leaf[symbol as usize] = leaf as u16;
+                self.nyt = nyt;
+                leaf
+            }
+            leaf => leaf as usize,
+        };
+        loop {
+            let weight = self.weight[node];
+            let mut leader = node;
+            while leader < ROOT && self.weight[leader + 1] == weight {
+                leader += 1;
+            }
+            if leader != node && leader != self.parent[node] as usize {
+                self.swap(node, leader);
+                node = leader;
+            }
+            self.weight[node] += 1;
+            if node == ROOT {
+                return;
+            }
+            node = self.parent[node] as usize;
+        }
+    }
+
+    // Exchange the subtrees at two positions of the numbering, which keep their parents
+    fn swap(&mut self, a: usize, b: usize) {
+        self.weight.swap(a, b);
+        self.children.swap(a, b);
+        self.symbol.swap(a, b);
+        for &node in &[a, b] {
+            for &child in &self.children[node] {
+                if child != NONE {
+                    self.parent[child as usize] = node as u16;
+                }
+            }
+            if self.symbol[node] != NONE {
+                self.leaf[self.symbol[node] as usize] = node as u16;
+            }
+        }
+    }
+}
+
+// Adaptively Huffman code `data` into whole bytes, the last one filled up with 0
+// bits. Returns the bytes and how many bits of them are codes.
+pub fn adaptive_encode_bits(data: &[u8]) -> (Vec<u8>, usize) {
+    let mut tree = AdaptiveTree::new();
+    let mut encoded_data = Vec::new();
+    let mut current_byte = 0u8;
+    let mut bit_len = 0;
+    let mut bits = Vec::new();
+    for &symbol in data {
+        bits.clear();
+        match tree.leaf[symbol as usize] {
+            NONE => {
+                tree.path(tree.nyt, &mut bits);
+                bits.extend((0..8).rev().map(|shift| (symbol >> shift) & 1));
+            }
+            leaf => tree.path(leaf as usize, &mut bits),
+        }
+        for &bit in &bits {
+            current_byte = (current_byte << 1) | bit;
+            bit_len += 1;
+            if bit_len % 8 == 0 {
+                encoded_data.push(current_byte);
+                current_byte = 0;
+            }
+        }
+        tree.update(symbol);
+    }
+    if bit_len % 8 != 0 {
+        encoded_data.push(current_byte << (8 - bit_len % 8));
+    }
+    (encoded_data, bit_len)
+}
+
+// Decode the first `total_bits` bits of `body` from adaptive_encode_bits, at most
+// `max_output` bytes. A byte sent as new that has been seen before is an invalid code.
+pub fn adaptive_decode_bits(body: &[u8], total_bits: usize, max_output: usize) -> Result<Vec<u8>, DecodeError> {
+    if total_bits > body.len() * 8 {
+        return Err(DecodeError::IncompleteCode);
+    }
+    let bit = |offset: usize| (body[offset / 8] >> (7 - offset % 8)) & 1;
+
+    let mut tree = AdaptiveTree::new();
+    let mut decoded_data = Vec::with_capacity((total_bits / 8).min(max_output));
+    let mut bit_offset = 0;
+    while bit_offset < total_bits {
+        let code_start = bit_offset;
+        let mut node = ROOT;
+        while !tree.is_leaf(node) {
+            if bit_offset == total_bits {
+                return Err(DecodeError::IncompleteCode);
+            }
+            node = tree.children[node][bit(bit_offset) as usize] as usize;
+            bit_offset += 1;
+        }
+        let symbol = if node == tree.nyt {
+            if bit_offset + 8 > total_bits {
+                return Err(DecodeError::IncompleteCode);
+            }
+            let symbol = (bit_offset..bit_offset + 8).fold(0u8, |symbol, offset| (symbol << 1) | bit(offset));
+            bit_offset += 8;
+            if tree.leaf[symbol as usize] != NONE {
+                return Err(DecodeError::InvalidCode { bit_offset: code_start });
+            }
+            symbol
+        } else {
+            tree.symbol[node] as u8
+        };
+        if decoded_data.len() == max_output {
+            return Err(DecodeError::OutputLimitExceeded { limit: max_output });
+        }
+        decoded_data.push(symbol);
+        tree.update(symbol);
+    }
+    Ok(decoded_data)
+}
+
+// Bytes the decoder's tree takes, for decode_memory
+pub(crate) fn adaptive_tree_size() -> usize {
+    std::mem::size_of::<AdaptiveTree>()
+}
@@ -1,93 +1,149 @@
-use std::collections::{BinaryHeap, HashMap, BTreeMap};
+use std::collections::{BinaryHeap, BTreeMap};
 use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
 
 use crate::adaptive_dictionary::AdaptiveDictionary;
 
+mod adaptive;
+
+pub use adaptive::{adaptive_decode_bits, adaptive_encode_bits};
+pub(crate) use adaptive::adaptive_tree_size;
+
+// An element of the alphabet being coded. Frames hold bytes; pre-tokenized data can
+// be coded as 16 or 32 bit symbols, see Compressor::compress_symbols.
+pub trait Symbol: Copy + Ord + Hash + Default + fmt::Debug {
+    // Bytes per symbol in decoded output
+    const WIDTH: usize;
+
+    fn to_u32(self) -> u32;
+
+    // None if `value` does not fit the symbol type
+    fn from_u32(value: u32) -> Option<Self>;
+}
+
+impl Symbol for u8 {
+    const WIDTH: usize = 1;
+
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+
+    fn from_u32(value: u32) -> Option<Self> {
+        if value <= u8::MAX as u32 { Some(value as u8) } else { None }
+    }
+}
+
+impl Symbol for u16 {
+    const WIDTH: usize = 2;
+
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+
+    fn from_u32(value: u32) -> Option<Self> {
+        if value <= u16::MAX as u32 { Some(value as u16) } else { None }
+    }
+}
+
+impl Symbol for u32 {
+    const WIDTH: usize = 4;
+
+    fn to_u32(self) -> u32 {
+        self
+    }
+
+    fn from_u32(value: u32) -> Option<Self> {
+        Some(value)
+    }
+}
+
 #[derive(Debug)]
-pub struct HuffmanNode {
+pub struct HuffmanNode<S = u8> {
     pub frequency: u32,
-    pub value: u8,
-    left: Option<Box<HuffmanNode>>,
-    right: Option<Box<HuffmanNode>>,
+    pub value: S,
+    left: Option<Box<HuffmanNode<S>>>,
+    right: Option<Box<HuffmanNode<S>>>,
 }
 
-impl HuffmanNode {
-    fn new(frequency: u32, value: u8, left: Option<Box<HuffmanNode>>, right: Option<Box<HuffmanNode>>) -> Self {
+impl<S: Symbol> HuffmanNode<S> {
+    fn new(frequency: u32, value: S, left: Option<Box<HuffmanNode<S>>>, right: Option<Box<HuffmanNode<S>>>) -> Self {
         HuffmanNode { frequency, value, left, right }
     }
 }
 
 #[derive(Debug)]
-pub struct HuffmanTuple {
+pub struct HuffmanTuple<S = u8> {
     frequency: u32,
-    value: u8,
-    left: Option<Box<HuffmanNode>>,
-    right: Option<Box<HuffmanNode>>,
+    // Breaks ties between equal frequencies, lowest first; see tie_rank
+    rank: u64,
+    value: S,
+    left: Option<Box<HuffmanNode<S>>>,
+    right: Option<Box<HuffmanNode<S>>>,
 }
 
-impl HuffmanTuple {
-    fn new(frequency: u32, value: u8, left: Option<Box<HuffmanNode>>, right: Option<Box<HuffmanNode>>) -> Self {
-        HuffmanTuple { frequency, value, left, right }
+impl<S: Symbol> HuffmanTuple<S> {
+    fn new(frequency: u32, value: S, left: Option<Box<HuffmanNode<S>>>, right: Option<Box<HuffmanNode<S>>>) -> Self {
+        HuffmanTuple { frequency, rank: 0, value, left, right }
+    }
+
+    fn ranked(mut self, rank: u64) -> Self {
+        self.rank = rank;
+        self
     }
 }
 
-impl Ord for HuffmanTuple {
+// Lowest frequency first, then lowest rank
+impl<S> Ord for HuffmanTuple<S> {
     fn cmp(&self, other: &Self) -> Ordering {
-        other.frequency.cmp(&self.frequency)
+        other.frequency.cmp(&self.frequency).then_with(|| other.rank.cmp(&self.rank))
     }
 }
 
-impl PartialOrd for HuffmanTuple {
+impl<S> PartialOrd for HuffmanTuple<S> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl Eq for HuffmanTuple {}
+impl<S> Eq for HuffmanTuple<S> {}
 
-impl PartialEq for HuffmanTuple {
+impl<S> PartialEq for HuffmanTuple<S> {
     fn eq(&self, other: &Self) -> bool {
-        self.frequency == other.frequency
+        self.frequency == other.frequency && self.rank == other.rank
     }
 }
 
-pub fn build_huffman_tree(data: &[u8]) -> Option<Box<HuffmanNode>> {
-    let mut frequencies = HashMap::new();
-    for &byte in data {
-        *frequencies.entry(byte).or_insert(0) += 1;
-    }
-
-    // Print the frequencies for debugging
-    // println!("Frequencies: {:?}", frequencies);
-
-    let mut heap: BinaryHeap<HuffmanTuple> = frequencies.into_iter()
-        .map(|(value, frequency)| {
-            // println!("Inserting into heap: value={}, frequency={}", value, frequency);
-            HuffmanTuple::new(frequency, value, None, None)
-        })
-        .collect();
-
-    while heap.len() > 1 {
-        let left = heap.pop().unwrap();
-        let right = heap.pop().unwrap();
-        // println!("Combining nodes: left=(value={}, freq={}), right=(value={}, freq={})", left.value, left.frequency, right.value, right.frequency);
-
-        let merged_freq = left.frequency + right.frequency;
-        heap.push(HuffmanTuple::new(merged_freq, std::cmp::min(left.value, right.value), Some(Box::new(HuffmanNode::new(left.frequency, left.value, left.left, left.right))), Some(Box::new(HuffmanNode::new(right.frequency, right.value, right.left, right.right)))));
+// Huffman tree of the symbols in `data`, with ties broken as the encoder breaks them
+// under seed 0 (see tie_rank), so the codes are the same on every run
+pub fn build_huffman_tree<S: Symbol>(data: &[S]) -> Option<Box<HuffmanNode<S>>> {
+    build_huffman_tree_seeded(&count_symbols(data), 0)
+}
 
-        // Print the state of the heap after each merge
-        println!("Heap after merge: {:?}", heap);
+// Symbol frequencies of `data`. Bytes are counted in a fixed table rather than a map.
+pub(crate) fn count_symbols<S: Symbol>(data: &[S]) -> AdaptiveDictionary<S> {
+    let mut frequencies = AdaptiveDictionary::new();
+    if S::WIDTH > 1 {
+        frequencies.update(data);
+        return frequencies;
     }
-
-    let root = heap.pop();
-    println!("Final Huffman tree root: {:?}", &root.as_ref());
-
-    root.map(|tuple| Box::new(HuffmanNode::new(tuple.frequency, tuple.value, tuple.left, tuple.right)))
+    let mut histogram = [0u32; 256];
+    for &symbol in data {
+        histogram[symbol.to_u32() as usize] += 1;
+    }
+    frequencies.frequencies = (0..=u8::MAX as u32)
+        .filter(|&value| histogram[value as usize] > 0)
+        .filter_map(|value| S::from_u32(value).map(|symbol| (symbol, histogram[value as usize])))
+        .collect();
+    frequencies
 }
 
-pub fn generate_huffman_codes(node: &HuffmanNode, prefix: &mut Vec<u8>, codes: &mut BTreeMap<u8, Vec<u8>>) {
+pub fn generate_huffman_codes<S: Symbol>(node: &HuffmanNode<S>, prefix: &mut Vec<u8>, codes: &mut BTreeMap<S, Vec<u8>>) {
     if node.left.is_none() && node.right.is_none() {
-        codes.insert(node.value, prefix.clone());
+        // A tree of a single symbol still needs one bit per occurrence
+        let code = if prefix.is_empty() { vec![0] } else { prefix.clone() };
+        codes.insert(node.value, code);
         return;
     }
     
@@ -104,7 +160,56 @@ pub fn generate_huffman_codes(node: &HuffmanNode, prefix: &mut Vec<u8>, codes: &
     }
 }
 
-pub fn build_huffman_tree_with_dictionary(dictionary: &AdaptiveDictionary) -> Option<Box<HuffmanNode>> {
+// Code length of every symbol; a lone symbol still needs a one bit code
+pub fn code_lengths<S: Symbol>(codes: &BTreeMap<S, Vec<u8>>) -> BTreeMap<S, u8> {
+    codes.iter().map(|(&symbol, code)| (symbol, code.len().max(1) as u8)).collect()
+}
+
+// Canonical Huffman codes: symbols ordered by (length, value) get consecutive codes,
+// so the code lengths alone are enough to reproduce the exact same codes
+pub fn canonical_codes<S: Symbol>(lengths: &BTreeMap<S, u8>) -> BTreeMap<S, Vec<u8>> {
+    let mut symbols: Vec<(u8, S)> = lengths.iter().map(|(&symbol, &length)| (length, symbol)).collect();
+    symbols.sort_unstable();
+
+    let mut codes = BTreeMap::new();
+    let mut code: Vec<u8> = Vec::new();
+    for (index, &(length, symbol)) in symbols.iter().enumerate() {
+        if index > 0 {
+            // Increment the previous code as a binary number
+            let mut position = code.len();
+            while position > 0 {
+                position -= 1;
+                if code[position] == 0 {
+                    code[position] = 1;
+                    break;
+                }
+                code[position] = 0;
+            }
+        }
+        code.resize(length as usize, 0);
+        codes.insert(symbol, code.clone());
+    }
+    codes
+}
+
+// Rebuild a decoding tree from explicit codes, e.g. ones produced by canonical_codes
+pub fn build_huffman_tree_from_codes<S: Symbol>(codes: &BTreeMap<S, Vec<u8>>) -> Option<Box<HuffmanNode<S>>> {
+    if codes.is_empty() {
+        return None;
+    }
+    let mut root = HuffmanNode::new(0, S::default(), None, None);
+    for (&symbol, code) in codes {
+        let mut node = &mut root;
+        for &bit in code {
+            let child = if bit == 0 { &mut node.left } else { &mut node.right };
+            node = child.get_or_insert_with(|| Box::new(HuffmanNode::new(0, S::default(), None, None)));
+        }
+        node.value = symbol;
+    }
+    Some(Box::new(root))
+}
+
+pub fn build_huffman_tree_with_dictionary<S: Symbol>(dictionary: &AdaptiveDictionary<S>) -> Option<Box<HuffmanNode<S>>> {
     let mut heap = BinaryHeap::new();
 
     // Insert all characters and their frequencies into the heap
@@ -130,80 +235,179 @@ pub fn build_huffman_tree_with_dictionary(dictionary: &AdaptiveDictionary) -> Op
     heap.pop().map(|tuple| Box::new(HuffmanNode::new(tuple.frequency, tuple.value, tuple.left, tuple.right)))
 }
 
-pub fn huffman_decode(encoded_data: &[u8], huffman_tree: &HuffmanNode) -> Vec<u8> {
-    if encoded_data.is_empty() {
-        return Vec::new();
+// Where symbols of equal frequency meet in the heap the one of lower rank is merged
+// first and becomes the left child. Seed 0 ranks symbols by value; any other seed
+// by a fixed bijective mix of the seed and the value (the splitmix64 finalizer over
+// seed << 32 | value), so every seed gives its own order and no two symbols share a
+// rank. A merged node takes the lower rank of its children. Encoders that follow
+// this get the same tree, and so the same code lengths, from the same frequencies.
+pub fn tie_rank<S: Symbol>(symbol: S, seed: u32) -> u64 {
+    let value = symbol.to_u32() as u64;
+    if seed == 0 {
+        return value;
     }
+    let mut rank = (seed as u64) << 32 | value;
+    rank = (rank ^ (rank >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    rank = (rank ^ (rank >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    rank ^ (rank >> 31)
+}
 
-    let mut decoded_data = Vec::new();
-    let mut current_node = huffman_tree;
-    let bits_in_last_byte = encoded_data[encoded_data.len() - 1] as usize;
+// Huffman tree of `frequencies` with ties broken by tie_rank under `seed`, as the
+// encoder builds it. build_huffman_tree_with_dictionary leaves ties to the heap and
+// is kept for version 1 frames, whose decoders rebuild the tree from frequencies.
+pub fn build_huffman_tree_seeded<S: Symbol>(frequencies: &AdaptiveDictionary<S>, seed: u32) -> Option<Box<HuffmanNode<S>>> {
+    trace!("Huffman tree of {} symbols, tie seed {}", frequencies.get_frequencies().len(), seed);
+    let mut heap: BinaryHeap<HuffmanTuple<S>> = frequencies.get_frequencies()
+        .iter()
+        .map(|(&value, &frequency)| HuffmanTuple::new(frequency, value, None, None).ranked(tie_rank(value, seed)))
+        .collect();
 
-    for (index, &byte) in encoded_data.iter().enumerate().take(encoded_data.len() - 1) {
-        let bits_to_process = if index == encoded_data.len() - 2 { bits_in_last_byte } else { 8 };
+    while heap.len() > 1 {
+        let left = heap.pop().unwrap();
+        let right = heap.pop().unwrap();
+        let rank = left.rank.min(right.rank);
+        let merged = HuffmanTuple::new(
+            left.frequency + right.frequency,
+            std::cmp::min(left.value, right.value),
+            Some(Box::new(HuffmanNode::new(left.frequency, left.value, left.left, left.right))),
+            Some(Box::new(HuffmanNode::new(right.frequency, right.value, right.left, right.right))),
+        );
+        heap.push(merged.ranked(rank));
+    }
 
-        for i in 0..bits_to_process {
-            let bit = (byte >> (7 - i)) & 1;
-            // println!("Decoding byte {}, bit {}: {}", index, i, bit);
+    heap.pop().map(|tuple| Box::new(HuffmanNode::new(tuple.frequency, tuple.value, tuple.left, tuple.right)))
+}
 
-            current_node = if bit == 0 {
-                current_node.left.as_ref().unwrap()
-            } else {
-                current_node.right.as_ref().unwrap()
-            };
+// Why a Huffman bitstream could not be decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    // The final byte, which counts the bits used in the byte before it, is out of range
+    InvalidBitCount(u8),
+    // The code starting at `bit_offset` leads to a branch the tree does not have
+    InvalidCode { bit_offset: usize },
+    // The bitstream ends in the middle of a code
+    IncompleteCode,
+    // Decoding would produce more than `limit` symbols
+    OutputLimitExceeded { limit: usize },
+}
 
-            if current_node.left.is_none() && current_node.right.is_none() {
-                decoded_data.push(current_node.value);
-                current_node = huffman_tree;
-            }
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::InvalidBitCount(bits) => write!(f, "invalid bit count {} in the last byte", bits),
+            DecodeError::InvalidCode { bit_offset } => write!(f, "invalid Huffman code at bit {}", bit_offset),
+            DecodeError::IncompleteCode => write!(f, "Huffman data ends in the middle of a code"),
+            DecodeError::OutputLimitExceeded { limit } => write!(f, "decoded data exceeds the limit of {} bytes", limit),
         }
     }
+}
+
+impl Error for DecodeError {}
 
-    decoded_data
+pub fn huffman_decode<S: Symbol>(encoded_data: &[u8], huffman_tree: &HuffmanNode<S>) -> Result<Vec<S>, DecodeError> {
+    huffman_decode_limited(encoded_data, huffman_tree, usize::MAX)
 }
 
-pub fn huffman_encode(data: &[u8], codes: &BTreeMap<u8, Vec<u8>>) -> Vec<u8> {
-    let mut encoded_data = Vec::new();
-    let mut current_bitstring: Vec<u8> = Vec::new();
+// Decode at most `max_output` symbols of data from huffman_encode
+pub fn huffman_decode_limited<S: Symbol>(encoded_data: &[u8], huffman_tree: &HuffmanNode<S>, max_output: usize) -> Result<Vec<S>, DecodeError> {
+    let (body, total_bits) = split_bit_count(encoded_data)?;
+    huffman_decode_bits(body, total_bits, huffman_tree, max_output)
+}
 
-    // Encode the data into a bitstring
-    for &byte in data {
-        if let Some(code) = codes.get(&byte) {
-            // println!("Encoding byte: {}, Code: {:?}", byte, code);
-            current_bitstring.extend(code);
-        }
+// Split data from huffman_encode into its code bytes and the number of bits they hold
+pub fn split_bit_count(encoded_data: &[u8]) -> Result<(&[u8], usize), DecodeError> {
+    let (&bits_in_last_byte, body) = match encoded_data.split_last() {
+        Some(split) => split,
+        None => return Ok((&[], 0)),
+    };
+    match (body.len(), bits_in_last_byte) {
+        (0, 0) => Ok((body, 0)),
+        (len, 1..=8) if len > 0 => Ok((body, (len - 1) * 8 + bits_in_last_byte as usize)),
+        _ => Err(DecodeError::InvalidBitCount(bits_in_last_byte)),
     }
+}
 
-    // Process the bitstring into bytes
-    let mut i = 0;
-    while i + 8 <= current_bitstring.len() {
-        let byte = current_bitstring[i..i + 8].iter().fold(0, |acc, &bit| (acc << 1) | bit);
-        // println!("Encoded byte: {}", byte);
-        encoded_data.push(byte);
-        i += 8;
+// Decode the first `total_bits` bits of `body`, at most `max_output` symbols.
+// Malformed input is reported, never trusted: trees rebuilt from a corrupted code
+// length table may have missing branches.
+pub fn huffman_decode_bits<S: Symbol>(body: &[u8], total_bits: usize, huffman_tree: &HuffmanNode<S>, max_output: usize) -> Result<Vec<S>, DecodeError> {
+    if total_bits > body.len() * 8 {
+        return Err(DecodeError::IncompleteCode);
     }
 
-    // Handle the last byte
-    if i < current_bitstring.len() {
-        let remaining_bits = &current_bitstring[i..];
-        let mut last_byte = 0;
-        for &bit in remaining_bits {
-            last_byte = (last_byte << 1) | bit;
+    // Every code is at least one bit long
+    let mut decoded_data = Vec::with_capacity(total_bits.min(max_output));
+    let mut current_node = huffman_tree;
+    let mut code_start = 0;
+    for bit_offset in 0..total_bits {
+        let bit = (body[bit_offset / 8] >> (7 - bit_offset % 8)) & 1;
+        if is_leaf(huffman_tree) {
+            // The lone symbol of a single symbol tree has the code 0
+            if bit != 0 {
+                return Err(DecodeError::InvalidCode { bit_offset });
+            }
+        } else {
+            let child = if bit == 0 { &current_node.left } else { &current_node.right };
+            current_node = child.as_deref().ok_or(DecodeError::InvalidCode { bit_offset: code_start })?;
+            if !is_leaf(current_node) {
+                continue;
+            }
+        }
+        if decoded_data.len() == max_output {
+            return Err(DecodeError::OutputLimitExceeded { limit: max_output });
         }
-        last_byte <<= 8 - remaining_bits.len(); // Pad the remaining bits
-        // println!("Last encoded byte: {}", last_byte);
-        encoded_data.push(last_byte);
+        decoded_data.push(current_node.value);
+        current_node = huffman_tree;
+        code_start = bit_offset + 1;
     }
-
-    // Append the number of bits in the last byte (including padding)
-    let bits_in_last_byte = (current_bitstring.len() % 8) as u8;
-    if bits_in_last_byte == 0 && !current_bitstring.is_empty() {
-        // If the bitstring divides evenly by 8, the last byte is fully used
-        encoded_data.push(8);
-    } else {
-        // Otherwise, record the actual number of bits used in the last byte
-        encoded_data.push(bits_in_last_byte);
+    if code_start != total_bits {
+        return Err(DecodeError::IncompleteCode);
     }
 
+    Ok(decoded_data)
+}
+
+fn is_leaf<S>(node: &HuffmanNode<S>) -> bool {
+    node.left.is_none() && node.right.is_none()
+}
+
+// Huffman code `data` and append the bit count byte that huffman_decode expects,
+// the number of bits of the last byte that hold codes
+pub fn huffman_encode<S: Symbol>(data: &[S], codes: &BTreeMap<S, Vec<u8>>) -> Vec<u8> {
+    let (mut encoded_data, bit_len) = huffman_encode_bits(data, codes);
+    push_bit_count(&mut encoded_data, bit_len);
     encoded_data
 }
+
+// Append the bit count byte of huffman_encode to `bit_len` bits of codes
+pub(crate) fn push_bit_count(encoded_data: &mut Vec<u8>, bit_len: usize) {
+    encoded_data.push(match bit_len % 8 {
+        // A full last byte counts 8, no bits at all count 0
+        0 if bit_len > 0 => 8,
+        bits => bits as u8,
+    });
+}
+
+// Huffman code `data` into whole bytes, the last one filled up with 0 bits. Returns
+// the bytes and how many bits of them are codes.
+pub fn huffman_encode_bits<S: Symbol>(data: &[S], codes: &BTreeMap<S, Vec<u8>>) -> (Vec<u8>, usize) {
+    let mut encoded_data = Vec::new();
+    let mut current_byte = 0u8;
+    let mut bit_len = 0;
+    for &symbol in data {
+        if let Some(code) = codes.get(&symbol) {
+            for &bit in code {
+                current_byte = (current_byte << 1) | bit;
+                bit_len += 1;
+                if bit_len % 8 == 0 {
+                    encoded_data.push(current_byte);
+                    current_byte = 0;
+                }
+            }
+        }
+    }
+    if bit_len % 8 != 0 {
+        encoded_data.push(current_byte << (8 - bit_len % 8));
+    }
+    (encoded_data, bit_len)
+}
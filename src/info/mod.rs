@@ -0,0 +1,47 @@
+use std::time::{Duration, Instant};
+
+use crate::analysis::entropy;
+use crate::compression::Compressor;
+use crate::error::QuantumPackError;
+
+// How well one input compressed, for callers that log or alert on compression
+// effectiveness. Timing it needs the clock, which the compression core never reads.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressionInfo {
+    pub input_len: usize,
+    // All frames written for the input, padding included
+    pub output_len: usize,
+    // Output over input size, below 1 when the input shrank
+    pub ratio: f64,
+    // Shannon entropy of the input in bits per byte, 0 to 8
+    pub entropy: f64,
+    // Dictionary patterns the frames use, counted per block; 0 for stored blocks
+    pub patterns_used: usize,
+    pub elapsed: Duration,
+}
+
+impl Compressor {
+    // compress_shared, also describing how it went
+    pub fn compress_with_info(&self, region: &[u8], output: &mut Vec<u8>) -> Result<CompressionInfo, QuantumPackError> {
+        let start = Instant::now();
+        let output_start = output.len();
+        let patterns_used = self.compress_with_metadata(region, None, output)?;
+        let elapsed = start.elapsed();
+        let output_len = output.len() - output_start;
+        Ok(CompressionInfo {
+            input_len: region.len(),
+            output_len,
+            ratio: output_len as f64 / region.len().max(1) as f64,
+            entropy: entropy(region),
+            patterns_used,
+            elapsed,
+        })
+    }
+}
+
+// Compress `data` into a frame with the default settings, with its CompressionInfo
+pub fn compress_with_info(data: &[u8]) -> Result<(Vec<u8>, CompressionInfo), QuantumPackError> {
+    let mut frame = Vec::new();
+    let info = Compressor::new().compress_with_info(data, &mut frame)?;
+    Ok((frame, info))
+}
@@ -1,6 +1,59 @@
+// Diagnostics go to the log crate's debug and trace levels with the log feature and
+// compile to nothing without it, so nothing should be computed only to be logged.
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::debug!($($arg)*);
+    };
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::trace!($($arg)*);
+    };
+}
+
 pub mod huffman;
 pub mod adaptive_dictionary;
 
 pub mod preprocessor;
+pub mod throttle;
+pub mod checksum;
+pub mod page;
+pub mod stage;
+pub mod stream;
+pub mod profile;
+pub mod convert;
+pub mod archive;
+pub mod wire;
+pub mod warning;
+pub mod progress;
+pub mod hook;
+pub mod tuning;
+pub mod compressibility;
+pub mod cost;
+pub mod analysis;
+pub mod sniff;
+pub mod evaluation;
+pub mod info;
+pub mod benchmark;
+pub mod messages;
+pub mod completions;
+pub mod error;
+mod file;
+#[cfg(feature = "arbitrary")]
+pub mod roundtrip;
+#[cfg(feature = "trace")]
+pub mod trace;
 mod compression; // Import the new module
-pub use compression::{compress, decompress, compress_file, decompress_file, deserialize_frequency_table, serialize_frequency_table};
\ No newline at end of file
+pub use error::QuantumPackError;
+pub use warning::{Warning, WarningHandler};
+pub use progress::ProgressHandler;
+pub use hook::{FrameHook, FrameHookHandle};
+pub use analysis::{analyze, byte_histogram, entropy, Analysis, Recommendation};
+pub use compressibility::{compressibility_profile, estimate_compressed_size, WindowProfile};
+pub use info::{compress_with_info, CompressionInfo};
+pub use benchmark::{self_benchmark, Throughput};
+pub use file::{compress_file, concat_files, convert_file, append_archive, create_archive, create_archive_keep_going, create_split_archive, extract_archive, extract_archive_entry, extract_archive_keep_going, BatchFailure, BatchReport, index_path, list_archive, reindex_archive, decompress_file, compress_file_throttled, decompress_file_throttled, OutputFactory, OutputPolicy};
+pub use compression::{CompressionLevel, Compressor, DecodeMemory, Decompressor, FileMetadata, TableEncoding, TrailingData, TrailingDataPolicy, compress, compress_shared, compress_with_dictionary, decode_memory, decompress, decompress_adaptive, decompress_with_dictionary, frame_dictionary_id, frame_metadata, frame_tie_seed, deserialize_frequency_table, serialize_frequency_table, deserialize_code_lengths, serialize_code_lengths, deserialize_code_length_table, serialize_code_length_table, deserialize_symbol_length_table, serialize_symbol_length_table};
\ No newline at end of file
@@ -1,31 +1,706 @@
-use std::{env, process};
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use std::{env, io, process};
 
-use quantum_pack::{compress_file, decompress_file};
+use quantum_pack::{analyze, concat_files, convert_file, append_archive, create_archive, create_archive_keep_going, create_split_archive, extract_archive, extract_archive_entry, extract_archive_keep_going, list_archive, reindex_archive, BatchReport, CompressionLevel, Compressor, Decompressor, QuantumPackError, ProgressHandler, Recommendation, TrailingDataPolicy, Warning, WarningHandler};
+use quantum_pack::archive::{self, ArchiveStats, RATIO_BUCKETS};
+use quantum_pack::completions::{self, Shell};
+use quantum_pack::messages::{load_catalog, locale_names, Catalog, Message};
+use quantum_pack::preprocessor::{read_pattern_file, DictionaryMode, Preprocessor};
+use quantum_pack::sniff::CompressedFormat;
+#[cfg(feature = "trace")]
+use quantum_pack::trace;
+
+fn usage(program: &str) -> ! {
+    let mut lines = vec![
+        Message::Usage,
+        Message::UsageConcat,
+        Message::UsageRecompress,
+        Message::UsageConvert,
+        Message::UsageArchive,
+        Message::UsageExtract,
+        Message::UsageExtractEntry,
+        Message::UsageList,
+        Message::UsageReindex,
+        Message::UsageAnalyze,
+        Message::UsageCompletions,
+        Message::UsageExitStatus,
+    ];
+    if cfg!(feature = "trace") {
+        lines.extend_from_slice(&[Message::UsageTrace, Message::UsageReplay]);
+    }
+    if json_errors() {
+        fail(Message::Usage, &[&program]);
+    }
+    for line in lines {
+        eprintln!("{}", tr(line, &[&program]));
+    }
+    process::exit(EXIT_USAGE);
+}
+
+// Exit statuses. Scripts branch on these, so they only ever gain new values.
+const EXIT_USAGE: i32 = 1;
+// Some inputs of a --keep-going batch failed, or --salvage lost blocks
+const EXIT_PARTIAL: i32 = 2;
+const EXIT_IO: i32 = 3;
+const EXIT_CORRUPT: i32 = 4;
+const EXIT_CHECKSUM: i32 = 5;
+// replay found the decoder reading a frame differently from how it was written
+#[cfg(feature = "trace")]
+const EXIT_DIVERGED: i32 = 6;
+
+fn exit_status(error: &QuantumPackError) -> i32 {
+    match error {
+        QuantumPackError::Io(_) => EXIT_IO,
+        QuantumPackError::ChecksumMismatch { .. } => EXIT_CHECKSUM,
+        QuantumPackError::InvalidInput(_) | QuantumPackError::InputTooLarge | QuantumPackError::DictionaryMismatch { .. } => EXIT_USAGE,
+        _ => EXIT_CORRUPT,
+    }
+}
+
+// Whether failures are reported as JSON, see `--error-format`
+static JSON_ERRORS: OnceLock<bool> = OnceLock::new();
+
+fn json_errors() -> bool {
+    JSON_ERRORS.get().copied().unwrap_or(false)
+}
+
+// Print `message` to stderr and exit with EXIT_USAGE
+fn fail(message: Message, arguments: &[&dyn Display]) -> ! {
+    fail_with(message, arguments, None, &QuantumPackError::InvalidInput(tr(message, arguments)))
+}
+
+// Like fail, for `error` while working on `path`, with the exit status for the error
+fn fail_on(message: Message, path: Option<&str>, error: &QuantumPackError) -> ! {
+    fail_with(message, &[error], path, error)
+}
+
+// With `--error-format json` the message is replaced by one line of JSON carrying
+// the message key as the code, e.g. {"code":"error.decompress","kind":"checksum_mismatch",...}
+fn fail_with(message: Message, arguments: &[&dyn Display], path: Option<&str>, error: &QuantumPackError) -> ! {
+    clear_progress();
+    if json_errors() {
+        eprintln!("{}", error.to_json(message.key(), path));
+    } else {
+        eprintln!("{}", tr(message, arguments));
+    }
+    process::exit(exit_status(error));
+}
+
+// Report the failures of a --keep-going batch, each like fail_on would, then a
+// summary. Exits with EXIT_PARTIAL if anything failed.
+fn finish_batch(message: Message, report: &BatchReport) {
+    if report.is_complete() {
+        return;
+    }
+    for failure in &report.failed {
+        if json_errors() {
+            eprintln!("{}", failure.error.to_json(message.key(), Some(&failure.path)));
+        } else {
+            eprintln!("{}", tr(Message::BatchFailed, &[&failure.path, &failure.error]));
+        }
+    }
+    let total = report.succeeded.len() + report.failed.len();
+    if !json_errors() {
+        eprintln!("{}", tr(Message::BatchSummary, &[&report.failed.len(), &total]));
+    }
+    process::exit(EXIT_PARTIAL);
+}
+
+// The text of `message` in the user's language
+fn tr(message: Message, arguments: &[&dyn Display]) -> String {
+    static CATALOG: OnceLock<Catalog> = OnceLock::new();
+    CATALOG.get_or_init(user_catalog).format(message, arguments)
+}
+
+// The catalog "<locale>.msg" in QP_LOCALE_DIR for the first of QP_LANG, LC_ALL,
+// LC_MESSAGES and LANG that is set. Packagers can also set QP_LOCALE_DIR when
+// building. English when there is no catalog for the locale.
+fn user_catalog() -> Catalog {
+    let dir = match env::var("QP_LOCALE_DIR").ok().or_else(|| option_env!("QP_LOCALE_DIR").map(String::from)) {
+        Some(dir) => dir,
+        None => return Catalog::english(),
+    };
+    let locale = ["QP_LANG", "LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    for name in locale_names(&locale) {
+        let path = Path::new(&dir).join(format!("{}.msg", name));
+        if !path.is_file() {
+            continue;
+        }
+        match load_catalog(&path.to_string_lossy()) {
+            Ok(catalog) => return catalog,
+            Err(e) => {
+                eprintln!("Ignoring message catalog {}: {}", path.display(), e);
+                break;
+            }
+        }
+    }
+    Catalog::english()
+}
+
+// Ratio histogram of `qp list --stats`, one row per 10% bucket
+fn print_stats(stats: &ArchiveStats) {
+    let total: usize = stats.buckets.iter().sum();
+    println!();
+    println!("{}", tr(Message::StatsSummary, &[&total, &stats.size, &stats.compressed_len]));
+    for (index, count) in stats.buckets.iter().enumerate() {
+        let label = if index == RATIO_BUCKETS - 1 { ">=100%".to_string() } else { format!("{:>3}-{}%", index * 10, index * 10 + 10) };
+        let bar = "#".repeat((count * 40).div_ceil(total.max(1)));
+        println!("{:>8} {:>6}  {}", label, count, bar);
+    }
+    if !stats.expanded.is_empty() {
+        println!("{}", tr(Message::StatsExpanded, &[]));
+        for path in &stats.expanded {
+            println!("  {}", path);
+        }
+    }
+}
+
+// `qp trace`: compress a file into a single frame and write the encoder's decisions next to it
+#[cfg(feature = "trace")]
+fn record_trace(compressor: &Compressor, input_path: &str, output_path: &str, trace_path: &str) -> Result<(), quantum_pack::QuantumPackError> {
+    let (frame, trace) = trace::record(compressor, &std::fs::read(input_path)?)?;
+    std::fs::write(output_path, frame)?;
+    std::fs::write(trace_path, trace.to_bytes())?;
+    Ok(())
+}
+
+#[cfg(feature = "trace")]
+fn replay_trace(frame_path: &str, trace_path: &str) -> Result<Option<trace::Divergence>, quantum_pack::QuantumPackError> {
+    let trace = trace::Trace::parse(&std::fs::read(trace_path)?)?;
+    trace::replay(&std::fs::read(frame_path)?, &trace)
+}
+
+// How much qp prints besides errors and what was asked for, e.g. --stats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    // -q: nothing else
+    Quiet,
+    // Warnings, notes such as ignored trailing data and the progress line
+    Normal,
+    // -v: also a summary line for each file
+    Summary,
+    // -vv: also the encoder's diagnostics, from the library's log records
+    Diagnostics,
+}
+
+static VERBOSITY: OnceLock<Verbosity> = OnceLock::new();
+
+fn verbosity() -> Verbosity {
+    VERBOSITY.get().copied().unwrap_or(Verbosity::Normal)
+}
+
+// Prints the library's log records on stderr for -vv
+#[cfg(feature = "log")]
+struct StderrLogger;
+
+#[cfg(feature = "log")]
+impl log::Log for StderrLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        clear_progress();
+        eprintln!("{} {}: {}", record.level(), record.target(), record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(feature = "log")]
+static LOGGER: StderrLogger = StderrLogger;
+
+fn print_warning() -> WarningHandler {
+    Arc::new(|warning| {
+        if verbosity() == Verbosity::Quiet {
+            return;
+        }
+        clear_progress();
+        eprintln!("{}", tr(Message::Warning, &[&warning]))
+    })
+}
+
+// Whether a progress line is on stderr and has to be cleared before anything else
+static PROGRESS_LINE: AtomicBool = AtomicBool::new(false);
+
+fn clear_progress() {
+    if PROGRESS_LINE.swap(false, Ordering::Relaxed) {
+        eprint!("\r\x1b[K");
+    }
+}
+
+// Progress line of `qp compress` and `qp decompress` on a terminal: bytes read,
+// throughput and, when the input size is known, the time left. The library reports
+// after each frame, so without --block-size the line only appears at the end.
+fn progress_bar(total: Option<u64>) -> ProgressHandler {
+    let start = Instant::now();
+    let last_drawn: Mutex<Option<Instant>> = Mutex::new(None);
+    Arc::new(move |bytes_in, _| {
+        let now = Instant::now();
+        let mut last_drawn = last_drawn.lock().unwrap();
+        if last_drawn.is_some_and(|last| now - last < Duration::from_millis(100)) {
+            return;
+        }
+        *last_drawn = Some(now);
+        let rate = bytes_in as f64 / (now - start).as_secs_f64().max(0.001);
+        let line = match total {
+            Some(total) if total > 0 => {
+                let left = total.saturating_sub(bytes_in) as f64 / rate.max(1.0);
+                let percent = (bytes_in.min(total) * 100 / total).to_string();
+                tr(Message::ProgressEta, &[&human_bytes(bytes_in as f64), &human_bytes(total as f64), &percent, &human_bytes(rate), &human_duration(left)])
+            }
+            _ => tr(Message::Progress, &[&human_bytes(bytes_in as f64), &human_bytes(rate)]),
+        };
+        eprint!("\r{}\x1b[K", line);
+        PROGRESS_LINE.store(true, Ordering::Relaxed);
+    })
+}
+
+// Bytes in and out as of the last progress report, for the -v summary
+#[derive(Default)]
+struct Totals {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    // Set when the input was stored because it is compressed already
+    stored: Mutex<Option<CompressedFormat>>,
+}
+
+// Record every report in `totals` and pass it on to the progress line, if any
+fn counting(totals: &Arc<Totals>, progress: Option<ProgressHandler>) -> ProgressHandler {
+    let totals = Arc::clone(totals);
+    Arc::new(move |bytes_in, bytes_out| {
+        totals.bytes_in.store(bytes_in, Ordering::Relaxed);
+        totals.bytes_out.store(bytes_out, Ordering::Relaxed);
+        if let Some(progress) = &progress {
+            progress(bytes_in, bytes_out);
+        }
+    })
+}
+
+// Note inputs stored as they are in `totals`, then print the warning as usual
+fn noting_stored(totals: &Arc<Totals>) -> WarningHandler {
+    let totals = Arc::clone(totals);
+    let print = print_warning();
+    Arc::new(move |warning| {
+        if let Warning::AlreadyCompressed { format } = &warning {
+            *totals.stored.lock().unwrap() = Some(*format);
+        }
+        print(warning);
+    })
+}
+
+fn print_summary(path: &str, totals: &Totals, start: Instant) {
+    clear_progress();
+    if let Some(format) = *totals.stored.lock().unwrap() {
+        eprintln!("{}", tr(Message::SummaryStored, &[&path, &format.name()]));
+    }
+    let bytes_in = totals.bytes_in.load(Ordering::Relaxed);
+    let bytes_out = totals.bytes_out.load(Ordering::Relaxed);
+    let percent = format!("{:.1}", bytes_out as f64 * 100.0 / bytes_in.max(1) as f64);
+    eprintln!("{}", tr(Message::Summary, &[&path, &bytes_in, &bytes_out, &percent, &human_duration(start.elapsed().as_secs_f64())]));
+}
+
+fn human_bytes(bytes: f64) -> String {
+    let mut value = bytes;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if value < 1024.0 {
+            return if unit == "B" { format!("{} B", value as u64) } else { format!("{:.1} {}", value, unit) };
+        }
+        value /= 1024.0;
+    }
+    format!("{:.1} TiB", value)
+}
+
+fn human_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+// Size of the input at `path` for the progress line, None for stdin
+fn input_len(path: &str) -> Option<u64> {
+    Some(path).filter(|&path| path != "-").and_then(|path| fs::metadata(path).ok()).map(|metadata| metadata.len())
+}
+
+// `qp decompress --salvage`: write every block that still decodes and report the
+// rest. Returns whether anything was lost.
+fn salvage_file(input_path: &str, output_path: &str) -> Result<bool, QuantumPackError> {
+    let salvaged = Decompressor::new().salvage_file(input_path, output_path)?;
+    for block in salvaged.damaged.iter().filter(|_| verbosity() > Verbosity::Quiet) {
+        let missing = block.len.map_or_else(|| tr(Message::SalvageUnknownLength, &[]), |len| len.to_string());
+        eprintln!("{}", tr(Message::SalvageDamaged, &[&block.input.start, &block.input.end, &missing, &block.output_offset]));
+    }
+    Ok(!salvaged.damaged.is_empty())
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    // Known before parsing so that errors in the other options are already reported as asked
+    let json = args.windows(2).any(|pair| pair[0] == "--error-format" && pair[1] == "json");
+    JSON_ERRORS.set(json).unwrap();
 
-    if args.len() < 4 {
-        eprintln!("Usage: {} [compress|decompress] <input file> <output file>", args[0]);
-        process::exit(1);
+    // Split the arguments into positionals and options
+    let mut positional = Vec::new();
+    let mut bwlimit: Option<u64> = None;
+    let mut dict_file: Option<String> = None;
+    let mut dict_mode = DictionaryMode::Merge;
+    let mut trailing_data = TrailingDataPolicy::Strict;
+    let mut output: Option<String> = None;
+    let mut level = CompressionLevel::Default;
+    let mut stats = false;
+    let mut append = false;
+    let mut split_index = false;
+    let mut preserve_metadata = true;
+    let mut block_size: Option<usize> = None;
+    let mut pad_to: Option<usize> = None;
+    let mut salvage = false;
+    let mut keep_going = false;
+    let mut progress = true;
+    let mut quiet = false;
+    let mut verbose = 0;
+    let mut top = 10;
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--bwlimit" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                match value.parse::<u64>() {
+                    Ok(limit) if limit > 0 => bwlimit = Some(limit),
+                    _ => fail(Message::InvalidBwlimit, &[value]),
+                }
+            }
+            "--dict-file" => dict_file = Some(iter.next().unwrap_or_else(|| usage(&args[0])).clone()),
+            "--dict-replace" => dict_mode = DictionaryMode::Replace,
+            "--allow-trailing" => trailing_data = TrailingDataPolicy::Permissive,
+            "-1" | "-2" | "-3" | "-4" | "-5" | "-6" | "-7" | "-8" | "-9" => {
+                level = CompressionLevel::from_number(arg.as_bytes()[1] - b'0').unwrap();
+            }
+            "--level" => {
+                level = iter.next().and_then(|name| CompressionLevel::from_name(name)).unwrap_or_else(|| usage(&args[0]));
+            }
+            "--stats" => stats = true,
+            "--append" => append = true,
+            "--split-index" => split_index = true,
+            "--no-metadata" => preserve_metadata = false,
+            "--block-size" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                match value.parse::<usize>() {
+                    Ok(size) if size > 0 => block_size = Some(size),
+                    _ => fail(Message::InvalidBlockSize, &[value]),
+                }
+            }
+            "--pad-to" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                match value.parse::<usize>() {
+                    Ok(size) => pad_to = Some(size),
+                    _ => fail(Message::InvalidPadTo, &[value]),
+                }
+            }
+            "--top" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                match value.parse::<usize>() {
+                    Ok(count) => top = count,
+                    _ => fail(Message::InvalidTop, &[value]),
+                }
+            }
+            "--salvage" => salvage = true,
+            "--keep-going" => keep_going = true,
+            "--no-progress" => progress = false,
+            "-q" => quiet = true,
+            "-v" => verbose += 1,
+            "-vv" => verbose += 2,
+            "--error-format" => match iter.next().map(String::as_str) {
+                Some("text") | Some("json") => {}
+                Some(value) => fail(Message::InvalidErrorFormat, &[&value]),
+                None => usage(&args[0]),
+            },
+            "-o" => output = Some(iter.next().unwrap_or_else(|| usage(&args[0])).clone()),
+            _ => positional.push(arg.as_str()),
+        }
     }
 
-    match args[1].as_str() {
+    let verbosity = match (quiet, verbose) {
+        (true, _) => Verbosity::Quiet,
+        (false, 0) => Verbosity::Normal,
+        (false, 1) => Verbosity::Summary,
+        _ => Verbosity::Diagnostics,
+    };
+    VERBOSITY.set(verbosity).unwrap();
+    #[cfg(feature = "log")]
+    if verbosity == Verbosity::Diagnostics && log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Trace);
+    }
+
+    if positional.first() == Some(&"concat") {
+        let output_path = output.unwrap_or_else(|| usage(&args[0]));
+        if positional.len() < 2 {
+            usage(&args[0]);
+        }
+        if let Err(e) = concat_files(&positional[1..], &output_path) {
+            fail_on(Message::ErrorConcat, None, &e);
+        }
+        return;
+    }
+
+    if positional.first() == Some(&"completions") {
+        if positional.len() != 2 {
+            usage(&args[0]);
+        }
+        let shell = Shell::from_name(positional[1]).unwrap_or_else(|| fail(Message::InvalidShell, &[&positional[1]]));
+        // Complete the name the program was run as, e.g. an installed `qp`
+        let program = Path::new(&args[0]).file_name().map_or_else(|| args[0].clone(), |name| name.to_string_lossy().into_owned());
+        print!("{}", completions::script(shell, &program));
+        return;
+    }
+
+    if positional.first() == Some(&"list") {
+        if positional.len() != 2 {
+            usage(&args[0]);
+        }
+        let entries = list_archive(positional[1]).unwrap_or_else(|e| fail_on(Message::ErrorReadArchive, Some(positional[1]), &e));
+        println!("{}", tr(Message::ListHeader, &[]));
+        for entry in &entries {
+            println!("{:>12} {:>12} {:>6.1}%  {}", entry.size(), entry.compressed_len(), entry.ratio() * 100.0, entry.path());
+        }
+        if stats {
+            print_stats(&archive::stats(&entries));
+        }
+        return;
+    }
+
+    if positional.first() == Some(&"reindex") {
+        if positional.len() != 2 {
+            usage(&args[0]);
+        }
+        if let Err(e) = reindex_archive(positional[1]) {
+            fail_on(Message::ErrorReindex, Some(positional[1]), &e);
+        }
+        return;
+    }
+
+    if positional.first() == Some(&"analyze") {
+        if positional.len() != 2 {
+            usage(&args[0]);
+        }
+        let data = fs::read(positional[1]).unwrap_or_else(|e| fail_on(Message::ErrorAnalyze, Some(positional[1]), &e.into()));
+        let analysis = analyze(&data);
+        println!("{}", tr(Message::AnalyzeEntropy, &[&format!("{:.3}", analysis.entropy)]));
+        if let Some(format) = analysis.compressed_format {
+            println!("{}", tr(Message::AnalyzeCompressed, &[&format.name()]));
+        }
+        if top > 0 && !analysis.patterns.is_empty() {
+            println!("{}", tr(Message::AnalyzePatterns, &[&analysis.sample_len]));
+            println!("{}", tr(Message::AnalyzePatternsHeader, &[]));
+            for usage in analysis.patterns.iter().take(top) {
+                println!("{:>11}  {:>11}  \"{}\"", usage.occurrences, usage.bytes_saved, usage.pattern.escape_ascii());
+            }
+        }
+        let percent = format!("{:.1}", analysis.predicted_ratio() * 100.0);
+        println!("{}", tr(Message::AnalyzePredicted, &[&analysis.predicted_size, &analysis.len, &percent]));
+        match analysis.recommendation {
+            Recommendation::Level(level) => println!("{}", tr(Message::AnalyzeRecommended, &[&level.name()])),
+            Recommendation::Store => println!("{}", tr(Message::AnalyzeRecommendStore, &[])),
+        }
+        return;
+    }
+
+    let compressor = || {
+        let mut builder = Preprocessor::builder().dictionary_mode(dict_mode);
+        if let Some(path) = &dict_file {
+            let patterns = read_pattern_file(path).unwrap_or_else(|e| {
+                let error = QuantumPackError::from(e);
+                fail_with(Message::ErrorDictionaryFile, &[path, &error], Some(path), &error)
+            });
+            builder = builder.patterns(patterns);
+        }
+        let mut compressor = Compressor::new()
+            .preprocessor(builder.build())
+            .level(level)
+            .preserve_metadata(preserve_metadata)
+            .on_warning(print_warning());
+        if let Some(limit) = bwlimit {
+            compressor = compressor.bwlimit(limit);
+        }
+        if let Some(size) = block_size {
+            compressor = compressor.block_size(size);
+        }
+        if let Some(size) = pad_to {
+            compressor = compressor.pad_to(size);
+        }
+        compressor
+    };
+
+    if positional.first() == Some(&"recompress") {
+        let output_path = output.unwrap_or_else(|| usage(&args[0]));
+        if positional.len() != 2 {
+            usage(&args[0]);
+        }
+        if let Err(e) = compressor().recompress_file(positional[1], &output_path) {
+            fail_on(Message::ErrorRecompress, Some(positional[1]), &e);
+        }
+        return;
+    }
+
+    if positional.first() == Some(&"archive") {
+        let output_path = output.unwrap_or_else(|| usage(&args[0]));
+        if positional.len() < 2 {
+            usage(&args[0]);
+        }
+        let result = match (append, keep_going) {
+            (_, true) if split_index || append => usage(&args[0]),
+            (true, _) => append_archive(&output_path, &positional[1..], &compressor()),
+            (false, false) if split_index => create_split_archive(&positional[1..], &output_path, &compressor()),
+            (false, true) => create_archive_keep_going(&positional[1..], &output_path, &compressor()).map(|report| finish_batch(Message::ErrorCreateArchive, &report)),
+            (false, false) => create_archive(&positional[1..], &output_path, &compressor()),
+        };
+        if let Err(e) = result {
+            fail_on(Message::ErrorCreateArchive, Some(&output_path), &e);
+        }
+        return;
+    }
+
+    if positional.first() == Some(&"extract") {
+        let mut decompressor = Decompressor::new().preserve_metadata(preserve_metadata).on_warning(print_warning());
+        if let Some(limit) = bwlimit {
+            decompressor = decompressor.bwlimit(limit);
+        }
+        let result = match positional.len() {
+            2 if keep_going => extract_archive_keep_going(positional[1], output.as_deref().unwrap_or("."), &decompressor).map(|report| finish_batch(Message::ErrorExtract, &report)),
+            2 => extract_archive(positional[1], output.as_deref().unwrap_or("."), &decompressor).map(|_| ()),
+            3 => {
+                // Without -o the entry lands in the current directory under its own name
+                let entry_path = positional[2];
+                let output_path = output.as_deref().unwrap_or_else(|| entry_path.rsplit('/').next().unwrap());
+                extract_archive_entry(positional[1], entry_path, output_path, &decompressor)
+            }
+            _ => usage(&args[0]),
+        };
+        if let Err(e) = result {
+            fail_on(Message::ErrorExtract, Some(positional[1]), &e);
+        }
+        return;
+    }
+
+    #[cfg(feature = "trace")]
+    {
+        if positional.first() == Some(&"trace") {
+            if positional.len() != 4 {
+                usage(&args[0]);
+            }
+            if let Err(e) = record_trace(&compressor(), positional[1], positional[2], positional[3]) {
+                fail_on(Message::ErrorTrace, Some(positional[1]), &e);
+            }
+            return;
+        }
+        if positional.first() == Some(&"replay") {
+            if positional.len() != 3 {
+                usage(&args[0]);
+            }
+            match replay_trace(positional[1], positional[2]) {
+                Ok(None) => println!("{}", tr(Message::ReplayFollowed, &[])),
+                Ok(Some(divergence)) => {
+                    println!("{}", tr(Message::ReplayDiverged, &[&divergence]));
+                    process::exit(EXIT_DIVERGED);
+                }
+                Err(e) => fail_on(Message::ErrorReplay, Some(positional[1]), &e),
+            }
+            return;
+        }
+    }
+
+    if positional.len() < 3 {
+        usage(&args[0]);
+    }
+
+    // Only for people watching, never in JSON mode where tools read stderr
+    let progress = progress && verbosity > Verbosity::Quiet && !json_errors() && io::stderr().is_terminal();
+    let totals = Arc::new(Totals::default());
+    let start = Instant::now();
+
+    match positional[0] {
         "compress" => {
-            let input_path = &args[2];
-            let output_path = &args[3];
-            compress_file(input_path, output_path).expect("Error compressing file");
+            let input_path = positional[1];
+            let output_path = positional[2];
+            let mut compressor = compressor();
+            let bar = Some(input_len(input_path)).filter(|_| progress).map(progress_bar);
+            if verbosity >= Verbosity::Summary {
+                compressor = compressor.on_progress(counting(&totals, bar)).on_warning(noting_stored(&totals));
+            } else if let Some(bar) = bar {
+                compressor = compressor.on_progress(bar);
+            }
+            let result = match (input_path, output_path) {
+                ("-", "-") => compressor.compress_to(io::stdin().lock(), io::stdout().lock()),
+                ("-", _) => File::create(output_path).map_err(Into::into).and_then(|output| compressor.compress_to(io::stdin().lock(), output)),
+                (_, "-") => File::open(input_path).map_err(Into::into).and_then(|input| compressor.compress_to(input, io::stdout().lock())),
+                _ => compressor.compress_file(input_path, output_path),
+            };
+            if let Err(e) = result {
+                fail_on(Message::ErrorCompress, Some(input_path), &e);
+            }
+            if verbosity >= Verbosity::Summary {
+                print_summary(input_path, &totals, start);
+            }
         }
-        "decompress" => {
-            let input_path = &args[2];
-            let output_path = &args[3];
-            println!("{:?}", input_path);
-            decompress_file(input_path, output_path).expect("Error decompressing file");
+        "convert" => {
+            if let Err(e) = convert_file(positional[1], positional[2], &compressor()) {
+                fail_on(Message::ErrorConvert, Some(positional[1]), &e);
+            }
         }
-        _ => {
-            eprintln!("Invalid command. Use 'compress' or 'decompress'.");
-            process::exit(1);
+        "decompress" if salvage => match salvage_file(positional[1], positional[2]) {
+            Ok(false) => {}
+            Ok(true) => process::exit(EXIT_PARTIAL),
+            Err(e) => fail_on(Message::ErrorSalvage, Some(positional[1]), &e),
+        },
+        "decompress" => {
+            let input_path = positional[1];
+            let output_path = positional[2];
+            // Files joined by `concat` hold several frames
+            let mut decompressor = Decompressor::new()
+                .trailing_data(trailing_data)
+                .concatenated(true)
+                .preserve_metadata(preserve_metadata)
+                .on_warning(print_warning());
+            if let Some(limit) = bwlimit {
+                decompressor = decompressor.bwlimit(limit);
+            }
+            let bar = Some(input_len(input_path)).filter(|_| progress).map(progress_bar);
+            if verbosity >= Verbosity::Summary {
+                decompressor = decompressor.on_progress(counting(&totals, bar));
+            } else if let Some(bar) = bar {
+                decompressor = decompressor.on_progress(bar);
+            }
+            let result = match (input_path, output_path) {
+                ("-", "-") => decompressor.decompress_to(io::stdin().lock(), io::stdout().lock()),
+                ("-", _) => File::create(output_path).map_err(Into::into).and_then(|output| decompressor.decompress_to(io::stdin().lock(), output)),
+                (_, "-") => File::open(input_path).map_err(Into::into).and_then(|input| decompressor.decompress_to(input, io::stdout().lock())),
+                _ => decompressor.decompress_file(input_path, output_path),
+            };
+            match result {
+                Ok(Some(trailing)) if verbosity > Verbosity::Quiet => {
+                    clear_progress();
+                    eprintln!("{}", tr(Message::TrailingIgnored, &[&trailing.len, &trailing.offset]))
+                }
+                Ok(_) => {}
+                Err(e) => fail_on(Message::ErrorDecompress, Some(input_path), &e),
+            }
+            if verbosity >= Verbosity::Summary {
+                print_summary(input_path, &totals, start);
+            }
         }
+        _ => fail(Message::InvalidCommand, &[]),
     }
+    clear_progress();
 }
-
@@ -0,0 +1,365 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+
+use crate::error::QuantumPackError;
+
+// Reading files is the job of the outer layer
+pub use crate::file::load_catalog;
+
+// Every user-facing string of the qp command line tool. Each has a stable key and a
+// built-in English text; `{0}`, `{1}`, ... stand for the message's arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Message {
+    Usage,
+    UsageConcat,
+    UsageRecompress,
+    UsageConvert,
+    UsageArchive,
+    UsageExtract,
+    UsageExtractEntry,
+    UsageList,
+    UsageReindex,
+    UsageAnalyze,
+    UsageCompletions,
+    UsageExitStatus,
+    UsageTrace,
+    UsageReplay,
+    InvalidCommand,
+    InvalidBwlimit,
+    InvalidBlockSize,
+    InvalidPadTo,
+    InvalidErrorFormat,
+    InvalidShell,
+    InvalidTop,
+    BatchFailed,
+    BatchSummary,
+    Progress,
+    ProgressEta,
+    Summary,
+    SummaryStored,
+    StatsSummary,
+    StatsExpanded,
+    ListHeader,
+    AnalyzeEntropy,
+    AnalyzeCompressed,
+    AnalyzePatterns,
+    AnalyzePatternsHeader,
+    AnalyzePredicted,
+    AnalyzeRecommended,
+    AnalyzeRecommendStore,
+    Warning,
+    TrailingIgnored,
+    SalvageDamaged,
+    SalvageUnknownLength,
+    ReplayFollowed,
+    ReplayDiverged,
+    ErrorCompress,
+    ErrorDecompress,
+    ErrorConcat,
+    ErrorRecompress,
+    ErrorConvert,
+    ErrorDictionaryFile,
+    ErrorCreateArchive,
+    ErrorReadArchive,
+    ErrorReindex,
+    ErrorAnalyze,
+    ErrorExtract,
+    ErrorSalvage,
+    ErrorTrace,
+    ErrorReplay,
+}
+
+impl Message {
+    pub const ALL: [Message; 57] = [
+        Message::Usage,
+        Message::UsageConcat,
+        Message::UsageRecompress,
+        Message::UsageConvert,
+        Message::UsageArchive,
+        Message::UsageExtract,
+        Message::UsageExtractEntry,
+        Message::UsageList,
+        Message::UsageReindex,
+        Message::UsageAnalyze,
+        Message::UsageCompletions,
+        Message::UsageExitStatus,
+        Message::UsageTrace,
+        Message::UsageReplay,
+        Message::InvalidCommand,
+        Message::InvalidBwlimit,
+        Message::InvalidBlockSize,
+        Message::InvalidPadTo,
+        Message::InvalidErrorFormat,
+        Message::InvalidShell,
+        Message::InvalidTop,
+        Message::BatchFailed,
+        Message::BatchSummary,
+        Message::Progress,
+        Message::ProgressEta,
+        Message::Summary,
+        Message::SummaryStored,
+        Message::StatsSummary,
+        Message::StatsExpanded,
+        Message::ListHeader,
+        Message::AnalyzeEntropy,
+        Message::AnalyzeCompressed,
+        Message::AnalyzePatterns,
+        Message::AnalyzePatternsHeader,
+        Message::AnalyzePredicted,
+        Message::AnalyzeRecommended,
+        Message::AnalyzeRecommendStore,
+        Message::Warning,
+        Message::TrailingIgnored,
+        Message::SalvageDamaged,
+        Message::SalvageUnknownLength,
+        Message::ReplayFollowed,
+        Message::ReplayDiverged,
+        Message::ErrorCompress,
+        Message::ErrorDecompress,
+        Message::ErrorConcat,
+        Message::ErrorRecompress,
+        Message::ErrorConvert,
+        Message::ErrorDictionaryFile,
+        Message::ErrorCreateArchive,
+        Message::ErrorReadArchive,
+        Message::ErrorReindex,
+        Message::ErrorAnalyze,
+        Message::ErrorExtract,
+        Message::ErrorSalvage,
+        Message::ErrorTrace,
+        Message::ErrorReplay,
+    ];
+
+    // Name of the message in catalog files
+    pub fn key(self) -> &'static str {
+        match self {
+            Message::Usage => "usage",
+            Message::UsageConcat => "usage.concat",
+            Message::UsageRecompress => "usage.recompress",
+            Message::UsageConvert => "usage.convert",
+            Message::UsageArchive => "usage.archive",
+            Message::UsageExtract => "usage.extract",
+            Message::UsageExtractEntry => "usage.extract_entry",
+            Message::UsageList => "usage.list",
+            Message::UsageReindex => "usage.reindex",
+            Message::UsageAnalyze => "usage.analyze",
+            Message::UsageCompletions => "usage.completions",
+            Message::UsageExitStatus => "usage.exit_status",
+            Message::UsageTrace => "usage.trace",
+            Message::UsageReplay => "usage.replay",
+            Message::InvalidCommand => "invalid.command",
+            Message::InvalidBwlimit => "invalid.bwlimit",
+            Message::InvalidBlockSize => "invalid.block_size",
+            Message::InvalidPadTo => "invalid.pad_to",
+            Message::InvalidErrorFormat => "invalid.error_format",
+            Message::InvalidShell => "invalid.shell",
+            Message::InvalidTop => "invalid.top",
+            Message::BatchFailed => "batch.failed",
+            Message::BatchSummary => "batch.summary",
+            Message::Progress => "progress",
+            Message::ProgressEta => "progress.eta",
+            Message::Summary => "summary",
+            Message::SummaryStored => "summary.stored",
+            Message::StatsSummary => "stats.summary",
+            Message::StatsExpanded => "stats.expanded",
+            Message::ListHeader => "list.header",
+            Message::AnalyzeEntropy => "analyze.entropy",
+            Message::AnalyzeCompressed => "analyze.compressed",
+            Message::AnalyzePatterns => "analyze.patterns",
+            Message::AnalyzePatternsHeader => "analyze.patterns_header",
+            Message::AnalyzePredicted => "analyze.predicted",
+            Message::AnalyzeRecommended => "analyze.recommended",
+            Message::AnalyzeRecommendStore => "analyze.recommend_store",
+            Message::Warning => "warning",
+            Message::TrailingIgnored => "trailing.ignored",
+            Message::SalvageDamaged => "salvage.damaged",
+            Message::SalvageUnknownLength => "salvage.unknown_length",
+            Message::ReplayFollowed => "replay.followed",
+            Message::ReplayDiverged => "replay.diverged",
+            Message::ErrorCompress => "error.compress",
+            Message::ErrorDecompress => "error.decompress",
+            Message::ErrorConcat => "error.concat",
+            Message::ErrorRecompress => "error.recompress",
+            Message::ErrorConvert => "error.convert",
+            Message::ErrorDictionaryFile => "error.dictionary_file",
+            Message::ErrorCreateArchive => "error.create_archive",
+            Message::ErrorReadArchive => "error.read_archive",
+            Message::ErrorReindex => "error.reindex",
+            Message::ErrorAnalyze => "error.analyze",
+            Message::ErrorExtract => "error.extract",
+            Message::ErrorSalvage => "error.salvage",
+            Message::ErrorTrace => "error.trace",
+            Message::ErrorReplay => "error.replay",
+        }
+    }
+
+    pub fn english(self) -> &'static str {
+        match self {
+            Message::Usage => "Usage: {0} [compress|decompress] <input file|-> <output file|-> [-1..-9] [--bwlimit <bytes/sec>] [--dict-file <path> [--dict-replace]] [--allow-trailing] [--no-metadata] [--block-size <bytes>] [--pad-to <bytes>] [--salvage] [--no-progress] [--error-format text|json] [-q|-v|-vv]",
+            Message::UsageConcat => "       {0} concat <input file>... -o <output file>",
+            Message::UsageRecompress => "       {0} recompress <input file> -o <output file> [--level fast|default|best] [-1..-9] [--dict-file <path> [--dict-replace]]",
+            Message::UsageConvert => "       {0} convert <input file> <output file>  (gzip/zstd by magic bytes in, by .gz/.zst extension out)",
+            Message::UsageArchive => "       {0} archive <input file or directory>... -o <output .qpa> [--append | --split-index | --keep-going] [--no-metadata] [-1..-9] [--dict-file <path> [--dict-replace]]",
+            Message::UsageExtract => "       {0} extract <input .qpa> [-o <output directory>] [--keep-going] [--no-metadata]",
+            Message::UsageExtractEntry => "       {0} extract <input .qpa> <path in archive> [-o <output file>] [--no-metadata]",
+            Message::UsageList => "       {0} list <input .qpa> [--stats]",
+            Message::UsageReindex => "       {0} reindex <split .qpa>",
+            Message::UsageAnalyze => "       {0} analyze <input file> [--top <n>]",
+            Message::UsageCompletions => "       {0} completions <bash|zsh|fish|powershell>",
+            Message::UsageExitStatus => "Exit status: 0 success, 1 invalid arguments, 2 partial failure, 3 I/O error, 4 corrupt input, 5 checksum mismatch, 6 replay diverged",
+            Message::UsageTrace => "       {0} trace <input file> <output file> <trace file>",
+            Message::UsageReplay => "       {0} replay <compressed file> <trace file>",
+            Message::InvalidCommand => "Invalid command. Use 'compress', 'decompress', 'concat', 'recompress' or 'convert'.",
+            Message::InvalidBwlimit => "Invalid --bwlimit value: {0}",
+            Message::InvalidBlockSize => "Invalid --block-size value: {0}",
+            Message::InvalidPadTo => "Invalid --pad-to value: {0}",
+            Message::InvalidErrorFormat => "Invalid --error-format value: {0} (expected text or json)",
+            Message::InvalidShell => "Unknown shell: {0} (expected bash, zsh, fish or powershell)",
+            Message::InvalidTop => "Invalid --top value: {0}",
+            Message::BatchFailed => "Failed: {0}: {1}",
+            Message::BatchSummary => "{0} of {1} inputs failed",
+            Message::Progress => "{0}, {1}/s",
+            Message::ProgressEta => "{0} of {1} ({2}%), {3}/s, {4} left",
+            Message::Summary => "{0}: {1} -> {2} bytes ({3}%) in {4}",
+            Message::SummaryStored => "{0}: stored as is, the input is {1} data",
+            Message::StatsSummary => "{0} members, {1} -> {2} bytes",
+            Message::StatsExpanded => "Expanded members (consider excluding them):",
+            Message::ListHeader => "        size   compressed   ratio  name",
+            Message::AnalyzeEntropy => "Entropy: {0} bits per byte",
+            Message::AnalyzeCompressed => "Already compressed: {0}",
+            Message::AnalyzePatterns => "Top patterns in a {0} byte sample:",
+            Message::AnalyzePatternsHeader => "occurrences  bytes saved  pattern",
+            Message::AnalyzePredicted => "Predicted size: {0} of {1} bytes ({2}%)",
+            Message::AnalyzeRecommended => "Recommended level: {0}",
+            Message::AnalyzeRecommendStore => "Recommended: leave it uncompressed, coding will not make it smaller",
+            Message::Warning => "Warning: {0}",
+            Message::TrailingIgnored => "Ignored {0} trailing bytes at offset {1}",
+            Message::SalvageDamaged => "Damaged input at bytes {0}..{1}: {2} bytes missing at output offset {3}",
+            Message::SalvageUnknownLength => "an unknown number of",
+            Message::ReplayFollowed => "The decoder followed the recorded path",
+            Message::ReplayDiverged => "Diverged: {0}",
+            Message::ErrorCompress => "Error compressing file: {0}",
+            Message::ErrorDecompress => "Error decompressing file: {0}",
+            Message::ErrorConcat => "Error concatenating files: {0}",
+            Message::ErrorRecompress => "Error recompressing file: {0}",
+            Message::ErrorConvert => "Error converting file: {0}",
+            Message::ErrorDictionaryFile => "Error reading dictionary file {0}: {1}",
+            Message::ErrorCreateArchive => "Error creating archive: {0}",
+            Message::ErrorReadArchive => "Error reading archive: {0}",
+            Message::ErrorReindex => "Error rebuilding archive index: {0}",
+            Message::ErrorAnalyze => "Error analyzing file: {0}",
+            Message::ErrorExtract => "Error extracting archive: {0}",
+            Message::ErrorSalvage => "Error salvaging file: {0}",
+            Message::ErrorTrace => "Error recording trace: {0}",
+            Message::ErrorReplay => "Error replaying trace: {0}",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Message> {
+        Message::ALL.iter().copied().find(|message| message.key() == key)
+    }
+}
+
+// Message texts for one locale. Messages without a translation use the English
+// text, so a partial catalog is still usable. Catalog files hold one message per
+// line:
+//
+//     # German
+//     error.compress = Fehler beim Komprimieren: {0}
+//     stats.summary = {0} Dateien, {1} -> {2} Bytes
+//
+// Lines starting with '#' are comments. A translation may reorder or leave out the
+// arguments but not refer to ones the message does not have. The messages of the
+// library's errors, passed in as arguments, stay English.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Catalog {
+    translations: BTreeMap<Message, String>,
+}
+
+impl Catalog {
+    // The built-in English texts
+    pub fn english() -> Self {
+        Catalog::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, QuantumPackError> {
+        let mut translations = BTreeMap::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim_start();
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| QuantumPackError::InvalidCatalog { line: index + 1, message };
+            let (key, text) = match line.find('=') {
+                Some(position) => (line[..position].trim(), line[position + 1..].trim()),
+                None => return Err(error(format!("expected key = text, found {:?}", line))),
+            };
+            let message = Message::from_key(key).ok_or_else(|| error(format!("unknown message {:?}", key)))?;
+            let arguments = placeholders(message.english()).max().map_or(0, |last| last + 1);
+            if let Some(argument) = placeholders(text).find(|&argument| argument >= arguments) {
+                return Err(error(format!("{} has no argument {{{}}}", key, argument)));
+            }
+            if translations.insert(message, text.to_string()).is_some() {
+                return Err(error(format!("{} is translated twice", key)));
+            }
+        }
+        Ok(Catalog { translations })
+    }
+
+    pub fn text(&self, message: Message) -> &str {
+        self.translations.get(&message).map_or(message.english(), String::as_str)
+    }
+
+    // The text of `message` with its placeholders replaced by `arguments`
+    pub fn format(&self, message: Message, arguments: &[&dyn fmt::Display]) -> String {
+        let mut output = String::new();
+        let mut rest = self.text(message);
+        while let Some(start) = rest.find('{') {
+            output.push_str(&rest[..start]);
+            rest = &rest[start..];
+            match parse_placeholder(rest) {
+                Some((argument, len)) => {
+                    if let Some(value) = arguments.get(argument) {
+                        let _ = write!(output, "{}", value);
+                    }
+                    rest = &rest[len..];
+                }
+                None => {
+                    output.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        output.push_str(rest);
+        output
+    }
+}
+
+// Candidate catalog names for a POSIX locale such as "de_AT.UTF-8@euro", most
+// specific first: ["de_AT", "de"]. The C locale has none.
+pub fn locale_names(locale: &str) -> Vec<String> {
+    let locale = locale.split(['.', '@']).next().unwrap_or("");
+    if locale.is_empty() || locale == "C" || locale == "POSIX" {
+        return Vec::new();
+    }
+    let mut names = vec![locale.to_string()];
+    if let Some(position) = locale.find('_') {
+        names.push(locale[..position].to_string());
+    }
+    names
+}
+
+// The argument numbers used in `text`
+fn placeholders(text: &str) -> impl Iterator<Item = usize> + '_ {
+    text.match_indices('{').filter_map(move |(position, _)| parse_placeholder(&text[position..]).map(|(argument, _)| argument))
+}
+
+// "{n}" at the start of `text`: the argument number and the placeholder's length
+fn parse_placeholder(text: &str) -> Option<(usize, usize)> {
+    let end = text.find('}')?;
+    let digits = &text[1..end];
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    Some((digits.parse().ok()?, end + 1))
+}
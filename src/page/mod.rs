@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+
+use crate::adaptive_dictionary::AdaptiveDictionary;
+use crate::compression::{deserialize_code_lengths, serialize_code_lengths};
+use crate::error::QuantumPackError;
+use crate::huffman::{HuffmanNode, build_huffman_tree_from_codes, build_huffman_tree_seeded, canonical_codes, code_lengths, generate_huffman_codes, huffman_decode, huffman_encode};
+use crate::preprocessor::{Preprocessor, TrainedDictionary};
+use crate::wire::{self, Reader};
+
+// The page header is a big-endian u16: the top bit marks a page stored as is,
+// the other 15 bits hold the length of the page body
+const STORED_FLAG: u16 = 0x8000;
+pub const MAX_PAGE_SIZE: usize = 0x7FFF;
+// Every byte value gets a code so pages may contain bytes the sample did not
+const LONGEST_CODE: u8 = 64;
+
+// Compresses fixed-size pages (database pages, column blocks, records) one at a time.
+// The dictionary and the Huffman code are trained once on a sample and shared by
+// every page, so a page only carries a 2 byte header and its encoded bits and can
+// be decoded without touching its neighbours.
+pub struct PageCodec {
+    page_size: usize,
+    preprocessor: Preprocessor,
+    lengths: BTreeMap<u8, u8>,
+    codes: BTreeMap<u8, Vec<u8>>,
+    tree: Box<HuffmanNode>,
+}
+
+impl PageCodec {
+    pub fn train(sample: &[u8], page_size: usize) -> Result<Self, QuantumPackError> {
+        PageCodec::train_with(Preprocessor::new(), sample, page_size)
+    }
+
+    // Fit `preprocessor` (e.g. one built with user patterns) to the sample
+    pub fn train_with(mut preprocessor: Preprocessor, sample: &[u8], page_size: usize) -> Result<Self, QuantumPackError> {
+        preprocessor.fit(sample);
+
+        let mut frequencies = AdaptiveDictionary::new();
+        for byte in 0..=255u8 {
+            frequencies.frequencies.insert(byte, 1);
+        }
+        frequencies.update(&preprocessor.apply(sample));
+        let tree = build_huffman_tree_seeded(&frequencies, preprocessor.tie_seed()).unwrap();
+        let mut codes = BTreeMap::new();
+        generate_huffman_codes(&tree, &mut vec![], &mut codes);
+
+        PageCodec::from_parts(page_size, preprocessor.dictionary().clone(), code_lengths(&codes))
+    }
+
+    fn from_parts(page_size: usize, dictionary: TrainedDictionary, lengths: BTreeMap<u8, u8>) -> Result<Self, QuantumPackError> {
+        if page_size == 0 || page_size > MAX_PAGE_SIZE {
+            return Err(invalid_page(format!("page size must be between 1 and {} bytes", MAX_PAGE_SIZE)));
+        }
+        if !is_complete_code(&lengths) {
+            return Err(invalid_page("page model has an invalid Huffman code"));
+        }
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.set_dictionary(dictionary);
+        let codes = canonical_codes(&lengths);
+        let tree = build_huffman_tree_from_codes(&codes).unwrap();
+        Ok(PageCodec { page_size, preprocessor, lengths, codes, tree })
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    pub fn dictionary(&self) -> &TrainedDictionary {
+        self.preprocessor.dictionary()
+    }
+
+    // Layout: u32 page size, u32 code table length, (symbol, length) pairs, dictionary
+    pub fn serialize(&self) -> Vec<u8> {
+        let table = serialize_code_lengths(&self.lengths);
+        let mut serialized = Vec::new();
+        wire::write_u32(&mut serialized, self.page_size as u32);
+        wire::write_u32(&mut serialized, table.len() as u32);
+        serialized.extend_from_slice(&table);
+        serialized.extend_from_slice(&self.dictionary().serialize());
+        serialized
+    }
+
+    pub fn deserialize(serialized: &[u8]) -> Result<Self, QuantumPackError> {
+        let mut reader = Reader::new(serialized, "page model");
+        let page_size = reader.u32()? as usize;
+        let table_len = reader.u32()? as usize;
+        let lengths = deserialize_code_lengths(reader.bytes(table_len)?);
+        let dictionary = TrainedDictionary::deserialize(reader.rest())?;
+        PageCodec::from_parts(page_size, dictionary, lengths)
+    }
+
+    // Append the header and body of one page to `output`. Pages that would not
+    // shrink are stored.
+    pub fn encode_page(&self, page: &[u8], output: &mut Vec<u8>) -> Result<(), QuantumPackError> {
+        if page.len() > self.page_size {
+            return Err(QuantumPackError::InvalidInput(format!(
+                "page of {} bytes exceeds the page size of {}",
+                page.len(),
+                self.page_size
+            )));
+        }
+        let transformed = self.preprocessor.apply(page);
+        let encoded = huffman_encode(&transformed, &self.codes);
+
+        if encoded.len() < page.len() {
+            wire::write_u16(output, encoded.len() as u16);
+            output.extend_from_slice(&encoded);
+        } else {
+            wire::write_u16(output, page.len() as u16 | STORED_FLAG);
+            output.extend_from_slice(page);
+        }
+        Ok(())
+    }
+
+    // Decode the page at the start of `input`. Returns the page and the number of
+    // bytes it occupied, i.e. where the next page starts.
+    pub fn decode_page(&self, input: &[u8]) -> Result<(Vec<u8>, usize), QuantumPackError> {
+        let mut reader = Reader::new(input, "page");
+        let header = reader.u16()?;
+        let body_len = (header & !STORED_FLAG) as usize;
+        let body = reader.bytes(body_len)?;
+
+        let page = if header & STORED_FLAG != 0 {
+            body.to_vec()
+        } else {
+            self.preprocessor.reverse_transform_data(&huffman_decode(body, &self.tree)?)
+        };
+        if page.len() > self.page_size {
+            return Err(invalid_page("decoded page exceeds the page size"));
+        }
+        Ok((page, 2 + body_len))
+    }
+
+    // Split `data` into pages and encode them back to back
+    pub fn encode_pages(&self, data: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+        let mut output = Vec::new();
+        for page in data.chunks(self.page_size) {
+            self.encode_page(page, &mut output)?;
+        }
+        Ok(output)
+    }
+
+    pub fn decode_pages(&self, mut input: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+        let mut output = Vec::new();
+        while !input.is_empty() {
+            let (page, used) = self.decode_page(input)?;
+            output.extend_from_slice(&page);
+            input = &input[used..];
+        }
+        Ok(output)
+    }
+}
+
+// A code that assigns every byte value and leaves no bit pattern undecodable,
+// i.e. the Kraft sum is exactly one
+fn is_complete_code(lengths: &BTreeMap<u8, u8>) -> bool {
+    if lengths.len() != 256 || lengths.values().any(|&length| length == 0 || length > LONGEST_CODE) {
+        return false;
+    }
+    let kraft: u128 = lengths.values().map(|&length| 1u128 << (LONGEST_CODE - length)).sum();
+    kraft == 1u128 << LONGEST_CODE
+}
+
+fn invalid_page<E: Into<String>>(message: E) -> QuantumPackError {
+    QuantumPackError::InvalidPage(message.into())
+}
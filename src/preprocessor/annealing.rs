@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+// Only this much of the input is parsed when scoring a candidate subset
+const SAMPLE_LEN: usize = 4096;
+
+// Small deterministic PRNG (xorshift64*) so a given seed always yields the same dictionary
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng(seed.max(1))
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub(crate) fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    pub(crate) fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+pub(crate) struct Annealer<'a> {
+    pub sample: &'a [u8],
+    // Patterns that are always in the dictionary, with their frequencies
+    pub fixed: &'a [(Vec<u8>, u32)],
+    // Mined patterns to choose from, best first
+    pub candidates: &'a [(Vec<u8>, u32)],
+    pub slots: usize,
+    pub max_pattern_length: usize,
+    pub iterations: u32,
+    pub seed: u64,
+}
+
+impl<'a> Annealer<'a> {
+    // Indices into `candidates` of the best subset found, starting from the greedy choice
+    pub(crate) fn run(&self) -> Vec<usize> {
+        let sample = &self.sample[..self.sample.len().min(SAMPLE_LEN)];
+        let mut chosen: Vec<bool> = (0..self.candidates.len()).map(|index| index < self.slots).collect();
+        if self.candidates.is_empty() || self.slots == 0 {
+            return Vec::new();
+        }
+
+        let mut rng = Rng::new(self.seed);
+        let mut current_cost = self.cost(sample, &chosen);
+        let mut best = chosen.clone();
+        let mut best_cost = current_cost;
+        let initial_temperature = (current_cost as f64 * 0.01).max(1.0);
+
+        for iteration in 0..self.iterations {
+            let temperature = initial_temperature * (1.0 - iteration as f64 / self.iterations as f64);
+
+            // Toggle one candidate; when the dictionary is full, swap instead of adding
+            let index = rng.below(self.candidates.len());
+            let mut proposal = chosen.clone();
+            proposal[index] = !proposal[index];
+            if proposal.iter().filter(|&&included| included).count() > self.slots {
+                let included: Vec<usize> = (0..proposal.len()).filter(|&i| proposal[i] && i != index).collect();
+                proposal[included[rng.below(included.len())]] = false;
+            }
+
+            let cost = self.cost(sample, &proposal);
+            let delta = cost as f64 - current_cost as f64;
+            if delta <= 0.0 || (temperature > 0.0 && rng.unit() < (-delta / temperature).exp()) {
+                chosen = proposal;
+                current_cost = cost;
+                if cost < best_cost {
+                    best = chosen.clone();
+                    best_cost = cost;
+                }
+            }
+        }
+
+        (0..best.len()).filter(|&index| best[index]).collect()
+    }
+
+    // Preprocessed size of the sample plus the dictionary header, approximating transform_data
+    fn cost(&self, sample: &[u8], chosen: &[bool]) -> usize {
+        let mut dictionary: HashMap<&[u8], u32> = HashMap::new();
+        let selected = self.candidates.iter().zip(chosen).filter(|(_, &included)| included).map(|(candidate, _)| candidate);
+        for (pattern, freq) in self.fixed.iter().chain(selected) {
+            dictionary.insert(pattern, *freq);
+        }
+
+        let header: usize = dictionary.keys().map(|pattern| 3 + pattern.len()).sum();
+        let mut size = 0;
+        let mut i = 0;
+        while i < sample.len() {
+            let longest = self.max_pattern_length.min(sample.len() - i);
+            match (2..=longest).rev().find(|&len| dictionary.contains_key(&sample[i..i + len])) {
+                Some(len) => i += len,
+                None => {
+                    // Single byte patterns take the long form unless they are frequent
+                    if let Some(&freq) = dictionary.get(&sample[i..i + 1]) {
+                        if freq <= 100 {
+                            size += 1;
+                        }
+                    }
+                    i += 1;
+                }
+            }
+            size += 1;
+        }
+        size + header
+    }
+}
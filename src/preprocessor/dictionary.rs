@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use crate::error::QuantumPackError;
+use crate::wire::{self, Reader};
+
+// Version 2 introduced the leading version byte and varint pattern lengths
+const DICTIONARY_FORMAT_VERSION: u8 = 2;
+
+// FNV-1a, used for the dictionary ID so it is stable across builds and platforms
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// The patterns learned by Preprocessor::fit and the codes assigned to them. Pattern
+// frequencies are statistics from the fitted input; they are not serialized and do
+// not take part in equality or the ID, so a dictionary read back from its
+// serialized form equals the one that was written.
+#[derive(Debug, Clone, Default)]
+pub struct TrainedDictionary {
+    patterns: BTreeMap<Vec<u8>, u16>,
+    codes: BTreeMap<u16, Vec<u8>>,
+    frequencies: BTreeMap<u16, u32>,
+}
+
+impl PartialEq for TrainedDictionary {
+    fn eq(&self, other: &Self) -> bool {
+        self.codes == other.codes
+    }
+}
+
+impl Eq for TrainedDictionary {}
+
+impl TrainedDictionary {
+    pub fn new() -> Self {
+        TrainedDictionary::default()
+    }
+
+    // Fails if the pattern is empty, the code is the reserved code 0 or the pattern
+    // or code is already in the dictionary
+    pub fn insert(&mut self, code: u16, pattern: Vec<u8>, frequency: u32) -> Result<(), QuantumPackError> {
+        if code == 0 {
+            return Err(invalid_dictionary("pattern code 0 is reserved"));
+        }
+        if pattern.is_empty() {
+            return Err(invalid_dictionary(format!("empty pattern for code {}", code)));
+        }
+        if self.codes.contains_key(&code) {
+            return Err(invalid_dictionary(format!("duplicate pattern code {}", code)));
+        }
+        if self.patterns.contains_key(&pattern) {
+            return Err(invalid_dictionary(format!("duplicate pattern {:?}", pattern)));
+        }
+        self.patterns.insert(pattern.clone(), code);
+        self.codes.insert(code, pattern);
+        self.frequencies.insert(code, frequency);
+        Ok(())
+    }
+
+    pub fn code(&self, pattern: &[u8]) -> Option<u16> {
+        self.patterns.get(pattern).cloned()
+    }
+
+    pub fn pattern(&self, code: u16) -> Option<&[u8]> {
+        self.codes.get(&code).map(Vec::as_slice)
+    }
+
+    // Occurrences in the fitted input; 0 for unknown codes and deserialized dictionaries
+    pub fn frequency(&self, code: u16) -> u32 {
+        self.frequencies.get(&code).cloned().unwrap_or(0)
+    }
+
+    pub fn contains_pattern(&self, pattern: &[u8]) -> bool {
+        self.patterns.contains_key(pattern)
+    }
+
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+
+    // Entries in code order
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &[u8])> {
+        self.codes.iter().map(|(&code, pattern)| (code, pattern.as_slice()))
+    }
+
+    // Which bytes a pattern of two or more bytes starts with. Parsers skip runs of
+    // the other bytes without looking anything up: they can only be single tokens.
+    pub(crate) fn pattern_starts(&self) -> [bool; 256] {
+        let mut starts = [false; 256];
+        for pattern in self.patterns.keys().filter(|pattern| pattern.len() > 1) {
+            starts[pattern[0] as usize] = true;
+        }
+        starts
+    }
+
+    pub fn longest_pattern(&self) -> usize {
+        self.patterns.keys().map(Vec::len).max().unwrap_or(0)
+    }
+
+    // Size of `serialize()` without the version byte
+    pub fn serialized_len(&self) -> usize {
+        self.patterns.keys().map(|pattern| serialized_entry_len(pattern)).sum()
+    }
+
+    // Content hash of the serialized entries; equal dictionaries have equal IDs
+    pub fn id(&self) -> u64 {
+        self.serialize().iter().fold(FNV_OFFSET_BASIS, |hash, &byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+    }
+
+    // The ID frames compressed with this as a preset dictionary record instead of the
+    // dictionary itself, see Compressor::preset_dictionary. Both halves of `id` are
+    // folded in.
+    pub fn preset_id(&self) -> u32 {
+        let id = self.id();
+        (id ^ (id >> 32)) as u32
+    }
+
+    // Layout: version byte, then per entry a big-endian u16 code, the pattern
+    // length as a LEB128 varint and the pattern bytes
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut serialized = vec![DICTIONARY_FORMAT_VERSION];
+        for (&code, pattern) in &self.codes {
+            wire::write_u16(&mut serialized, code); // Code to bytes
+            wire::write_varint(&mut serialized, pattern.len() as u64); // Length of the pattern
+            serialized.extend(pattern); // The pattern itself
+        }
+        serialized
+    }
+
+    pub fn deserialize(serialized: &[u8]) -> Result<Self, QuantumPackError> {
+        let mut dictionary = TrainedDictionary::new();
+        let (version, entries) = match serialized.split_first() {
+            None => return Ok(dictionary),
+            // Version 1 had no version byte; its first byte is the high byte of code 1
+            Some((0, _)) => (1, serialized),
+            Some((&DICTIONARY_FORMAT_VERSION, rest)) => (DICTIONARY_FORMAT_VERSION, rest),
+            Some((&version, _)) => {
+                return Err(invalid_dictionary(format!("unsupported dictionary format version {}", version)));
+            }
+        };
+
+        let mut reader = Reader::new(entries, "dictionary");
+        while !reader.is_empty() {
+            let code = reader.u16()?;
+            let pattern_len = if version == 1 { reader.u8()? as u64 } else { reader.varint()? };
+            if pattern_len == 0 || pattern_len > reader.remaining() as u64 {
+                return Err(invalid_dictionary(format!("invalid length {} for pattern code {}", pattern_len, code)));
+            }
+            dictionary.insert(code, reader.bytes(pattern_len as usize)?.to_vec(), 0)?;
+        }
+        Ok(dictionary)
+    }
+}
+
+// A trained dictionary that can be replaced while compressors use it, for services
+// that retrain periodically. Every frame takes a snapshot when it starts and embeds
+// it, so a swap only affects frames started afterwards and streams in flight keep
+// decoding.
+#[derive(Debug, Default)]
+pub struct SharedDictionary {
+    current: RwLock<Arc<TrainedDictionary>>,
+}
+
+impl SharedDictionary {
+    pub fn new(dictionary: TrainedDictionary) -> Self {
+        SharedDictionary { current: RwLock::new(Arc::new(dictionary)) }
+    }
+
+    // The dictionary new frames are compressed with
+    pub fn load(&self) -> Arc<TrainedDictionary> {
+        Arc::clone(&self.current.read().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+
+    // Atomically replace the dictionary, returning the one it replaces
+    pub fn swap(&self, dictionary: TrainedDictionary) -> Arc<TrainedDictionary> {
+        let mut current = self.current.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        std::mem::replace(&mut *current, Arc::new(dictionary))
+    }
+
+    pub fn id(&self) -> u64 {
+        self.load().id()
+    }
+}
+
+// Code, length varint and pattern bytes, as written by serialize
+pub(crate) fn serialized_entry_len(pattern: &[u8]) -> usize {
+    2 + wire::varint_len(pattern.len() as u64) + pattern.len()
+}
+
+fn invalid_dictionary<E: Into<String>>(message: E) -> QuantumPackError {
+    QuantumPackError::InvalidDictionary(message.into())
+}
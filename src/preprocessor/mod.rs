@@ -1,132 +1,663 @@
 use std::collections::{BTreeMap, BTreeSet};
 use std::iter::FromIterator;
-use std::thread;
+
+use crate::analysis;
+use crate::cost::{self, symbol_bits};
+use crate::error::{escape_json, QuantumPackError};
+use crate::wire;
+
+mod annealing;
+mod dictionary;
+mod parallel;
+mod training;
+
+use annealing::Annealer;
+use dictionary::serialized_entry_len;
+use parallel::for_each_chunk;
+use training::training_corpus;
+pub(crate) use training::take_spread;
+
+pub use dictionary::{SharedDictionary, TrainedDictionary};
+pub use training::TrainingSample;
+// Kept here for existing callers; reading files is the job of the outer layer
+pub use crate::file::read_pattern_file;
+
+// Codes up to 254 are emitted as single bytes, 0xFF is reserved as the long-code prefix
+const MAX_SHORT_CODE: u16 = 254;
+// 0xFF starts every token that is not a single byte: 0xFF c is the long form of
+// code c, 0xFF 0x00 b a literal b that would otherwise read as a code or escape
+const ESCAPE: u8 = 0xFF;
+const LITERAL_ESCAPE: [u8; 2] = [ESCAPE, 0x00];
+// Larger codes are emitted as 0xFF 0xFF followed by the big-endian code
+const WIDE_CODE_PREFIX: [u8; 2] = [ESCAPE, ESCAPE];
+const WIDE_CODE_LEN: usize = 4;
+// Input is split into chunks of this size for the parallel transform
+const PARALLEL_CHUNK_SIZE: usize = 64 * 1024;
+// Mining patterns is superlinear in the input size, so by default only the start of
+// large inputs is used to fit the model. Inputs past this size compress with patterns
+// mined from their first 8 MiB; see PreprocessorBuilder::sample_size.
+pub const DEFAULT_SAMPLE_SIZE: usize = 8 * 1024 * 1024;
+// Bits per byte above which the input looks random and mining patterns is skipped,
+// e.g. compressed or encrypted data (8 bits at most)
+const DEFAULT_ENTROPY_BYPASS: f64 = 7.5;
+
+// How mined patterns are chosen for the dictionary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternSelection {
+    // Most frequent patterns first
+    Greedy,
+    // Simulated annealing over subsets of the mined patterns, scored by the encoded size
+    // of a sample of the input. Slower, but never worse than greedy on that sample.
+    Annealing { iterations: u32, seed: u64 },
+}
+
+// How the input is split into literals and pattern codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tokenization {
+    // Longest match at each position
+    Greedy,
+    // Dynamic programming over all matches, minimizing the estimated encoded bits
+    // (-log2 of each token's frequency in the fitted input)
+    Optimal,
+}
+
+// Decides which single byte pattern codes get the one byte form and which the
+// 0xFF-prefixed two byte form
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CodeLengthModel {
+    // One byte for codes whose share of the input is at least `min_probability`.
+    // The default of 1/256 gives the short form to every code an optimal entropy
+    // coder would spend at most 8 bits on.
+    ProbabilityMass { min_probability: f64 },
+    // One byte for codes seen more than this many times, regardless of input size
+    FrequencyThreshold(u32),
+}
+
+impl Default for CodeLengthModel {
+    fn default() -> Self {
+        CodeLengthModel::ProbabilityMass { min_probability: 1.0 / 256.0 }
+    }
+}
+
+// How user supplied patterns combine with the ones mined from the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DictionaryMode {
+    // User patterns get the first codes, mined patterns fill the remaining slots
+    Merge,
+    // Only the user patterns are used, nothing is mined
+    Replace,
+}
+
+// Occurrence count and net bytes saved, per pattern code
+type UsageCounts = BTreeMap<u16, (u32, i64)>;
+
+// How a byte parsed as a token of its own is written out
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ByteKind {
+    // Copied through as is
+    Plain,
+    // Preceded by LITERAL_ESCAPE, see needs_escape
+    Escaped,
+    // A single byte pattern, replaced by its code
+    Pattern(u16),
+}
+
+// Iterator returned by Preprocessor::transform_stream, yielding transformed bytes
+// for each input piece (possibly empty) and a final flush of the held back tail
+pub struct TransformStream<'a, I> {
+    preprocessor: &'a Preprocessor,
+    chunks: I,
+    pending: Vec<u8>,
+    finished: bool,
+}
+
+impl<'a, I, C> Iterator for TransformStream<'a, I>
+where
+    I: Iterator<Item = C>,
+    C: AsRef<[u8]>,
+{
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        if self.finished {
+            return None;
+        }
+        let is_last = match self.chunks.next() {
+            Some(chunk) => {
+                self.pending.extend_from_slice(chunk.as_ref());
+                false
+            }
+            None => {
+                self.finished = true;
+                true
+            }
+        };
+
+        let (sizes, consumed) = self.preprocessor.parse_prefix(&self.pending, is_last);
+        let (output, _) = self.preprocessor.emit_tokens(&self.pending[..consumed], &sizes);
+        self.pending.drain(..consumed);
+        Some(output)
+    }
+}
+
+// One step of the transform, before it is written out as bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    // An input byte copied through, escaped in the output if it would read as a code
+    Literal(u8),
+    // `len` input bytes replaced by the code of the dictionary pattern they match
+    Pattern { code: u16, len: usize },
+}
+
+impl Token {
+    // Input bytes the token stands for
+    pub fn input_len(&self) -> usize {
+        match *self {
+            Token::Literal(_) => 1,
+            Token::Pattern { len, .. } => len,
+        }
+    }
+}
+
+// How much a single dictionary pattern contributed to the preprocessed output
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatternUsage {
+    pub pattern: Vec<u8>,
+    pub code: u16,
+    pub occurrences: u32,
+    // Net bytes saved; negative when the pattern's encoding is longer than what it replaced
+    pub bytes_saved: i64,
+}
 
 #[derive(Clone)]
 pub struct Preprocessor {
-    pub pattern_map: BTreeMap<Vec<u8>, u16>,
-    pub reverse_pattern_map: BTreeMap<u16, Vec<u8>>,
-    pub next_code: u16,
+    dictionary: TrainedDictionary,
+    // Codes not handed out yet, the next one last
+    free_codes: Vec<u16>,
     max_pattern_length: usize,
-    code_frequency: BTreeMap<u16, u32>,
     prediction_model: BTreeMap<Vec<u8>, u8>,
+    pattern_usage: UsageCounts,
+    entropy: f64,
+    // Mining is skipped for samples with more bits per byte than this
+    entropy_bypass: f64,
+    // Whether the last fit skipped mining
+    bypassed: bool,
+    user_patterns: Vec<Vec<u8>>,
+    dictionary_mode: DictionaryMode,
+    required_patterns: Vec<Vec<u8>>,
+    denied_sequences: Vec<Vec<u8>>,
+    max_entries: usize,
+    max_dictionary_bytes: usize,
+    // Longest pattern mined from the input
+    pattern_length_limit: usize,
+    // Only this many leading bytes of the input are used to fit the model
+    sample_size: usize,
+    pattern_selection: PatternSelection,
+    // Orders mined patterns of equal frequency, see pattern_rank
+    tie_seed: u32,
+    tokenization: Tokenization,
+    code_length_model: CodeLengthModel,
+    literal_bits: Vec<f64>,
+    fitted_len: usize,
+}
+
+// Configures a Preprocessor before it is fitted to any data
+#[derive(Clone)]
+pub struct PreprocessorBuilder {
+    preprocessor: Preprocessor,
+}
+
+impl PreprocessorBuilder {
+    pub fn new() -> Self {
+        PreprocessorBuilder {
+            preprocessor: Preprocessor::new(),
+        }
+    }
+
+    // Add a domain specific pattern (SQL keyword, protocol field name, ...)
+    pub fn pattern(mut self, pattern: &[u8]) -> Self {
+        self.preprocessor.user_patterns.push(pattern.to_vec());
+        self
+    }
+
+    pub fn patterns<I, P>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<[u8]>,
+    {
+        self.preprocessor.user_patterns.extend(patterns.into_iter().map(|pattern| pattern.as_ref().to_vec()));
+        self
+    }
+
+    pub fn dictionary_mode(mut self, mode: DictionaryMode) -> Self {
+        self.preprocessor.dictionary_mode = mode;
+        self
+    }
+
+    // Always include this pattern, ahead of user and mined patterns
+    pub fn allow(mut self, pattern: &[u8]) -> Self {
+        self.preprocessor.required_patterns.push(pattern.to_vec());
+        self
+    }
+
+    // Never substitute any part of this sequence (e.g. a record delimiter that must
+    // stay visible in the preprocessed stream). No code byte will equal one of its
+    // bytes either, so it cannot appear spuriously. Deny wins over allow.
+    pub fn deny(mut self, sequence: &[u8]) -> Self {
+        if !sequence.is_empty() {
+            self.preprocessor.denied_sequences.push(sequence.to_vec());
+        }
+        self
+    }
+
+    // Maximum number of dictionary entries. Beyond 254 entries the extra codes use
+    // a 4 byte wide form, so they only pay off for patterns longer than 4 bytes.
+    pub fn max_entries(mut self, entries: usize) -> Self {
+        self.preprocessor.max_entries = entries.min(u16::MAX as usize - 1);
+        self
+    }
+
+    // Upper bound on the serialized dictionary size stored in the header
+    pub fn max_dictionary_bytes(mut self, bytes: usize) -> Self {
+        self.preprocessor.max_dictionary_bytes = bytes;
+        self
+    }
+
+    // Longest pattern mined from the input; user patterns may be longer. Longer
+    // patterns find more savings but mining them is slower and uses more memory.
+    pub fn max_pattern_length(mut self, length: usize) -> Self {
+        self.preprocessor.pattern_length_limit = length.max(1);
+        self
+    }
+
+    // Fit the dictionary to at most the first `bytes` of the input and apply it to
+    // the rest, bounding the time spent mining patterns on large inputs. The default
+    // is DEFAULT_SAMPLE_SIZE.
+    pub fn sample_size(mut self, bytes: usize) -> Self {
+        self.preprocessor.sample_size = bytes.max(1);
+        self
+    }
+
+    // Skip mining patterns when the sample's entropy is above `bits_per_byte`, so
+    // random-looking input goes straight to Huffman coding; configured patterns are
+    // still used. The default is 7.5; 8 or more never skips.
+    pub fn entropy_bypass(mut self, bits_per_byte: f64) -> Self {
+        self.preprocessor.entropy_bypass = bits_per_byte;
+        self
+    }
+
+    pub fn pattern_selection(mut self, selection: PatternSelection) -> Self {
+        self.preprocessor.pattern_selection = selection;
+        self
+    }
+
+    // Seed of the order in which mined patterns of equal frequency are considered;
+    // 0, the default, takes them in byte order. See pattern_rank.
+    pub fn tie_seed(mut self, seed: u32) -> Self {
+        self.preprocessor.tie_seed = seed;
+        self
+    }
+
+    pub fn tokenization(mut self, tokenization: Tokenization) -> Self {
+        self.preprocessor.tokenization = tokenization;
+        self
+    }
+
+    pub fn code_length_model(mut self, model: CodeLengthModel) -> Self {
+        self.preprocessor.code_length_model = model;
+        self
+    }
+
+    pub fn build(self) -> Preprocessor {
+        self.preprocessor
+    }
+}
+
+impl Default for PreprocessorBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for Preprocessor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Preprocessor {
+    pub fn builder() -> PreprocessorBuilder {
+        PreprocessorBuilder::new()
+    }
+
     pub fn new() -> Self {
         Preprocessor {
-            pattern_map: BTreeMap::new(),
-            reverse_pattern_map: BTreeMap::new(),
-            next_code: 1,
+            dictionary: TrainedDictionary::new(),
+            free_codes: Vec::new(),
             max_pattern_length: 4,
-            code_frequency: BTreeMap::new(),
             prediction_model: BTreeMap::new(),
+            pattern_usage: BTreeMap::new(),
+            entropy: 0.0,
+            entropy_bypass: DEFAULT_ENTROPY_BYPASS,
+            bypassed: false,
+            user_patterns: Vec::new(),
+            dictionary_mode: DictionaryMode::Merge,
+            required_patterns: Vec::new(),
+            denied_sequences: Vec::new(),
+            max_entries: MAX_SHORT_CODE as usize,
+            max_dictionary_bytes: usize::MAX,
+            pattern_length_limit: 4,
+            sample_size: DEFAULT_SAMPLE_SIZE,
+            pattern_selection: PatternSelection::Greedy,
+            tie_seed: 0,
+            tokenization: Tokenization::Greedy,
+            code_length_model: CodeLengthModel::default(),
+            literal_bits: Vec::new(),
+            fitted_len: 0,
         }
     }
 
+    // Builder starting from this preprocessor's configuration
+    pub fn to_builder(&self) -> PreprocessorBuilder {
+        PreprocessorBuilder { preprocessor: self.clone() }
+    }
+
+    pub fn dictionary(&self) -> &TrainedDictionary {
+        &self.dictionary
+    }
+
+    pub(crate) fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    pub fn tie_seed(&self) -> u32 {
+        self.tie_seed
+    }
+
+    // The compressor records its seed in the frame, so it overrides the template's
+    pub(crate) fn set_tie_seed(&mut self, seed: u32) {
+        self.tie_seed = seed;
+    }
+
+    // Use a dictionary trained elsewhere, e.g. to `apply` it or to reverse data
+    // transformed with it. Replaces the fitted dictionary.
+    pub fn set_dictionary(&mut self, dictionary: TrainedDictionary) {
+        self.free_codes.clear();
+        self.max_pattern_length = dictionary.longest_pattern().max(1);
+        self.pattern_usage.clear();
+        self.dictionary = dictionary;
+    }
+
     pub fn serialize_dictionary(&self) -> Vec<u8> {
-        let mut serialized = Vec::new();
-        for (&code, pattern) in &self.reverse_pattern_map {
-            serialized.extend(&code.to_be_bytes()); // Code to bytes
-            serialized.push(pattern.len() as u8); // Length of the pattern
-            serialized.extend(pattern); // The pattern itself
-        }
-        serialized
+        self.dictionary.serialize()
     }
-    
-    
-    pub fn deserialize_dictionary(&mut self, serialized: &[u8]) {
-        let mut i = 0;
-        while i < serialized.len() {
-            let code = u16::from_be_bytes([serialized[i], serialized[i+1]]);
-            i += 2;
-            let pattern_len = serialized[i] as usize;
-            i += 1;
-            let pattern = serialized[i..i + pattern_len].to_vec();
-            i += pattern_len;
-    
-            self.pattern_map.insert(pattern.clone(), code);
-            self.reverse_pattern_map.insert(code, pattern);
-        }
+
+    pub fn deserialize_dictionary(&mut self, serialized: &[u8]) -> Result<(), QuantumPackError> {
+        self.set_dictionary(TrainedDictionary::deserialize(serialized)?);
+        Ok(())
     }
-    
+
+    // Fit the model to `data` and transform it in one go
     pub fn preprocess(&mut self, data: &[u8]) -> Vec<u8> {
+        self.fit(&data[..data.len().min(self.sample_size)]);
+        let (transformed_data, usage) = self.parallel_transform(data);
+        self.pattern_usage = usage;
+        transformed_data
+    }
+
+    // Build the dictionary and token model from `data`, replacing anything learned
+    // before. The configuration from the builder is kept.
+    pub fn fit(&mut self, data: &[u8]) {
+        self.dictionary = TrainedDictionary::new();
+        self.pattern_usage.clear();
+        self.free_codes = self.code_order(data);
+
         self.max_pattern_length = self.determine_max_pattern_length(data);
-        self.analyze_data(data);
+        self.entropy = self.analyze_data(data);
+        self.bypassed = self.entropy > self.entropy_bypass;
         self.identify_patterns(data);
-        self.build_prediction_model(data);
-        self.parallel_transform_data(data)
+        // User patterns may be longer than anything mined from the input
+        let longest_user_pattern = self.dictionary.longest_pattern();
+        self.max_pattern_length = self.max_pattern_length.max(longest_user_pattern);
+        if self.bypassed {
+            self.prediction_model.clear();
+        } else {
+            self.build_prediction_model(data);
+        }
+        self.fit_token_costs(data);
+        debug!(
+            "fitted {} patterns to {} bytes at {:.3} bits per byte{}",
+            self.dictionary.len(),
+            data.len(),
+            self.entropy,
+            if self.bypassed { ", mining skipped" } else { "" }
+        );
+    }
+
+    // Fit the model to a corpus drawn from many inputs, at most the sample size long:
+    // each category of samples gets an equal part and each sample a part of its
+    // category's by weight, so over-represented payload types do not dominate the
+    // dictionary. See TrainingSample.
+    pub fn train(&mut self, samples: &[TrainingSample]) {
+        self.fit(&training_corpus(samples, self.sample_size));
+    }
+
+    // Whether the last fit skipped mining because the input looked random, see
+    // PreprocessorBuilder::entropy_bypass
+    pub fn mining_skipped(&self) -> bool {
+        self.bypassed
+    }
+
+    // Transform `data` with the fitted model, which may come from a sample or from
+    // different data altogether. Does not change the model or the usage report.
+    pub fn apply(&self, data: &[u8]) -> Vec<u8> {
+        self.parallel_transform(data).0
+    }
+
+    // Literal costs for the optimal parser, from the byte histogram of the input
+    fn fit_token_costs(&mut self, data: &[u8]) {
+        let histogram = cost::histogram(data);
+        self.fitted_len = data.len();
+        self.literal_bits = (0..=u8::MAX)
+            .map(|byte| symbol_bits(histogram.get(&byte).copied().unwrap_or(0) as u64, data.len() as u64).max(1.0))
+            .collect();
+    }
+
+    // Patterns used by the last call to `preprocess`, most bytes saved first
+    pub fn usage_report(&self) -> Vec<PatternUsage> {
+        let mut report: Vec<PatternUsage> = self.pattern_usage.iter()
+            .filter_map(|(&code, &(occurrences, bytes_saved))| {
+                self.dictionary.pattern(code).map(|pattern| PatternUsage {
+                    pattern: pattern.to_vec(),
+                    code,
+                    occurrences,
+                    bytes_saved,
+                })
+            })
+            .collect();
+        report.sort_by(|a, b| b.bytes_saved.cmp(&a.bytes_saved).then_with(|| a.code.cmp(&b.code)));
+        report
+    }
+
+    // JSON description of the fitted model (patterns, codes, frequencies, usage and
+    // input entropy) for offline analysis and visualization tools
+    pub fn export_report(&self) -> String {
+        let mut json = String::from("{");
+        json.push_str(&format!("\"max_pattern_length\":{},", self.max_pattern_length));
+        json.push_str(&format!("\"entropy\":{},", self.entropy));
+        json.push_str("\"patterns\":[");
+        for (index, (code, pattern)) in self.dictionary.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            let (used, bytes_saved) = self.pattern_usage.get(&code).cloned().unwrap_or((0, 0));
+            let bytes: Vec<String> = pattern.iter().map(|byte| byte.to_string()).collect();
+            json.push_str(&format!(
+                "{{\"code\":{},\"bytes\":[{}],\"text\":\"{}\",\"frequency\":{},\"used\":{},\"bytes_saved\":{}}}",
+                code,
+                bytes.join(","),
+                escape_json(&String::from_utf8_lossy(pattern)),
+                self.dictionary.frequency(code),
+                used,
+                bytes_saved,
+            ));
+        }
+        json.push_str("]}");
+        json
     }
 
     pub fn determine_max_pattern_length(&self, data: &[u8]) -> usize {
         let unique_bytes = data.iter().collect::<BTreeSet<&u8>>().len();
-        match unique_bytes {
+        let length = match unique_bytes {
             0..=16 => 2,  // Few unique bytes, shorter patterns might be better
             17..=32 => 3, // Moderate variety in bytes
-            _ => 4        // High variety, longer patterns might be better
-        }
-    }
-    
-    pub fn analyze_data(&self, data: &[u8]) {
-        let mut byte_frequency: BTreeMap<u8, usize> = BTreeMap::new();
-    
-        for &byte in data {
-            *byte_frequency.entry(byte).or_insert(0) += 1;
-        }
-    
-        // Print each byte's frequency
-        for (byte, freq) in &byte_frequency {
-            println!("Byte: {:?} ({}), Frequency: {}", *byte as char, byte, freq);
-        }
-    
-        let entropy = self.calculate_entropy(&byte_frequency, data.len());
-        println!("Data Entropy: {}", entropy);
+            _ => self.pattern_length_limit // High variety, longer patterns might be better
+        };
+        length.min(self.pattern_length_limit)
     }
     
-
-    fn calculate_entropy(&self, frequency: &BTreeMap<u8, usize>, total: usize) -> f64 {
-        frequency.values().fold(0.0, |acc, &freq| {
-            let probability = freq as f64 / total as f64;
-            acc - probability * probability.log2()
-        })
+    // Entropy of `data` in bits per byte, see analysis::entropy
+    pub fn analyze_data(&self, data: &[u8]) -> f64 {
+        analysis::entropy(data)
     }
 
     fn identify_patterns(&mut self, data: &[u8]) {
-        let mut frequency_map: BTreeMap<Vec<u8>, u32> = BTreeMap::new();
-    
-        // Include single characters as well in the pattern identification
-        for &byte in data {
-            *frequency_map.entry(vec![byte]).or_insert(0) += 1;
+        // Required and user supplied patterns come first so they are guaranteed a code
+        let configured: Vec<Vec<u8>> = self.required_patterns.iter().chain(self.user_patterns.iter()).cloned().collect();
+        for pattern in configured {
+            if pattern.is_empty() || self.dictionary.contains_pattern(&pattern) {
+                continue;
+            }
+            let freq = data.windows(pattern.len()).filter(|window| *window == &pattern[..]).count() as u32;
+            if !self.insert_pattern(pattern, freq) {
+                return;
+            }
+        }
+
+        if self.dictionary_mode == DictionaryMode::Replace || self.bypassed {
+            return;
         }
+
+        // Windows borrow from `data`; only patterns seen more than once are copied
+        let mut frequency_map: BTreeMap<&[u8], u32> = BTreeMap::new();
     
-        for window_size in 2..=self.max_pattern_length {
+        // Include single characters as well in the pattern identification
+        for window_size in 1..=self.max_pattern_length {
             for window in data.windows(window_size) {
-                *frequency_map.entry(window.to_vec()).or_insert(0) += 1;
+                *frequency_map.entry(window).or_insert(0) += 1;
             }
         }
     
-        frequency_map.retain(|_, &mut freq| freq > 1);
-    
         // Sort patterns
-        let mut patterns: Vec<_> = frequency_map.into_iter().collect();
+        let mut patterns: Vec<(Vec<u8>, u32)> = frequency_map.into_iter()
+            .filter(|&(_, freq)| freq > 1)
+            .map(|(pattern, freq)| (pattern.to_vec(), freq))
+            .collect();
         patterns.sort_unstable_by(|(a_pattern, a_freq), (b_pattern, b_freq)| {
-            b_freq.cmp(a_freq).then_with(|| a_pattern.cmp(b_pattern))
+            b_freq.cmp(a_freq)
+                .then_with(|| pattern_rank(a_pattern, self.tie_seed).cmp(&pattern_rank(b_pattern, self.tie_seed)))
+                .then_with(|| a_pattern.cmp(b_pattern))
         });
+
+        if let PatternSelection::Annealing { iterations, seed } = self.pattern_selection {
+            patterns = self.anneal_patterns(data, patterns, iterations, seed);
+        }
     
-        for (pattern, freq) in patterns.iter().take(254) {
-            let code = self.next_code;
-            self.next_code += 1;
-            self.pattern_map.insert(pattern.clone(), code);
-            self.reverse_pattern_map.insert(code, pattern.clone());
-            self.code_frequency.insert(code, *freq);
-            println!("Identified Pattern: {:?}, Code: {}, Frequency: {}", pattern, code, freq);
+        for (pattern, freq) in patterns {
+            if !self.dictionary.contains_pattern(&pattern) && !self.insert_pattern(pattern, freq) {
+                break;
+            }
+        }
+    }
+
+    fn anneal_patterns(&self, data: &[u8], patterns: Vec<(Vec<u8>, u32)>, iterations: u32, seed: u64) -> Vec<(Vec<u8>, u32)> {
+        let slots = self.max_entries.saturating_sub(self.dictionary.len());
+        // Twice as many candidates as free slots gives the search room to trade patterns
+        let candidates: Vec<(Vec<u8>, u32)> = patterns.into_iter()
+            .filter(|(pattern, _)| !self.dictionary.contains_pattern(pattern) && !self.is_denied(pattern))
+            .take(slots * 2)
+            .collect();
+        let fixed: Vec<(Vec<u8>, u32)> = self.dictionary.iter()
+            .map(|(code, pattern)| (pattern.to_vec(), self.dictionary.frequency(code)))
+            .collect();
+
+        let annealer = Annealer {
+            sample: data,
+            fixed: &fixed,
+            candidates: &candidates,
+            slots,
+            max_pattern_length: self.max_pattern_length,
+            iterations,
+            seed,
+        };
+        annealer.run().into_iter().map(|index| candidates[index].clone()).collect()
+    }
+
+    // Returns false once the dictionary is full
+    fn insert_pattern(&mut self, pattern: Vec<u8>, freq: u32) -> bool {
+        if self.dictionary.len() >= self.max_entries {
+            return false;
+        }
+        if self.is_denied(&pattern) || self.dictionary_bytes() + serialized_entry_len(&pattern) > self.max_dictionary_bytes {
+            return true;
+        }
+        let code = match self.allocate_code() {
+            Some(code) => code,
+            None => return false,
+        };
+        trace!("pattern {:?} gets code {}, seen {} times", String::from_utf8_lossy(&pattern), code, freq);
+        // Callers skip patterns that are already in the dictionary and codes are never reused
+        self.dictionary.insert(code, pattern, freq).is_ok()
+    }
+
+    // Short codes whose byte value does not occur in `data` come first, because a
+    // literal with the same value as a code has to be escaped; then the other short
+    // codes, rarest byte first. Wide codes are only handed out when the dictionary
+    // was configured to need them.
+    fn code_order(&self, data: &[u8]) -> Vec<u16> {
+        let mut histogram = [0usize; 256];
+        for &byte in data {
+            histogram[byte as usize] += 1;
+        }
+        let mut order: Vec<u16> = (1..=MAX_SHORT_CODE).collect();
+        order.sort_by_key(|&code| histogram[code as usize]);
+        if self.max_entries > MAX_SHORT_CODE as usize {
+            order.extend(MAX_SHORT_CODE + 1..u16::MAX);
+        }
+        order.reverse();
+        order
+    }
+
+    // Next free code none of whose emitted bytes occur in a denied sequence
+    fn allocate_code(&mut self) -> Option<u16> {
+        while let Some(code) = self.free_codes.pop() {
+            let emitted = encode_token(code);
+            if !self.denied_sequences.iter().any(|sequence| emitted.iter().any(|byte| sequence.contains(byte))) {
+                return Some(code);
+            }
         }
+        None
+    }
+
+    fn dictionary_bytes(&self) -> usize {
+        self.dictionary.serialized_len()
+    }
+
+    // A pattern is denied if substituting it could swallow any byte of a denied
+    // sequence: either one contains the other or they overlap at the edges
+    fn is_denied(&self, pattern: &[u8]) -> bool {
+        self.denied_sequences.iter().any(|sequence| {
+            let shortest = pattern.len().min(sequence.len());
+            contains(pattern, sequence)
+                || contains(sequence, pattern)
+                || (1..shortest).any(|k| pattern.ends_with(&sequence[..k]) || sequence.ends_with(&pattern[..k]))
+        })
     }
     
     fn build_prediction_model(&mut self, data: &[u8]) {
-        let mut frequency_map: BTreeMap<Vec<u8>, u32> = BTreeMap::new();
+        let mut frequency_map: BTreeMap<&[u8], u32> = BTreeMap::new();
         for window in data.windows(3) {
-            *frequency_map.entry(window.to_vec()).or_insert(0) += 1;
+            *frequency_map.entry(window).or_insert(0) += 1;
         }
 
         self.prediction_model = BTreeMap::from_iter(
@@ -137,111 +668,358 @@ impl Preprocessor {
     }
 
     pub fn parallel_transform_data(&self, data: &[u8]) -> Vec<u8> {
-        // self.transform_data(data)
-        let num_threads = std::thread::available_parallelism().unwrap_or_else(|_| std::num::NonZeroUsize::new(1).unwrap()).get();
-        let chunk_size = std::cmp::max(data.len() / num_threads, self.max_pattern_length);
-        let mut threads = Vec::new();
-    
-        // Process each chunk in parallel
-        for (index, chunk) in data.chunks(chunk_size).enumerate() {
-            let chunk = chunk.to_vec();
-            let preprocessor = self.clone();
-    
-            threads.push((index, thread::spawn(move || {
-                preprocessor.transform_data(&chunk)
-            })));
-        }
-    
-        // Collect results, maintaining the order
-        threads.sort_by_key(|&(index, _)| index);
-        let mut transformed_data = Vec::new();
-        for (_, thread) in threads {
-            transformed_data.extend(thread.join().unwrap());
-        }
-    
-        // No additional post-processing step is needed if the state is not altered during processing
+        self.parallel_transform(data).0
+    }
+
+    // apply, keeping the pattern usage for usage_report as preprocess does
+    pub(crate) fn apply_recorded(&mut self, data: &[u8]) -> Vec<u8> {
+        let (transformed_data, usage) = self.parallel_transform(data);
+        self.pattern_usage = usage;
         transformed_data
     }
+
+    // How many dictionary patterns the last preprocess or apply_recorded used
+    pub(crate) fn patterns_used(&self) -> usize {
+        self.pattern_usage.len()
+    }
+
+    // Chunks are transformed independently (no match spans two chunks), so the chunk
+    // size is fixed rather than derived from the thread count to keep the output
+    // identical on every machine and with or without the `parallel` feature
+    fn parallel_transform(&self, data: &[u8]) -> (Vec<u8>, UsageCounts) {
+        let chunks: Vec<&[u8]> = data.chunks(self.chunk_size()).collect();
+        let mut transformed_data = Vec::with_capacity(data.len());
+        let mut usage = UsageCounts::new();
+        for_each_chunk(&chunks, |chunk| self.transform_chunk(chunk), |(chunk_data, chunk_usage)| {
+            transformed_data.extend(chunk_data);
+            merge_usage(&mut usage, chunk_usage);
+        });
+        (transformed_data, usage)
+    }
     
+    fn chunk_size(&self) -> usize {
+        PARALLEL_CHUNK_SIZE.max(self.max_pattern_length)
+    }
+
+    // The tokens `apply` writes out for `data`, as typed values rather than bytes, for
+    // analyzing the transform or trying other entropy coders on its output
+    pub fn tokenize(&self, data: &[u8]) -> Vec<Token> {
+        data.chunks(self.chunk_size()).flat_map(|chunk| self.tokens(chunk, &self.parse(chunk)).collect::<Vec<_>>()).collect()
+    }
+
     pub fn transform_data(&self, data: &[u8]) -> Vec<u8> {
-        println!("--- Transforming data ---");
-        let mut transformed_data = Vec::new();
+        self.transform_chunk(data).0
+    }
+
+    fn transform_chunk(&self, data: &[u8]) -> (Vec<u8>, UsageCounts) {
+        let sizes = self.parse(data);
+        self.emit_tokens(data, &sizes)
+    }
+
+    // Transform input that arrives in pieces without buffering all of it. Bytes near
+    // the end of a piece are held back until the next one arrives, so patterns that
+    // straddle a boundary are still found. With greedy tokenization the concatenated
+    // output is identical to `transform_data` over the concatenated input; the
+    // optimal parser only looks ahead within the buffered bytes.
+    pub fn transform_stream<I, C>(&self, chunks: I) -> TransformStream<'_, I::IntoIter>
+    where
+        I: IntoIterator<Item = C>,
+        C: AsRef<[u8]>,
+    {
+        TransformStream {
+            preprocessor: self,
+            chunks: chunks.into_iter(),
+            pending: Vec::new(),
+            finished: false,
+        }
+    }
+
+    // Tokens covering a prefix of `data` that can be decided without seeing more input
+    fn parse_prefix(&self, data: &[u8], is_last: bool) -> (Vec<usize>, usize) {
+        let limit = if is_last { data.len() } else { (data.len() + 1).saturating_sub(self.max_pattern_length) };
+        let sizes = match self.tokenization {
+            Tokenization::Greedy => self.greedy_parse_until(data, limit),
+            Tokenization::Optimal => self.optimal_parse(data),
+        };
+        let mut consumed = 0;
+        let mut decided = Vec::new();
+        for size in sizes {
+            if consumed >= limit {
+                break;
+            }
+            decided.push(size);
+            consumed += size;
+        }
+        (decided, consumed)
+    }
+
+    // Write out the tokens of `data` split at `sizes`. Runs of plain literals, most of
+    // the input when few patterns match, are copied in one go.
+    fn emit_tokens(&self, data: &[u8], sizes: &[usize]) -> (Vec<u8>, UsageCounts) {
+        let mut usage = UsageCounts::new();
+        let mut encoded_data = Vec::with_capacity(data.len());
+        let kinds = self.byte_kinds();
         let mut i = 0;
-    
-        while i < data.len() {
-            let mut found_match = false;
-            for size in (2..=self.max_pattern_length.min(data.len() - i)).rev() {
-                let pattern = &data[i..i + size];
-                if let Some(&code) = self.pattern_map.get(pattern) {
-                    println!("Pattern found: {:?}, Replacing with code: {}", pattern, code);
-                    transformed_data.push(code as u8);
-                    i += size;
-                    found_match = true;
-                    break;
+        let mut run_start = 0;
+        for &size in sizes {
+            let kind = if size == 1 { kinds[data[i] as usize] } else { ByteKind::Escaped };
+            if kind == ByteKind::Plain {
+                i += 1;
+                continue;
+            }
+            encoded_data.extend_from_slice(&data[run_start..i]);
+            let token = match kind {
+                ByteKind::Pattern(code) => Token::Pattern { code, len: 1 },
+                _ => match self.dictionary.code(&data[i..i + size]) {
+                    Some(code) => Token::Pattern { code, len: size },
+                    None => Token::Literal(data[i]),
+                },
+            };
+            match token {
+                Token::Pattern { code, len } => {
+                    // Only single byte patterns get the frequency dependent code length
+                    let encoded_code = if len == 1 && code <= MAX_SHORT_CODE {
+                        self.encode_code(code, self.dictionary.frequency(code))
+                    } else {
+                        encode_token(code)
+                    };
+                    record_usage(&mut usage, code, len as i64 - encoded_code.len() as i64);
+                    encoded_data.extend_from_slice(&encoded_code);
+                }
+                Token::Literal(byte) => {
+                    if self.needs_escape(byte) {
+                        encoded_data.extend_from_slice(&LITERAL_ESCAPE);
+                    }
+                    encoded_data.push(byte);
                 }
             }
-            if !found_match {
-                println!("No pattern found for byte: {}, Adding as is", data[i]);
-                transformed_data.push(data[i]);
-                i += 1;
+            i += size;
+            run_start = i;
+        }
+        encoded_data.extend_from_slice(&data[run_start..i]);
+        (encoded_data, usage)
+    }
+
+    // How each byte value is written when it is a token of its own
+    fn byte_kinds(&self) -> [ByteKind; 256] {
+        let mut kinds = [ByteKind::Plain; 256];
+        for (byte, kind) in kinds.iter_mut().enumerate() {
+            if let Some(code) = self.dictionary.code(&[byte as u8]) {
+                *kind = ByteKind::Pattern(code);
+            } else if self.needs_escape(byte as u8) {
+                *kind = ByteKind::Escaped;
             }
         }
-        println!("Transformed data: {:?}", transformed_data);
-        self.variable_length_encode(&transformed_data)
+        kinds
     }
-    
-    fn variable_length_encode(&self, data: &[u8]) -> Vec<u8> {
-        let mut encoded_data = Vec::new();
-        for &byte in data {
-            if let Some(&code) = self.pattern_map.get(&vec![byte]) {
-                let frequency = self.code_frequency.get(&code).unwrap_or(&1);
-                let encoded_code = self.encode_code(code, *frequency);
-                println!("Encoding byte: {}, Code: {}, Frequency: {}", byte, code, frequency);
-                encoded_data.extend_from_slice(&encoded_code);
-            } else {
-                encoded_data.push(byte);
+
+    // Tokens of `data` split at `sizes`, see parse
+    fn tokens<'a>(&'a self, data: &'a [u8], sizes: &'a [usize]) -> impl Iterator<Item = Token> + 'a {
+        let mut i = 0;
+        sizes.iter().map(move |&size| {
+            let token = &data[i..i + size];
+            i += size;
+            match self.dictionary.code(token) {
+                Some(code) => Token::Pattern { code, len: size },
+                None => Token::Literal(token[0]),
+            }
+        })
+    }
+
+    // Literals that would read as a code or as the escape byte
+    fn needs_escape(&self, byte: u8) -> bool {
+        byte == ESCAPE || self.dictionary.pattern(byte as u16).is_some()
+    }
+
+    // Length of every token in order; 1 is a literal byte, anything longer a pattern
+    fn parse(&self, data: &[u8]) -> Vec<usize> {
+        match self.tokenization {
+            Tokenization::Greedy => self.greedy_parse(data),
+            Tokenization::Optimal => self.optimal_parse(data),
+        }
+    }
+
+    fn greedy_parse(&self, data: &[u8]) -> Vec<usize> {
+        self.greedy_parse_until(data, data.len())
+    }
+
+    // Greedy tokens for every position before `limit`; the last one may extend past it
+    fn greedy_parse_until(&self, data: &[u8], limit: usize) -> Vec<usize> {
+        let starts = self.dictionary.pattern_starts();
+        let mut sizes = Vec::with_capacity(limit);
+        let mut i = 0;
+        while i < limit {
+            let run = data[i..limit].iter().position(|&byte| starts[byte as usize]).unwrap_or(limit - i);
+            if run > 0 {
+                sizes.resize(sizes.len() + run, 1);
+                i += run;
+                continue;
+            }
+            let size = (2..=self.max_pattern_length.min(data.len() - i)).rev()
+                .find(|&size| self.dictionary.contains_pattern(&data[i..i + size]))
+                .unwrap_or(1);
+            sizes.push(size);
+            i += size;
+        }
+        sizes
+    }
+
+    // cost[i] is the cheapest encoding of data[i..]; filled back to front
+    fn optimal_parse(&self, data: &[u8]) -> Vec<usize> {
+        let n = data.len();
+        let mut cost = vec![0.0; n + 1];
+        let mut choice = vec![1; n];
+        let starts = self.dictionary.pattern_starts();
+        let literal_costs: [f64; 256] = std::array::from_fn(|byte| self.literal_cost(byte as u8));
+        for i in (0..n).rev() {
+            cost[i] = literal_costs[data[i] as usize] + cost[i + 1];
+            if !starts[data[i] as usize] {
+                continue;
+            }
+            for size in 2..=self.max_pattern_length.min(n - i) {
+                if let Some(code) = self.dictionary.code(&data[i..i + size]) {
+                    let candidate = self.code_cost(code) + cost[i + size];
+                    if candidate < cost[i] {
+                        cost[i] = candidate;
+                        choice[i] = size;
+                    }
+                }
+            }
+        }
+
+        let mut sizes = Vec::new();
+        let mut i = 0;
+        while i < n {
+            sizes.push(choice[i]);
+            i += choice[i];
+        }
+        sizes
+    }
+
+    // Estimated bits for a literal byte, which becomes a code if it is a single byte pattern
+    fn literal_cost(&self, byte: u8) -> f64 {
+        match self.dictionary.code(&[byte]) {
+            Some(code) => {
+                let frequency = self.dictionary.frequency(code);
+                let prefix_bits = 8.0 * (self.encode_code(code, frequency).len() - 1) as f64;
+                self.code_cost(code) + prefix_bits
+            }
+            None => {
+                let bits = self.literal_bits.get(byte as usize).cloned().unwrap_or(8.0);
+                if self.needs_escape(byte) { bits + 8.0 * LITERAL_ESCAPE.len() as f64 } else { bits }
             }
         }
-        // println!("Encoded data: {:?}", encoded_data);
-        encoded_data
     }
 
+    fn code_cost(&self, code: u16) -> f64 {
+        if code > MAX_SHORT_CODE {
+            return 8.0 * WIDE_CODE_LEN as f64;
+        }
+        if self.fitted_len == 0 {
+            return 8.0;
+        }
+        symbol_bits(self.dictionary.frequency(code) as u64, self.fitted_len as u64)
+    }
+    
     pub fn encode_code(&self, code: u16, frequency: u32) -> Vec<u8> {
-        // Simplified variable-length encoding based on frequency
-        if frequency > 100 {
-            vec![code as u8] // More frequent patterns get shorter codes
+        let short = match self.code_length_model {
+            CodeLengthModel::ProbabilityMass { min_probability } => {
+                // Before fitting there is no distribution to compare against
+                self.fitted_len == 0 || frequency as f64 / self.fitted_len as f64 >= min_probability
+            }
+            CodeLengthModel::FrequencyThreshold(threshold) => frequency > threshold,
+        };
+        if short {
+            vec![code as u8] // More probable patterns get shorter codes
         } else {
-            vec![0xFF, code as u8] // Less frequent patterns get longer codes
+            vec![ESCAPE, code as u8] // Less probable patterns get longer codes
         }
     }
-    // ... additional methods as needed ...
+    // Inverse of the transform for any dictionary; bytes that do not form a valid
+    // token are copied through unchanged
     pub fn reverse_transform_data(&self, data: &[u8]) -> Vec<u8> {
-        println!("--- Reverse transforming data ---");
-        let mut decoded_data = Vec::new();
+        match self.reverse_transform_limited(data, usize::MAX) {
+            Ok(decoded_data) => decoded_data,
+            Err(_) => unreachable!("no output is longer than usize::MAX"),
+        }
+    }
+
+    // reverse_transform_data, failing with OutputLimitExceeded as soon as the output
+    // grows past `max_output`. A short token can stand for a long pattern, so the
+    // output of untrusted input is only bounded this way.
+    pub(crate) fn reverse_transform_limited(&self, data: &[u8], max_output: usize) -> Result<Vec<u8>, QuantumPackError> {
+        let mut decoded_data = Vec::with_capacity(data.len().min(max_output));
         let mut i = 0;
     
         while i < data.len() {
-            if data[i] == 255 {
-                println!("Prefix 255 found at index: {}", i);
-                i += 1; // Skip the prefix
-                let code = data[i] as u16;
-                if let Some(pattern) = self.reverse_pattern_map.get(&code) {
-                    println!("Index: {}, Decoding code: {} to pattern: {:?}", i, code, pattern);
-                    decoded_data.extend_from_slice(pattern);
-                }
-            } else {
-                let code = data[i] as u16;
-                if let Some(pattern) = self.reverse_pattern_map.get(&code) {
-                    println!("Index: {}, Decoding code: {} to pattern: {:?}", i, code, pattern);
-                    decoded_data.extend_from_slice(pattern);
-                } else {
-                    println!("Index: {}, No pattern found for code: {}, treating as original byte", i, code);
-                    decoded_data.push(data[i]);
+            let (code, used) = match data[i..] {
+                [ESCAPE, ESCAPE, high, low, ..] => (u16::from_be_bytes([high, low]), WIDE_CODE_LEN),
+                [ESCAPE, 0x00, literal, ..] => {
+                    decoded_data.push(literal);
+                    i += LITERAL_ESCAPE.len() + 1;
+                    continue;
                 }
+                [ESCAPE, code, ..] => (code as u16, 2),
+                [code, ..] => (code as u16, 1),
+                [] => break,
+            };
+            let expansion = self.dictionary.pattern(code).unwrap_or(&data[i..i + used]);
+            if decoded_data.len() + expansion.len() > max_output {
+                return Err(QuantumPackError::OutputLimitExceeded { limit: max_output });
             }
-            i += 1;
+            decoded_data.extend_from_slice(expansion);
+            i += used;
+        }
+        if decoded_data.len() > max_output {
+            return Err(QuantumPackError::OutputLimitExceeded { limit: max_output });
         }
-        println!("Decoded data: {:?}", decoded_data);
-        decoded_data
-    } 
+        Ok(decoded_data)
+    }
+}
+
+// Train a dictionary with the default settings, e.g. for Compressor::preset_dictionary
+pub fn train_dictionary(samples: &[TrainingSample]) -> TrainedDictionary {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.train(samples);
+    preprocessor.dictionary().clone()
+}
+
+// Mined patterns are considered by descending frequency; among patterns of equal
+// frequency by ascending rank, then in byte order. Seed 0 ranks every pattern 0,
+// leaving plain byte order; any other seed ranks by the 64-bit FNV-1a hash of the
+// seed's four little-endian bytes followed by the pattern.
+pub fn pattern_rank(pattern: &[u8], seed: u32) -> u64 {
+    if seed == 0 {
+        return 0;
+    }
+    seed.to_le_bytes().iter().chain(pattern).fold(0xcbf2_9ce4_8422_2325, |hash: u64, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+// Bytes a pattern code occupies in the transformed stream
+fn encode_token(code: u16) -> Vec<u8> {
+    if code <= MAX_SHORT_CODE {
+        vec![code as u8]
+    } else {
+        let mut token = WIDE_CODE_PREFIX.to_vec();
+        wire::write_u16(&mut token, code);
+        token
+    }
+}
+
+fn record_usage(usage: &mut UsageCounts, code: u16, bytes_saved: i64) {
+    let entry = usage.entry(code).or_insert((0, 0));
+    entry.0 += 1;
+    entry.1 += bytes_saved;
+}
+
+fn merge_usage(total: &mut UsageCounts, other: UsageCounts) {
+    for (code, (occurrences, bytes_saved)) in other {
+        let entry = total.entry(code).or_insert((0, 0));
+        entry.0 += occurrences;
+        entry.1 += bytes_saved;
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
 }
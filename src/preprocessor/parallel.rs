@@ -0,0 +1,82 @@
+// Runs `transform` over every chunk and hands the results to `sink` in chunk order.
+// This is the only place the preprocessor spawns threads; without the `parallel`
+// feature (e.g. for WASM) the chunks are transformed one after another on the
+// calling thread and the output is the same.
+
+#[cfg(feature = "parallel")]
+pub(super) fn for_each_chunk<T, F, S>(chunks: &[&[u8]], transform: F, mut sink: S)
+where
+    T: Send,
+    F: Fn(&[u8]) -> T + Sync,
+    S: FnMut(T),
+{
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Condvar, Mutex};
+    use std::thread;
+
+    // Finished chunks waiting to be handed to the sink in order
+    struct Reassembly<T> {
+        finished: BTreeMap<usize, T>,
+        emitted: usize,
+    }
+
+    let num_threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(chunks.len());
+    if num_threads <= 1 {
+        chunks.iter().for_each(|chunk| sink(transform(chunk)));
+        return;
+    }
+
+    // Workers claim the next chunk from a shared counter, so a slow chunk never
+    // leaves the others idle. A worker may only run `max_in_flight` chunks ahead of
+    // the reassembly point, which bounds the memory held in finished chunks.
+    let max_in_flight = num_threads * 2;
+    let next_chunk = AtomicUsize::new(0);
+    let state = Mutex::new(Reassembly { finished: BTreeMap::new(), emitted: 0 });
+    let progress = Condvar::new();
+
+    thread::scope(|scope| {
+        for _ in 0..num_threads {
+            scope.spawn(|| loop {
+                let index = next_chunk.fetch_add(1, Ordering::SeqCst);
+                if index >= chunks.len() {
+                    break;
+                }
+                {
+                    let mut state = state.lock().unwrap();
+                    while index >= state.emitted + max_in_flight {
+                        state = progress.wait(state).unwrap();
+                    }
+                }
+                let result = transform(chunks[index]);
+                state.lock().unwrap().finished.insert(index, result);
+                progress.notify_all();
+            });
+        }
+
+        // Reassemble in order on this thread while the workers run
+        for index in 0..chunks.len() {
+            let result = {
+                let mut state = state.lock().unwrap();
+                loop {
+                    if let Some(result) = state.finished.remove(&index) {
+                        state.emitted = index + 1;
+                        break result;
+                    }
+                    state = progress.wait(state).unwrap();
+                }
+            };
+            progress.notify_all();
+            sink(result);
+        }
+    });
+}
+
+#[cfg(not(feature = "parallel"))]
+pub(super) fn for_each_chunk<T, F, S>(chunks: &[&[u8]], transform: F, mut sink: S)
+where
+    F: Fn(&[u8]) -> T,
+    S: FnMut(T),
+{
+    chunks.iter().for_each(|chunk| sink(transform(chunk)));
+}
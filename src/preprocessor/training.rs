@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+
+// Samples longer than their share contribute pieces of this size from along their length
+const PIECE_LEN: usize = 4096;
+
+// One input to train a dictionary on, see Preprocessor::train
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrainingSample<'a> {
+    pub data: &'a [u8],
+    // Share of its category's part of the training corpus relative to the other
+    // samples of the category. Samples with a weight of 0 or less are left out.
+    pub weight: f64,
+    // Every category gets the same part of the corpus however many bytes it has,
+    // e.g. "json" and "logs" so a flood of logs cannot crowd out the JSON patterns
+    pub category: &'a str,
+}
+
+impl<'a> TrainingSample<'a> {
+    // A sample of weight 1 in the unnamed category
+    pub fn new(data: &'a [u8]) -> Self {
+        TrainingSample { data, weight: 1.0, category: "" }
+    }
+
+    pub fn weight(mut self, weight: f64) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub fn category(mut self, category: &'a str) -> Self {
+        self.category = category;
+        self
+    }
+}
+
+// Stratified corpus of `budget` bytes at most (and no more than the samples hold):
+// equal parts per category, split between its samples by weight. A sample shorter
+// than its share is repeated, so its patterns are counted as often as its weight
+// asks; a longer one contributes evenly spaced pieces.
+pub(super) fn training_corpus(samples: &[TrainingSample], budget: usize) -> Vec<u8> {
+    let mut categories: BTreeMap<&str, Vec<&TrainingSample>> = BTreeMap::new();
+    for sample in samples.iter().filter(|sample| sample.weight > 0.0 && !sample.data.is_empty()) {
+        categories.entry(sample.category).or_default().push(sample);
+    }
+    let total: usize = categories.values().flatten().map(|sample| sample.data.len()).sum();
+    let budget = budget.min(total);
+    let mut corpus = Vec::with_capacity(budget);
+    for (index, members) in categories.values().enumerate() {
+        // The first categories take the bytes left over from rounding
+        let share = budget / categories.len() + usize::from(index < budget % categories.len());
+        let weights: f64 = members.iter().map(|sample| sample.weight).sum();
+        let mut assigned = 0;
+        for (position, sample) in members.iter().enumerate() {
+            let len = if position + 1 == members.len() {
+                share - assigned
+            } else {
+                ((share as f64 * sample.weight / weights) as usize).min(share - assigned)
+            };
+            assigned += len;
+            take_spread(sample.data, len, &mut corpus);
+        }
+    }
+    corpus
+}
+
+// Append `len` bytes drawn from all along `data` to `output`
+pub(crate) fn take_spread(data: &[u8], len: usize, output: &mut Vec<u8>) {
+    if len >= data.len() {
+        output.extend(data.iter().cycle().take(len));
+        return;
+    }
+    let pieces = len.div_ceil(PIECE_LEN);
+    let stride = data.len() / pieces;
+    let mut remaining = len;
+    for piece in 0..pieces {
+        let piece_len = remaining.min(PIECE_LEN);
+        let start = (piece * stride).min(data.len() - piece_len);
+        output.extend_from_slice(&data[start..start + piece_len]);
+        remaining -= piece_len;
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::compression::{CompressionLevel, Compressor, Decompressor, TableEncoding};
+use crate::error::QuantumPackError;
+use crate::preprocessor::{DictionaryMode, PreprocessorBuilder, SharedDictionary, Tokenization, TrainedDictionary};
+use crate::stage::{ByteOrder, FloatStage, FloatWidth, IntegerCodec, IntegerStage, IntegerWidth, Stage};
+
+// Reading files is the job of the outer layer
+pub use crate::file::load_profiles;
+
+// Compression and decompression settings for one class of payload
+#[derive(Clone, Default)]
+pub struct Profile {
+    compressor: Compressor,
+    decompressor: Decompressor,
+}
+
+impl Profile {
+    pub fn new(compressor: Compressor, decompressor: Decompressor) -> Self {
+        Profile { compressor, decompressor }
+    }
+
+    pub fn compressor(&self) -> &Compressor {
+        &self.compressor
+    }
+
+    pub fn decompressor(&self) -> &Decompressor {
+        &self.decompressor
+    }
+}
+
+// Named profiles, so a service can compress logs, JSON, blobs, ... with different
+// settings through one entry point. Profiles are built in code or parsed from text:
+//
+//     [logs]
+//     tokenization = optimal
+//     pattern = ERROR
+//     pattern = WARN
+//     max_output_size = 16777216
+//
+//     [metrics]
+//     stage = integer frame_of_reference 64 signed
+//
+// Lines starting with '#' are comments. Keys:
+//   level                 fast | default | best
+//   tokenization          greedy | optimal
+//   huffman               static | adaptive
+//   table_encoding        smallest | dense | sparse
+//   dictionary_mode       merge | replace
+//   pattern               a user pattern, repeatable
+//   max_entries           dictionary entries
+//   max_dictionary_bytes  serialized dictionary size
+//   dictionary            hex of TrainedDictionary::serialize, used for every frame
+//   stage                 integer <bit_packing|frame_of_reference|zigzag_varint> <8|16|32|64> [signed] [big_endian]
+//                         or float <32|64> [big_endian]
+//   bwlimit               bytes per second for the file helpers
+//   max_output_size       largest frame the profile decodes
+#[derive(Clone, Default)]
+pub struct Profiles {
+    profiles: BTreeMap<String, Profile>,
+}
+
+impl Profiles {
+    pub fn new() -> Self {
+        Profiles::default()
+    }
+
+    // Add or replace a profile, returning the one it replaces
+    pub fn insert(&mut self, name: &str, profile: Profile) -> Option<Profile> {
+        self.profiles.insert(name.to_string(), profile)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(String::as_str)
+    }
+
+    // Compress `data` into a frame with the settings of profile `name`
+    pub fn compress(&self, name: &str, data: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+        let mut frame = Vec::new();
+        self.profile(name)?.compressor.compress_shared(data, &mut frame)?;
+        Ok(frame)
+    }
+
+    pub fn decompress(&self, name: &str, frame: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+        Ok(self.profile(name)?.decompressor.decompress(frame)?.0)
+    }
+
+    fn profile(&self, name: &str) -> Result<&Profile, QuantumPackError> {
+        self.get(name).ok_or_else(|| QuantumPackError::InvalidInput(format!("unknown profile {}", name)))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, QuantumPackError> {
+        let mut profiles = Profiles::new();
+        let mut current: Option<(String, ProfileBuilder)> = None;
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| invalid_profile(index + 1, message);
+            if line.starts_with('[') && line.ends_with(']') {
+                if let Some((name, builder)) = current.take() {
+                    profiles.insert(&name, builder.build());
+                }
+                let name = line[1..line.len() - 1].trim();
+                if name.is_empty() || profiles.get(name).is_some() {
+                    return Err(error(format!("missing or duplicate profile name {:?}", name)));
+                }
+                current = Some((name.to_string(), ProfileBuilder::default()));
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(position) => (line[..position].trim(), line[position + 1..].trim()),
+                None => return Err(error(format!("expected key = value, found {:?}", line))),
+            };
+            match current.as_mut() {
+                Some((_, builder)) => builder.set(key, value).map_err(error)?,
+                None => return Err(error("setting outside of a [profile] section".to_string())),
+            }
+        }
+        if let Some((name, builder)) = current {
+            profiles.insert(&name, builder.build());
+        }
+        Ok(profiles)
+    }
+}
+
+// Settings collected while parsing one section
+#[derive(Default)]
+struct ProfileBuilder {
+    preprocessor: PreprocessorBuilder,
+    level: CompressionLevel,
+    adaptive: bool,
+    table_encoding: TableEncoding,
+    dictionary: Option<TrainedDictionary>,
+    stage: Option<Stage>,
+    bwlimit: Option<u64>,
+    max_output_size: Option<usize>,
+}
+
+impl ProfileBuilder {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "level" => {
+                self.level = CompressionLevel::from_name(value).ok_or_else(|| format!("unknown level {:?}", value))?;
+            }
+            "tokenization" => {
+                let tokenization = match value {
+                    "greedy" => Tokenization::Greedy,
+                    "optimal" => Tokenization::Optimal,
+                    _ => return Err(format!("unknown tokenization {:?}", value)),
+                };
+                self.update_preprocessor(|builder| builder.tokenization(tokenization));
+            }
+            "huffman" => {
+                self.adaptive = match value {
+                    "static" => false,
+                    "adaptive" => true,
+                    _ => return Err(format!("unknown huffman coding {:?}", value)),
+                }
+            }
+            "table_encoding" => {
+                self.table_encoding = match value {
+                    "smallest" => TableEncoding::Smallest,
+                    "dense" => TableEncoding::Dense,
+                    "sparse" => TableEncoding::Sparse,
+                    _ => return Err(format!("unknown table encoding {:?}", value)),
+                }
+            }
+            "dictionary_mode" => {
+                let mode = match value {
+                    "merge" => DictionaryMode::Merge,
+                    "replace" => DictionaryMode::Replace,
+                    _ => return Err(format!("unknown dictionary mode {:?}", value)),
+                };
+                self.update_preprocessor(|builder| builder.dictionary_mode(mode));
+            }
+            "pattern" => self.update_preprocessor(|builder| builder.pattern(value.as_bytes())),
+            "max_entries" => {
+                let entries = parse_number(key, value)?;
+                self.update_preprocessor(|builder| builder.max_entries(entries as usize));
+            }
+            "max_dictionary_bytes" => {
+                let bytes = parse_number(key, value)?;
+                self.update_preprocessor(|builder| builder.max_dictionary_bytes(bytes as usize));
+            }
+            "dictionary" => {
+                let serialized = parse_hex(value).ok_or_else(|| "dictionary is not valid hex".to_string())?;
+                let dictionary = TrainedDictionary::deserialize(&serialized).map_err(|error| error.to_string())?;
+                self.dictionary = Some(dictionary);
+            }
+            "stage" => self.stage = Some(parse_stage(value)?),
+            "bwlimit" => self.bwlimit = Some(parse_number(key, value)?),
+            "max_output_size" => self.max_output_size = Some(parse_number(key, value)? as usize),
+            _ => return Err(format!("unknown setting {:?}", key)),
+        }
+        Ok(())
+    }
+
+    fn update_preprocessor<F: FnOnce(PreprocessorBuilder) -> PreprocessorBuilder>(&mut self, update: F) {
+        self.preprocessor = update(std::mem::take(&mut self.preprocessor));
+    }
+
+    fn build(self) -> Profile {
+        let mut compressor = Compressor::new()
+            .preprocessor(self.preprocessor.build())
+            .level(self.level)
+            .adaptive_huffman(self.adaptive)
+            .table_encoding(self.table_encoding);
+        let mut decompressor = Decompressor::new();
+        if let Some(dictionary) = self.dictionary {
+            compressor = compressor.shared_dictionary(Arc::new(SharedDictionary::new(dictionary)));
+        }
+        if let Some(stage) = self.stage {
+            compressor = compressor.stage(stage);
+        }
+        if let Some(limit) = self.bwlimit {
+            compressor = compressor.bwlimit(limit);
+            decompressor = decompressor.bwlimit(limit);
+        }
+        if let Some(bytes) = self.max_output_size {
+            decompressor = decompressor.max_output_size(bytes);
+        }
+        Profile::new(compressor, decompressor)
+    }
+}
+
+fn parse_stage(value: &str) -> Result<Stage, String> {
+    let words: Vec<&str> = value.split_whitespace().collect();
+    let options = |from: usize, allowed: &[&str]| -> Result<Vec<&str>, String> {
+        let options = words.get(from..).unwrap_or(&[]).to_vec();
+        match options.iter().find(|option| !allowed.contains(option)) {
+            Some(option) => Err(format!("unknown stage option {:?}", option)),
+            None => Ok(options),
+        }
+    };
+    let byte_order = |options: &[&str]| if options.contains(&"big_endian") { ByteOrder::BigEndian } else { ByteOrder::LittleEndian };
+
+    match words.as_slice() {
+        ["integer", codec, width, ..] => {
+            let codec = match *codec {
+                "bit_packing" => IntegerCodec::BitPacking,
+                "frame_of_reference" => IntegerCodec::FrameOfReference,
+                "zigzag_varint" => IntegerCodec::ZigzagVarint,
+                _ => return Err(format!("unknown integer codec {:?}", codec)),
+            };
+            let width = match *width {
+                "8" => IntegerWidth::Bits8,
+                "16" => IntegerWidth::Bits16,
+                "32" => IntegerWidth::Bits32,
+                "64" => IntegerWidth::Bits64,
+                _ => return Err(format!("unsupported integer width {:?}", width)),
+            };
+            let options = options(3, &["signed", "big_endian"])?;
+            let stage = IntegerStage::new(codec, width).signed(options.contains(&"signed")).byte_order(byte_order(&options));
+            Ok(Stage::Integer(stage))
+        }
+        ["float", width, ..] => {
+            let width = match *width {
+                "32" => FloatWidth::F32,
+                "64" => FloatWidth::F64,
+                _ => return Err(format!("unsupported float width {:?}", width)),
+            };
+            let options = options(2, &["big_endian"])?;
+            Ok(Stage::Float(FloatStage::new(width).byte_order(byte_order(&options))))
+        }
+        _ => Err(format!("invalid stage {:?}", value)),
+    }
+}
+
+fn parse_number(key: &str, value: &str) -> Result<u64, String> {
+    value.parse().map_err(|_| format!("{} must be a non-negative integer, found {:?}", key, value))
+}
+
+fn parse_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len()).step_by(2).map(|index| u8::from_str_radix(value.get(index..index + 2)?, 16).ok()).collect()
+}
+
+fn invalid_profile(line: usize, message: String) -> QuantumPackError {
+    QuantumPackError::InvalidProfile { line, message }
+}
@@ -0,0 +1,13 @@
+use std::sync::Arc;
+
+// Called by the file and stream helpers of Compressor and Decompressor after each
+// frame they write or decode, with the input bytes consumed and the output bytes
+// produced so far. Set a block size to get more than one call per input. Set with
+// Compressor::on_progress or Decompressor::on_progress.
+pub type ProgressHandler = Arc<dyn Fn(u64, u64) + Send + Sync>;
+
+pub(crate) fn report(handler: &Option<ProgressHandler>, bytes_in: u64, bytes_out: u64) {
+    if let Some(handler) = handler {
+        handler(bytes_in, bytes_out);
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt;
+
+use arbitrary::{Arbitrary, Unstructured};
+
+use crate::compression::Compressor;
+use crate::preprocessor::{Preprocessor, Tokenization};
+
+// How a compress -> decompress round trip went wrong
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    // The input could not be compressed
+    Encode(String),
+    // The compressed frame could not be decoded at all
+    Decode(String),
+    // The decoded bytes differ from the input, first at `offset`
+    Content { offset: usize, original_len: usize, decoded_len: usize },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Encode(error) => write!(f, "encoding failed: {}", error),
+            Mismatch::Decode(error) => write!(f, "decoding failed: {}", error),
+            Mismatch::Content { offset, original_len, decoded_len } => write!(
+                f,
+                "decoded data differs at byte {} ({} bytes in, {} bytes out)",
+                offset, original_len, decoded_len
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+// Input for fuzz targets and property tests: the data plus the settings it is compressed with
+#[derive(Debug, Clone)]
+pub struct RoundtripCase {
+    pub data: Vec<u8>,
+    pub tokenization: Tokenization,
+}
+
+impl<'a> Arbitrary<'a> for RoundtripCase {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let tokenization = if u.arbitrary()? { Tokenization::Optimal } else { Tokenization::Greedy };
+        // The rest of the input is the data, so fuzzer corpora stay readable
+        let data = u.bytes(u.len())?.to_vec();
+        Ok(RoundtripCase { data, tokenization })
+    }
+}
+
+impl RoundtripCase {
+    pub fn check(&self) -> Result<(), Mismatch> {
+        let preprocessor = Preprocessor::builder().tokenization(self.tokenization).build();
+        check_with(&Compressor::new().preprocessor(preprocessor), &self.data)
+    }
+}
+
+// Compress `data` with the default settings, decompress it and compare
+pub fn roundtrip_check(data: &[u8]) -> Result<(), Mismatch> {
+    check_with(&Compressor::new(), data)
+}
+
+fn check_with(compressor: &Compressor, data: &[u8]) -> Result<(), Mismatch> {
+    let mut frame = Vec::new();
+    compressor.compress_shared(data, &mut frame).map_err(|error| Mismatch::Encode(error.to_string()))?;
+    let (decoded, _) = compressor.decompressor().decode_frame(&frame, usize::MAX).map_err(|error| Mismatch::Decode(error.to_string()))?;
+    if decoded == data {
+        return Ok(());
+    }
+    let offset = data.iter().zip(&decoded).take_while(|(a, b)| a == b).count();
+    Err(Mismatch::Content { offset, original_len: data.len(), decoded_len: decoded.len() })
+}
@@ -0,0 +1,52 @@
+use crate::analysis::entropy;
+
+// Recognizing inputs that are compressed already, e.g. photos or downloads, so
+// compress_file can store them as they are instead of spending minutes looking for
+// patterns in bytes that will not shrink. Only the start of the input is looked at.
+
+// Bytes of an input sniff looks at
+pub const SNIFF_LEN: usize = 64 * 1024;
+// Formats such as zip can also hold data that was not compressed, which shows as
+// fewer bits per byte than compressed data ever has
+const MIN_ENTROPY: f64 = 7.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressedFormat {
+    Gzip,
+    Zip,
+    Png,
+    Jpeg,
+    Mp4,
+}
+
+impl CompressedFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            CompressedFormat::Gzip => "gzip",
+            CompressedFormat::Zip => "zip",
+            CompressedFormat::Png => "PNG",
+            CompressedFormat::Jpeg => "JPEG",
+            CompressedFormat::Mp4 => "MP4",
+        }
+    }
+
+    // The format whose signature `data` starts with
+    pub fn from_magic(data: &[u8]) -> Option<Self> {
+        match data {
+            [0x1f, 0x8b, ..] => Some(CompressedFormat::Gzip),
+            [b'P', b'K', 0x03, 0x04, ..] | [b'P', b'K', 0x07, 0x08, ..] => Some(CompressedFormat::Zip),
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => Some(CompressedFormat::Png),
+            [0xff, 0xd8, 0xff, ..] => Some(CompressedFormat::Jpeg),
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] => Some(CompressedFormat::Mp4),
+            _ => None,
+        }
+    }
+}
+
+// The compressed format `head`, the first SNIFF_LEN bytes of an input or all of a
+// shorter one, is in, if it has a known signature and looks compressed
+pub fn sniff(head: &[u8]) -> Option<CompressedFormat> {
+    let format = CompressedFormat::from_magic(head)?;
+    let sample = &head[..head.len().min(SNIFF_LEN)];
+    Some(format).filter(|_| entropy(sample) > MIN_ENTROPY)
+}
@@ -0,0 +1,228 @@
+use super::{decoded_len, invalid_stage, ByteOrder};
+use crate::error::QuantumPackError;
+use crate::wire::{write_varint, Reader};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatWidth {
+    F32,
+    F64,
+}
+
+impl FloatWidth {
+    pub fn bytes(self) -> usize {
+        match self {
+            FloatWidth::F32 => 4,
+            FloatWidth::F64 => 8,
+        }
+    }
+
+    fn bits(self) -> u32 {
+        self.bytes() as u32 * 8
+    }
+
+    // Bits needed to store a leading zero count or a meaningful bit count minus one
+    fn field_bits(self) -> u32 {
+        match self {
+            FloatWidth::F32 => 5,
+            FloatWidth::F64 => 6,
+        }
+    }
+}
+
+// Gorilla-style compression of a stream of floats: each value is XORed with the
+// previous one and only the meaningful bits of the result are written. Slowly
+// changing series (metrics, sensor readings) XOR to mostly zero bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FloatStage {
+    pub width: FloatWidth,
+    pub byte_order: ByteOrder,
+}
+
+impl FloatStage {
+    // Little-endian floats
+    pub fn new(width: FloatWidth) -> Self {
+        FloatStage { width, byte_order: ByteOrder::LittleEndian }
+    }
+
+    pub fn byte_order(mut self, byte_order: ByteOrder) -> Self {
+        self.byte_order = byte_order;
+        self
+    }
+
+    pub(crate) fn descriptor(&self) -> [u8; 2] {
+        [self.width.bytes() as u8, self.byte_order.descriptor()]
+    }
+
+    pub(crate) fn from_descriptor(descriptor: &[u8]) -> Result<Self, QuantumPackError> {
+        if descriptor.len() != 2 {
+            return Err(invalid_stage("float stage descriptor has the wrong length"));
+        }
+        let width = match descriptor[0] {
+            4 => FloatWidth::F32,
+            8 => FloatWidth::F64,
+            other => return Err(invalid_stage(format!("unsupported float width {}", other))),
+        };
+        Ok(FloatStage { width, byte_order: ByteOrder::from_descriptor(descriptor[1])? })
+    }
+
+    // Layout: varint value count, varint tail length, tail bytes, then a bit stream:
+    // the first value in full, then per value `0` for a repeat, `10` and the
+    // meaningful bits when they fit the previous window, or `11`, the leading zero
+    // count, the meaningful bit count minus one and the meaningful bits
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        let width = self.width.bytes();
+        let whole = data.len() - data.len() % width;
+
+        let mut encoded = Vec::new();
+        write_varint(&mut encoded, (whole / width) as u64);
+        write_varint(&mut encoded, (data.len() - whole) as u64);
+        encoded.extend_from_slice(&data[whole..]);
+
+        let total_bits = self.width.bits();
+        let field_bits = self.width.field_bits();
+        let mut bits = BitWriter::new(&mut encoded);
+        let mut previous = 0u64;
+        // Leading zeros and meaningful bit count of the last written window
+        let mut window: Option<(u32, u32)> = None;
+        for (index, bytes) in data[..whole].chunks_exact(width).enumerate() {
+            let value = self.byte_order.read(bytes);
+            if index == 0 {
+                bits.write(value, total_bits);
+                previous = value;
+                continue;
+            }
+            let xor = value ^ previous;
+            previous = value;
+            if xor == 0 {
+                bits.write(0, 1);
+                continue;
+            }
+            // Leading zeros are capped so the count fits its field
+            let leading = (xor.leading_zeros() - (64 - total_bits)).min((1 << field_bits) - 1);
+            let trailing = xor.trailing_zeros();
+            match window {
+                Some((window_leading, meaningful)) if leading >= window_leading && trailing >= total_bits - window_leading - meaningful => {
+                    bits.write(0b10, 2);
+                    bits.write(xor >> (total_bits - window_leading - meaningful), meaningful);
+                }
+                _ => {
+                    let meaningful = total_bits - leading - trailing;
+                    bits.write(0b11, 2);
+                    bits.write(leading as u64, field_bits);
+                    bits.write((meaningful - 1) as u64, field_bits);
+                    bits.write(xor >> trailing, meaningful);
+                    window = Some((leading, meaningful));
+                }
+            }
+        }
+        bits.finish();
+        encoded
+    }
+
+    pub fn decode(&self, encoded: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+        self.decode_limited(encoded, usize::MAX)
+    }
+
+    pub(crate) fn decode_limited(&self, encoded: &[u8], max_output: usize) -> Result<Vec<u8>, QuantumPackError> {
+        let mut reader = Reader::new(encoded, "stage data");
+        let count = reader.varint()? as usize;
+        let tail_len = reader.varint()? as usize;
+        let tail = reader.bytes(tail_len)?;
+        decoded_len(count, self.width.bytes(), tail_len, max_output)?;
+
+        let width = self.width.bytes();
+        let total_bits = self.width.bits();
+        let field_bits = self.width.field_bits();
+        let mut bits = BitReader::new(reader.rest());
+        let mut decoded = Vec::with_capacity(count.min(encoded.len() * 8) * width + tail.len());
+        let mut previous = 0u64;
+        let mut window: Option<(u32, u32)> = None;
+        for index in 0..count {
+            let value = if index == 0 {
+                bits.read(total_bits)?
+            } else if bits.read(1)? == 0 {
+                previous
+            } else {
+                if bits.read(1)? == 1 {
+                    let leading = bits.read(field_bits)? as u32;
+                    let meaningful = bits.read(field_bits)? as u32 + 1;
+                    if leading + meaningful > total_bits {
+                        return Err(invalid_stage("float window exceeds the value width"));
+                    }
+                    window = Some((leading, meaningful));
+                }
+                let (leading, meaningful) = window.ok_or_else(|| invalid_stage("float window used before it was set"))?;
+                previous ^ (bits.read(meaningful)? << (total_bits - leading - meaningful))
+            };
+            decoded.extend_from_slice(&self.byte_order.write(value, width));
+            previous = value;
+        }
+        if !bits.only_padding_left() {
+            return Err(invalid_stage("unexpected bits after the float stream"));
+        }
+        decoded.extend_from_slice(tail);
+        Ok(decoded)
+    }
+}
+
+// Most significant bit first
+struct BitWriter<'a> {
+    out: &'a mut Vec<u8>,
+    current: u8,
+    filled: u32,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(out: &'a mut Vec<u8>) -> Self {
+        BitWriter { out, current: 0, filled: 0 }
+    }
+
+    // The low `count` bits of `value`
+    fn write(&mut self, value: u64, count: u32) {
+        for shift in (0..count).rev() {
+            self.current = (self.current << 1) | ((value >> shift) & 1) as u8;
+            self.filled += 1;
+            if self.filled == 8 {
+                self.out.push(self.current);
+                self.current = 0;
+                self.filled = 0;
+            }
+        }
+    }
+
+    fn finish(self) {
+        if self.filled > 0 {
+            self.out.push(self.current << (8 - self.filled));
+        }
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader { data, position: 0 }
+    }
+
+    fn read(&mut self, count: u32) -> Result<u64, QuantumPackError> {
+        if self.position + count as usize > self.data.len() * 8 {
+            return Err(invalid_stage("float stream is truncated"));
+        }
+        let mut value = 0u64;
+        for _ in 0..count {
+            let bit = (self.data[self.position / 8] >> (7 - self.position % 8)) & 1;
+            value = (value << 1) | bit as u64;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    // Only the zero bits that complete the last byte may remain
+    fn only_padding_left(&self) -> bool {
+        let remaining = self.data.len() * 8 - self.position;
+        remaining < 8 && (remaining == 0 || self.data[self.data.len() - 1] & ((1u8 << remaining) - 1) == 0)
+    }
+}
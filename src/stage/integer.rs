@@ -0,0 +1,255 @@
+use super::{decoded_len, invalid_stage, ByteOrder};
+use crate::error::QuantumPackError;
+use crate::wire::{write_varint, Reader};
+
+// How the integers of a numeric buffer are encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegerCodec {
+    // Every value in as many bits as the largest one needs
+    BitPacking,
+    // The minimum once, then every value's offset from it bit-packed
+    FrameOfReference,
+    // One varint per value; signed values are zigzag mapped first so small
+    // negative numbers stay short
+    ZigzagVarint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegerWidth {
+    Bits8,
+    Bits16,
+    Bits32,
+    Bits64,
+}
+
+impl IntegerWidth {
+    pub fn bytes(self) -> usize {
+        match self {
+            IntegerWidth::Bits8 => 1,
+            IntegerWidth::Bits16 => 2,
+            IntegerWidth::Bits32 => 4,
+            IntegerWidth::Bits64 => 8,
+        }
+    }
+
+    fn from_bytes(bytes: u8) -> Option<Self> {
+        match bytes {
+            1 => Some(IntegerWidth::Bits8),
+            2 => Some(IntegerWidth::Bits16),
+            4 => Some(IntegerWidth::Bits32),
+            8 => Some(IntegerWidth::Bits64),
+            _ => None,
+        }
+    }
+}
+
+// Treats the input as a column of fixed-width integers. Bytes after the last
+// whole integer are carried through unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntegerStage {
+    pub codec: IntegerCodec,
+    pub width: IntegerWidth,
+    pub signed: bool,
+    pub byte_order: ByteOrder,
+}
+
+impl IntegerStage {
+    // Unsigned little-endian integers
+    pub fn new(codec: IntegerCodec, width: IntegerWidth) -> Self {
+        IntegerStage { codec, width, signed: false, byte_order: ByteOrder::LittleEndian }
+    }
+
+    pub fn signed(mut self, signed: bool) -> Self {
+        self.signed = signed;
+        self
+    }
+
+    pub fn byte_order(mut self, byte_order: ByteOrder) -> Self {
+        self.byte_order = byte_order;
+        self
+    }
+
+    pub(crate) fn descriptor(&self) -> [u8; 4] {
+        let codec = match self.codec {
+            IntegerCodec::BitPacking => 0,
+            IntegerCodec::FrameOfReference => 1,
+            IntegerCodec::ZigzagVarint => 2,
+        };
+        [codec, self.width.bytes() as u8, self.signed as u8, self.byte_order.descriptor()]
+    }
+
+    pub(crate) fn from_descriptor(descriptor: &[u8]) -> Result<Self, QuantumPackError> {
+        if descriptor.len() != 4 {
+            return Err(invalid_stage("integer stage descriptor has the wrong length"));
+        }
+        let codec = match descriptor[0] {
+            0 => IntegerCodec::BitPacking,
+            1 => IntegerCodec::FrameOfReference,
+            2 => IntegerCodec::ZigzagVarint,
+            other => return Err(invalid_stage(format!("unknown integer codec {}", other))),
+        };
+        let width = IntegerWidth::from_bytes(descriptor[1])
+            .ok_or_else(|| invalid_stage(format!("unsupported integer width {}", descriptor[1])))?;
+        let signed = match descriptor[2] {
+            0 => false,
+            1 => true,
+            _ => return Err(invalid_stage("invalid signedness")),
+        };
+        Ok(IntegerStage { codec, width, signed, byte_order: ByteOrder::from_descriptor(descriptor[3])? })
+    }
+
+    // Layout: varint value count, varint tail length, tail bytes, codec body
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        let width = self.width.bytes();
+        let whole = data.len() - data.len() % width;
+        let values: Vec<u64> = data[..whole].chunks_exact(width).map(|bytes| self.read_value(bytes)).collect();
+
+        let mut encoded = Vec::new();
+        write_varint(&mut encoded, values.len() as u64);
+        write_varint(&mut encoded, (data.len() - whole) as u64);
+        encoded.extend_from_slice(&data[whole..]);
+
+        match self.codec {
+            IntegerCodec::BitPacking => pack_all(&mut encoded, &values),
+            IntegerCodec::FrameOfReference => {
+                // Offsets are taken in the signed domain so a column of small negative
+                // numbers packs as tightly as one of small positive numbers
+                let signed: Vec<i128> = values.iter().map(|&value| self.signed_value(value)).collect();
+                let reference = signed.iter().cloned().min().unwrap_or(0);
+                write_varint(&mut encoded, zigzag(reference as i64));
+                let offsets: Vec<u64> = signed.iter().map(|&value| (value - reference) as u64).collect();
+                pack_all(&mut encoded, &offsets);
+            }
+            IntegerCodec::ZigzagVarint => {
+                for &value in &values {
+                    write_varint(&mut encoded, value);
+                }
+            }
+        }
+        encoded
+    }
+
+    pub fn decode(&self, encoded: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+        self.decode_limited(encoded, usize::MAX)
+    }
+
+    pub(crate) fn decode_limited(&self, encoded: &[u8], max_output: usize) -> Result<Vec<u8>, QuantumPackError> {
+        let mut reader = Reader::new(encoded, "stage data");
+        let count = reader.varint()? as usize;
+        let tail_len = reader.varint()? as usize;
+        let tail = reader.bytes(tail_len)?;
+        let len = decoded_len(count, self.width.bytes(), tail_len, max_output)?;
+
+        let values = match self.codec {
+            IntegerCodec::BitPacking => unpack_all(&mut reader, count)?,
+            IntegerCodec::FrameOfReference => {
+                let reference = unzigzag(reader.varint()?) as i128;
+                unpack_all(&mut reader, count)?
+                    .into_iter()
+                    .map(|offset| self.mapped_value(reference + offset as i128))
+                    .collect()
+            }
+            IntegerCodec::ZigzagVarint => (0..count).map(|_| reader.varint()).collect::<Result<Vec<u64>, QuantumPackError>>()?,
+        };
+        if !reader.is_empty() {
+            return Err(invalid_stage("unexpected bytes after the integer column"));
+        }
+
+        let mut decoded = Vec::with_capacity(len);
+        for value in values {
+            decoded.extend_from_slice(&self.write_value(value)?);
+        }
+        decoded.extend_from_slice(tail);
+        Ok(decoded)
+    }
+
+    // Signed values are zigzag mapped so the bit-packed and varint forms stay short
+    fn read_value(&self, bytes: &[u8]) -> u64 {
+        let raw = self.byte_order.read(bytes);
+        if self.signed {
+            zigzag(sign_extend(raw, bytes.len()))
+        } else {
+            raw
+        }
+    }
+
+    fn write_value(&self, value: u64) -> Result<Vec<u8>, QuantumPackError> {
+        let width = self.width.bytes();
+        let raw = if self.signed { unzigzag(value) as u64 } else { value };
+        let fits = if self.signed { sign_extend(raw, width) as u64 == raw } else { width == 8 || raw >> (width * 8) == 0 };
+        if !fits {
+            return Err(invalid_stage("integer does not fit the column width"));
+        }
+        Ok(self.byte_order.write(raw, width))
+    }
+
+    fn signed_value(&self, value: u64) -> i128 {
+        if self.signed { unzigzag(value) as i128 } else { value as i128 }
+    }
+
+    fn mapped_value(&self, value: i128) -> u64 {
+        if self.signed { zigzag(value as i64) } else { value as u64 }
+    }
+}
+
+fn sign_extend(raw: u64, bytes: usize) -> i64 {
+    let shift = 64 - bytes * 8;
+    ((raw << shift) as i64) >> shift
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+// Bit count, then the values least significant bit first
+fn pack_all(out: &mut Vec<u8>, values: &[u64]) {
+    let bits = values.iter().map(|&value| 64 - value.leading_zeros()).max().unwrap_or(0);
+    out.push(bits as u8);
+    let mut accumulator: u128 = 0;
+    let mut filled = 0;
+    for &value in values {
+        accumulator |= (value as u128) << filled;
+        filled += bits;
+        while filled >= 8 {
+            out.push(accumulator as u8);
+            accumulator >>= 8;
+            filled -= 8;
+        }
+    }
+    if filled > 0 {
+        out.push(accumulator as u8);
+    }
+}
+
+fn unpack_all(reader: &mut Reader, count: usize) -> Result<Vec<u64>, QuantumPackError> {
+    let bits = reader.u8()? as u32;
+    if bits > 64 {
+        return Err(invalid_stage(format!("invalid bit width {}", bits)));
+    }
+    let packed_len = (count as u128 * bits as u128).div_ceil(8);
+    if packed_len > reader.remaining() as u128 {
+        return Err(invalid_stage("bit-packed integers are truncated"));
+    }
+    let packed = reader.bytes(packed_len as usize)?;
+
+    let mask = if bits == 64 { u64::MAX } else { (1u64 << bits) - 1 };
+    // Values of 0 bits take no space, so the count alone does not prove the data is there
+    let mut values = Vec::with_capacity(count.min(reader.remaining() * 8));
+    let mut accumulator: u128 = 0;
+    let mut filled = 0;
+    let mut bytes = packed.iter();
+    for _ in 0..count {
+        while filled < bits {
+            accumulator |= (*bytes.next().unwrap() as u128) << filled;
+            filled += 8;
+        }
+        values.push(accumulator as u64 & mask);
+        accumulator >>= bits;
+        filled -= bits;
+    }
+    Ok(values)
+}
@@ -0,0 +1,127 @@
+use crate::error::QuantumPackError;
+
+mod float;
+mod integer;
+
+pub use float::{FloatStage, FloatWidth};
+pub use integer::{IntegerCodec, IntegerStage, IntegerWidth};
+
+// A reversible transform applied to the input before the preprocessor, for data
+// with a known layout such as a column of integers or a series of floats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Integer(IntegerStage),
+    Float(FloatStage),
+}
+
+// Stage kinds as written in the frame header
+const INTEGER_STAGE: u8 = 1;
+const FLOAT_STAGE: u8 = 2;
+
+impl Stage {
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Stage::Integer(stage) => stage.encode(data),
+            Stage::Float(stage) => stage.encode(data),
+        }
+    }
+
+    pub fn decode(&self, encoded: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+        self.decode_limited(encoded, usize::MAX)
+    }
+
+    // decode, failing with OutputLimitExceeded rather than producing more than
+    // `max_output` bytes
+    pub(crate) fn decode_limited(&self, encoded: &[u8], max_output: usize) -> Result<Vec<u8>, QuantumPackError> {
+        match self {
+            Stage::Integer(stage) => stage.decode_limited(encoded, max_output),
+            Stage::Float(stage) => stage.decode_limited(encoded, max_output),
+        }
+    }
+
+    // Kind byte followed by the stage's parameters
+    pub(crate) fn serialize(&self) -> Vec<u8> {
+        let mut serialized = Vec::new();
+        match self {
+            Stage::Integer(stage) => {
+                serialized.push(INTEGER_STAGE);
+                serialized.extend_from_slice(&stage.descriptor());
+            }
+            Stage::Float(stage) => {
+                serialized.push(FLOAT_STAGE);
+                serialized.extend_from_slice(&stage.descriptor());
+            }
+        }
+        serialized
+    }
+
+    pub(crate) fn deserialize(serialized: &[u8]) -> Result<Self, QuantumPackError> {
+        match serialized.split_first() {
+            Some((&INTEGER_STAGE, descriptor)) => Ok(Stage::Integer(IntegerStage::from_descriptor(descriptor)?)),
+            Some((&FLOAT_STAGE, descriptor)) => Ok(Stage::Float(FloatStage::from_descriptor(descriptor)?)),
+            Some((kind, _)) => Err(invalid_stage(format!("unknown stage kind {}", kind))),
+            None => Err(invalid_stage("empty stage descriptor")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    LittleEndian,
+    BigEndian,
+}
+
+impl ByteOrder {
+    fn descriptor(self) -> u8 {
+        match self {
+            ByteOrder::LittleEndian => 0,
+            ByteOrder::BigEndian => 1,
+        }
+    }
+
+    fn from_descriptor(byte: u8) -> Result<Self, QuantumPackError> {
+        match byte {
+            0 => Ok(ByteOrder::LittleEndian),
+            1 => Ok(ByteOrder::BigEndian),
+            _ => Err(invalid_stage(format!("unknown byte order {}", byte))),
+        }
+    }
+
+    // Up to 8 bytes as an unsigned integer
+    fn read(self, bytes: &[u8]) -> u64 {
+        match self {
+            ByteOrder::LittleEndian => bytes.iter().rev().fold(0, |acc, &byte| (acc << 8) | byte as u64),
+            ByteOrder::BigEndian => bytes.iter().fold(0, |acc, &byte| (acc << 8) | byte as u64),
+        }
+    }
+
+    // The low `width` bytes of `value`
+    fn write(self, value: u64, width: usize) -> Vec<u8> {
+        let mut bytes: Vec<u8> = (0..width).map(|index| (value >> (8 * index)) as u8).collect();
+        if self == ByteOrder::BigEndian {
+            bytes.reverse();
+        }
+        bytes
+    }
+}
+
+// Upper bound on the length a stage encodes `len` bytes to: zigzag varints of 8-bit
+// values take at most two bytes each, every other codec less, plus a few varints of
+// counts
+pub(crate) fn max_encoded_len(len: usize) -> usize {
+    len.saturating_mul(2).saturating_add(32)
+}
+
+// Length of `count` values of `width` bytes and a tail, which must not exceed
+// `max_output`. The count comes from the frame, so it is checked before anything
+// is allocated for it.
+fn decoded_len(count: usize, width: usize, tail_len: usize, max_output: usize) -> Result<usize, QuantumPackError> {
+    match count.checked_mul(width).and_then(|len| len.checked_add(tail_len)) {
+        Some(len) if len <= max_output => Ok(len),
+        _ => Err(QuantumPackError::OutputLimitExceeded { limit: max_output }),
+    }
+}
+
+fn invalid_stage<E: Into<String>>(message: E) -> QuantumPackError {
+    QuantumPackError::InvalidStage(message.into())
+}
@@ -0,0 +1,451 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use crate::compression::{MAGIC, frame_continues, frame_extent, frame_metadata, frame_stage, frame_symbol_width, read_frame, seek_frame, symbols_from_le, Compressor, Decompressor, TrailingData};
+use crate::error::QuantumPackError;
+
+// Input is compressed in blocks of this size, each written as its own frame
+pub const DEFAULT_BLOCK_SIZE: usize = 1024 * 1024;
+
+// Compresses everything written to it into `inner`. Data is buffered until a
+// block is full, then written as one frame, so memory use is bounded by the block
+// size rather than the input size. Call `finish` to write the last block; dropping
+// the encoder does the same but ignores errors.
+//
+// The readers below seek and salvage by the decoded length in each frame's footer,
+// which a sealed frame does not show, so a compressor with a FrameHook is refused
+// when the first block is written.
+pub struct QpEncoder<W: Write> {
+    inner: Option<W>,
+    compressor: Compressor,
+    block_size: usize,
+    buffer: Vec<u8>,
+    frame: Vec<u8>,
+}
+
+impl<W: Write> QpEncoder<W> {
+    pub fn new(inner: W) -> Self {
+        QpEncoder::with_compressor(inner, Compressor::new())
+    }
+
+    pub fn with_compressor(inner: W, compressor: Compressor) -> Self {
+        QpEncoder {
+            inner: Some(inner),
+            compressor,
+            block_size: DEFAULT_BLOCK_SIZE,
+            buffer: Vec::new(),
+            frame: Vec::new(),
+        }
+    }
+
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    // Write the buffered data and return the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_block()?;
+        let mut inner = self.inner.take().unwrap();
+        inner.flush()?;
+        Ok(inner)
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        if self.compressor.hook.is_some() {
+            return Err(sealed_stream().into());
+        }
+        self.frame.clear();
+        self.compressor.compress_shared(&self.buffer, &mut self.frame)?;
+        self.buffer.clear();
+        self.inner.as_mut().unwrap().write_all(&self.frame)
+    }
+}
+
+impl<W: Write> Write for QpEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.block_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == self.block_size {
+            self.write_block()?;
+        }
+        Ok(len)
+    }
+
+    // Ends the current block early so everything written so far can be decoded
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for QpEncoder<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.write_block();
+        }
+    }
+}
+
+// Decompresses the frames read from `inner`, one at a time
+pub struct QpDecoder<R: Read> {
+    inner: R,
+    decompressor: Decompressor,
+    block: Vec<u8>,
+    position: usize,
+    // Where the next frame starts in the input
+    frame_offset: u64,
+    // What the decompressor's max_output_size leaves for the frames still to come
+    output_left: usize,
+}
+
+impl<R: Read> QpDecoder<R> {
+    pub fn new(inner: R) -> Self {
+        QpDecoder::with_decompressor(inner, Decompressor::new())
+    }
+
+    // Decode with the preset dictionary of `decompressor`, for streams written by a
+    // compressor with one, and stop at its max_output_size. Its frame hook is not
+    // used, see QpEncoder.
+    pub fn with_decompressor(inner: R, decompressor: Decompressor) -> Self {
+        let output_left = decompressor.max_output();
+        QpDecoder { inner, decompressor, block: Vec::new(), position: 0, frame_offset: 0, output_left }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for QpDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.block.len() {
+            match read_frame(&mut self.inner).map_err(|error| error.at(self.frame_offset))? {
+                Some(frame) => {
+                    self.block = self.decompressor.decode_frame(&frame, self.output_left).map_err(|error| error.at(self.frame_offset))?.0;
+                    self.output_left -= self.block.len();
+                    self.position = 0;
+                    self.frame_offset += frame.len() as u64;
+                }
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.block.len() - self.position);
+        buf[..len].copy_from_slice(&self.block[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+// Where one frame of a compressed file lies and which part of the decoded data it holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexedBlock {
+    pub offset: u64,
+    pub frame_len: u64,
+    pub decoded_offset: u64,
+    pub decoded_len: u64,
+}
+
+// Index the frames of `input`, a sequence of frames such as a file written with a
+// block size or by QpEncoder. Only headers, size fields and footers are read; the
+// rest of each frame is skipped by seeking.
+pub fn block_index<R: Read + Seek>(mut input: R) -> Result<Vec<IndexedBlock>, QuantumPackError> {
+    let mut blocks = Vec::new();
+    let mut offset = input.stream_position()?;
+    let mut decoded_offset = 0u64;
+    while let Some((frame_len, decoded_len)) = seek_frame(&mut input).map_err(|error| error.at(offset))? {
+        blocks.push(IndexedBlock { offset, frame_len, decoded_offset, decoded_len });
+        offset += frame_len;
+        decoded_offset = decoded_offset
+            .checked_add(decoded_len)
+            .ok_or_else(|| QuantumPackError::corrupt_header("decoded lengths overflow"))?;
+    }
+    Ok(blocks)
+}
+
+// Reads the decoded data of a compressed file at any position. Only the frames that
+// hold the requested bytes are decoded, and the most recently used decoded blocks
+// are kept, so serving byte ranges of a file compressed with a block size costs
+// about one block per range.
+pub struct SeekableReader<R: Read + Seek> {
+    inner: R,
+    decompressor: Decompressor,
+    blocks: Vec<IndexedBlock>,
+    position: u64,
+    // Decoded blocks by index, the most recently used last
+    cache: Vec<(usize, Vec<u8>)>,
+    cache_blocks: usize,
+}
+
+impl<R: Read + Seek> SeekableReader<R> {
+    // Index the frames of `inner`, see block_index
+    pub fn new(inner: R) -> Result<Self, QuantumPackError> {
+        SeekableReader::with_decompressor(inner, Decompressor::new())
+    }
+
+    // Like new, decoding blocks with the preset dictionary of `decompressor`
+    pub fn with_decompressor(mut inner: R, decompressor: Decompressor) -> Result<Self, QuantumPackError> {
+        let blocks = block_index(&mut inner)?;
+        Ok(SeekableReader { inner, decompressor, blocks, position: 0, cache: Vec::new(), cache_blocks: 4 })
+    }
+
+    // Keep up to `blocks` decoded blocks, at least one
+    pub fn cache_blocks(mut self, blocks: usize) -> Self {
+        self.cache_blocks = blocks.max(1);
+        // The most recently used blocks are at the end
+        let excess = self.cache.len().saturating_sub(self.cache_blocks);
+        self.cache.drain(..excess);
+        self
+    }
+
+    pub fn blocks(&self) -> &[IndexedBlock] {
+        &self.blocks
+    }
+
+    // Length of the decoded data
+    pub fn len(&self) -> u64 {
+        self.blocks.last().map_or(0, |block| block.decoded_offset + block.decoded_len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn block(&mut self, index: usize) -> Result<&[u8], QuantumPackError> {
+        match self.cache.iter().position(|(cached, _)| *cached == index) {
+            Some(position) => {
+                let entry = self.cache.remove(position);
+                self.cache.push(entry);
+            }
+            None => {
+                let block = self.blocks[index];
+                self.inner.seek(SeekFrom::Start(block.offset))?;
+                let mut frame = Vec::new();
+                (&mut self.inner).take(block.frame_len).read_to_end(&mut frame)?;
+                let decoded = self
+                    .decompressor
+                    .decode_frame(&frame, block.decoded_len.min(usize::MAX as u64) as usize)
+                    .map_err(|error| error.at(block.offset))?
+                    .0;
+                if self.cache.len() == self.cache_blocks {
+                    self.cache.remove(0);
+                }
+                self.cache.push((index, decoded));
+            }
+        }
+        Ok(&self.cache.last().expect("just cached").1)
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let position = self.position;
+        let index = self.blocks.partition_point(|block| block.decoded_offset + block.decoded_len <= position);
+        if index == self.blocks.len() || buf.is_empty() {
+            return Ok(0);
+        }
+        let from = (position - self.blocks[index].decoded_offset) as usize;
+        let block = self.block(index)?;
+        let len = buf.len().min(block.len() - from);
+        buf[..len].copy_from_slice(&block[from..from + len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableReader<R> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match position {
+            SeekFrom::Start(position) => {
+                self.position = position;
+                return Ok(position);
+            }
+            SeekFrom::End(delta) => (self.len(), delta),
+            SeekFrom::Current(delta) => (self.position, delta),
+        };
+        match base.checked_add_signed(delta) {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")),
+        }
+    }
+}
+
+// Decode only the bytes in `range` of the data in `input`, a sequence of frames as
+// written by QpEncoder. Frames entirely outside the range are skipped using the
+// decoded length in their footer, without Huffman decoding them; the range is
+// clamped to the end of the data.
+pub fn decompress_filtered(input: &[u8], range: Range<u64>) -> Result<Vec<u8>, QuantumPackError> {
+    decompress_filtered_with(input, range, &Decompressor::new())
+}
+
+// decompress_filtered for frames written with the preset dictionary of `decompressor`.
+// Its max_output_size holds for all the frames that are decoded together.
+pub fn decompress_filtered_with(input: &[u8], range: Range<u64>, decompressor: &Decompressor) -> Result<Vec<u8>, QuantumPackError> {
+    let mut output = Vec::new();
+    if range.start >= range.end {
+        return Ok(output);
+    }
+    let mut rest = input;
+    let mut offset = 0u64;
+    let mut output_left = decompressor.max_output();
+    while !rest.is_empty() && offset < range.end {
+        let at = |error: QuantumPackError| error.at((input.len() - rest.len()) as u64);
+        let (frame_len, decoded_len) = frame_extent(rest).map_err(at)?;
+        let (frame, next) = rest.split_at(frame_len);
+        let end = offset.saturating_add(decoded_len);
+        if end > range.start {
+            if decoded_len > output_left as u64 {
+                return Err(at(QuantumPackError::OutputLimitExceeded { limit: output_left }));
+            }
+            let block = decompressor.decode_frame(frame, output_left).map_err(at)?.0;
+            output_left -= block.len();
+            let from = range.start.saturating_sub(offset) as usize;
+            let to = (range.end.min(end) - offset) as usize;
+            output.extend_from_slice(&block[from..to]);
+        }
+        offset = end;
+        rest = next;
+    }
+    Ok(output)
+}
+
+// A stretch of input that salvage could not decode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DamagedBlock {
+    // Where the damage lies in the input
+    pub input: Range<u64>,
+    // Where the block's data is missing from the salvaged output
+    pub output_offset: u64,
+    // How many bytes are missing, if the frame's footer could still be read
+    pub len: Option<u64>,
+}
+
+// The data recovered from damaged input by salvage
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Salvaged {
+    pub data: Vec<u8>,
+    pub damaged: Vec<DamagedBlock>,
+}
+
+// Decode every intact frame of `input`, a sequence of frames as written with a block
+// size or by QpEncoder, skipping the damaged ones. A frame whose sizes are intact is
+// skipped as a whole; otherwise decoding resumes at the next frame magic. A frame
+// that decodes to more than its footer says counts as damaged. The salvaged data
+// holds the intact blocks back to back.
+pub fn salvage(input: &[u8]) -> Salvaged {
+    salvage_with(input, &Decompressor::new())
+}
+
+// salvage for frames written with the preset dictionary of `decompressor`; without
+// it they all count as damaged
+pub fn salvage_with(input: &[u8], decompressor: &Decompressor) -> Salvaged {
+    let mut salvaged = Salvaged::default();
+    let mut offset = 0;
+    while offset < input.len() {
+        let rest = &input[offset..];
+        let (end, len) = match frame_extent(rest) {
+            Ok((frame_len, decoded_len)) => match decompressor.decode_frame(&rest[..frame_len], decoded_len.min(usize::MAX as u64) as usize) {
+                Ok((block, _)) => {
+                    salvaged.data.extend_from_slice(&block);
+                    offset += frame_len;
+                    continue;
+                }
+                Err(_) => (offset + frame_len, Some(decoded_len)),
+            },
+            Err(_) => {
+                let next = input[offset + 1..].windows(MAGIC.len()).position(|window| window == MAGIC);
+                (next.map_or(input.len(), |next| offset + 1 + next), None)
+            }
+        };
+        salvaged.damaged.push(DamagedBlock { input: offset as u64..end as u64, output_offset: salvaged.data.len() as u64, len });
+        offset = end;
+    }
+    salvaged
+}
+
+// Append `inputs`, each a sequence of complete frames, to `output` as one stream
+// without decoding them. Frames are self-contained, so nothing has to be rewritten;
+// each input is only checked to end exactly at a frame boundary.
+pub fn concat(inputs: &[&[u8]], output: &mut Vec<u8>) -> Result<(), QuantumPackError> {
+    for input in inputs {
+        let mut offset = 0;
+        while offset < input.len() {
+            match frame_extent(&input[offset..]) {
+                Ok((frame_len, _)) => offset += frame_len,
+                // Anything after the first frame that is not a frame is trailing data
+                Err(QuantumPackError::NotAFrame) if offset > 0 => {
+                    let trailing = TrailingData { offset: offset as u64, len: (input.len() - offset) as u64 };
+                    return Err(QuantumPackError::TrailingData(trailing));
+                }
+                Err(error) => return Err(error.at(offset as u64)),
+            }
+        }
+        output.extend_from_slice(input);
+    }
+    Ok(())
+}
+
+// Decode every frame read from `input` and encode it again with `compressor`, one
+// frame at a time, so memory use is bounded by the largest frame. The frame
+// boundaries are kept, and so is each frame's stage unless `compressor` sets one.
+// Symbol frames stay symbol frames of the same width, and recorded file metadata is
+// carried over.
+// The new frames use the current format version. Sealed frames are neither read
+// nor written. Frames are decoded with the preset dictionary of `compressor`, if it
+// has one, so recompressing keeps a stream's dictionary rather than changing it.
+pub fn recompress<R: Read, W: Write>(input: R, output: W, compressor: &Compressor) -> Result<(), QuantumPackError> {
+    recompress_with(input, output, compressor, &compressor.decompressor())
+}
+
+// recompress, decoding with the preset dictionary of `decompressor` instead, e.g. the
+// one of the same Profile. Its max_output_size holds for all the frames read.
+pub fn recompress_with<R: Read, W: Write>(mut input: R, mut output: W, compressor: &Compressor, decompressor: &Decompressor) -> Result<(), QuantumPackError> {
+    if compressor.hook.is_some() {
+        return Err(sealed_stream());
+    }
+    let mut reencoded = Vec::new();
+    let mut offset = 0u64;
+    let mut output_left = decompressor.max_output();
+    while let Some(frame) = read_frame(&mut input).map_err(|error| error.at(offset))? {
+        let (decoded, _) = decompressor.decode_frame(&frame, output_left).map_err(|error| error.at(offset))?;
+        output_left -= decoded.len();
+        reencoded.clear();
+        match (frame_symbol_width(&frame)?, frame_stage(&frame)?) {
+            (Some(2), _) => compressor.compress_symbols::<u16>(&symbols_from_le(&decoded), &mut reencoded)?,
+            (Some(4), _) => compressor.compress_symbols::<u32>(&symbols_from_le(&decoded), &mut reencoded)?,
+            (Some(_), _) => compressor.compress_symbols::<u8>(&decoded, &mut reencoded)?,
+            (_, Some(stage)) if compressor.stage.is_none() => {
+                compressor.clone().stage(stage).compress_block(&decoded, frame_metadata(&frame)?, frame_continues(&frame)?, &mut reencoded)?;
+            }
+            _ => {
+                compressor.compress_block(&decoded, frame_metadata(&frame)?, frame_continues(&frame)?, &mut reencoded)?;
+            }
+        }
+        output.write_all(&reencoded)?;
+        offset += frame.len() as u64;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+fn sealed_stream() -> QuantumPackError {
+    QuantumPackError::InvalidInput("streams of sealed frames are not supported, use Compressor::compress_shared and Decompressor::frame_hook".to_string())
+}
@@ -0,0 +1,102 @@
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Token bucket used to cap the throughput of a reader or writer.
+// Tokens are bytes; the bucket refills at `rate` bytes per second and holds
+// at most one second worth of tokens, so short bursts are allowed but the
+// long-run average never exceeds the configured limit.
+pub struct TokenBucket {
+    rate: u64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec.max(1);
+        TokenBucket {
+            rate,
+            tokens: rate as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    // Largest request that can ever be satisfied in one go
+    pub fn capacity(&self) -> usize {
+        self.rate as usize
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        self.last_refill = now;
+    }
+
+    // Block until `amount` bytes may be transferred, then take them from the bucket
+    pub fn acquire(&mut self, amount: usize) {
+        let amount = amount.min(self.capacity()) as f64;
+        self.refill();
+        if self.tokens < amount {
+            let missing = amount - self.tokens;
+            thread::sleep(Duration::from_secs_f64(missing / self.rate as f64));
+            self.refill();
+        }
+        self.tokens -= amount;
+    }
+
+    // Give back tokens that were acquired but not used (e.g. a short read)
+    pub fn refund(&mut self, amount: usize) {
+        self.tokens = (self.tokens + amount as f64).min(self.rate as f64);
+    }
+}
+
+// Wraps a reader or writer and limits it to a fixed number of bytes per second
+pub struct Throttled<T> {
+    inner: T,
+    bucket: TokenBucket,
+}
+
+impl<T> Throttled<T> {
+    pub fn new(inner: T, bytes_per_sec: u64) -> Self {
+        Throttled {
+            inner,
+            bucket: TokenBucket::new(bytes_per_sec),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let wanted = buf.len().min(self.bucket.capacity());
+        self.bucket.acquire(wanted);
+        let read = self.inner.read(&mut buf[..wanted])?;
+        self.bucket.refund(wanted - read);
+        Ok(read)
+    }
+}
+
+impl<W: Write> Write for Throttled<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let wanted = buf.len().min(self.bucket.capacity());
+        self.bucket.acquire(wanted);
+        let written = self.inner.write(&buf[..wanted])?;
+        self.bucket.refund(wanted - written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::compression::{check_no_symbols, frame_parts, Compressor, Decompressor};
+use crate::error::QuantumPackError;
+use crate::huffman::{huffman_decode_bits, DecodeError};
+use crate::wire::{self, Reader};
+use crate::{deserialize_code_length_table, serialize_code_length_table};
+
+// Bit-exact replay for debugging the codec: `record` compresses a frame and keeps
+// every decision the encoder made in a sidecar trace, `replay` decodes the frame
+// step by step and reports the first point where the decoder left that path.
+//
+// Trace layout:
+//
+//   "QPTR", u8 version
+//   u32 size, code length table (see serialize_code_length_table)
+//   u32 size, serialized preprocessor dictionary
+//   u32 count, the tokens that were Huffman coded
+const MAGIC: [u8; 4] = *b"QPTR";
+const TRACE_VERSION: u8 = 1;
+
+// The encoder decisions behind one byte frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    pub code_lengths: BTreeMap<u8, u8>,
+    pub dictionary: Vec<u8>,
+    pub tokens: Vec<u8>,
+}
+
+impl Trace {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = MAGIC.to_vec();
+        output.push(TRACE_VERSION);
+        for section in [serialize_code_length_table(&self.code_lengths).as_slice(), &self.dictionary, &self.tokens].iter() {
+            wire::write_u32(&mut output, section.len() as u32);
+            output.extend_from_slice(section);
+        }
+        output
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, QuantumPackError> {
+        let mut reader = Reader::new(data, "trace");
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(QuantumPackError::InvalidInput("missing QPTR magic, not a trace".to_string()));
+        }
+        let version = reader.u8()?;
+        if version != TRACE_VERSION {
+            return Err(QuantumPackError::UnsupportedVersion(version));
+        }
+        let mut section = || -> Result<&[u8], QuantumPackError> {
+            let len = reader.u32()?;
+            reader.bytes(len as usize)
+        };
+        let code_lengths = deserialize_code_length_table(section()?)?;
+        let dictionary = section()?.to_vec();
+        let tokens = section()?.to_vec();
+        Ok(Trace { code_lengths, dictionary, tokens })
+    }
+
+    fn code_length(&self, token: u8) -> usize {
+        self.code_lengths.get(&token).copied().unwrap_or(0) as usize
+    }
+
+    // Bit position of token `index` in the frame's Huffman data
+    fn bit_offset(&self, index: usize) -> usize {
+        self.tokens[..index].iter().map(|&token| self.code_length(token)).sum()
+    }
+
+    // The recorded token whose code covers `bit_offset`, and where that code starts
+    fn token_at(&self, bit_offset: usize) -> (usize, usize) {
+        let mut start = 0;
+        for (index, &token) in self.tokens.iter().enumerate() {
+            let end = start + self.code_length(token);
+            if end > bit_offset {
+                return (index, start);
+            }
+            start = end;
+        }
+        (self.tokens.len(), start)
+    }
+}
+
+// The first point where decoding a frame left the recorded path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    // The frame's code length for `symbol` is not the one the encoder assigned; 0
+    // means the symbol has no code
+    CodeLength { symbol: u8, recorded: u8, frame: u8 },
+    // The frame carries another preprocessor dictionary
+    Dictionary,
+    // The decoder produced another token at `index`, or none where the encoder
+    // wrote one. `bit_offset` is where the token's code starts.
+    Token { index: usize, bit_offset: usize, recorded: Option<u8>, decoded: Option<u8> },
+    // Every token matches, but the preprocessor reversed them into other bytes
+    Output,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::CodeLength { symbol, recorded, frame } => {
+                write!(f, "symbol {:#04x} has code length {} in the frame, {} was recorded", symbol, frame, recorded)
+            }
+            Divergence::Dictionary => write!(f, "the frame's dictionary differs from the recorded one"),
+            Divergence::Token { index, bit_offset, recorded, decoded } => write!(
+                f,
+                "token {} at bit {} decoded as {:?}, {:?} was recorded",
+                index, bit_offset, decoded, recorded
+            ),
+            Divergence::Output => write!(f, "the tokens match but the decoded output fails its checksum"),
+        }
+    }
+}
+
+// Compress `data` into a frame and record the decisions behind it
+pub fn record(compressor: &Compressor, data: &[u8]) -> Result<(Vec<u8>, Trace), QuantumPackError> {
+    let mut frame = Vec::new();
+    let decisions = compressor.compress_traced(data, &mut frame)?;
+    let trace = Trace { code_lengths: decisions.code_lengths, dictionary: decisions.dictionary, tokens: decisions.tokens };
+    Ok((frame, trace))
+}
+
+// Decode `frame` following `trace`. Returns None when the decoder took exactly the
+// recorded path. Frames of pre-tokenized symbols have no trace.
+pub fn replay(frame: &[u8], trace: &Trace) -> Result<Option<Divergence>, QuantumPackError> {
+    let parts = frame_parts(frame)?;
+    let code_lengths = parts.code_lengths()?;
+    for symbol in 0..=u8::MAX {
+        let recorded = trace.code_lengths.get(&symbol).copied().unwrap_or(0);
+        let frame = code_lengths.get(&symbol).copied().unwrap_or(0);
+        if recorded != frame {
+            return Ok(Some(Divergence::CodeLength { symbol, recorded, frame }));
+        }
+    }
+    if parts.dictionary != trace.dictionary.as_slice() {
+        return Ok(Some(Divergence::Dictionary));
+    }
+
+    let (code, code_bits) = parts.code_bits()?;
+    let decoded = match parts.huffman_tree()? {
+        Some(huffman_tree) => match huffman_decode_bits(code, code_bits, &huffman_tree, usize::MAX) {
+            Ok(decoded) => decoded,
+            // The code lengths matched, so the recorded lengths locate the token
+            Err(DecodeError::InvalidCode { bit_offset }) => {
+                let (index, bit_offset) = trace.token_at(bit_offset);
+                return Ok(Some(Divergence::Token { index, bit_offset, recorded: trace.tokens.get(index).copied(), decoded: None }));
+            }
+            Err(error) => return Err(error.into()),
+        },
+        None => {
+            check_no_symbols(code_bits)?;
+            Vec::new()
+        }
+    };
+    let mismatch = decoded.iter().zip(&trace.tokens).position(|(decoded, recorded)| decoded != recorded);
+    let index = match mismatch {
+        Some(index) => Some(index),
+        None if decoded.len() != trace.tokens.len() => Some(decoded.len().min(trace.tokens.len())),
+        None => None,
+    };
+    if let Some(index) = index {
+        let (recorded, decoded) = (trace.tokens.get(index).copied(), decoded.get(index).copied());
+        return Ok(Some(Divergence::Token { index, bit_offset: trace.bit_offset(index), recorded, decoded }));
+    }
+
+    match Decompressor::new().decode_frame(frame, usize::MAX) {
+        Ok(_) => Ok(None),
+        Err(QuantumPackError::ChecksumMismatch { .. }) => Ok(Some(Divergence::Output)),
+        Err(error) => Err(error),
+    }
+}
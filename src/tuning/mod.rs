@@ -0,0 +1,184 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::compression::{CompressionLevel, Compressor};
+use crate::error::QuantumPackError;
+
+// Picking a compression level by trying them all on a sample of the data, e.g. the
+// first few hundred KiB of each file type a batch tool sees, or by watching how long
+// compressing takes under load. This lives outside the compression core because it
+// reads the clock.
+
+// What pick_level optimizes for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpeedOrRatio {
+    // The smallest output, however long it takes
+    Ratio,
+    // The smallest output among the levels that compress at least this many bytes per
+    // second; the fastest level if none does
+    MinSpeed(u64),
+    // The fastest level whose output is at most this fraction of the input, e.g. 0.5;
+    // the smallest output if none gets there
+    MaxRatio(f64),
+}
+
+// How one level did on the sample
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelTrial {
+    pub level: CompressionLevel,
+    pub input_len: usize,
+    pub compressed_len: usize,
+    pub elapsed: Duration,
+}
+
+impl LevelTrial {
+    pub fn ratio(&self) -> f64 {
+        self.compressed_len as f64 / self.input_len.max(1) as f64
+    }
+
+    // Input bytes per second
+    pub fn speed(&self) -> f64 {
+        self.input_len as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+}
+
+impl SpeedOrRatio {
+    // The level of `trials` that best meets the target, None without trials. Ties go
+    // to the faster level.
+    pub fn choose(self, trials: &[LevelTrial]) -> Option<CompressionLevel> {
+        match self {
+            SpeedOrRatio::Ratio => smallest(trials.iter()),
+            SpeedOrRatio::MinSpeed(bytes_per_sec) => {
+                smallest(trials.iter().filter(|trial| trial.speed() >= bytes_per_sec as f64)).or_else(|| fastest(trials.iter()))
+            }
+            SpeedOrRatio::MaxRatio(ratio) => fastest(trials.iter().filter(|trial| trial.ratio() <= ratio)).or_else(|| smallest(trials.iter())),
+        }
+    }
+}
+
+fn smallest<'a>(trials: impl Iterator<Item = &'a LevelTrial>) -> Option<CompressionLevel> {
+    trials.min_by_key(|trial| (trial.compressed_len, trial.elapsed)).map(|trial| trial.level)
+}
+
+fn fastest<'a>(trials: impl Iterator<Item = &'a LevelTrial>) -> Option<CompressionLevel> {
+    trials.min_by_key(|trial| trial.elapsed).map(|trial| trial.level)
+}
+
+impl Compressor {
+    // Compress `sample` at every level with these settings and measure each. Levels
+    // that fail on the sample are left out. Padding and warnings are turned off for
+    // the trials.
+    pub fn try_levels(&self, sample: &[u8]) -> Vec<LevelTrial> {
+        let mut compressor = self.clone();
+        compressor.pad_to = None;
+        compressor.warnings = None;
+        let mut frame = Vec::new();
+        let mut trials = Vec::new();
+        for level in CompressionLevel::ALL {
+            let compressor = compressor.clone().level(level);
+            frame.clear();
+            let start = Instant::now();
+            if compressor.compress_shared(sample, &mut frame).is_ok() {
+                trials.push(LevelTrial { level, input_len: sample.len(), compressed_len: frame.len(), elapsed: start.elapsed() });
+            }
+        }
+        trials
+    }
+
+    // The level that best meets `target` on `sample`, see try_levels. Default if no
+    // level compresses the sample.
+    pub fn pick_level(&self, sample: &[u8], target: SpeedOrRatio) -> CompressionLevel {
+        target.choose(&self.try_levels(sample)).unwrap_or_default()
+    }
+}
+
+// Pick a level for data like `sample` with the default settings
+pub fn pick_level(sample: &[u8], target: SpeedOrRatio) -> CompressionLevel {
+    Compressor::new().pick_level(sample, target)
+}
+
+// Holds encode latency under a target for services with a latency budget per
+// request: after every `window` compressions the slowest of them stands in for the
+// tail latency. Over the target the level goes one step down towards Fast; under
+// half of it, when there is time to spare, one step back up to the highest level
+// allowed. Shared by reference between the threads of a service.
+pub struct LevelController {
+    // One per level of CompressionLevel::ALL
+    compressors: Vec<Compressor>,
+    target: Duration,
+    window: usize,
+    max_level: usize,
+    state: Mutex<ControllerState>,
+}
+
+struct ControllerState {
+    // Index into CompressionLevel::ALL
+    level: usize,
+    slowest: Duration,
+    samples: usize,
+}
+
+impl LevelController {
+    // Starts at Best; `compressor` supplies every setting but the level
+    pub fn new(compressor: Compressor, target: Duration) -> Self {
+        let compressors = CompressionLevel::ALL.iter().map(|&level| compressor.clone().level(level)).collect();
+        let max_level = CompressionLevel::ALL.len() - 1;
+        let state = Mutex::new(ControllerState { level: max_level, slowest: Duration::ZERO, samples: 0 });
+        LevelController { compressors, target, window: 32, max_level, state }
+    }
+
+    // Compressions per adjustment (32 by default). Longer windows react later but
+    // catch rarer slow requests.
+    pub fn window(mut self, compressions: usize) -> Self {
+        self.window = compressions.max(1);
+        self
+    }
+
+    // The highest level the controller goes up to, also where it starts
+    pub fn max_level(mut self, level: CompressionLevel) -> Self {
+        self.max_level = level_index(level);
+        self.state.get_mut().unwrap_or_else(PoisonError::into_inner).level = self.max_level;
+        self
+    }
+
+    // The level the next compression runs at
+    pub fn level(&self) -> CompressionLevel {
+        CompressionLevel::ALL[self.lock().level]
+    }
+
+    // Compress `data` into a frame appended to `output` at the current level, see
+    // Compressor::compress_shared, and record how long it took
+    pub fn compress(&self, data: &[u8], output: &mut Vec<u8>) -> Result<(), QuantumPackError> {
+        let compressor = &self.compressors[self.lock().level];
+        let start = Instant::now();
+        compressor.compress_shared(data, output)?;
+        self.record(start.elapsed());
+        Ok(())
+    }
+
+    // Count a compression that took `latency`, for callers that compress at level()
+    // themselves, e.g. through a stream
+    pub fn record(&self, latency: Duration) {
+        let mut state = self.lock();
+        state.slowest = state.slowest.max(latency);
+        state.samples += 1;
+        if state.samples < self.window {
+            return;
+        }
+        if state.slowest > self.target {
+            state.level = state.level.saturating_sub(1);
+        } else if state.slowest < self.target / 2 {
+            state.level = (state.level + 1).min(self.max_level);
+        }
+        state.slowest = Duration::ZERO;
+        state.samples = 0;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ControllerState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn level_index(level: CompressionLevel) -> usize {
+    CompressionLevel::ALL.iter().position(|&candidate| candidate == level).expect("every level is in ALL")
+}
@@ -0,0 +1,43 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::sniff::CompressedFormat;
+
+// Conditions that do not stop compression or decompression but that a caller may
+// want to know about. They are handed to the handler set with
+// Compressor::on_warning or Decompressor::on_warning; without one they are dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    // The preprocessor dictionary matched nothing in the input, so the frame stores
+    // it for nothing
+    DictionaryUnused,
+    // Block `index` of an input (0 unless a block size is set) compressed to more
+    // bytes than it holds
+    BlockExpanded { index: usize, input_len: usize, frame_len: usize },
+    // The output at `path` kept its default modification time and permissions
+    MetadataNotRestored { path: String, reason: String },
+    // The input is in `format`, which is compressed already, and was stored as is
+    AlreadyCompressed { format: CompressedFormat },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::DictionaryUnused => write!(f, "the dictionary matched nothing in the input"),
+            Warning::BlockExpanded { index, input_len, frame_len } => {
+                write!(f, "block {} expanded from {} to {} bytes", index, input_len, frame_len)
+            }
+            Warning::MetadataNotRestored { path, reason } => write!(f, "could not restore the metadata of {}: {}", path, reason),
+            Warning::AlreadyCompressed { format } => write!(f, "the input is {} data, which is compressed already; stored as is", format.name()),
+        }
+    }
+}
+
+// Receives warnings, e.g. to log them or collect them into a Vec
+pub type WarningHandler = Arc<dyn Fn(Warning) + Send + Sync>;
+
+pub(crate) fn warn(handler: &Option<WarningHandler>, warning: Warning) {
+    if let Some(handler) = handler {
+        handler(warning);
+    }
+}
@@ -0,0 +1,100 @@
+use crate::error::QuantumPackError;
+
+// Byte layout shared by every on-disk structure: the container frame, the trained
+// dictionary, stage descriptors and page models. Fixed-width integers are always
+// big-endian and lengths inside dictionaries and stages are LEB128 varints, so a
+// file decodes the same on every platform regardless of the host byte order.
+
+pub fn write_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn write_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+// Number of bytes write_varint uses for `value`
+pub fn varint_len(value: u64) -> usize {
+    let bits = 64 - value.leading_zeros() as usize;
+    bits.max(1).div_ceil(7)
+}
+
+// Bounds-checked cursor over encoded data. Running out of input is reported as
+// "<what> is truncated", with `what` naming the structure being read.
+pub struct Reader<'a> {
+    data: &'a [u8],
+    what: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8], what: &'static str) -> Self {
+        Reader { data, what }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    // The bytes not read yet
+    pub fn rest(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], QuantumPackError> {
+        if len > self.data.len() {
+            return Err(QuantumPackError::truncated(self.what));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, QuantumPackError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, QuantumPackError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, QuantumPackError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, QuantumPackError> {
+        let bytes = self.bytes(8)?;
+        let mut array = [0; 8];
+        array.copy_from_slice(bytes);
+        Ok(u64::from_be_bytes(array))
+    }
+
+    pub fn varint(&mut self) -> Result<u64, QuantumPackError> {
+        let mut value = 0u64;
+        for index in 0..10 {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7F) as u64) << (7 * index);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(QuantumPackError::MalformedVarint(self.what))
+    }
+}
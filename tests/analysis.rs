@@ -0,0 +1,99 @@
+use quantum_pack::cost::{entropy as histogram_entropy, histogram};
+use quantum_pack::sniff::CompressedFormat;
+use quantum_pack::{analyze, byte_histogram, entropy, estimate_compressed_size, CompressionLevel, Recommendation};
+
+mod common;
+use common::noise;
+
+#[test]
+fn test_byte_histogram() {
+    let histogram = byte_histogram(b"abracadabra");
+    assert_eq!((histogram[b'a' as usize], histogram[b'b' as usize], histogram[b'r' as usize]), (5, 2, 2));
+    assert_eq!(histogram.iter().sum::<u64>(), 11);
+    assert_eq!(histogram.iter().filter(|&&count| count > 0).count(), 5);
+    assert_eq!(byte_histogram(&[]), [0; 256]);
+}
+
+#[test]
+fn test_entropy() {
+    assert_eq!(entropy(&[]), 0.0);
+    assert_eq!(entropy(&[7; 100]), 0.0);
+    assert_eq!(entropy(b"abab"), 1.0);
+    let every_byte: Vec<u8> = (0..=255).collect();
+    assert_eq!(entropy(&every_byte), 8.0);
+
+    // The same figure the cost model computes from a symbol histogram
+    let text = b"it was the best of times, it was the worst of times";
+    assert_eq!(entropy(text), histogram_entropy(&histogram(text)));
+}
+
+#[test]
+fn test_analyze_recommends_a_level() {
+    let text = b"2024-05-01 12:00:00 INFO request served in 3 ms\n".repeat(2000);
+    let analysis = analyze(&text);
+    assert_eq!((analysis.len, analysis.sample_len), (text.len(), 64 * 1024));
+    assert_eq!(analysis.entropy, entropy(&text));
+    assert_eq!(analysis.predicted_size, estimate_compressed_size(&text));
+    assert!(analysis.predicted_ratio() < 0.5);
+    assert!(!analysis.patterns.is_empty());
+    assert!(analysis.patterns.windows(2).all(|pair| pair[0].bytes_saved >= pair[1].bytes_saved));
+    assert_eq!(analysis.compressed_format, None);
+    assert_eq!(analysis.recommendation, Recommendation::Level(CompressionLevel::Best));
+
+    // Skewed bytes in no particular order: Huffman coding is all that helps
+    let skewed: Vec<u8> = noise(50_000).iter().map(|&byte| byte.leading_zeros() as u8).collect();
+    let analysis = analyze(&skewed);
+    assert_eq!(analysis.recommendation, Recommendation::Level(CompressionLevel::Fast));
+    assert!(analysis.predicted_ratio() < 0.5);
+
+    let analysis = analyze(&noise(100_000));
+    assert_eq!(analysis.recommendation, Recommendation::Store);
+    assert!(analysis.predicted_ratio() > 1.0);
+
+    let mut gzip = vec![0x1f, 0x8b, 8, 0];
+    gzip.extend(noise(10_000));
+    let analysis = analyze(&gzip);
+    assert_eq!(analysis.compressed_format, Some(CompressedFormat::Gzip));
+    assert_eq!(analysis.recommendation, Recommendation::Store);
+
+    for level in CompressionLevel::ALL {
+        assert_eq!(CompressionLevel::from_name(level.name()), Some(level));
+    }
+    assert_eq!(CompressionLevel::from_name("store"), None);
+}
+
+#[test]
+fn test_analyze_command() {
+    use std::process::Command;
+
+    let dir = std::env::temp_dir().join("quantum_pack_analyze");
+    std::fs::create_dir_all(&dir).unwrap();
+    let analyze_file = |name: &str, data: &[u8]| {
+        let path = dir.join(name);
+        std::fs::write(&path, data).unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_quantum_pack")).arg("analyze").arg(&path).args(["--top", "3"]).env_remove("QP_LOCALE_DIR").output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // The recommended level is one --level takes
+    let logs: Vec<u8> = (0..2000).flat_map(|n| format!("{{\"id\":{},\"user\":\"user{}\",\"plan\":\"pro\"}}\n", n, n % 7).into_bytes()).collect();
+    let report = analyze_file("logs.json", &logs);
+    let level = analyze(&logs).recommendation;
+    assert!(report.contains("Top patterns in a"), "{}", report);
+    assert_eq!(report.lines().filter(|line| line.ends_with('"')).count(), 3, "{}", report);
+    match level {
+        Recommendation::Level(level) => assert!(report.ends_with(&format!("Recommended level: {}\n", level.name())), "{}", report),
+        Recommendation::Store => panic!("logs should compress"),
+    }
+
+    let mut gzip = vec![0x1f, 0x8b, 8, 0];
+    gzip.extend(noise(10_000));
+    let report = analyze_file("data.gz", &gzip);
+    assert!(report.contains("Already compressed: gzip"), "{}", report);
+    assert!(report.ends_with("Recommended: leave it uncompressed, coding will not make it smaller\n"), "{}", report);
+
+    let missing = Command::new(env!("CARGO_BIN_EXE_quantum_pack")).arg("analyze").arg(dir.join("missing")).output().unwrap();
+    assert_eq!(missing.status.code(), Some(3));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
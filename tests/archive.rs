@@ -0,0 +1,427 @@
+use quantum_pack::archive::{pack, Archive};
+use quantum_pack::{Compressor, Decompressor, QuantumPackError};
+
+fn files() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("notes.txt", b"remember the milk, remember the milk".to_vec()),
+        ("logs/app.log", (0..200).flat_map(|n| format!("request {} ok\n", n % 9).into_bytes()).collect()),
+        ("empty", Vec::new()),
+    ]
+}
+
+fn archive() -> Vec<u8> {
+    let files = files();
+    let entries: Vec<(&str, &[u8])> = files.iter().map(|(path, contents)| (*path, contents.as_slice())).collect();
+    pack(&entries, &Compressor::new()).unwrap()
+}
+
+// files() as a version 1 archive, with the entry table in front
+fn legacy_archive() -> Vec<u8> {
+    let files = files();
+    let mut frames = Vec::new();
+    let mut table = (files.len() as u32).to_be_bytes().to_vec();
+    let table_len: usize = files.iter().map(|(path, _)| 1 + path.len() + 24).sum();
+    for (path, contents) in &files {
+        let start = frames.len();
+        Compressor::new().compress_shared(contents, &mut frames).unwrap();
+        table.push(path.len() as u8);
+        table.extend_from_slice(path.as_bytes());
+        table.extend_from_slice(&(contents.len() as u64).to_be_bytes());
+        table.extend_from_slice(&((9 + table_len + start) as u64).to_be_bytes());
+        table.extend_from_slice(&((frames.len() - start) as u64).to_be_bytes());
+    }
+    let mut archive = b"QPA1\x01".to_vec();
+    archive.extend_from_slice(&table);
+    archive.extend_from_slice(&frames);
+    archive
+}
+
+#[test]
+fn test_archive_round_trip() {
+    let data = archive();
+    let archive = Archive::parse(&data).unwrap();
+    let paths: Vec<&str> = archive.entries().iter().map(|entry| entry.path()).collect();
+    assert_eq!(paths, ["notes.txt", "logs/app.log", "empty"]);
+    for (path, contents) in files() {
+        let entry = archive.entry(path).unwrap();
+        assert_eq!(entry.size(), contents.len() as u64);
+        assert_eq!(archive.read(entry, &Decompressor::new()).unwrap(), contents);
+    }
+    assert!(archive.entry("missing").is_none());
+}
+
+#[test]
+fn test_version_one_archives_still_read() -> std::io::Result<()> {
+    let data = legacy_archive();
+    let archive = Archive::parse(&data).unwrap();
+    assert_eq!(archive.entries().len(), 3);
+    for (path, contents) in files() {
+        assert_eq!(archive.read(archive.entry(path).unwrap(), &Decompressor::new()).unwrap(), contents);
+    }
+
+    // Appending moves the table to the end and makes it version 2
+    let dir = std::env::temp_dir().join("quantum_pack_legacy");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    let archive_path = dir.join("old.qpa");
+    let later = dir.join("later.txt");
+    std::fs::write(&archive_path, &data)?;
+    std::fs::write(&later, "appended later\n")?;
+    quantum_pack::append_archive(archive_path.to_str().unwrap(), &[later.to_str().unwrap()], &Compressor::new())?;
+    let appended = std::fs::read(&archive_path)?;
+    assert_eq!(appended[4], 2);
+    assert_eq!(appended[5..data.len()], data[5..]);
+    let archive = Archive::parse(&appended).unwrap();
+    assert_eq!(archive.entries().len(), 4);
+    for (path, contents) in files() {
+        assert_eq!(archive.read(archive.entry(path).unwrap(), &Decompressor::new()).unwrap(), contents);
+    }
+    assert_eq!(archive.read(&archive.entries()[3], &Decompressor::new()).unwrap(), b"appended later\n");
+    std::fs::remove_dir_all(&dir)
+}
+
+#[test]
+fn test_archive_rejects_unsafe_paths() {
+    for path in ["", "/etc/passwd", "../up", "a/../../b", "a//b", "./a", "a\\b"].iter() {
+        let result = pack(&[(path, b"x")], &Compressor::new());
+        assert!(matches!(result, Err(QuantumPackError::InvalidArchive(_))), "{:?}", path);
+    }
+    assert!(matches!(pack(&[("a", b"x"), ("a", b"y")], &Compressor::new()), Err(QuantumPackError::InvalidInput(_))));
+
+    // A crafted entry table is checked as well
+    let mut data = pack(&[("ab", b"x")], &Compressor::new()).unwrap();
+    let path = data.len() - 12 - 24 - 2;
+    data[path..path + 2].copy_from_slice(b"..");
+    assert!(matches!(Archive::parse(&data), Err(QuantumPackError::InvalidArchive(_))));
+
+    // So is a table that holds a path twice
+    let mut data = pack(&[("ab", b"x"), ("ac", b"y")], &Compressor::new()).unwrap();
+    let path = data.len() - 12 - 24 - 2;
+    data[path..path + 2].copy_from_slice(b"ab");
+    assert!(matches!(Archive::parse(&data), Err(QuantumPackError::InvalidArchive(_))));
+    assert!(quantum_pack::archive::read_entries(std::io::Cursor::new(&data)).is_err());
+}
+
+#[test]
+fn test_corrupt_archives_are_rejected() {
+    let data = archive();
+    assert!(matches!(Archive::parse(b"QPK1"), Err(QuantumPackError::InvalidArchive(_))));
+    assert!(matches!(Archive::parse(b"QPA1\x02"), Err(QuantumPackError::Truncated { what: "archive", .. })));
+    // A cut archive has lost the trailer pointing at its entry table
+    assert!(matches!(Archive::parse(&data[..data.len() - 1]), Err(QuantumPackError::InvalidArchive(_))));
+
+    // An entry table offset past the end of the archive
+    let mut offset = data.clone();
+    let trailer = offset.len() - 12;
+    offset[trailer..trailer + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+    assert!(matches!(Archive::parse(&offset), Err(QuantumPackError::InvalidArchive(_))));
+
+    let mut version = data.clone();
+    version[4] = 9;
+    assert!(matches!(Archive::parse(&version), Err(QuantumPackError::UnsupportedVersion(9))));
+}
+
+#[test]
+fn test_create_and_extract_archive() -> std::io::Result<()> {
+    let dir = std::env::temp_dir().join("quantum_pack_archive");
+    let _ = std::fs::remove_dir_all(&dir);
+    for (path, contents) in files() {
+        let path = dir.join("input").join(path);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path, contents)?;
+    }
+    let archive_path = dir.join("backup.qpa");
+    let output_dir = dir.join("output");
+
+    let input_dir = dir.join("input");
+    quantum_pack::create_archive(&[input_dir.to_str().unwrap()], archive_path.to_str().unwrap(), &Compressor::new())?;
+    let extracted = quantum_pack::extract_archive(archive_path.to_str().unwrap(), output_dir.to_str().unwrap(), &Decompressor::new())?;
+
+    // Entries keep the input path without its leading '/', in name order
+    let prefix = input_dir.to_str().unwrap().trim_start_matches('/').to_string();
+    let expected: Vec<String> = ["empty", "logs/app.log", "notes.txt"].iter().map(|name| format!("{}/{}", prefix, name)).collect();
+    assert_eq!(extracted, expected);
+    for (path, contents) in files() {
+        assert_eq!(std::fs::read(output_dir.join(&prefix).join(path))?, contents);
+    }
+    std::fs::remove_dir_all(&dir)
+}
+
+#[test]
+fn test_archive_stats() {
+    let data = archive();
+    let entries = Archive::parse(&data).unwrap().entries().to_vec();
+    let stats = quantum_pack::archive::stats(&entries);
+    assert_eq!(stats.size, entries.iter().map(|entry| entry.size()).sum::<u64>());
+    assert_eq!(stats.compressed_len, entries.iter().map(|entry| entry.compressed_len()).sum::<u64>());
+    assert_eq!(stats.buckets.iter().sum::<usize>(), entries.len());
+
+    // The log shrinks, the short note and the empty file only gain frame overhead
+    let log = &entries[1];
+    assert!(log.ratio() < 1.0);
+    assert_eq!(stats.buckets[(log.ratio() * 10.0) as usize], 1);
+    assert_eq!(stats.buckets[quantum_pack::archive::RATIO_BUCKETS - 1], 2);
+    assert_eq!(stats.expanded, ["notes.txt", "empty"]);
+
+    // Sizes claimed by a crafted entry table add up to at most u64::MAX
+    let mut data = pack(&[("a", b"x"), ("b", b"y")], &Compressor::new()).unwrap();
+    for &size in &[data.len() - 12 - 24, data.len() - 12 - 24 - 2 - 24] {
+        data[size..size + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+    }
+    let stats = quantum_pack::archive::stats(Archive::parse(&data).unwrap().entries());
+    assert_eq!(stats.size, u64::MAX);
+}
+
+#[test]
+fn test_entry_table_reads_without_the_frames() -> std::io::Result<()> {
+    struct FramesUnread<'a> {
+        data: std::io::Cursor<&'a [u8]>,
+        frames_end: u64,
+    }
+
+    impl std::io::Read for FramesUnread<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let position = self.data.position();
+            assert!(position < 5 || position >= self.frames_end, "read a frame at {}", position);
+            self.data.read(buf)
+        }
+    }
+
+    impl std::io::Seek for FramesUnread<'_> {
+        fn seek(&mut self, position: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.data.seek(position)
+        }
+    }
+
+    let data = archive();
+    let entries = Archive::parse(&data).unwrap().entries().to_vec();
+    let frames_end = entries.iter().map(|entry| entry.offset() + entry.compressed_len()).max().unwrap();
+    let input = FramesUnread { data: std::io::Cursor::new(&data), frames_end };
+    assert_eq!(quantum_pack::archive::read_entries(input).unwrap(), entries);
+
+    let path = std::env::temp_dir().join("quantum_pack_list.qpa");
+    std::fs::write(&path, &data)?;
+    assert_eq!(quantum_pack::list_archive(path.to_str().unwrap()).unwrap(), entries);
+    std::fs::remove_file(&path)
+}
+
+#[test]
+fn test_extract_single_entry() -> std::io::Result<()> {
+    let dir = std::env::temp_dir();
+    let archive_path = dir.join("quantum_pack_single_entry.qpa");
+    let output_path = dir.join("quantum_pack_single_entry.log");
+    std::fs::write(&archive_path, archive())?;
+
+    let (path, contents) = files().remove(1);
+    quantum_pack::extract_archive_entry(archive_path.to_str().unwrap(), path, output_path.to_str().unwrap(), &Decompressor::new())?;
+    assert_eq!(std::fs::read(&output_path)?, contents);
+
+    let missing = quantum_pack::extract_archive_entry(archive_path.to_str().unwrap(), "logs", output_path.to_str().unwrap(), &Decompressor::new());
+    assert!(matches!(missing, Err(QuantumPackError::EntryNotFound(path)) if path == "logs"));
+
+    for path in [archive_path, output_path].iter() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_append_keeps_existing_frames() {
+    use quantum_pack::archive::append;
+
+    let data = archive();
+    let entries = Archive::parse(&data).unwrap().entries().to_vec();
+    let (offset, tail) = append(&entries, &[("later.txt", b"appended later, appended later")], &Compressor::new()).unwrap();
+    let mut appended = data[..offset as usize].to_vec();
+    appended.extend_from_slice(&tail);
+
+    let archive = Archive::parse(&appended).unwrap();
+    assert_eq!(&archive.entries()[..entries.len()], &entries[..]);
+    let later = archive.entry("later.txt").unwrap();
+    assert_eq!(archive.read(later, &Decompressor::new()).unwrap(), b"appended later, appended later");
+
+    let duplicate = append(&entries, &[("notes.txt", b"again")], &Compressor::new());
+    assert!(matches!(duplicate, Err(QuantumPackError::InvalidInput(_))));
+}
+
+#[test]
+fn test_append_archive() -> std::io::Result<()> {
+    let dir = std::env::temp_dir().join("quantum_pack_append");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    let first = dir.join("first.log");
+    let second = dir.join("second.log");
+    let archive_path = dir.join("logs.qpa");
+    std::fs::write(&first, "monday: all quiet\n")?;
+    std::fs::write(&second, "tuesday: disk full\n")?;
+
+    quantum_pack::create_archive(&[first.to_str().unwrap()], archive_path.to_str().unwrap(), &Compressor::new())?;
+    let before = std::fs::read(&archive_path)?;
+    quantum_pack::append_archive(archive_path.to_str().unwrap(), &[second.to_str().unwrap()], &Compressor::new())?;
+    let after = std::fs::read(&archive_path)?;
+
+    let entries = quantum_pack::list_archive(archive_path.to_str().unwrap())?;
+    assert_eq!(entries.len(), 2);
+    let frames_end = (entries[0].offset() + entries[0].compressed_len()) as usize;
+    assert_eq!(before[..frames_end], after[..frames_end]);
+
+    let archive = Archive::parse(&after).unwrap();
+    assert_eq!(archive.read(&entries[1], &Decompressor::new()).unwrap(), b"tuesday: disk full\n");
+    std::fs::remove_dir_all(&dir)
+}
+
+#[cfg(unix)]
+#[test]
+fn test_extract_restores_file_metadata() -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let dir = std::env::temp_dir().join("quantum_pack_archive_metadata");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    let script = dir.join("run.sh");
+    let archive_path = dir.join("scripts.qpa");
+    let output_dir = dir.join("output");
+    std::fs::write(&script, "#!/bin/sh\necho hello\n")?;
+    let mtime = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    std::fs::OpenOptions::new().write(true).open(&script)?.set_modified(mtime)?;
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+
+    quantum_pack::create_archive(&[script.to_str().unwrap()], archive_path.to_str().unwrap(), &Compressor::new().preserve_metadata(true))?;
+    quantum_pack::extract_archive(archive_path.to_str().unwrap(), output_dir.to_str().unwrap(), &Decompressor::new())?;
+    let extracted = output_dir.join(script.to_str().unwrap().trim_start_matches('/'));
+    let metadata = std::fs::metadata(&extracted)?;
+    assert_eq!(metadata.modified()?, mtime);
+    assert_eq!(metadata.permissions().mode() & 0o7777, 0o755);
+
+    let data = std::fs::read(&archive_path)?;
+    let archive = Archive::parse(&data).unwrap();
+    let recorded = archive.metadata(&archive.entries()[0]).unwrap().unwrap();
+    assert_eq!((recorded.mtime, recorded.mode), (1_500_000_000, 0o755));
+    std::fs::remove_dir_all(&dir)
+}
+
+#[cfg(unix)]
+#[test]
+fn test_archive_skips_linked_directories() -> std::io::Result<()> {
+    let dir = std::env::temp_dir().join("quantum_pack_archive_links");
+    let _ = std::fs::remove_dir_all(&dir);
+    let input_dir = dir.join("input");
+    std::fs::create_dir_all(input_dir.join("sub"))?;
+    std::fs::write(input_dir.join("sub/file.txt"), "linked twice")?;
+    std::os::unix::fs::symlink(&input_dir, input_dir.join("sub/loop"))?;
+    std::os::unix::fs::symlink(input_dir.join("sub/file.txt"), input_dir.join("link.txt"))?;
+    let archive_path = dir.join("links.qpa");
+
+    quantum_pack::create_archive(&[input_dir.to_str().unwrap()], archive_path.to_str().unwrap(), &Compressor::new())?;
+    let data = std::fs::read(&archive_path)?;
+    let archive = Archive::parse(&data).unwrap();
+    let prefix = input_dir.to_str().unwrap().trim_start_matches('/');
+    let paths: Vec<&str> = archive.entries().iter().map(|entry| entry.path().trim_start_matches(prefix)).collect();
+    assert_eq!(paths, ["/link.txt", "/sub/file.txt"]);
+    std::fs::remove_dir_all(&dir)
+}
+
+#[test]
+fn test_keep_going_reports_failed_inputs() -> std::io::Result<()> {
+    let dir = std::env::temp_dir().join("quantum_pack_keep_going");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    let a = dir.join("a.txt");
+    let b = dir.join("b.txt");
+    let missing = dir.join("missing.txt");
+    std::fs::write(&a, "first file, first file")?;
+    std::fs::write(&b, "second file, second file")?;
+    let archive_path = dir.join("batch.qpa");
+    let inputs = [a.to_str().unwrap(), missing.to_str().unwrap(), b.to_str().unwrap()];
+
+    assert!(quantum_pack::create_archive(&inputs, archive_path.to_str().unwrap(), &Compressor::new()).is_err());
+    let report = quantum_pack::create_archive_keep_going(&inputs, archive_path.to_str().unwrap(), &Compressor::new())?;
+    let name = |path: &std::path::Path| path.to_str().unwrap().trim_start_matches('/').to_string();
+    assert_eq!(report.succeeded, vec![name(&a), name(&b)]);
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].path, missing.to_str().unwrap());
+    assert!(matches!(report.failed[0].error, QuantumPackError::Io(_)));
+
+    // Break the checksum of the first entry, the second still extracts
+    let entries = quantum_pack::list_archive(archive_path.to_str().unwrap())?;
+    let mut data = std::fs::read(&archive_path)?;
+    data[(entries[0].offset() + entries[0].compressed_len() - 1) as usize] ^= 0xFF;
+    std::fs::write(&archive_path, &data)?;
+
+    let output_dir = dir.join("output");
+    assert!(matches!(
+        quantum_pack::extract_archive(archive_path.to_str().unwrap(), output_dir.to_str().unwrap(), &Decompressor::new()),
+        Err(QuantumPackError::ChecksumMismatch { .. })
+    ));
+    let report = quantum_pack::extract_archive_keep_going(archive_path.to_str().unwrap(), output_dir.to_str().unwrap(), &Decompressor::new())?;
+    assert!(!report.is_complete());
+    assert_eq!(report.succeeded, vec![name(&b)]);
+    assert_eq!(report.failed[0].path, name(&a));
+    assert!(matches!(report.failed[0].error, QuantumPackError::ChecksumMismatch { .. }));
+    assert_eq!(std::fs::read(output_dir.join(name(&b)))?, b"second file, second file");
+    std::fs::remove_dir_all(&dir)
+}
+
+#[test]
+fn test_split_archive_index() -> std::io::Result<()> {
+    let dir = std::env::temp_dir().join("quantum_pack_split");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("in"))?;
+    std::fs::write(dir.join("in/a.txt"), b"first file, first file, first file")?;
+    std::fs::write(dir.join("in/b.txt"), b"second")?;
+    let data_path = dir.join("logs.qpa");
+    let data = data_path.to_str().unwrap();
+    let index = quantum_pack::index_path(data);
+    assert_eq!(std::path::Path::new(&index), dir.join("logs.qpi"));
+
+    quantum_pack::create_split_archive(&[dir.join("in/a.txt").to_str().unwrap()], data, &Compressor::new()).unwrap();
+    let original = std::fs::read(&data_path)?;
+    quantum_pack::append_archive(data, &[dir.join("in/b.txt").to_str().unwrap()], &Compressor::new()).unwrap();
+    // Appending leaves what is already in the data file alone
+    let appended = std::fs::read(&data_path)?;
+    assert_eq!(&appended[..original.len()], original.as_slice());
+
+    let names = |entries: Vec<quantum_pack::archive::ArchiveEntry>| entries.iter().map(|entry| entry.path().rsplit('/').next().unwrap().to_string()).collect::<Vec<_>>();
+    assert_eq!(names(quantum_pack::list_archive(data).unwrap()), ["a.txt", "b.txt"]);
+    let index_bytes = std::fs::read(&index)?;
+    let (covered, entries) = quantum_pack::archive::read_index(&index_bytes).unwrap();
+    assert_eq!(covered, appended.len() as u64);
+    assert_eq!(entries, Archive::parse(&appended).unwrap().entries());
+
+    // An index left behind by the append still finds the new entry, and a lost one is rebuilt
+    let stale = quantum_pack::archive::write_index(&entries[..1], original.len() as u64);
+    std::fs::write(&index, stale)?;
+    assert_eq!(names(quantum_pack::list_archive(data).unwrap()), ["a.txt", "b.txt"]);
+    std::fs::remove_file(&index)?;
+    assert_eq!(names(quantum_pack::list_archive(data).unwrap()), ["a.txt", "b.txt"]);
+    assert_eq!(names(quantum_pack::reindex_archive(data).unwrap()), ["a.txt", "b.txt"]);
+    assert_eq!(std::fs::read(&index)?, index_bytes);
+
+    let entry = entries[1].path().to_string();
+    let out = dir.join("b.out");
+    quantum_pack::extract_archive_entry(data, &entry, out.to_str().unwrap(), &Decompressor::new()).unwrap();
+    assert_eq!(std::fs::read(&out)?, b"second");
+    quantum_pack::extract_archive(data, dir.join("out").to_str().unwrap(), &Decompressor::new()).unwrap();
+    assert_eq!(std::fs::read(dir.join("out").join(&entry))?, b"second");
+
+    // An index covering more than the data file holds does not belong to it, and a
+    // damaged one is no use either; the records are read instead
+    std::fs::write(&index, quantum_pack::archive::write_index(&entries, appended.len() as u64 + 10))?;
+    assert_eq!(names(quantum_pack::list_archive(data).unwrap()), ["a.txt", "b.txt"]);
+    assert!(matches!(quantum_pack::reindex_archive(&index), Err(QuantumPackError::InvalidArchive(_))));
+    std::fs::write(&index, &index_bytes[..index_bytes.len() - 3])?;
+    assert_eq!(names(quantum_pack::list_archive(data).unwrap()), ["a.txt", "b.txt"]);
+    std::fs::remove_file(&out)?;
+    quantum_pack::extract_archive_entry(data, &entry, out.to_str().unwrap(), &Decompressor::new()).unwrap();
+    assert_eq!(std::fs::read(&out)?, b"second");
+    std::fs::write(dir.join("in/c.txt"), b"third")?;
+    quantum_pack::append_archive(data, &[dir.join("in/c.txt").to_str().unwrap()], &Compressor::new()).unwrap();
+    assert_eq!(names(quantum_pack::list_archive(data).unwrap()), ["a.txt", "b.txt", "c.txt"]);
+
+    // The index is replaced whole, without a temporary file left next to it
+    let mut files: Vec<_> = std::fs::read_dir(&dir)?.map(|entry| entry.unwrap().file_name()).collect();
+    files.sort();
+    assert_eq!(files, ["b.out", "in", "logs.qpa", "logs.qpi", "out"]);
+    std::fs::remove_dir_all(&dir)
+}
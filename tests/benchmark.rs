@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+use quantum_pack::{self_benchmark, CompressionLevel, Compressor};
+
+#[test]
+fn test_self_benchmark() {
+    let throughput = self_benchmark(Duration::from_millis(200));
+    assert!(throughput.compress_mb_per_sec > 0.0 && throughput.compress_mb_per_sec.is_finite());
+    assert!(throughput.decompress_mb_per_sec > 0.0 && throughput.decompress_mb_per_sec.is_finite());
+    assert!(throughput.ratio > 0.0 && throughput.ratio < 0.8);
+
+    // Settings are measured as given, and a zero duration still runs once
+    let fast = Compressor::new().level(CompressionLevel::Fast).self_benchmark(Duration::ZERO).unwrap();
+    assert!(fast.compress_mb_per_sec > 0.0);
+    assert!(fast.ratio > throughput.ratio);
+}
+
+#[test]
+fn test_self_benchmark_with_preset_dictionary() {
+    let mut preprocessor = quantum_pack::preprocessor::Preprocessor::new();
+    preprocessor.fit(&b"{\"level\":\"info\",\"path\":\"/api/users\",\"status\":200}\n".repeat(100));
+    let compressor = Compressor::new().preset_dictionary(preprocessor.dictionary().clone());
+    assert!(compressor.self_benchmark(Duration::ZERO).unwrap().decompress_mb_per_sec > 0.0);
+}
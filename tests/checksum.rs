@@ -0,0 +1,15 @@
+use quantum_pack::checksum::{crc32, Crc32};
+
+#[test]
+fn test_crc32_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32(b""), 0);
+}
+
+#[test]
+fn test_crc32_incremental_matches_one_shot() {
+    let mut crc = Crc32::new();
+    crc.update(b"1234");
+    crc.update(b"56789");
+    assert_eq!(crc.finish(), crc32(b"123456789"));
+}
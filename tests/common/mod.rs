@@ -0,0 +1,15 @@
+// Helpers shared by the integration tests
+
+// Bytes from a xorshift generator, about as incompressible as it gets. The same
+// `len` always gives the same bytes.
+pub fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
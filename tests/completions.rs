@@ -0,0 +1,40 @@
+use quantum_pack::completions::{script, Shell, COMMANDS, OPTIONS};
+use quantum_pack::messages::Message;
+
+#[test]
+fn test_shell_names_round_trip() {
+    for shell in Shell::ALL {
+        assert_eq!(Shell::from_name(shell.name()), Some(shell));
+    }
+    assert_eq!(Shell::from_name("tcsh"), None);
+}
+
+#[test]
+fn test_scripts_cover_the_whole_cli() {
+    for shell in Shell::ALL {
+        let script = script(shell, "qp");
+        for command in COMMANDS {
+            assert!(script.contains(command.name), "{} script lacks {}", shell.name(), command.name);
+        }
+        for option in OPTIONS {
+            let name = if shell == Shell::Fish { option.name.trim_start_matches('-') } else { option.name };
+            assert!(script.contains(name), "{} script lacks {}", shell.name(), option.name);
+        }
+    }
+    assert!(script(Shell::Bash, "qp").ends_with("complete -o filenames -F _qp qp\n"));
+    assert!(script(Shell::Zsh, "qp-dev").starts_with("#compdef qp-dev\n\n_qp_dev() {"));
+}
+
+// The table is kept by hand next to the parser in main.rs, so at least make sure
+// the usage text and the completions describe the same options
+#[test]
+fn test_options_match_usage() {
+    let usage: String = Message::ALL.iter().filter(|message| message.key().starts_with("usage")).map(|message| message.english()).collect();
+    for option in OPTIONS {
+        if option.name.starts_with("--") || option.name == "-o" {
+            assert!(usage.contains(option.name), "usage does not mention {}", option.name);
+        }
+    }
+    assert!(usage.contains("-1..-9"));
+    assert!(usage.contains("-q|-v|-vv"));
+}
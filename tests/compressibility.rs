@@ -0,0 +1,55 @@
+use quantum_pack::{compress_shared, compressibility_profile, estimate_compressed_size, CompressionLevel, Compressor};
+
+mod common;
+use common::noise;
+
+#[test]
+fn test_profile_tells_regions_apart() {
+    let text = b"the quick brown fox jumps over the lazy dog. ".repeat(100);
+    let mut data = text[..4096].to_vec();
+    data.extend(noise(4096));
+    data.extend(vec![0u8; 4096]);
+    data.extend_from_slice(b"tail");
+
+    let profile = compressibility_profile(&data, 4096);
+    assert_eq!(profile.iter().map(|window| (window.offset, window.len)).collect::<Vec<_>>(), vec![(0, 4096), (4096, 4096), (8192, 4096), (12288, 4)]);
+
+    let (text, noise, zeros) = (profile[0], profile[1], profile[2]);
+    assert!(text.entropy > 3.0 && text.entropy < 5.0);
+    assert!(text.is_compressible());
+    assert!(noise.entropy > 7.9);
+    assert!(!noise.is_compressible());
+    // A lone symbol still costs a bit per byte
+    assert_eq!(zeros.entropy, 0.0);
+    assert_eq!(zeros.predicted_ratio, (4096 / 8 + 1) as f64 / 4096.0);
+
+    assert!(compressibility_profile(&[], 4096).is_empty());
+    assert_eq!(compressibility_profile(b"abc", 0).len(), 3);
+}
+
+#[test]
+fn test_predicted_ratio_matches_fast_level() {
+    let data = b"log line 17: request served in 12ms\n".repeat(50);
+    let window = compressibility_profile(&data, data.len())[0];
+    let (compressed, _, _) = Compressor::new().level(CompressionLevel::Fast).compress(&data).unwrap();
+    assert_eq!(window.predicted_ratio, compressed.len() as f64 / data.len() as f64);
+}
+
+#[test]
+fn test_estimated_size_is_close_to_the_frame() {
+    // Inputs the sample covers completely are predicted exactly
+    let small = b"log line 17: request served in 12ms\n".repeat(50);
+    for data in [&[][..], b"a", &small, &noise(20_000)] {
+        assert_eq!(estimate_compressed_size(data), compress_shared(data).unwrap().len());
+    }
+
+    let text: Vec<u8> = (0..400_000u32).flat_map(|n| format!("{{\"id\":{},\"name\":\"user{}\"}}\n", n, n % 97).into_bytes()).take(400_000).collect();
+    let mut mixed = noise(100_000);
+    mixed.extend_from_slice(&text[..100_000]);
+    mixed.extend(noise(100_000));
+    for data in [text, mixed, noise(400_000)] {
+        let estimate = estimate_compressed_size(&data) as f64;
+        let actual = compress_shared(&data).unwrap().len() as f64;
+        assert!((estimate / actual - 1.0).abs() < 0.1, "estimated {} for {} bytes", estimate, actual);
+    }
+}
@@ -2,6 +2,8 @@ use std::collections::BTreeMap;
 
 use quantum_pack::{huffman::{ build_huffman_tree, generate_huffman_codes, huffman_encode, huffman_decode}, adaptive_dictionary::AdaptiveDictionary, preprocessor::Preprocessor, serialize_frequency_table, deserialize_frequency_table};
 
+mod common;
+
 #[test]
 fn test_huffman_with_preprocessor_integration() {
     let input_data = b"The quick brown fox jumps over the lazy dog";
@@ -21,7 +23,7 @@ fn test_huffman_with_preprocessor_integration() {
     let encoded_data = huffman_encode(&processed_data, &codes);
     
     // Step 4: Decode the data
-    let decoded_data = huffman_decode(&encoded_data, &huffman_tree);
+    let decoded_data = huffman_decode(&encoded_data, &huffman_tree).unwrap();
 
     // Step 5: Reverse preprocess the data
     let original_data = preprocessor.reverse_transform_data(&decoded_data);
@@ -93,4 +95,921 @@ mod tests {
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_code_length_table_round_trip() -> std::io::Result<()> {
+    use quantum_pack::{Compressor, preprocessor::{DictionaryMode, Preprocessor}};
+
+    let dir = std::env::temp_dir();
+    let input_path = dir.join("quantum_pack_code_lengths.txt");
+    let compressed_path = dir.join("quantum_pack_code_lengths.qp");
+    let decompressed_path = dir.join("quantum_pack_code_lengths.out");
+    let contents = "code lengths instead of frequencies, code lengths instead of frequencies";
+    std::fs::write(&input_path, contents)?;
+
+    // No patterns, so the round trip only exercises the Huffman table
+    let compressor = Compressor::new()
+        .preprocessor(Preprocessor::builder().dictionary_mode(DictionaryMode::Replace).build());
+    compressor.compress_file(input_path.to_str().unwrap(), compressed_path.to_str().unwrap())?;
+    quantum_pack::decompress_file(compressed_path.to_str().unwrap(), decompressed_path.to_str().unwrap())?;
+
+    assert_eq!(std::fs::read_to_string(&decompressed_path)?, contents);
+
+    for path in [input_path, compressed_path, decompressed_path].iter() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_truncated_file_is_rejected() -> std::io::Result<()> {
+    use quantum_pack::QuantumPackError;
+
+    let dir = std::env::temp_dir();
+    let input_path = dir.join("quantum_pack_truncated.txt");
+    let compressed_path = dir.join("quantum_pack_truncated.qp");
+    let decompressed_path = dir.join("quantum_pack_truncated.out");
+    std::fs::write(&input_path, "truncated frames must not decode, truncated frames must not decode")?;
+    quantum_pack::compress_file(input_path.to_str().unwrap(), compressed_path.to_str().unwrap())?;
+
+    let compressed = std::fs::read(&compressed_path)?;
+    for cut in [1, 8, 17, 20].iter() {
+        std::fs::write(&compressed_path, &compressed[..compressed.len() - cut])?;
+        let result = quantum_pack::decompress_file(compressed_path.to_str().unwrap(), decompressed_path.to_str().unwrap());
+        assert!(matches!(result, Err(QuantumPackError::Truncated { .. })));
+    }
+    assert!(!decompressed_path.exists());
+
+    for path in [input_path, compressed_path].iter() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_corrupted_payload_fails_checksum() -> std::io::Result<()> {
+    use quantum_pack::{Compressor, QuantumPackError, preprocessor::{DictionaryMode, Preprocessor}};
+
+    let dir = std::env::temp_dir();
+    let input_path = dir.join("quantum_pack_corrupted.txt");
+    let compressed_path = dir.join("quantum_pack_corrupted.qp");
+    let decompressed_path = dir.join("quantum_pack_corrupted.out");
+    std::fs::write(&input_path, "abcabcabcabcabcabc")?;
+    Compressor::new()
+        .preprocessor(Preprocessor::builder().dictionary_mode(DictionaryMode::Replace).build())
+        .compress_file(input_path.to_str().unwrap(), compressed_path.to_str().unwrap())?;
+
+    // Flip the last bit of the checksum
+    let mut compressed = std::fs::read(&compressed_path)?;
+    let last = compressed.len() - 1;
+    compressed[last] ^= 1;
+    std::fs::write(&compressed_path, &compressed)?;
+
+    let result = quantum_pack::decompress_file(compressed_path.to_str().unwrap(), decompressed_path.to_str().unwrap());
+    assert!(matches!(result, Err(QuantumPackError::ChecksumMismatch { .. })));
+
+    for path in [input_path, compressed_path].iter() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_trailing_data_policy() -> std::io::Result<()> {
+    use quantum_pack::{Decompressor, QuantumPackError, TrailingData, TrailingDataPolicy};
+
+    let dir = std::env::temp_dir();
+    let input_path = dir.join("quantum_pack_trailing.txt");
+    let compressed_path = dir.join("quantum_pack_trailing.qp");
+    let decompressed_path = dir.join("quantum_pack_trailing.out");
+    let contents = "aaaaaaaabbbbbbbbaaaaaaaabbbbbbbb";
+    std::fs::write(&input_path, contents)?;
+    quantum_pack::compress_file(input_path.to_str().unwrap(), compressed_path.to_str().unwrap())?;
+
+    let mut compressed = std::fs::read(&compressed_path)?;
+    let frame_len = compressed.len() as u64;
+    compressed.extend_from_slice(b"\0\0pad");
+    std::fs::write(&compressed_path, &compressed)?;
+
+    let strict = Decompressor::new().decompress_file(compressed_path.to_str().unwrap(), decompressed_path.to_str().unwrap());
+    match strict {
+        Err(QuantumPackError::TrailingData(trailing)) => assert_eq!(trailing, TrailingData { offset: frame_len, len: 5 }),
+        other => panic!("expected trailing data error, got {:?}", other),
+    }
+
+    let trailing = Decompressor::new()
+        .trailing_data(TrailingDataPolicy::Permissive)
+        .decompress_file(compressed_path.to_str().unwrap(), decompressed_path.to_str().unwrap())?;
+    assert_eq!(trailing, Some(TrailingData { offset: frame_len, len: 5 }));
+    assert_eq!(std::fs::read_to_string(&decompressed_path)?, contents);
+
+    for path in [input_path, compressed_path, decompressed_path].iter() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_compress_shared_appends_decodable_frames() -> std::io::Result<()> {
+    use quantum_pack::{Compressor, Decompressor, TrailingDataPolicy};
+
+    let pages: [&[u8]; 2] = [b"page one, page one, page one", b"page two, page two, page two"];
+    let mut output = Vec::new();
+    let compressor = Compressor::new();
+    compressor.compress_shared(pages[0], &mut output)?;
+    let first_len = output.len();
+    compressor.compress_shared(pages[1], &mut output)?;
+
+    let (first, trailing) = Decompressor::new()
+        .trailing_data(TrailingDataPolicy::Permissive)
+        .decompress(&output)?;
+    assert_eq!(first, pages[0]);
+    assert_eq!(trailing.unwrap().offset, first_len as u64);
+
+    let (second, trailing) = Decompressor::new().decompress(&output[first_len..])?;
+    assert_eq!(second, pages[1]);
+    assert_eq!(trailing, None);
+    assert_eq!(quantum_pack::compress_shared(pages[1])?, output[first_len..].to_vec());
+    Ok(())
+}
+
+#[test]
+fn test_binary_data_round_trip() -> std::io::Result<()> {
+    use quantum_pack::{Decompressor, compress_file, decompress_file};
+
+    let mut inputs: Vec<Vec<u8>> = vec![Vec::new(), vec![0xFF; 100], vec![0; 1]];
+    inputs.push((0..=255u8).collect());
+    inputs.push((0..4096u32).map(|n| (n.wrapping_mul(2_654_435_761) >> 24) as u8).collect());
+    inputs.push([0xFF, 0x00].repeat(500));
+
+    for input in &inputs {
+        let frame = quantum_pack::compress_shared(input)?;
+        let (decoded, _) = Decompressor::new().decompress(&frame)?;
+        assert_eq!(&decoded, input);
+    }
+
+    // Invalid UTF-8 goes through the file functions unchanged
+    let input_path = "./test_binary_round_trip.bin";
+    let compressed_path = "./test_binary_round_trip.qp";
+    let decompressed_path = "./test_binary_round_trip.out";
+    std::fs::write(input_path, &inputs[3])?;
+    compress_file(input_path, compressed_path)?;
+    decompress_file(compressed_path, decompressed_path)?;
+    assert_eq!(std::fs::read(decompressed_path)?, inputs[3]);
+
+    for path in [input_path, compressed_path, decompressed_path].iter() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_malformed_input_is_an_error() {
+    use quantum_pack::{Decompressor, QuantumPackError};
+
+    let decompressor = Decompressor::new();
+    assert!(matches!(decompressor.decompress(b""), Err(QuantumPackError::Truncated { .. })));
+    assert!(matches!(decompressor.decompress(b"QPK"), Err(QuantumPackError::Truncated { .. })));
+    assert!(matches!(decompressor.decompress(b"\xFF\xFF\xFF\xFFnot a frame"), Err(QuantumPackError::NotAFrame)));
+
+    let mut newer = quantum_pack::compress_shared(b"from the future").unwrap();
+    newer[4] = 4;
+    assert!(matches!(decompressor.decompress(&newer), Err(QuantumPackError::UnsupportedVersion(4))));
+    // Version 2 uses every flag bit; version 1 knew only the low two
+    newer[4] = 1;
+    newer[5] = 0x04;
+    assert!(matches!(decompressor.decompress(&newer), Err(QuantumPackError::CorruptHeader { .. })));
+
+    // A frame whose footer does not start with the end marker
+    let mut frame = quantum_pack::compress_shared(b"marker, marker, marker").unwrap();
+    let marker = frame.len() - 16;
+    frame[marker] = b'X';
+    let error = decompressor.decompress(&frame).unwrap_err();
+    assert!(matches!(error, QuantumPackError::CorruptHeader { .. }));
+
+    // Read and Write adapters see the error as InvalidData
+    let error: std::io::Error = error.into();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+    let missing = quantum_pack::decompress_file("./does-not-exist.qp", "./does-not-exist.out").unwrap_err();
+    assert!(matches!(missing, QuantumPackError::Io(_)));
+}
+
+#[test]
+fn test_version_one_frames_still_decode() {
+    use quantum_pack::Decompressor;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect()
+    }
+    // The same input written by version 1 with a frequency table and with code length pairs
+    let frequencies = hex("51504b310100000000370100000002020000000105000000020a000000020b000000020e000000021000000002130000000216000000021a000000022c00000001000000780200010165000201200003016e0004016f0005026f6e000601720007022066000802206f00090161000a02616d000b026520000c026572000d0166000e026672000f0169001002696f0011016d0012026d650013026e200014026e650015027261001602727300170173001802736900190176001a0276650000000a11f2ca7f7a11f2ca7c0651504e44000000000000002401076e72");
+    let code_lengths = hex("51504b310101000000160104020505030a030b040e031003130416031a032c05000000780200010165000201200003016e0004016f0005026f6e000601720007022066000802206f00090161000a02616d000b026520000c026572000d0166000e026672000f0169001002696f0011016d0012026d650013026e200014026e650015027261001602727300170173001802736900190176001a0276650000000ab1f0d473feb1f0d4700651504e44000000000000002401076e72");
+    for frame in [frequencies, code_lengths] {
+        let (decoded, _) = Decompressor::new().decompress(&frame).unwrap();
+        assert_eq!(decoded, b"version one frame, version one frame");
+    }
+
+    // Later versions store one byte per symbol instead of a symbol and a u32 frequency
+    let frame = quantum_pack::compress_shared(&b"version one frame, ".repeat(20)).unwrap();
+    let table_len = u32::from_be_bytes([frame[7], frame[8], frame[9], frame[10]]);
+    assert!(table_len < 40);
+}
+
+#[test]
+fn test_table_encodings() {
+    use quantum_pack::{decode_memory, CompressionLevel, Compressor, Decompressor, QuantumPackError, TableEncoding};
+
+    // Without the preprocessor the bytes are the symbols: a few letters, all far from 0
+    let text = b"hello world, hello there world, ".repeat(30);
+    let frame = |encoding| {
+        let mut frame = Vec::new();
+        Compressor::new().level(CompressionLevel::Fast).table_encoding(encoding).compress_shared(&text, &mut frame).unwrap();
+        assert_eq!(Decompressor::new().decompress(&frame).unwrap().0, text);
+        frame
+    };
+    let (smallest, dense, sparse) = (frame(TableEncoding::Smallest), frame(TableEncoding::Dense), frame(TableEncoding::Sparse));
+    // A dense table runs up to the highest symbol, 'w'; a sparse one has a pair per
+    // symbol and a block type byte announcing it
+    let dense_table = u32::from_be_bytes([dense[7], dense[8], dense[9], dense[10]]) as usize;
+    let sparse_table = u32::from_be_bytes([sparse[8], sparse[9], sparse[10], sparse[11]]) as usize;
+    assert_eq!(dense_table, b'w' as usize + 1);
+    assert_eq!(sparse_table, 2 * 10);
+    assert_eq!(sparse.len() + dense_table, dense.len() + sparse_table + 1);
+    assert_eq!(smallest, sparse);
+    assert_eq!(decode_memory(&sparse).unwrap(), decode_memory(&dense).unwrap());
+
+    // Every byte value is a symbol, so the dense table is the smaller one
+    let bytes: Vec<u8> = (0..=255u8).cycle().take(4000).collect();
+    let mut frame = Vec::new();
+    Compressor::new().level(CompressionLevel::Fast).compress_shared(&bytes, &mut frame).unwrap();
+    let mut dense = Vec::new();
+    Compressor::new().level(CompressionLevel::Fast).table_encoding(TableEncoding::Dense).compress_shared(&bytes, &mut dense).unwrap();
+    assert_eq!(frame, dense);
+
+    // Sparse tables list each symbol once, in order, with a length
+    let mut unordered = sparse.clone();
+    unordered.swap(12, 14);
+    assert!(matches!(Decompressor::new().decompress(&unordered), Err(QuantumPackError::CorruptHeader { .. })));
+    let mut uncoded = sparse.clone();
+    uncoded[13] = 0;
+    assert!(matches!(Decompressor::new().decompress(&uncoded), Err(QuantumPackError::CorruptHeader { .. })));
+}
+
+#[test]
+fn test_version_two_frames_still_decode() {
+    use quantum_pack::{decode_memory, Decompressor};
+
+    // Version 2 ends the data section with the number of bits used in its last byte
+    // instead of recording the padding bits in the header
+    let symbols = [0x51, 0x50, 0x4b, 0x31, 0x02, 0x04, 0x02, 0, 0, 0, 0x08, 0x03, 0x01, 0x02, 0x00, 0x02, 0xa9, 0x02, 0x01, 0, 0, 0, 0, 0, 0, 0, 0x02, 0x46, 0x07, 0x51, 0x50, 0x4e, 0x44, 0, 0, 0, 0, 0, 0, 0, 0x0a, 0xf9, 0x24, 0x6b, 0x3b];
+    assert_eq!(Decompressor::new().decompress_symbols::<u16>(&symbols).unwrap().0, [300, 1, 300, 300, 2]);
+
+    // The same frame in both versions: drop the padding bits and append the bit count
+    let input = b"version two frame, ".repeat(20);
+    let frame = quantum_pack::compress_shared(&input).unwrap();
+    assert_eq!(frame[4..6], [3, 0]);
+    let padding_bits = frame[6];
+    let mut v2 = vec![];
+    v2.extend_from_slice(b"QPK1\x02\x00");
+    let mut position = 7;
+    for section in 0..3 {
+        let size = u32::from_be_bytes([frame[position], frame[position + 1], frame[position + 2], frame[position + 3]]) as usize;
+        let mut content = frame[position + 4..position + 4 + size].to_vec();
+        if section == 2 {
+            content.push(8 - padding_bits);
+        }
+        v2.extend_from_slice(&(content.len() as u32).to_be_bytes());
+        v2.extend_from_slice(&content);
+        position += 4 + size;
+    }
+    v2.extend_from_slice(&frame[position..]);
+    assert_eq!(v2.len(), frame.len());
+    assert_eq!(Decompressor::new().decompress(&v2).unwrap().0, input);
+    assert!(decode_memory(&v2).is_ok());
+
+    // Version 2 has no padding bits in the header, so the count byte cannot be left out
+    let mut short = v2.clone();
+    let footer = short.split_off(short.len() - 16);
+    short.pop();
+    short.extend_from_slice(&footer);
+    assert!(Decompressor::new().decompress(&short).is_err());
+}
+
+#[test]
+fn test_compression_levels() {
+    use quantum_pack::{CompressionLevel, Compressor, Decompressor};
+
+    let data: Vec<u8> = (0..400).flat_map(|n| format!("{{\"id\":{},\"name\":\"user{}\"}}\n", n, n % 13).into_bytes()).collect();
+    let mut sizes = Vec::new();
+    for level in [CompressionLevel::Fast, CompressionLevel::Default, CompressionLevel::Best] {
+        let mut frame = Vec::new();
+        Compressor::new().level(level).compress_shared(&data, &mut frame).unwrap();
+        assert_eq!(Decompressor::new().decompress(&frame).unwrap().0, data);
+        sizes.push(frame.len());
+    }
+    assert!(sizes[0] > sizes[1] && sizes[1] >= sizes[2], "{:?}", sizes);
+
+    // Best keeps the smallest of its variants, one of which may use wide codes
+    let wide = Preprocessor::builder().max_pattern_length(8).max_entries(1024).sample_size(usize::MAX).build();
+    let mut frame = Vec::new();
+    Compressor::new().preprocessor(wide).compress_shared(&data, &mut frame).unwrap();
+    assert!(sizes[2] <= frame.len(), "{} {}", sizes[2], frame.len());
+    assert_eq!(quantum_pack::preprocessor::DEFAULT_SAMPLE_SIZE, 8 << 20);
+
+    // Fast does not run the preprocessor, so the frame carries an empty dictionary
+    let (_, _, dictionary) = Compressor::new().level(CompressionLevel::Fast).compress(&data).unwrap();
+    assert_eq!(dictionary, [2]);
+
+    assert_eq!(CompressionLevel::from_number(1), Some(CompressionLevel::Fast));
+    assert_eq!(CompressionLevel::from_number(6), Some(CompressionLevel::Default));
+    assert_eq!(CompressionLevel::from_number(9), Some(CompressionLevel::Best));
+    assert_eq!(CompressionLevel::from_number(0), None);
+}
+
+#[test]
+fn test_compress_to_and_decompress_to_use_readers_and_writers() {
+    use quantum_pack::{Compressor, Decompressor, QuantumPackError};
+
+    let data = b"piped through stdin and stdout, piped through stdin and stdout".to_vec();
+    let mut compressed = Vec::new();
+    Compressor::new().compress_to(&data[..], &mut compressed).unwrap();
+    assert_eq!(compressed, quantum_pack::compress_shared(&data).unwrap());
+
+    let mut decompressed = Vec::new();
+    assert_eq!(Decompressor::new().decompress_to(&compressed[..], &mut decompressed).unwrap(), None);
+    assert_eq!(decompressed, data);
+
+    // Nothing is written for a corrupt frame
+    let mut output = Vec::new();
+    let error = Decompressor::new().decompress_to(&compressed[..compressed.len() - 1], &mut output).unwrap_err();
+    assert!(matches!(error, QuantumPackError::Truncated { .. }));
+    assert!(output.is_empty());
+}
+
+#[test]
+fn test_compress_to_writes_each_block_when_done() {
+    use std::io::Read;
+    use quantum_pack::{Compressor, Decompressor};
+
+    // Yields `data` and then fails, like a disk error halfway through a huge file
+    struct FailingReader<'a> {
+        data: &'a [u8],
+    }
+    impl Read for FailingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.data.is_empty() {
+                return Err(std::io::Error::other("disk error"));
+            }
+            self.data.read(buf)
+        }
+    }
+
+    let data = b"streamed a block at a time, ".repeat(200);
+    let compressor = Compressor::new().preserve_metadata(false).block_size(1000);
+    let mut streamed = Vec::new();
+    compressor.compress_to(&data[..], &mut streamed).unwrap();
+    let mut expected = Vec::new();
+    compressor.compress_shared(&data, &mut expected).unwrap();
+    assert_eq!(streamed, expected);
+    assert_eq!(Decompressor::new().decompress(&streamed).unwrap().0, data);
+
+    // The frames of the blocks before the failure are already out
+    let mut partial = Vec::new();
+    assert!(compressor.compress_to(FailingReader { data: &data[..3500] }, &mut partial).is_err());
+    let mut first = Vec::new();
+    compressor.compress_shared(&data[..3500], &mut first).unwrap();
+    assert!(!partial.is_empty());
+    assert!(first.starts_with(&partial));
+}
+
+#[test]
+fn test_max_output_size() {
+    use quantum_pack::{Decompressor, QuantumPackError};
+
+    let data = vec![b'z'; 10_000];
+    let frame = quantum_pack::compress_shared(&data).unwrap();
+    let (decoded, _) = Decompressor::new().max_output_size(10_000).decompress(&frame).unwrap();
+    assert_eq!(decoded, data);
+
+    let result = Decompressor::new().max_output_size(9_999).decompress(&frame);
+    assert!(matches!(result, Err(QuantumPackError::OutputLimitExceeded { limit: 9_999 })));
+
+    // A footer that understates the length stops the patterns there, within the limit
+    let mut lying = frame.clone();
+    let footer = lying.len() - 12;
+    lying[footer..footer + 8].copy_from_slice(&100u64.to_be_bytes());
+    let result = Decompressor::new().max_output_size(1_000).decompress(&lying);
+    assert!(matches!(result, Err(QuantumPackError::ChecksumMismatch { .. })));
+}
+
+#[test]
+fn test_failed_outputs_leave_no_files_behind() -> std::io::Result<()> {
+    use quantum_pack::{Compressor, Decompressor, OutputPolicy};
+
+    let dir = std::env::temp_dir().join("quantum_pack_output_policy");
+    std::fs::create_dir_all(&dir)?;
+    let input_path = dir.join("input.txt");
+    let compressed_path = dir.join("input.qp");
+    let output_path = dir.join("output.txt");
+    std::fs::write(&input_path, "replaced only once the whole output is written")?;
+    Compressor::new().compress_file(input_path.to_str().unwrap(), compressed_path.to_str().unwrap())?;
+    let mut compressed = std::fs::read(&compressed_path)?;
+    compressed.truncate(compressed.len() - 4);
+    std::fs::write(&compressed_path, &compressed)?;
+
+    // A failed decode keeps the previous output and leaves no temporary file
+    std::fs::write(&output_path, "previous")?;
+    assert!(Decompressor::new().decompress_file(compressed_path.to_str().unwrap(), output_path.to_str().unwrap()).is_err());
+    assert!(Compressor::new().recompress_file(compressed_path.to_str().unwrap(), output_path.to_str().unwrap()).is_err());
+    assert_eq!(std::fs::read_to_string(&output_path)?, "previous");
+
+    // In place, the half-written output is removed
+    let in_place = Compressor::new().output_policy(OutputPolicy::InPlace);
+    assert!(in_place.recompress_file(compressed_path.to_str().unwrap(), output_path.to_str().unwrap()).is_err());
+    assert!(!output_path.exists());
+
+    let mut names: Vec<_> = std::fs::read_dir(&dir)?.map(|entry| entry.unwrap().file_name()).collect();
+    names.sort();
+    assert_eq!(names, ["input.qp", "input.txt"]);
+    std::fs::remove_dir_all(&dir)
+}
+
+#[test]
+fn test_recompress_onto_the_input() -> std::io::Result<()> {
+    use quantum_pack::{Compressor, Decompressor, OutputPolicy};
+
+    let dir = std::env::temp_dir().join("quantum_pack_recompress_onto_input");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    let contents = b"recompressed where it lies, recompressed where it lies".repeat(20);
+    let path = dir.join("input.qp");
+    let path = path.to_str().unwrap();
+    std::fs::write(path, quantum_pack::compress_shared(&contents).unwrap())?;
+    let compressed = std::fs::read(path)?;
+
+    // In place the output would truncate the input before it is read
+    let in_place = Compressor::new().output_policy(OutputPolicy::InPlace);
+    assert!(in_place.recompress_file(path, path).is_err());
+    assert_eq!(std::fs::read(path)?, compressed);
+
+    // A temporary file only replaces the input once it is read
+    Compressor::new().block_size(200).recompress_file(path, path)?;
+    assert_eq!(Decompressor::new().concatenated(true).decompress(&std::fs::read(path)?).unwrap().0, contents);
+    std::fs::remove_dir_all(&dir)
+}
+
+#[test]
+fn test_output_policies_write_the_output() -> std::io::Result<()> {
+    use std::sync::{Arc, Mutex};
+    use quantum_pack::{Compressor, Decompressor, OutputPolicy};
+
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let dir = std::env::temp_dir();
+    let input_path = dir.join("quantum_pack_policies.txt");
+    let compressed_path = dir.join("quantum_pack_policies.qp");
+    let decompressed_path = dir.join("quantum_pack_policies.out");
+    let contents = "every policy produces the same output, every policy produces the same output";
+    std::fs::write(&input_path, contents)?;
+
+    Compressor::new()
+        .output_policy(OutputPolicy::InPlace)
+        .compress_file(input_path.to_str().unwrap(), compressed_path.to_str().unwrap())?;
+    Decompressor::new().decompress_file(compressed_path.to_str().unwrap(), decompressed_path.to_str().unwrap())?;
+    assert_eq!(std::fs::read_to_string(&decompressed_path)?, contents);
+
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let sink = written.clone();
+    let opened = Arc::new(Mutex::new(None));
+    let opened_path = opened.clone();
+    let custom = OutputPolicy::Custom(Arc::new(move |path: &std::path::Path| {
+        *opened_path.lock().unwrap() = Some(path.to_path_buf());
+        Ok(Box::new(Shared(sink.clone())) as Box<dyn std::io::Write + Send>)
+    }));
+    Decompressor::new()
+        .output_policy(custom)
+        .decompress_file(compressed_path.to_str().unwrap(), "objects/policies.out")?;
+    assert_eq!(&*written.lock().unwrap(), contents.as_bytes());
+    assert_eq!(opened.lock().unwrap().as_deref(), Some(std::path::Path::new("objects/policies.out")));
+
+    for path in [input_path, compressed_path, decompressed_path].iter() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_decode_memory_bounds_the_decoded_frame() {
+    use quantum_pack::stage::{IntegerCodec, IntegerStage, IntegerWidth, Stage};
+    use quantum_pack::{decode_memory, Compressor, QuantumPackError};
+
+    let data: Vec<u8> = (0..2000).flat_map(|n: u32| format!("{} ", n % 50).into_bytes()).collect();
+    let mut frame = Vec::new();
+    Compressor::new().compress_shared(&data, &mut frame).unwrap();
+    let memory = decode_memory(&frame).unwrap();
+    assert_eq!(memory.output, data.len());
+    assert!(memory.block_buffer > 0 && memory.block_buffer <= 3 * data.len());
+    assert!(memory.tables > 0);
+    assert_eq!(memory.total(), memory.tables + memory.block_buffer + memory.output);
+
+    // Reversing a stage needs its input next to the output
+    let integers: Vec<u8> = (0..500u32).flat_map(|n| n.to_le_bytes()).collect();
+    let mut staged = Vec::new();
+    Compressor::new()
+        .stage(Stage::Integer(IntegerStage::new(IntegerCodec::FrameOfReference, IntegerWidth::Bits32)))
+        .compress_shared(&integers, &mut staged)
+        .unwrap();
+    assert!(decode_memory(&staged).unwrap().output > 2 * integers.len());
+
+    assert!(matches!(decode_memory(&frame[..frame.len() - 1]), Err(QuantumPackError::Truncated { .. })));
+    assert!(matches!(decode_memory(b"not a frame"), Err(QuantumPackError::NotAFrame)));
+}
+
+#[test]
+fn test_symbol_frames() {
+    use quantum_pack::{decode_memory, Compressor, Decompressor, QuantumPackError};
+
+    let tokens: Vec<u16> = (0..3000u32).map(|n| [1000, 1001, 40_000, 7][(n % 7 % 4) as usize]).collect();
+    let mut frame = Vec::new();
+    Compressor::new().compress_symbols(&tokens, &mut frame).unwrap();
+    assert!(frame.len() < tokens.len());
+    assert_eq!(frame[5], 0x04);
+    assert_eq!(Decompressor::new().decompress_symbols::<u16>(&frame).unwrap().0, tokens);
+
+    // As bytes the frame decodes to the symbols in little-endian order
+    let bytes: Vec<u8> = tokens.iter().flat_map(|token| token.to_le_bytes().to_vec()).collect();
+    assert_eq!(Decompressor::new().decompress(&frame).unwrap().0, bytes);
+    assert_eq!(decode_memory(&frame).unwrap().output, bytes.len());
+    let wrong_width = Decompressor::new().decompress_symbols::<u32>(&frame);
+    assert!(matches!(wrong_width, Err(QuantumPackError::InvalidInput(_))));
+
+    let ids: Vec<u32> = (0..1000).map(|n| n * 65_537 % 5 + 1_000_000).collect();
+    let mut frame = Vec::new();
+    Compressor::new().compress_symbols(&ids, &mut frame).unwrap();
+    assert_eq!(Decompressor::new().decompress_symbols::<u32>(&frame).unwrap().0, ids);
+
+    // Byte frames decode as u8 symbols, and so do empty symbol frames
+    let mut frame = Vec::new();
+    Compressor::new().compress_shared(b"plain bytes", &mut frame).unwrap();
+    assert_eq!(Decompressor::new().decompress_symbols::<u8>(&frame).unwrap().0, b"plain bytes");
+    let mut frame = Vec::new();
+    Compressor::new().compress_symbols::<u32>(&[], &mut frame).unwrap();
+    assert!(Decompressor::new().decompress_symbols::<u32>(&frame).unwrap().0.is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_file_metadata_is_preserved() -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::{Duration, UNIX_EPOCH};
+    use quantum_pack::{frame_metadata, Compressor, Decompressor, FileMetadata};
+
+    let dir = std::env::temp_dir().join("quantum_pack_metadata");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    let input_path = dir.join("backup.txt");
+    let compressed_path = dir.join("backup.qp");
+    let restored_path = dir.join("restored.txt");
+    let plain_path = dir.join("plain.txt");
+    std::fs::write(&input_path, "nightly backup, nightly backup")?;
+    let mtime = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    std::fs::OpenOptions::new().write(true).open(&input_path)?.set_modified(mtime)?;
+    std::fs::set_permissions(&input_path, std::fs::Permissions::from_mode(0o640))?;
+
+    Compressor::new().preserve_metadata(true).compress_file(input_path.to_str().unwrap(), compressed_path.to_str().unwrap())?;
+    let frame = std::fs::read(&compressed_path)?;
+    assert_eq!(frame_metadata(&frame).unwrap(), Some(FileMetadata { mtime: 1_600_000_000, mode: 0o640 }));
+
+    Decompressor::new().decompress_file(compressed_path.to_str().unwrap(), restored_path.to_str().unwrap())?;
+    let restored = std::fs::metadata(&restored_path)?;
+    assert_eq!(restored.modified()?, mtime);
+    assert_eq!(restored.permissions().mode() & 0o7777, 0o640);
+
+    // Either side can leave the metadata out, and the compressor does by default
+    Decompressor::new().preserve_metadata(false).decompress_file(compressed_path.to_str().unwrap(), plain_path.to_str().unwrap())?;
+    assert_ne!(std::fs::metadata(&plain_path)?.modified()?, mtime);
+    Compressor::new().compress_file(input_path.to_str().unwrap(), compressed_path.to_str().unwrap())?;
+    assert_eq!(frame_metadata(&std::fs::read(&compressed_path)?).unwrap(), None);
+
+    // Permission bits only; a setuid bit in the frame is not restored
+    std::fs::set_permissions(&input_path, std::fs::Permissions::from_mode(0o4755))?;
+    Compressor::new().preserve_metadata(true).compress_file(input_path.to_str().unwrap(), compressed_path.to_str().unwrap())?;
+    std::fs::remove_file(&restored_path)?;
+    Decompressor::new().decompress_file(compressed_path.to_str().unwrap(), restored_path.to_str().unwrap())?;
+    assert_eq!(std::fs::metadata(&restored_path)?.permissions().mode() & 0o7777, 0o755);
+    std::fs::remove_dir_all(&dir)
+}
+
+#[test]
+fn test_error_as_json() {
+    use quantum_pack::{Compressor, Decompressor, QuantumPackError};
+
+    let mut frame = Vec::new();
+    Compressor::new().compress_shared(b"a \"quoted\" line\n", &mut frame).unwrap();
+    let frame_len = frame.len();
+    frame.extend_from_slice(b"junk");
+    let error = Decompressor::new().decompress(&frame).unwrap_err();
+    assert_eq!(error.kind(), "trailing_data");
+    assert_eq!(error.offset(), Some(frame_len as u64));
+    assert_eq!(
+        error.to_json("error.decompress", Some("dir\\\"odd\".qp")),
+        format!(
+            "{{\"code\":\"error.decompress\",\"kind\":\"trailing_data\",\"message\":\"4 unexpected bytes after the end of the frame at offset {}\",\"path\":\"dir\\\\\\\"odd\\\".qp\",\"offset\":{}}}",
+            frame_len, frame_len
+        )
+    );
+
+    // Errors in a frame point at where the frame starts, here the second block
+    let data = b"every block of this text is a frame of its own, ".repeat(20);
+    let mut frames = Vec::new();
+    Compressor::new().block_size(300).compress_shared(&data, &mut frames).unwrap();
+    let blocks = quantum_pack::stream::block_index(std::io::Cursor::new(&frames)).unwrap();
+    let second = blocks[1];
+    frames[(second.offset + second.frame_len) as usize - 1] ^= 1;
+    let error = Decompressor::new().decompress(&frames).unwrap_err();
+    assert!(matches!(error, QuantumPackError::ChecksumMismatch { offset } if offset == second.offset));
+    assert!(error.to_json("error.decompress", None).ends_with(&format!("\"offset\":{}}}", second.offset)));
+    frames.truncate(second.offset as usize + 10);
+    let error = Decompressor::new().decompress(&frames).unwrap_err();
+    assert!(matches!(error, QuantumPackError::Truncated { offset, .. } if offset == second.offset));
+
+    let error = QuantumPackError::InvalidInput("line\nbreak".to_string());
+    assert_eq!(error.to_json("usage", None), "{\"code\":\"usage\",\"kind\":\"invalid_input\",\"message\":\"line\\nbreak\",\"path\":null,\"offset\":null}");
+}
+
+// Holds with and without the mmap feature, which changes how compress_file reads
+#[test]
+fn test_compress_file_matches_in_memory() -> std::io::Result<()> {
+    use quantum_pack::Compressor;
+
+    let dir = std::env::temp_dir().join("quantum_pack_compress_file");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    let compressor = Compressor::new().preserve_metadata(false).block_size(4096);
+    for (name, contents) in [("empty", Vec::new()), ("text", b"mapped or read, the frame is the same\n".repeat(400))] {
+        let input_path = dir.join(name);
+        let output_path = dir.join(format!("{}.qp", name));
+        std::fs::write(&input_path, &contents)?;
+        compressor.compress_file(input_path.to_str().unwrap(), output_path.to_str().unwrap()).unwrap();
+        let mut expected = Vec::new();
+        compressor.compress_shared(&contents, &mut expected).unwrap();
+        assert_eq!(std::fs::read(&output_path)?, expected);
+    }
+    std::fs::remove_dir_all(&dir)
+}
+
+#[test]
+fn test_pad_to_fixed_size() {
+    use std::io::{Cursor, Read};
+    use quantum_pack::stream::{QpDecoder, SeekableReader};
+    use quantum_pack::{decode_memory, Compressor, Decompressor, QuantumPackError};
+
+    let data = b"fits in one slot, fits in one slot\n".repeat(40);
+    let mut frame = Vec::new();
+    Compressor::new().pad_to(4096).compress_shared(&data, &mut frame).unwrap();
+    assert_eq!(frame.len(), 4096);
+    assert_eq!(Decompressor::new().decompress(&frame).unwrap(), (data.clone(), None));
+    assert!(decode_memory(&frame).is_ok());
+
+    // Split into blocks only the last frame is padded, and the blocks still read as one input
+    let mut blocks = Vec::new();
+    Compressor::new().block_size(500).pad_to(8192).compress_shared(&data, &mut blocks).unwrap();
+    assert_eq!(blocks.len(), 8192);
+    assert_eq!(Decompressor::new().decompress(&blocks).unwrap().0, data);
+    let mut decoded = Vec::new();
+    QpDecoder::new(&blocks[..]).read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, data);
+    let mut reader = SeekableReader::new(Cursor::new(&blocks)).unwrap();
+    assert_eq!(reader.len(), data.len() as u64);
+    decoded.clear();
+    reader.read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, data);
+
+    let mut streamed = Vec::new();
+    Compressor::new().block_size(500).pad_to(8192).compress_to(&data[..], &mut streamed).unwrap();
+    assert_eq!(streamed, blocks);
+
+    // An exact fit needs no padding section; one too small for it is refused
+    let mut unpadded = Vec::new();
+    Compressor::new().compress_shared(&data, &mut unpadded).unwrap();
+    let mut exact = Vec::new();
+    Compressor::new().pad_to(unpadded.len()).compress_shared(&data, &mut exact).unwrap();
+    assert_eq!(exact, unpadded);
+    for size in [unpadded.len() - 1, unpadded.len() + 3] {
+        let result = Compressor::new().pad_to(size).compress_shared(&data, &mut Vec::new());
+        assert!(matches!(result, Err(QuantumPackError::InvalidInput(_))));
+    }
+}
+
+#[test]
+fn test_compressor_tokens() {
+    use quantum_pack::preprocessor::Token;
+    use quantum_pack::{CompressionLevel, Compressor};
+
+    let data = b"<item><name>widget</name></item>".repeat(40);
+    let (tokens, dictionary) = Compressor::new().tokens(&data).unwrap();
+    assert!(tokens.len() < data.len());
+    let expanded: Vec<u8> = tokens
+        .iter()
+        .flat_map(|token| match *token {
+            Token::Literal(byte) => vec![byte],
+            Token::Pattern { code, .. } => dictionary.pattern(code).unwrap().to_vec(),
+        })
+        .collect();
+    assert_eq!(expanded, data);
+
+    // Fast skips the preprocessor, so every byte is a literal
+    let (tokens, dictionary) = Compressor::new().level(CompressionLevel::Fast).tokens(&data).unwrap();
+    assert!(dictionary.is_empty());
+    assert_eq!(tokens, data.iter().map(|&byte| Token::Literal(byte)).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_preset_dictionary_frames() {
+    use quantum_pack::preprocessor::TrainedDictionary;
+    use quantum_pack::{compress_shared, compress_with_dictionary, decompress_with_dictionary, frame_dictionary_id, Decompressor, QuantumPackError};
+
+    let mut dictionary = TrainedDictionary::new();
+    dictionary.insert(1, b"\"status\":\"ok\"".to_vec(), 10).unwrap();
+    dictionary.insert(2, b"\"request_id\":".to_vec(), 10).unwrap();
+    let message = b"{\"request_id\":42,\"status\":\"ok\"}\n".repeat(8);
+
+    let frame = compress_with_dictionary(&message, &dictionary).unwrap();
+    assert_eq!(frame_dictionary_id(&frame).unwrap(), Some(dictionary.preset_id()));
+    assert!(frame.len() < compress_shared(&message).unwrap().len());
+    assert_eq!(decompress_with_dictionary(&frame, &dictionary).unwrap(), message);
+
+    let mut other = dictionary.clone();
+    other.insert(3, b"\"error\":".to_vec(), 1).unwrap();
+    match decompress_with_dictionary(&frame, &other) {
+        Err(QuantumPackError::DictionaryMismatch { expected, found }) => {
+            assert_eq!(expected, dictionary.preset_id());
+            assert_eq!(found, Some(other.preset_id()));
+        }
+        result => panic!("expected a dictionary mismatch, got {:?}", result),
+    }
+    assert!(matches!(Decompressor::new().decompress(&frame), Err(QuantumPackError::DictionaryMismatch { found: None, .. })));
+    // Empty messages still name their dictionary
+    let empty = compress_with_dictionary(b"", &dictionary).unwrap();
+    assert!(matches!(decompress_with_dictionary(&empty, &other), Err(QuantumPackError::DictionaryMismatch { .. })));
+    assert_eq!(decompress_with_dictionary(&empty, &dictionary).unwrap(), b"");
+    assert_eq!(frame_dictionary_id(&compress_shared(&message).unwrap()).unwrap(), None);
+}
+
+#[test]
+fn test_incompressible_blocks_are_stored() {
+    use quantum_pack::stage::{IntegerCodec, IntegerStage, IntegerWidth, Stage};
+    use quantum_pack::{decode_memory, Compressor, Decompressor};
+
+    let noise = common::noise(10_000);
+
+    // Header, block type, padding bits, three empty-or-raw sections and the footer
+    let frame = quantum_pack::compress_shared(&noise).unwrap();
+    assert_eq!(frame.len(), noise.len() + 6 + 1 + 1 + 12 + 16);
+    assert_eq!(Decompressor::new().decompress(&frame).unwrap().0, noise);
+    assert_eq!(decode_memory(&frame).unwrap().total(), noise.len());
+
+    // Only the blocks that do not compress are stored, and they drop the stage
+    let mut input = noise.clone();
+    input.extend(vec![b'z'; 10_000]);
+    let mut frame = Vec::new();
+    let stage = Stage::Integer(IntegerStage::new(IntegerCodec::FrameOfReference, IntegerWidth::Bits32));
+    Compressor::new().block_size(10_000).stage(stage).compress_shared(&input, &mut frame).unwrap();
+    assert!(frame.len() < noise.len() + 2_000);
+    assert_eq!(Decompressor::new().decompress(&frame).unwrap().0, input);
+}
+
+#[test]
+fn test_tie_seed_is_recorded_and_reproducible() {
+    use quantum_pack::{decode_memory, frame_tie_seed, Compressor, Decompressor};
+
+    let input: Vec<u8> = (0..2_000u32).flat_map(|index| format!("k{}=v{};", index % 7, index % 5).into_bytes()).collect();
+    let default = quantum_pack::compress_shared(&input).unwrap();
+    assert_eq!(default[5] & 0x01, 0);
+    assert_eq!(frame_tie_seed(&default).unwrap(), 0);
+
+    let seeded = Compressor::new().tie_seed(0x5eed);
+    let mut frame = Vec::new();
+    seeded.compress_shared(&input, &mut frame).unwrap();
+    assert_eq!(frame[5] & 0x01, 0x01);
+    assert_eq!(frame_tie_seed(&frame).unwrap(), 0x5eed);
+    assert_eq!(Decompressor::new().decompress(&frame).unwrap().0, input);
+    assert!(decode_memory(&frame).is_ok());
+
+    // The seed in the header is all it takes to write the same frame again
+    let mut again = Vec::new();
+    Compressor::new().tie_seed(frame_tie_seed(&frame).unwrap()).compress_shared(&input, &mut again).unwrap();
+    assert_eq!(again, frame);
+
+    // Every block records it, and the frames still walk back to back
+    let mut blocks = Vec::new();
+    seeded.clone().block_size(4_000).compress_shared(&input, &mut blocks).unwrap();
+    let mut joined = blocks.clone();
+    joined.extend_from_slice(&frame);
+    let (decoded, _) = Decompressor::new().concatenated(true).decompress(&joined).unwrap();
+    assert_eq!(decoded, [input.clone(), input].concat());
+}
+
+#[test]
+fn test_empty_and_single_symbol_input() {
+    use quantum_pack::{decode_memory, CompressionLevel, Compressor, Decompressor, QuantumPackError};
+
+    let inputs: [&[u8]; 4] = [b"", b"a", &[b'a'; 5_000], &[0xff; 5_000]];
+    for level in [CompressionLevel::Fast, CompressionLevel::Default, CompressionLevel::Best] {
+        for input in inputs {
+            for compressor in [Compressor::new().level(level), Compressor::new().level(level).block_size(3)] {
+                let mut frame = Vec::new();
+                compressor.compress_shared(input, &mut frame).unwrap();
+                let (decoded, _) = Decompressor::new().decompress(&frame).unwrap();
+                assert_eq!(decoded, input);
+                assert!(decode_memory(&frame).is_ok());
+            }
+        }
+    }
+    for symbols in [Vec::new(), vec![7u32; 1_000]] {
+        let mut frame = Vec::new();
+        Compressor::new().compress_symbols(&symbols, &mut frame).unwrap();
+        assert_eq!(Decompressor::new().decompress_symbols::<u32>(&frame).unwrap().0, symbols);
+    }
+
+    // A Huffman frame with an empty table leaves no room for symbols in the data
+    // section, which may only hold an empty bitstream
+    let mut empty = Vec::new();
+    Compressor::new().compress_shared(b"", &mut empty).unwrap();
+    let footer = &empty[empty.len() - 16..];
+    let huffman_frame = |padding_bits: u8, data: &[u8]| [&b"QPK1\x03\x00"[..], &[padding_bits], &[0; 8], &(data.len() as u32).to_be_bytes(), data, footer].concat();
+    assert!(Decompressor::new().decompress(&huffman_frame(0, &[])).unwrap().0.is_empty());
+    for (padding_bits, data) in [(0, &[0x00][..]), (7, &[0x00]), (1, &[])] {
+        assert!(matches!(Decompressor::new().decompress(&huffman_frame(padding_bits, data)), Err(QuantumPackError::CorruptHeader { .. })));
+    }
+
+    // The lone symbol of a single symbol table has the code 0, so a 1 bit is invalid
+    let mut single = Vec::new();
+    Compressor::new().level(CompressionLevel::Fast).compress_shared(&[b'a'; 5_000], &mut single).unwrap();
+    let data_start = single.len() - 16 - 625;
+    assert_eq!(single[data_start - 4..single.len() - 16], [&625u32.to_be_bytes()[..], &[0; 625]].concat());
+    single[data_start] = 0x80;
+    assert!(Decompressor::new().decompress(&single).is_err());
+}
+
+#[test]
+fn test_adaptive_huffman_frames() {
+    use quantum_pack::{decode_memory, CompressionLevel, Compressor, Decompressor, QuantumPackError};
+
+    let text = b"the quick brown fox jumps over the lazy dog, again and again. ".repeat(300);
+    let inputs: [&[u8]; 4] = [&text, b"", &[b'a'; 5_000], b"abracadabra"];
+    for level in [CompressionLevel::Fast, CompressionLevel::Default] {
+        for input in inputs {
+            for compressor in [Compressor::new().level(level), Compressor::new().level(level).block_size(1_000)] {
+                let mut frame = Vec::new();
+                compressor.adaptive_huffman(true).compress_shared(input, &mut frame).unwrap();
+                assert_eq!(Decompressor::new().decompress(&frame).unwrap().0, input);
+                assert!(decode_memory(&frame).is_ok());
+            }
+        }
+    }
+
+    // Block type 2 after the flags and padding bits, then an empty table
+    let mut frame = Vec::new();
+    Compressor::new().adaptive_huffman(true).compress_shared(&text, &mut frame).unwrap();
+    assert_eq!(frame[..6], *b"QPK1\x03\x80");
+    assert_eq!(frame[6], 2);
+    assert_eq!(frame[8..12], [0; 4]);
+    let mut static_frame = Vec::new();
+    Compressor::new().compress_shared(&text, &mut static_frame).unwrap();
+    assert!(frame.len() < static_frame.len() + static_frame.len() / 20);
+
+    // The code is learned from the data, so a table has no place in the frame
+    let mut with_table = frame.clone();
+    with_table[11] = 1;
+    with_table.insert(12, 1);
+    assert!(matches!(Decompressor::new().decompress(&with_table), Err(QuantumPackError::CorruptHeader { .. })));
+    let mut unknown = frame.clone();
+    unknown[6] = 3;
+    assert!(matches!(Decompressor::new().decompress(&unknown), Err(QuantumPackError::CorruptHeader { .. })));
+
+    // The parts compress returns have no table and decode without a tree
+    for input in inputs {
+        let (data, table, dictionary) = Compressor::new().adaptive_huffman(true).compress(input).unwrap();
+        assert!(table.is_empty());
+        assert_eq!(quantum_pack::decompress_adaptive(&data, &dictionary).unwrap(), input);
+    }
+
+    // Symbol frames keep their static table
+    let symbols: Vec<u16> = (0..2_000).map(|n| n % 7 * 1_000).collect();
+    let mut frame = Vec::new();
+    Compressor::new().adaptive_huffman(true).compress_symbols(&symbols, &mut frame).unwrap();
+    assert_eq!(Decompressor::new().decompress_symbols::<u16>(&frame).unwrap().0, symbols);
+}
@@ -0,0 +1,55 @@
+use quantum_pack::convert::{self, Format};
+use quantum_pack::{Compressor, Decompressor};
+
+fn text() -> Vec<u8> {
+    (0..200).flat_map(|n| format!("record {} converted between formats\n", n % 11).into_bytes()).collect()
+}
+
+#[test]
+fn test_detect_and_from_path() {
+    assert_eq!(Format::detect(&quantum_pack::compress_shared(b"qp").unwrap()), Some(Format::QuantumPack));
+    assert_eq!(Format::detect(&[0x1F, 0x8B, 8, 0]), Some(Format::Gzip));
+    assert_eq!(Format::detect(&[0x28, 0xB5, 0x2F, 0xFD]), Some(Format::Zstd));
+    assert_eq!(Format::detect(b"plain text"), None);
+
+    assert_eq!(Format::from_path("logs/app.log.gz"), Format::Gzip);
+    assert_eq!(Format::from_path("app.log.zst"), Format::Zstd);
+    assert_eq!(Format::from_path("app.log.qp"), Format::QuantumPack);
+}
+
+#[test]
+fn test_convert_quantum_pack_to_quantum_pack() {
+    let data = text();
+    let frame = quantum_pack::compress_shared(&data).unwrap();
+    let converted = convert::convert(&frame, Format::QuantumPack, &Compressor::new()).unwrap();
+    assert_eq!(Decompressor::new().decompress(&converted).unwrap().0, data);
+    assert!(convert::convert(b"plain text", Format::QuantumPack, &Compressor::new()).is_err());
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_gzip_round_trip() {
+    let data = text();
+    let gzip = convert::encode(&data, Format::Gzip, &Compressor::new()).unwrap();
+    let frame = convert::convert(&gzip, Format::QuantumPack, &Compressor::new()).unwrap();
+    assert_eq!(Decompressor::new().decompress(&frame).unwrap().0, data);
+    let back = convert::convert(&frame, Format::Gzip, &Compressor::new()).unwrap();
+    assert_eq!(convert::decode(&back, Format::Gzip).unwrap(), data);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_zstd_round_trip() {
+    let data = text();
+    let zstd = convert::encode(&data, Format::Zstd, &Compressor::new()).unwrap();
+    let frame = convert::convert(&zstd, Format::QuantumPack, &Compressor::new()).unwrap();
+    assert_eq!(Decompressor::new().decompress(&frame).unwrap().0, data);
+    assert_eq!(convert::decode(&zstd, Format::Zstd).unwrap(), data);
+}
+
+#[cfg(not(feature = "gzip"))]
+#[test]
+fn test_gzip_needs_the_feature() {
+    let error = convert::encode(b"data", Format::Gzip, &Compressor::new()).unwrap_err();
+    assert_eq!(error.to_string(), "gzip support requires the `gzip` feature");
+}
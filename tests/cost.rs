@@ -0,0 +1,35 @@
+use std::collections::BTreeMap;
+
+use quantum_pack::cost::{entropy, estimate_encoded_bits, estimate_encoded_len, histogram, huffman_code_lengths, symbol_bits};
+use quantum_pack::{CompressionLevel, Compressor};
+
+#[test]
+fn test_estimates_match_the_encoder() {
+    let data = b"it was the best of times, it was the worst of times".repeat(30);
+    let histogram = histogram(&data);
+    assert_eq!(histogram[&b'w'], 30 * 3);
+
+    let (compressed, table, _) = Compressor::new().level(CompressionLevel::Fast).compress(&data).unwrap();
+    assert_eq!(estimate_encoded_len(&histogram), compressed.len());
+    assert_eq!(quantum_pack::deserialize_code_length_table(&table).unwrap(), huffman_code_lengths(&histogram));
+
+    // Huffman codes are within a bit per symbol of the entropy
+    let bits_per_symbol = estimate_encoded_bits(&histogram) as f64 / data.len() as f64;
+    assert!(bits_per_symbol >= entropy(&histogram) && bits_per_symbol < entropy(&histogram) + 1.0);
+}
+
+#[test]
+fn test_cost_edge_cases() {
+    let empty: BTreeMap<u8, u32> = BTreeMap::new();
+    assert_eq!(estimate_encoded_bits(&empty), 0);
+    assert_eq!(estimate_encoded_len(&empty), 1);
+    assert_eq!(entropy(&empty), 0.0);
+
+    let lone: BTreeMap<u16, u32> = [(7, 10), (9, 0)].iter().copied().collect();
+    assert_eq!(huffman_code_lengths(&lone), [(7, 1)].iter().copied().collect());
+    assert_eq!(estimate_encoded_bits(&lone), 10);
+    assert_eq!(entropy(&lone), 0.0);
+
+    assert_eq!(symbol_bits(1, 8), 3.0);
+    assert_eq!(symbol_bits(0, 8), 3.0);
+}
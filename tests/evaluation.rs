@@ -0,0 +1,64 @@
+use quantum_pack::evaluation::evaluate_dictionary;
+use quantum_pack::preprocessor::{train_dictionary, TrainingSample};
+
+fn log_line(index: usize) -> Vec<u8> {
+    format!("2024-05-01T12:00:{:02} INFO request handled path=/api/v1/items/{} status=200\n", index % 60, index).into_bytes()
+}
+
+fn logs(from: usize, count: usize) -> Vec<u8> {
+    (from..from + count).flat_map(log_line).collect()
+}
+
+#[test]
+fn test_evaluate_dictionary_reports_ratio_and_variance() {
+    let training = logs(0, 400);
+    let dictionary = train_dictionary(&[TrainingSample::new(&training)]);
+    let holdout: Vec<Vec<u8>> = (0..4).map(|sample| logs(1000 + sample * 50, 20 + sample * 10)).collect();
+    let samples: Vec<&[u8]> = holdout.iter().map(|sample| sample.as_slice()).collect();
+
+    let report = evaluate_dictionary(&dictionary, &samples).unwrap();
+    assert_eq!(report.ratios.len(), 4);
+    assert!(report.mean_ratio > 0.0 && report.mean_ratio < 1.0, "{:?}", report);
+    let mean = report.ratios.iter().sum::<f64>() / 4.0;
+    assert!((report.mean_ratio - mean).abs() < 1e-12);
+    let variance = report.ratios.iter().map(|ratio| (ratio - mean).powi(2)).sum::<f64>() / 4.0;
+    assert!((report.ratio_variance - variance).abs() < 1e-12);
+    assert!(report.mean_overhead > 0.0);
+    assert_eq!(report.dictionary_len, dictionary.serialize().len());
+}
+
+#[test]
+fn test_evaluate_dictionary_prefers_matching_dictionary() {
+    let logs_dictionary = train_dictionary(&[TrainingSample::new(&logs(0, 400))]);
+    let json: Vec<u8> = (0..400).flat_map(|index| format!("{{\"id\":{},\"kind\":\"widget\",\"tags\":[\"blue\",\"large\"]}}\n", index).into_bytes()).collect();
+    let json_dictionary = train_dictionary(&[TrainingSample::new(&json)]);
+    let holdout = logs(2000, 30);
+
+    let matching = evaluate_dictionary(&logs_dictionary, &[&holdout]).unwrap();
+    let other = evaluate_dictionary(&json_dictionary, &[&holdout]).unwrap();
+    assert!(matching.mean_ratio < other.mean_ratio, "{} vs {}", matching.mean_ratio, other.mean_ratio);
+    assert_eq!(matching.ratio_variance, 0.0);
+}
+
+#[test]
+fn test_evaluate_dictionary_without_samples() {
+    let dictionary = train_dictionary(&[TrainingSample::new(&logs(0, 100))]);
+    let report = evaluate_dictionary(&dictionary, &[]).unwrap();
+    assert!(report.ratios.is_empty());
+    assert_eq!(report.mean_ratio, 0.0);
+    assert_eq!(report.ratio_variance, 0.0);
+}
+
+#[test]
+fn test_evaluate_dictionary_counts_every_frame() {
+    use quantum_pack::Compressor;
+
+    let dictionary = train_dictionary(&[TrainingSample::new(&logs(0, 400))]);
+    let holdout = logs(3000, 40);
+    let block_size = holdout.len() / 4;
+    let blocks = Compressor::new().block_size(block_size).evaluate_dictionary(&dictionary, &[&holdout]).unwrap();
+    // Each block costs what it would as a sample of its own
+    let chunks: Vec<&[u8]> = holdout.chunks(block_size).collect();
+    let separate = evaluate_dictionary(&dictionary, &chunks).unwrap();
+    assert_eq!(blocks.mean_overhead, separate.mean_overhead * chunks.len() as f64);
+}
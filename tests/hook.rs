@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use quantum_pack::{decode_memory, Compressor, Decompressor, FrameHook, QuantumPackError};
+
+mod common;
+use common::noise;
+
+// Stands in for real encryption: XORs the payload with a key and tags it
+struct XorHook(u8);
+
+impl FrameHook for XorHook {
+    fn before_write(&self, payload: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+        let mut sealed = b"SEAL".to_vec();
+        sealed.extend(payload.iter().map(|byte| byte ^ self.0));
+        sealed.push(self.0);
+        Ok(sealed)
+    }
+
+    fn after_read(&self, sealed: &[u8]) -> Result<Vec<u8>, QuantumPackError> {
+        match sealed.split_last() {
+            Some((&key, body)) if key == self.0 && body.starts_with(b"SEAL") => Ok(body[4..].iter().map(|byte| byte ^ self.0).collect()),
+            _ => Err(QuantumPackError::InvalidInput("wrong key".to_string())),
+        }
+    }
+}
+
+#[test]
+fn test_sealed_frames_round_trip() {
+    let text = b"the quick brown fox jumps over the lazy dog, the quick brown fox again".repeat(20);
+    let compressors = [
+        Compressor::new(),
+        Compressor::new().block_size(300),
+        Compressor::new().adaptive_huffman(true),
+    ];
+    for compressor in &compressors {
+        let hook = Arc::new(XorHook(0x5a));
+        let compressor = compressor.clone().frame_hook(hook.clone());
+        let decompressor = Decompressor::new().frame_hook(hook);
+        for data in &[text.clone(), Vec::new(), noise(2000)] {
+            let mut frame = Vec::new();
+            compressor.compress_shared(data, &mut frame).unwrap();
+            let (decoded, trailing) = decompressor.decompress(&frame).unwrap();
+            assert_eq!(&decoded, data);
+            assert!(trailing.is_none());
+            // The decoded length is sealed too
+            assert!(decode_memory(&frame).is_err());
+        }
+    }
+
+    let symbols: Vec<u16> = (0..500).map(|i| (i * i % 700) as u16).collect();
+    let compressor = Compressor::new().frame_hook(Arc::new(XorHook(0x33)));
+    let decompressor = Decompressor::new().frame_hook(Arc::new(XorHook(0x33)));
+    let mut frame = Vec::new();
+    compressor.compress_symbols(&symbols, &mut frame).unwrap();
+    let (decoded, _) = decompressor.decompress_symbols::<u16>(&frame).unwrap();
+    assert_eq!(decoded, symbols);
+}
+
+#[test]
+fn test_sealed_frames_hide_their_sections() {
+    // Too short to compress, so the plain frame stores it as it is
+    let text = b"attack at dawn".to_vec();
+    let mut plain = Vec::new();
+    Compressor::new().compress_shared(&text, &mut plain).unwrap();
+    assert!(plain.windows(6).any(|window| window == b"attack"));
+
+    let mut frame = Vec::new();
+    Compressor::new().frame_hook(Arc::new(XorHook(0x5a))).compress_shared(&text, &mut frame).unwrap();
+    assert!(!frame.windows(6).any(|window| window == b"attack"));
+    assert!(frame.windows(4).any(|window| window == b"SEAL"));
+    // Neither the length nor the CRC-32 of the text is left in the footer
+    assert!(frame.ends_with(&[0; 12]));
+}
+
+#[test]
+fn test_sealed_frames_need_the_hook() {
+    let text = b"some text to seal, some text to seal".to_vec();
+    let mut sealed = Vec::new();
+    Compressor::new().frame_hook(Arc::new(XorHook(0x5a))).compress_shared(&text, &mut sealed).unwrap();
+    let mut plain = Vec::new();
+    Compressor::new().compress_shared(&text, &mut plain).unwrap();
+
+    assert!(matches!(Decompressor::new().decompress(&sealed), Err(QuantumPackError::InvalidInput(_))));
+    assert!(matches!(Decompressor::new().frame_hook(Arc::new(XorHook(0x5a))).decompress(&plain), Err(QuantumPackError::InvalidInput(_))));
+    assert!(Decompressor::new().frame_hook(Arc::new(XorHook(0x11))).decompress(&sealed).is_err());
+    assert_eq!(Decompressor::new().frame_hook(Arc::new(XorHook(0x5a))).decompress(&sealed).unwrap().0, text);
+}
+
+#[test]
+fn test_streams_refuse_sealed_frames() {
+    use std::io::{Read, Write};
+    use quantum_pack::stream::{recompress, QpDecoder, QpEncoder};
+
+    let compressor = Compressor::new().frame_hook(Arc::new(XorHook(0x5a)));
+    let mut encoder = QpEncoder::with_compressor(Vec::new(), compressor.clone());
+    encoder.write_all(b"streamed text").unwrap();
+    assert!(encoder.finish().is_err());
+
+    let mut sealed = Vec::new();
+    compressor.compress_shared(b"streamed text", &mut sealed).unwrap();
+    assert!(QpDecoder::new(&sealed[..]).read_to_end(&mut Vec::new()).is_err());
+    assert!(matches!(recompress(&sealed[..], Vec::new(), &Compressor::new()), Err(QuantumPackError::InvalidInput(_))));
+    assert!(matches!(recompress(&sealed[..], Vec::new(), &compressor), Err(QuantumPackError::InvalidInput(_))));
+}
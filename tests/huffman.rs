@@ -1,9 +1,13 @@
 
+mod common;
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
 
-    use quantum_pack::{huffman::{build_huffman_tree, generate_huffman_codes, HuffmanNode, build_huffman_tree_with_dictionary, huffman_encode, huffman_decode}, adaptive_dictionary::AdaptiveDictionary};
+    use quantum_pack::{huffman::{build_huffman_tree, generate_huffman_codes, HuffmanNode, build_huffman_tree_with_dictionary, build_huffman_tree_seeded, build_huffman_tree_from_codes, tie_rank, canonical_codes, code_lengths, huffman_encode, huffman_decode, huffman_encode_bits, huffman_decode_bits, split_bit_count, adaptive_encode_bits, adaptive_decode_bits, DecodeError}, adaptive_dictionary::AdaptiveDictionary};
+
+    use crate::common::noise;
     
     fn create_test_tree() -> Option<Box<HuffmanNode>> {
         let data = b"example data for adaptive dictionary";
@@ -57,7 +61,7 @@ mod tests {
         generate_huffman_codes(&tree, &mut vec![], &mut codes);
         let encoded_data = huffman_encode(data, &codes);
         print!("{:?}", encoded_data);
-        let decoded_data = huffman_decode(&encoded_data, &tree);
+        let decoded_data = huffman_decode(&encoded_data, &tree).unwrap();
 
         assert_eq!(decoded_data, data);
     }
@@ -72,4 +76,220 @@ mod tests {
         let encoded_data = huffman_encode(data, &codes);
         assert!(!encoded_data.is_empty());
     }
+
+    #[test]
+    fn test_canonical_codes_round_trip() {
+        let data = b"example data for adaptive dictionary";
+        let tree = build_huffman_tree(data).unwrap();
+        let mut codes = BTreeMap::new();
+        generate_huffman_codes(&tree, &mut vec![], &mut codes);
+
+        // Canonical codes keep every length but only depend on the lengths
+        let lengths = code_lengths(&codes);
+        let canonical = canonical_codes(&lengths);
+        for (symbol, code) in &canonical {
+            assert_eq!(code.len(), codes[symbol].len());
+        }
+
+        let rebuilt = build_huffman_tree_from_codes(&canonical).unwrap();
+        let encoded = huffman_encode(data, &canonical);
+        assert_eq!(huffman_decode(&encoded, &rebuilt).unwrap(), data.to_vec());
+    }
+
+    #[test]
+    fn test_canonical_codes_single_symbol() {
+        let mut lengths = BTreeMap::new();
+        lengths.insert(b'a', 1);
+        let codes = canonical_codes(&lengths);
+        let tree = build_huffman_tree_from_codes(&codes).unwrap();
+
+        let encoded = huffman_encode(b"aaaa", &codes);
+        assert_eq!(huffman_decode(&encoded, &tree).unwrap(), b"aaaa".to_vec());
+    }
+
+    #[test]
+    fn test_huffman_decode_rejects_malformed_input() {
+        use quantum_pack::huffman::{huffman_decode_limited, DecodeError};
+
+        // An incomplete code: only "0" and "10" have symbols, "11" leads nowhere
+        let mut codes = BTreeMap::new();
+        codes.insert(b'a', vec![0]);
+        codes.insert(b'b', vec![1, 0]);
+        let tree = build_huffman_tree_from_codes(&codes).unwrap();
+
+        assert_eq!(huffman_decode(&[0b0111_0000, 4], &tree), Err(DecodeError::InvalidCode { bit_offset: 1 }));
+        assert_eq!(huffman_decode(&[0b0100_0000, 2], &tree), Err(DecodeError::IncompleteCode));
+        assert_eq!(huffman_decode(&[0, 9], &tree), Err(DecodeError::InvalidBitCount(9)));
+        assert_eq!(huffman_decode(&[0, 0], &tree), Err(DecodeError::InvalidBitCount(0)));
+        assert_eq!(huffman_decode(&[3], &tree), Err(DecodeError::InvalidBitCount(3)));
+        assert_eq!(huffman_decode(&[0], &tree), Ok(Vec::new()));
+
+        let encoded = huffman_encode(b"abab", &codes);
+        assert_eq!(huffman_decode_limited(&encoded, &tree, 4), Ok(b"abab".to_vec()));
+        assert_eq!(huffman_decode_limited(&encoded, &tree, 3), Err(DecodeError::OutputLimitExceeded { limit: 3 }));
+    }
+
+    #[test]
+    fn test_huffman_codes_wide_symbols() {
+        let tokens: Vec<u32> = (0..500u32).map(|n| [70_000, 3, 65_535, 3][n as usize % 4] + n % 3).collect();
+        let mut dictionary = AdaptiveDictionary::new();
+        dictionary.update(&tokens);
+        let tree = build_huffman_tree_with_dictionary(&dictionary).unwrap();
+        let mut codes = BTreeMap::new();
+        generate_huffman_codes(&tree, &mut Vec::new(), &mut codes);
+        let canonical = canonical_codes(&code_lengths(&codes));
+
+        let encoded = huffman_encode(&tokens, &canonical);
+        let decoded: Vec<u32> = huffman_decode(&encoded, &build_huffman_tree_from_codes(&canonical).unwrap()).unwrap();
+        assert_eq!(decoded, tokens);
+
+        let words: Vec<u16> = vec![500, 500, 7, 500];
+        let tree = build_huffman_tree(&words).unwrap();
+        let mut codes = BTreeMap::new();
+        generate_huffman_codes(&tree, &mut Vec::new(), &mut codes);
+        assert_eq!(huffman_decode(&huffman_encode(&words, &codes), &tree).unwrap(), words);
+    }
+
+    #[test]
+    fn test_merge_and_diff_shard_statistics() {
+        let shards: [&[u8]; 3] = [b"aaab", b"abcc", b"cccd"];
+        let mut global = AdaptiveDictionary::new();
+        for shard in shards.iter() {
+            let mut counts = AdaptiveDictionary::new();
+            counts.update(shard);
+            global.merge(&counts);
+        }
+        let mut whole = AdaptiveDictionary::new();
+        whole.update(&shards.concat());
+        assert_eq!(global, whole);
+
+        // A worker sends only what it counted since its last snapshot
+        let snapshot = global.clone();
+        global.update(b"dde");
+        let delta = global.diff(&snapshot);
+        assert_eq!(delta.get_frequencies(), &[(b'd', 2), (b'e', 1)].iter().copied().collect());
+        let mut rebuilt = snapshot.clone();
+        rebuilt.merge(&delta);
+        assert_eq!(rebuilt, global);
+        assert!(snapshot.diff(&global).get_frequencies().is_empty());
+
+        let mut saturated = AdaptiveDictionary::new();
+        saturated.frequencies.insert(b'x', u32::MAX);
+        saturated.merge(&saturated.clone());
+        assert_eq!(saturated.get_frequencies()[&b'x'], u32::MAX);
+    }
+
+    #[test]
+    fn test_seeded_tie_breaking() {
+        let ranks: std::collections::BTreeSet<u64> = (0..=255u8).map(|byte| tie_rank(byte, 7)).collect();
+        assert_eq!(ranks.len(), 256);
+        assert_eq!(tie_rank(b'c', 0), b'c' as u64);
+
+        // Of three equally frequent symbols the two of lowest rank are merged first
+        let mut frequencies = AdaptiveDictionary::new();
+        frequencies.update(b"abc");
+        let lengths = |seed: u32| {
+            let mut codes = BTreeMap::new();
+            generate_huffman_codes(&build_huffman_tree_seeded(&frequencies, seed).unwrap(), &mut vec![], &mut codes);
+            code_lengths(&codes)
+        };
+        assert_eq!(lengths(0), [(b'a', 2), (b'b', 2), (b'c', 1)].iter().copied().collect());
+        for seed in [1, 7, 0xdead_beef] {
+            let last = *b"abc".iter().max_by_key(|&&symbol| tie_rank(symbol, seed)).unwrap();
+            let expected: BTreeMap<u8, u8> = b"abc".iter().map(|&symbol| (symbol, if symbol == last { 1 } else { 2 })).collect();
+            assert_eq!(lengths(seed), expected);
+            assert_eq!(lengths(seed), lengths(seed));
+        }
+    }
+
+    #[test]
+    fn test_build_huffman_tree_is_deterministic() {
+        let data = b"abcabcabc ddee ff";
+        let mut codes = BTreeMap::new();
+        generate_huffman_codes(&build_huffman_tree(data).unwrap(), &mut vec![], &mut codes);
+        let mut frequencies = AdaptiveDictionary::new();
+        frequencies.update(data);
+        let mut encoder_codes = BTreeMap::new();
+        generate_huffman_codes(&build_huffman_tree_seeded(&frequencies, 0).unwrap(), &mut vec![], &mut encoder_codes);
+        assert_eq!(codes, encoder_codes);
+
+        // Equal counts merge in symbol order, for wider symbols too
+        let mut codes = BTreeMap::new();
+        generate_huffman_codes(&build_huffman_tree(&[300u16, 1, 2]).unwrap(), &mut vec![], &mut codes);
+        assert_eq!(code_lengths(&codes), [(1, 2), (2, 2), (300, 1)].iter().copied().collect());
+    }
+
+    #[test]
+    fn test_single_symbol_code_is_zero() {
+        let tree = build_huffman_tree(b"aaa").unwrap();
+        let mut codes = BTreeMap::new();
+        generate_huffman_codes(&tree, &mut Vec::new(), &mut codes);
+        assert_eq!(codes[&b'a'], vec![0]);
+
+        let encoded = huffman_encode(b"aaa", &codes);
+        assert_eq!(encoded, vec![0x00, 3]);
+        assert_eq!(huffman_decode(&encoded, &tree).unwrap(), b"aaa");
+        assert_eq!(huffman_decode(&[0x20, 3], &tree), Err(DecodeError::InvalidCode { bit_offset: 2 }));
+        assert!(huffman_decode(&[], &tree).unwrap().is_empty());
+        assert!(huffman_decode(&[0], &tree).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_huffman_bits_without_count_byte() {
+        let data = b"abracadabra";
+        let tree = build_huffman_tree(data).unwrap();
+        let mut codes = BTreeMap::new();
+        generate_huffman_codes(&tree, &mut Vec::new(), &mut codes);
+
+        let (code, bit_len) = huffman_encode_bits(data, &codes);
+        assert_eq!(code.len(), bit_len.div_ceil(8));
+        let padding_bits = code.len() * 8 - bit_len;
+        assert_eq!(code[code.len() - 1] & ((1 << padding_bits) - 1), 0);
+        let with_count = huffman_encode(data, &codes);
+        assert_eq!(split_bit_count(&with_count), Ok((&code[..], bit_len)));
+        assert_eq!(huffman_decode_bits(&code, bit_len, &tree, usize::MAX).unwrap(), data);
+
+        // Bits past the end of the code bytes are missing, not zero
+        assert_eq!(huffman_decode_bits(&code, code.len() * 8 + 1, &tree, usize::MAX), Err(DecodeError::IncompleteCode));
+        assert_eq!(split_bit_count(&[0x00, 9]), Err(DecodeError::InvalidBitCount(9)));
+        assert_eq!(split_bit_count(&[]), Ok((&[][..], 0)));
+    }
+
+    #[test]
+    fn test_adaptive_huffman() {
+        // The first byte goes out as is, the next new one after the 0 bit path to
+        // the not-yet-transmitted leaf
+        let (code, bit_len) = adaptive_encode_bits(b"abracadabra");
+        assert_eq!(code[..2], [0x61, 0x31]);
+        assert_eq!(code.len(), bit_len.div_ceil(8));
+        assert_eq!(adaptive_decode_bits(&code, bit_len, usize::MAX).unwrap(), b"abracadabra");
+
+        // Once learned, a lone byte costs one bit
+        let (code, bit_len) = adaptive_encode_bits(&[b'a'; 5_000]);
+        assert_eq!(bit_len, 8 + 4_999);
+        assert_eq!(adaptive_decode_bits(&code, bit_len, usize::MAX).unwrap(), vec![b'a'; 5_000]);
+        assert_eq!(adaptive_encode_bits(&[]), (Vec::new(), 0));
+
+        // Skewed bytes in every order the tree has to rebalance for cost about what a
+        // static code plus its table does
+        let data: Vec<u8> = noise(50_000)
+            .into_iter()
+            .map(|byte| byte.leading_zeros() as u8 * 31 + (byte & 7))
+            .chain(0..=255)
+            .collect();
+        let (code, bit_len) = adaptive_encode_bits(&data);
+        assert_eq!(adaptive_decode_bits(&code, bit_len, usize::MAX).unwrap(), data);
+        let tree = build_huffman_tree(&data).unwrap();
+        let mut codes = BTreeMap::new();
+        generate_huffman_codes(&tree, &mut Vec::new(), &mut codes);
+        let (_, static_bits) = huffman_encode_bits(&data, &codes);
+        assert!(bit_len < static_bits + static_bits / 50 + 256 * 8, "{} vs {}", bit_len, static_bits);
+
+        assert_eq!(adaptive_decode_bits(&code, bit_len, 100), Err(DecodeError::OutputLimitExceeded { limit: 100 }));
+        assert_eq!(adaptive_decode_bits(&code, code.len() * 8 + 1, usize::MAX), Err(DecodeError::IncompleteCode));
+        // 'a' sent as new twice
+        assert_eq!(adaptive_decode_bits(&[0x61, 0x30, 0x80], 17, usize::MAX), Err(DecodeError::InvalidCode { bit_offset: 8 }));
+        // A new byte cut short
+        assert_eq!(adaptive_decode_bits(&[0x61, 0x30], 12, usize::MAX), Err(DecodeError::IncompleteCode));
+    }
 }
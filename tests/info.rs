@@ -0,0 +1,29 @@
+use quantum_pack::{compress_with_info, Compressor, Decompressor};
+
+#[test]
+fn test_compression_info_describes_the_frame() {
+    let input = b"status=ok latency=12ms region=eu-west\n".repeat(200);
+    let (frame, info) = compress_with_info(&input).unwrap();
+    assert_eq!(info.input_len, input.len());
+    assert_eq!(info.output_len, frame.len());
+    assert!((info.ratio - frame.len() as f64 / input.len() as f64).abs() < 1e-12);
+    assert!(info.ratio < 0.5);
+    assert!(info.entropy > 3.0 && info.entropy < 5.0, "{}", info.entropy);
+    assert!(info.patterns_used > 0);
+    assert_eq!(Decompressor::new().decompress(&frame).unwrap().0, input);
+}
+
+#[test]
+fn test_compression_info_appends_and_counts_blocks() {
+    let input = b"abcdefgh".repeat(1_000);
+    let mut output = b"earlier".to_vec();
+    let single = Compressor::new().compress_with_info(&input, &mut Vec::new()).unwrap();
+    let blocks = Compressor::new().block_size(2_000).compress_with_info(&input, &mut output).unwrap();
+    assert_eq!(blocks.output_len, output.len() - 7);
+    assert_eq!(Decompressor::new().decompress(&output[7..]).unwrap().0, input);
+    assert!(blocks.patterns_used > single.patterns_used);
+    assert_eq!(blocks.entropy, 3.0);
+
+    let empty = Compressor::new().compress_with_info(b"", &mut Vec::new()).unwrap();
+    assert_eq!((empty.input_len, empty.entropy, empty.patterns_used), (0, 0.0, 0));
+}
@@ -0,0 +1,38 @@
+#![cfg(feature = "log")]
+
+use std::sync::Mutex;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use quantum_pack::Compressor;
+
+struct Collector(Mutex<Vec<(Level, String)>>);
+
+impl Log for Collector {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        self.0.lock().unwrap().push((record.level(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
+
+static COLLECTOR: Collector = Collector(Mutex::new(Vec::new()));
+
+#[test]
+fn test_diagnostics_go_to_the_logger() {
+    log::set_logger(&COLLECTOR).unwrap();
+    log::set_max_level(LevelFilter::Trace);
+
+    let input = b"GET /index.html HTTP/1.1\r\nHost: example.com\r\n".repeat(50);
+    let mut frame = Vec::new();
+    Compressor::new().compress_shared(&input, &mut frame).unwrap();
+
+    let records = COLLECTOR.0.lock().unwrap();
+    assert!(records.iter().any(|(level, message)| *level == Level::Debug && message.starts_with("fitted ")));
+    assert!(records.iter().any(|(level, message)| *level == Level::Debug && message.starts_with("coded ")));
+    assert!(records.iter().any(|(level, message)| *level == Level::Trace && message.starts_with("pattern ")));
+    assert!(records.iter().all(|(level, _)| *level >= Level::Debug));
+}
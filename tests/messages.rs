@@ -0,0 +1,51 @@
+use quantum_pack::messages::{locale_names, Catalog, Message};
+use quantum_pack::QuantumPackError;
+
+#[test]
+fn test_english_is_the_fallback() {
+    let english = Catalog::english();
+    assert_eq!(english.format(Message::StatsSummary, &[&3, &1200, &400]), "3 members, 1200 -> 400 bytes");
+
+    let partial = Catalog::parse("# German\nerror.compress = Fehler beim Komprimieren: {0}\n").unwrap();
+    assert_eq!(partial.format(Message::ErrorCompress, &[&"disk full"]), "Fehler beim Komprimieren: disk full");
+    assert_eq!(partial.text(Message::ErrorConcat), Message::ErrorConcat.english());
+}
+
+#[test]
+fn test_translations_may_reorder_arguments() {
+    let catalog = Catalog::parse("trailing.ignored = bei Offset {1}: {0} Bytes ignoriert {kept}").unwrap();
+    assert_eq!(catalog.format(Message::TrailingIgnored, &[&7, &100]), "bei Offset 100: 7 Bytes ignoriert {kept}");
+}
+
+#[test]
+fn test_catalog_errors_name_the_line() {
+    let errors = [
+        ("\nno separator", 2),
+        ("error.nope = x", 1),
+        ("warning = {0}\nwarning = {0}", 2),
+        // The warning message has a single argument
+        ("warning = {0} {1}", 1),
+    ];
+    for (text, expected) in errors.iter() {
+        match Catalog::parse(text) {
+            Err(QuantumPackError::InvalidCatalog { line, .. }) => assert_eq!(line, *expected, "{:?}", text),
+            other => panic!("{:?} parsed as {:?}", text, other),
+        }
+    }
+}
+
+#[test]
+fn test_every_message_has_a_unique_key() {
+    let mut keys: Vec<&str> = Message::ALL.iter().map(|message| message.key()).collect();
+    keys.sort_unstable();
+    keys.dedup();
+    assert_eq!(keys.len(), Message::ALL.len());
+}
+
+#[test]
+fn test_locale_names() {
+    assert_eq!(locale_names("de_AT.UTF-8@euro"), ["de_AT", "de"]);
+    assert_eq!(locale_names("fr"), ["fr"]);
+    assert!(locale_names("C.UTF-8").is_empty());
+    assert!(locale_names("").is_empty());
+}
@@ -0,0 +1,55 @@
+use quantum_pack::page::{PageCodec, MAX_PAGE_SIZE};
+use quantum_pack::preprocessor::{DictionaryMode, Preprocessor};
+
+fn sample_rows(count: usize) -> Vec<u8> {
+    (0..count).flat_map(|n| format!("id={:05};status=active;region=eu-west;", n).into_bytes()).collect()
+}
+
+#[test]
+fn test_pages_round_trip_independently() {
+    let preprocessor = Preprocessor::builder()
+        .patterns(vec!["status=active;", "region=eu-west;", "id=000"])
+        .dictionary_mode(DictionaryMode::Replace)
+        .build();
+    let codec = PageCodec::train_with(preprocessor, &sample_rows(200), 256).unwrap();
+    let data = sample_rows(40);
+    let encoded = codec.encode_pages(&data).unwrap();
+
+    assert!(encoded.len() < data.len());
+    assert_eq!(codec.decode_pages(&encoded).unwrap(), data);
+
+    // Each page decodes on its own
+    let (first, used) = codec.decode_page(&encoded).unwrap();
+    assert_eq!(first, &data[..256]);
+    let (second, _) = codec.decode_page(&encoded[used..]).unwrap();
+    assert_eq!(second, &data[256..512]);
+}
+
+#[test]
+fn test_unseen_bytes_round_trip() {
+    let codec = PageCodec::train(&sample_rows(50), 128).unwrap();
+    let data: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+
+    assert_eq!(codec.decode_pages(&codec.encode_pages(&data).unwrap()).unwrap(), data);
+}
+
+#[test]
+fn test_model_serialization_round_trip() {
+    let codec = PageCodec::train(&sample_rows(100), 512).unwrap();
+    let restored = PageCodec::deserialize(&codec.serialize()).unwrap();
+    let data = sample_rows(30);
+
+    assert_eq!(restored.page_size(), 512);
+    assert_eq!(restored.dictionary(), codec.dictionary());
+    assert_eq!(restored.encode_pages(&data).unwrap(), codec.encode_pages(&data).unwrap());
+}
+
+#[test]
+fn test_page_size_limits() {
+    assert!(PageCodec::train(b"sample", 0).is_err());
+    assert!(PageCodec::train(b"sample", MAX_PAGE_SIZE + 1).is_err());
+
+    let codec = PageCodec::train(b"sample", 16).unwrap();
+    assert!(codec.encode_page(&[0; 17], &mut Vec::new()).is_err());
+    assert!(codec.decode_page(&[0x00, 0x05, 1]).is_err());
+}
@@ -1,4 +1,8 @@
-use quantum_pack::preprocessor::Preprocessor;
+use quantum_pack::preprocessor::{train_dictionary, CodeLengthModel, DictionaryMode, PatternSelection, Preprocessor, Token, Tokenization, TrainedDictionary, TrainingSample};
+
+mod common;
+use common::noise;
+
 #[test]
 fn test_basic_functionality() {
     let mut preprocessor = Preprocessor::new();
@@ -60,22 +64,45 @@ fn test_parallel_processing_consistency() {
 
 #[test]
 fn test_encode_code_high_frequency() {
-    let preprocessor = Preprocessor::new();
+    let preprocessor = Preprocessor::builder().code_length_model(CodeLengthModel::FrequencyThreshold(100)).build();
     let encoded = preprocessor.encode_code(10, 101);
     assert_eq!(encoded, vec![10]);
 }
 
 #[test]
 fn test_encode_code_low_frequency() {
-    let preprocessor = Preprocessor::new();
+    let preprocessor = Preprocessor::builder().code_length_model(CodeLengthModel::FrequencyThreshold(100)).build();
     let encoded = preprocessor.encode_code(10, 50);
     assert_eq!(encoded, vec![0xFF, 10]);
 }
 
+#[test]
+fn test_encode_code_probability_mass() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.preprocess(&[b'a'; 1000]);
+
+    // 4 in 1000 is above 1/256, 3 in 1000 is below
+    assert_eq!(preprocessor.encode_code(10, 4), vec![10]);
+    assert_eq!(preprocessor.encode_code(10, 3), vec![0xFF, 10]);
+}
+
+#[test]
+fn test_encode_code_configurable_probability_mass() {
+    let mut preprocessor = Preprocessor::builder()
+        .code_length_model(CodeLengthModel::ProbabilityMass { min_probability: 0.5 })
+        .build();
+    preprocessor.preprocess(&[b'a'; 1000]);
+
+    assert_eq!(preprocessor.encode_code(10, 500), vec![10]);
+    assert_eq!(preprocessor.encode_code(10, 499), vec![0xFF, 10]);
+}
+
 #[test]
 fn test_reverse_transform_data_basic() {
+    let mut dictionary = TrainedDictionary::new();
+    dictionary.insert(1, vec![97, 98], 0).unwrap(); // 'ab' pattern
     let mut preprocessor = Preprocessor::new();
-    preprocessor.reverse_pattern_map.insert(1, vec![97, 98]); // 'ab' pattern
+    preprocessor.set_dictionary(dictionary);
     let data = vec![1];
     let decoded = preprocessor.reverse_transform_data(&data);
     assert_eq!(decoded, vec![97, 98]);
@@ -91,9 +118,11 @@ fn test_reverse_transform_data_no_pattern() {
 
 #[test]
 fn test_reverse_transform_data_various_patterns() {
+    let mut dictionary = TrainedDictionary::new();
+    dictionary.insert(1, vec![97], 0).unwrap(); // 'a'
+    dictionary.insert(2, vec![98], 0).unwrap(); // 'b'
     let mut preprocessor = Preprocessor::new();
-    preprocessor.reverse_pattern_map.insert(1, vec![97]); // 'a'
-    preprocessor.reverse_pattern_map.insert(2, vec![98]); // 'b'
+    preprocessor.set_dictionary(dictionary);
     let data = vec![1, 2, 1, 2];
     let decoded = preprocessor.reverse_transform_data(&data);
     assert_eq!(decoded, vec![97, 98, 97, 98]);
@@ -108,16 +137,365 @@ fn test_preprocessor_simple_data() {
     let compressed = preprocessor.preprocess(data);
     assert_ne!(compressed, data.to_vec());
 
-    // Reverse map for decompression
-    for (pattern, code) in &preprocessor.pattern_map {
-        preprocessor.reverse_pattern_map.insert(*code, pattern.clone());
-    }
-
     // Decompress the data
     let decompressed = preprocessor.reverse_transform_data(&compressed);
     assert_eq!(decompressed, data);
 }
 
+#[test]
+fn test_usage_report_counts_substituted_patterns() {
+    let mut preprocessor = Preprocessor::new();
+    let data = b"abcabcabcabcabcabc";
+    preprocessor.preprocess(data);
+
+    let report = preprocessor.usage_report();
+    assert!(!report.is_empty());
+    assert!(report.iter().all(|usage| usage.occurrences > 0));
+    assert!(report.iter().any(|usage| usage.pattern.len() > 1 && usage.bytes_saved > 0));
+
+    // Sorted by bytes saved, best first
+    for pair in report.windows(2) {
+        assert!(pair[0].bytes_saved >= pair[1].bytes_saved);
+    }
+}
+
+#[test]
+fn test_usage_report_empty_before_preprocess() {
+    let preprocessor = Preprocessor::new();
+    assert!(preprocessor.usage_report().is_empty());
+}
+
+#[test]
+fn test_export_report_describes_model() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.preprocess(b"say \"hello\"\nsay \"hello\"\n");
+
+    let report = preprocessor.export_report();
+    assert!(report.starts_with('{') && report.ends_with('}'));
+    assert!(report.contains("\"entropy\":"));
+    assert!(report.contains("\"patterns\":[{\"code\":1,"));
+    // Quotes and newlines inside pattern text are escaped
+    assert!(report.contains("\\\""));
+    assert!(!report.contains('\n'));
+}
+
+#[test]
+fn test_user_patterns_merged_with_mined_patterns() {
+    let mut preprocessor = Preprocessor::builder()
+        .patterns(vec!["SELECT", "WHERE"])
+        .build();
+    let data = b"SELECT a FROM t WHERE x; SELECT b FROM u WHERE y;";
+    preprocessor.preprocess(data);
+
+    // User patterns get the first codes, mined patterns follow
+    assert_eq!(preprocessor.dictionary().code(b"SELECT"), Some(1));
+    assert_eq!(preprocessor.dictionary().code(b"WHERE"), Some(2));
+    assert!(preprocessor.dictionary().len() > 2);
+}
+
+#[test]
+fn test_user_patterns_replace_mined_patterns() {
+    let mut preprocessor = Preprocessor::builder()
+        .pattern(b"SELECT")
+        .dictionary_mode(DictionaryMode::Replace)
+        .build();
+    let data = b"SELECT a FROM t WHERE x; SELECT b FROM u WHERE y;";
+    let processed = preprocessor.preprocess(data);
+
+    assert_eq!(preprocessor.dictionary().len(), 1);
+    assert_eq!(processed.len(), data.len() - 2 * (b"SELECT".len() - 1));
+}
+
+#[test]
+fn test_denied_sequences_survive_preprocessing() {
+    let mut preprocessor = Preprocessor::builder().deny(b"\n").build();
+    let data = b"id=1;name=a\nid=2;name=b\nid=3;name=c\n";
+    let processed = preprocessor.preprocess(data);
+
+    assert!(preprocessor.dictionary().iter().all(|(_, pattern)| !pattern.contains(&b'\n')));
+    assert!(!preprocessor.dictionary().iter().any(|(code, _)| code as u8 == b'\n'));
+    assert_eq!(processed.iter().filter(|&&byte| byte == b'\n').count(), 3);
+}
+
+#[test]
+fn test_allowed_patterns_always_included() {
+    let mut preprocessor = Preprocessor::builder()
+        .allow(b"zzz")
+        .pattern(b"id=")
+        .build();
+    preprocessor.preprocess(b"id=1;id=2;id=3");
+
+    assert_eq!(preprocessor.dictionary().code(b"zzz"), Some(1));
+    assert_eq!(preprocessor.dictionary().code(b"id="), Some(2));
+}
+
+#[test]
+fn test_deny_wins_over_allow() {
+    let mut preprocessor = Preprocessor::builder()
+        .allow(b"a;b")
+        .deny(b";")
+        .build();
+    preprocessor.preprocess(b"a;b;a;b;");
+
+    assert!(!preprocessor.dictionary().contains_pattern(b"a;b"));
+}
+
+#[test]
+fn test_max_entries_limits_dictionary() {
+    let mut preprocessor = Preprocessor::builder().max_entries(5).build();
+    preprocessor.preprocess(b"The quick brown fox jumps over the lazy dog");
+    assert_eq!(preprocessor.dictionary().len(), 5);
+}
+
+#[test]
+fn test_max_pattern_length_limits_mined_patterns() {
+    let high_variance_data = (0u8..100).collect::<Vec<u8>>();
+    let preprocessor = Preprocessor::builder().max_pattern_length(8).build();
+    assert_eq!(preprocessor.determine_max_pattern_length(&high_variance_data), 8);
+    assert_eq!(preprocessor.determine_max_pattern_length(&[1u8; 100]), 2);
+
+    let mut preprocessor = Preprocessor::builder().max_pattern_length(2).build();
+    preprocessor.preprocess(b"The quick brown fox jumps over the lazy dog, the quick brown fox");
+    assert!(preprocessor.dictionary().iter().all(|(_, pattern)| pattern.len() <= 2));
+}
+
+#[test]
+fn test_sample_size_fits_on_the_start_of_the_input() {
+    let mut data = b"abababababababababab".to_vec();
+    data.extend(b"xyzxyzxyzxyzxyzxyz");
+    let mut preprocessor = Preprocessor::builder().sample_size(20).build();
+    let processed = preprocessor.preprocess(&data);
+
+    assert!(preprocessor.dictionary().iter().all(|(_, pattern)| !pattern.contains(&b'x')));
+    assert_eq!(preprocessor.reverse_transform_data(&processed), data);
+}
+
+#[test]
+fn test_max_dictionary_bytes_limits_dictionary() {
+    let mut preprocessor = Preprocessor::builder().max_dictionary_bytes(20).build();
+    preprocessor.preprocess(b"The quick brown fox jumps over the lazy dog");
+
+    let size = preprocessor.serialize_dictionary().len();
+    assert!(size > 0 && size <= 20);
+}
+
+#[test]
+fn test_wide_codes_round_trip() {
+    let tokens: Vec<String> = (0..300).map(|n| format!("<token-{:03}>", n)).collect();
+    let mut preprocessor = Preprocessor::builder()
+        .patterns(&tokens)
+        .dictionary_mode(DictionaryMode::Replace)
+        .max_entries(300)
+        .build();
+    let data = format!("{}{}", tokens[3], tokens[299]);
+    let processed = preprocessor.preprocess(data.as_bytes());
+
+    assert_eq!(preprocessor.dictionary().len(), 300);
+    // One short code followed by one 4 byte wide code
+    assert_eq!(processed.len(), 5);
+    assert_eq!(preprocessor.reverse_transform_data(&processed), data.as_bytes());
+}
+
+#[test]
+fn test_dictionary_serialization_round_trip_long_patterns() {
+    let long_pattern = vec![b'x'; 300];
+    let mut preprocessor = Preprocessor::builder()
+        .pattern(&long_pattern)
+        .pattern(b"ab")
+        .dictionary_mode(DictionaryMode::Replace)
+        .build();
+    preprocessor.preprocess(b"abab");
+
+    let serialized = preprocessor.serialize_dictionary();
+    let mut restored = Preprocessor::new();
+    restored.deserialize_dictionary(&serialized).unwrap();
+
+    assert_eq!(restored.dictionary(), preprocessor.dictionary());
+    assert_eq!(restored.dictionary().pattern(1).unwrap().len(), 300);
+}
+
+#[test]
+fn test_deserialize_legacy_dictionary() {
+    // Version 1: no version byte, u8 pattern lengths
+    let legacy = [0, 1, 2, b'a', b'b', 0, 2, 1, b'c'];
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.deserialize_dictionary(&legacy).unwrap();
+
+    assert_eq!(preprocessor.dictionary().pattern(1), Some(&b"ab"[..]));
+    assert_eq!(preprocessor.dictionary().pattern(2), Some(&b"c"[..]));
+}
+
+#[test]
+fn test_deserialize_rejects_malformed_dictionaries() {
+    let cases: [&[u8]; 5] = [
+        &[9, 0, 1, 1, b'a'],      // unknown version
+        &[2, 0],                  // truncated code
+        &[2, 0, 1, 0x80],         // unterminated length varint
+        &[2, 0, 1, 5, b'a'],      // length past the end
+        &[2, 0, 1, 1, b'a', 0, 1, 1, b'b'], // duplicate code
+    ];
+    for case in cases.iter() {
+        let mut preprocessor = Preprocessor::new();
+        assert!(preprocessor.deserialize_dictionary(case).is_err(), "{:?} should be rejected", case);
+    }
+}
+
+#[test]
+fn test_annealing_selection_is_deterministic() {
+    let data = b"the cat sat on the mat, the cat ate the rat, the rat sat on the cat";
+    let build = || Preprocessor::builder()
+        .max_entries(12)
+        .pattern_selection(PatternSelection::Annealing { iterations: 200, seed: 7 })
+        .build();
+
+    let mut first = build();
+    let mut second = build();
+    let first_output = first.preprocess(data);
+    let second_output = second.preprocess(data);
+
+    assert_eq!(first.dictionary(), second.dictionary());
+    assert_eq!(first_output, second_output);
+    assert!(first.dictionary().len() <= 12);
+}
+
+#[test]
+fn test_annealing_not_worse_than_greedy() {
+    let data = b"the cat sat on the mat, the cat ate the rat, the rat sat on the cat";
+    let mut greedy = Preprocessor::builder().max_entries(12).build();
+    let mut annealed = Preprocessor::builder()
+        .max_entries(12)
+        .pattern_selection(PatternSelection::Annealing { iterations: 300, seed: 1 })
+        .build();
+
+    let greedy_size = greedy.preprocess(data).len() + greedy.serialize_dictionary().len();
+    let annealed_size = annealed.preprocess(data).len() + annealed.serialize_dictionary().len();
+    assert!(annealed_size <= greedy_size, "{} > {}", annealed_size, greedy_size);
+}
+
+#[test]
+fn test_optimal_tokenization_beats_greedy() {
+    let data = b"abcdefabcdefabcdefabcdef";
+    let build = |tokenization| Preprocessor::builder()
+        .patterns(vec!["ab", "abc", "cdef"])
+        .dictionary_mode(DictionaryMode::Replace)
+        .tokenization(tokenization)
+        .build();
+
+    // Greedy takes "abc" and is left with three literals; the optimal parse is "ab" + "cdef"
+    let mut greedy = build(Tokenization::Greedy);
+    assert_eq!(greedy.preprocess(data).len(), 16);
+
+    let mut optimal = build(Tokenization::Optimal);
+    let processed = optimal.preprocess(data);
+    assert_eq!(processed.len(), 8);
+    assert_eq!(optimal.reverse_transform_data(&processed), data.to_vec());
+}
+
+#[test]
+fn test_parallel_transform_reassembles_chunks_in_order() {
+    let data: Vec<u8> = (0..20_000).flat_map(|n| format!("hello world {} ", n % 97).into_bytes()).collect();
+    let mut preprocessor = Preprocessor::builder()
+        .patterns(vec!["hello", "world"])
+        .dictionary_mode(DictionaryMode::Replace)
+        .build();
+
+    let processed = preprocessor.preprocess(&data);
+    assert!(processed.len() < data.len());
+    assert_eq!(preprocessor.reverse_transform_data(&processed), data);
+    assert_eq!(preprocessor.parallel_transform_data(&data), processed);
+}
+
+#[test]
+fn test_transform_stream_matches_transform_data() {
+    let data = b"The quick brown fox jumps over the lazy dog. The quick brown fox jumps again.";
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.preprocess(data);
+
+    let expected = preprocessor.transform_data(data);
+    for &piece in [1usize, 3, 7, 64].iter() {
+        let streamed: Vec<u8> = preprocessor.transform_stream(data.chunks(piece)).flatten().collect();
+        assert_eq!(streamed, expected, "piece size {}", piece);
+    }
+}
+
+#[test]
+fn test_transform_stream_empty_input() {
+    let preprocessor = Preprocessor::new();
+    let chunks: Vec<&[u8]> = Vec::new();
+    let streamed: Vec<u8> = preprocessor.transform_stream(chunks).flatten().collect();
+    assert!(streamed.is_empty());
+}
+
+#[test]
+fn test_fit_then_apply_matches_preprocess() {
+    let data = b"Lorem ipsum dolor sit amet, lorem ipsum dolor sit amet";
+    let mut fitted = Preprocessor::new();
+    fitted.fit(data);
+    let mut preprocessed = Preprocessor::new();
+
+    assert_eq!(fitted.apply(data), preprocessed.preprocess(data));
+}
+
+#[test]
+fn test_apply_model_fitted_on_sample() {
+    let sample = b"GET /index.html HTTP/1.1\r\nHost: example.com\r\n";
+    let other = b"GET /about.html HTTP/1.1\r\nHost: example.org\r\n";
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.fit(sample);
+
+    let processed = preprocessor.apply(other);
+    assert!(processed.len() < other.len());
+}
+
+#[test]
+fn test_refit_replaces_previous_model() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.fit(b"aaaaaaaaaaaaaaaa");
+    preprocessor.fit(b"bcbcbcbcbcbcbcbc");
+
+    assert!(preprocessor.dictionary().iter().all(|(_, pattern)| !pattern.contains(&b'a')));
+    assert_eq!(preprocessor.dictionary().iter().map(|(code, _)| code).min(), Some(1));
+}
+
+#[test]
+fn test_trained_dictionary_rejects_duplicates() {
+    let mut dictionary = TrainedDictionary::new();
+    dictionary.insert(1, b"ab".to_vec(), 3).unwrap();
+
+    assert!(dictionary.insert(1, b"cd".to_vec(), 1).is_err());
+    assert!(dictionary.insert(2, b"ab".to_vec(), 1).is_err());
+    assert!(dictionary.insert(3, Vec::new(), 1).is_err());
+    assert_eq!(dictionary.len(), 1);
+}
+
+#[test]
+fn test_trained_dictionary_serialization_and_id() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.fit(b"the cat and the hat and the bat");
+    let dictionary = preprocessor.dictionary();
+
+    let restored = TrainedDictionary::deserialize(&dictionary.serialize()).unwrap();
+    assert_eq!(&restored, dictionary);
+    assert_eq!(restored.id(), dictionary.id());
+
+    let mut other = Preprocessor::new();
+    other.fit(b"something else entirely, else entirely");
+    assert_ne!(other.dictionary().id(), dictionary.id());
+}
+
+#[test]
+fn test_set_dictionary_applies_trained_model() {
+    let mut trained = Preprocessor::new();
+    trained.fit(b"GET /index.html GET /about.html");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.set_dictionary(trained.dictionary().clone());
+
+    let data = b"GET /index.html";
+    let processed = preprocessor.apply(data);
+    assert!(processed.len() < data.len());
+    assert_eq!(preprocessor.reverse_transform_data(&processed), data);
+}
+
 #[cfg(test)]
 mod tests {
     use quantum_pack::preprocessor::{Preprocessor, self};
@@ -183,6 +561,150 @@ mod tests {
         let decompressed = preprocessor.reverse_transform_data(&processed);
         assert_eq!(decompressed, data, "Decompressed data should match original, including spaces");
     }
+
+    #[test]
+    fn test_escape_bytes_round_trip() {
+        // 0xFF starts an escape and every byte value below may also be a pattern code
+        let mut data: Vec<u8> = (0..=255u8).rev().collect();
+        data.extend(b"abcabcabc\xFF\xFFabc\xFF\x00abcabc".iter());
+        data.extend((0..=255u8).cycle().take(2000));
+
+        let mut preprocessor = Preprocessor::new();
+        let processed = preprocessor.preprocess(&data);
+        assert_eq!(preprocessor.reverse_transform_data(&processed), data);
+    }
     
     
 }
+
+#[test]
+fn test_tokenize_matches_transform() {
+    let data = b"select id, name from users where id = 1; select id, name from orders where id = 2;".repeat(20);
+    let mut preprocessor = Preprocessor::new();
+    let transformed = preprocessor.preprocess(&data);
+    let tokens = preprocessor.tokenize(&data);
+
+    assert!(tokens.iter().any(|token| matches!(token, Token::Pattern { len, .. } if *len > 1)));
+    assert_eq!(tokens.iter().map(Token::input_len).sum::<usize>(), data.len());
+    let mut expanded = Vec::new();
+    for token in &tokens {
+        match *token {
+            Token::Literal(byte) => expanded.push(byte),
+            Token::Pattern { code, len } => {
+                let pattern = preprocessor.dictionary().pattern(code).unwrap();
+                assert_eq!(pattern.len(), len);
+                expanded.extend_from_slice(pattern);
+            }
+        }
+    }
+    assert_eq!(expanded, data);
+    // Fewer tokens than transformed bytes only when some codes take several bytes
+    assert!(tokens.len() <= transformed.len());
+    assert!(preprocessor.tokenize(b"").is_empty());
+}
+
+#[test]
+fn test_entropy_bypass_skips_mining_on_random_data() {
+    let noise = noise(32 * 1024);
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.fit(&noise);
+    assert!(preprocessor.mining_skipped());
+    assert!(preprocessor.dictionary().is_empty());
+
+    // Configured patterns are not mined, so they stay
+    let mut preprocessor = Preprocessor::builder().pattern(b"magic").build();
+    preprocessor.fit(&noise);
+    assert!(preprocessor.mining_skipped());
+    assert_eq!(preprocessor.dictionary().len(), 1);
+
+    let mut preprocessor = Preprocessor::builder().entropy_bypass(8.0).build();
+    preprocessor.fit(&noise);
+    assert!(!preprocessor.mining_skipped());
+    assert!(!preprocessor.dictionary().is_empty());
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.fit(&b"plain text is far from random ".repeat(100));
+    assert!(!preprocessor.mining_skipped());
+    assert!(!preprocessor.dictionary().is_empty());
+}
+
+#[test]
+fn test_training_gives_categories_equal_weight() {
+    // Plenty of hex log lines, whose two and three byte windows can fill the dictionary
+    let logs: Vec<u8> = noise(200_000)
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| if i % 64 == 63 { b'\n' } else { b"0123456789abcdef"[(byte % 16) as usize] })
+        .collect();
+    let json = b"{\"user_id\":7,\"plan\":\"pro\"}\n".repeat(40);
+    let json_patterns = |dictionary: &TrainedDictionary| dictionary.iter().filter(|(_, pattern)| pattern.len() > 1 && pattern.contains(&b'"')).count();
+
+    let mut concatenated = logs.clone();
+    concatenated.extend_from_slice(&json);
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.fit(&concatenated);
+    assert_eq!(json_patterns(preprocessor.dictionary()), 0);
+
+    let stratified = train_dictionary(&[TrainingSample::new(&logs).category("logs"), TrainingSample::new(&json).category("json")]);
+    assert!(json_patterns(&stratified) > 0);
+
+    // Weights split a category's part, and a weight of 0 leaves the sample out
+    let excluded = train_dictionary(&[TrainingSample::new(&logs), TrainingSample::new(&json).weight(0.0)]);
+    assert_eq!(json_patterns(&excluded), 0);
+    let weighted = train_dictionary(&[TrainingSample::new(&logs), TrainingSample::new(&json).weight(1.0)]);
+    assert!(json_patterns(&weighted) > 0);
+
+    assert!(train_dictionary(&[]).is_empty());
+}
+
+#[test]
+fn test_pattern_rank() {
+    use quantum_pack::preprocessor::pattern_rank;
+
+    assert_eq!(pattern_rank(b"abc", 0), 0);
+    assert_ne!(pattern_rank(b"abc", 1), pattern_rank(b"abd", 1));
+    assert_ne!(pattern_rank(b"abc", 1), pattern_rank(b"abc", 2));
+    assert_eq!(Preprocessor::builder().tie_seed(9).build().tie_seed(), 9);
+
+    // Mined output depends only on the data and the seed
+    let data = b"alpha beta gamma delta alpha beta gamma delta epsilon zeta eta theta".repeat(20);
+    let fitted = |seed: u32| {
+        let mut preprocessor = Preprocessor::builder().tie_seed(seed).build();
+        let processed = preprocessor.preprocess(&data);
+        (processed, preprocessor.serialize_dictionary())
+    };
+    assert_eq!(fitted(3), fitted(3));
+    let (processed, dictionary) = fitted(3);
+    let mut reverse = Preprocessor::new();
+    reverse.deserialize_dictionary(&dictionary).unwrap();
+    assert_eq!(reverse.reverse_transform_data(&processed), data);
+}
+
+#[test]
+fn test_literal_runs_are_copied_as_is() {
+    for tokenization in [Tokenization::Greedy, Tokenization::Optimal] {
+        let mut preprocessor = Preprocessor::builder()
+            .pattern(b"SELECT")
+            .dictionary_mode(DictionaryMode::Replace)
+            .tokenization(tokenization)
+            .build();
+        preprocessor.fit(b"SELECT");
+        let (code, _) = preprocessor.dictionary().iter().next().unwrap();
+
+        // Long runs no pattern can start in, around patterns, escapes and code bytes
+        let run: Vec<u8> = (0..5_000u32).map(|i| (i % 251) as u8).filter(|&byte| byte != b'S' && byte != code as u8 && byte != 0xFF).collect();
+        let mut data = run.clone();
+        data.extend_from_slice(b"SELECT\xFF");
+        data.push(code as u8);
+        data.extend_from_slice(&run);
+        data.extend_from_slice(b"SELECT");
+
+        let transformed = preprocessor.apply(&data);
+        assert_eq!(&transformed[..run.len()], &run[..]);
+        assert_eq!(transformed.windows(run.len()).filter(|window| *window == &run[..]).count(), 2);
+        assert_eq!(transformed.len(), data.len() - 2 * 5 + 2 + 2);
+        assert_eq!(preprocessor.reverse_transform_data(&transformed), data);
+        assert_eq!(preprocessor.transform_stream(data.chunks(777)).flatten().collect::<Vec<u8>>(), transformed);
+    }
+}
@@ -0,0 +1,97 @@
+use quantum_pack::preprocessor::TrainedDictionary;
+use quantum_pack::profile::{Profile, Profiles};
+use quantum_pack::{Compressor, Decompressor, QuantumPackError};
+
+const PROFILES: &str = "
+# Settings per payload class
+[logs]
+tokenization = optimal
+huffman = adaptive
+pattern = ERROR
+pattern = WARN
+max_output_size = 1048576
+
+[metrics]
+stage = integer frame_of_reference 64 signed
+dictionary_mode = replace
+table_encoding = dense
+
+[blobs]
+level = fast
+";
+
+#[test]
+fn test_parsed_profiles_round_trip() {
+    let profiles = Profiles::parse(PROFILES).unwrap();
+    assert_eq!(profiles.names().collect::<Vec<_>>(), ["blobs", "logs", "metrics"]);
+
+    let log = b"ERROR disk full\nWARN retrying\nERROR disk full\n".repeat(20);
+    let metrics: Vec<u8> = (0..500i64).flat_map(|n| (1_700_000_000 + n * 15).to_le_bytes().to_vec()).collect();
+    let blob: Vec<u8> = (0..2000u32).map(|n| (n * 7 % 251) as u8).collect();
+    for (name, data) in [("logs", &log), ("metrics", &metrics), ("blobs", &blob)] {
+        let frame = profiles.compress(name, data).unwrap();
+        assert_eq!(&profiles.decompress(name, &frame).unwrap(), data, "profile {}", name);
+    }
+    // An adaptive Huffman block
+    assert_eq!(profiles.compress("logs", &log).unwrap()[6], 2);
+}
+
+#[test]
+fn test_profile_limits_apply() {
+    let profiles = Profiles::parse("[small]\nmax_output_size = 16\n").unwrap();
+    let frame = profiles.compress("small", &[b'x'; 100]).unwrap();
+    assert!(matches!(profiles.decompress("small", &frame), Err(QuantumPackError::OutputLimitExceeded { limit: 16 })));
+}
+
+#[test]
+fn test_profile_with_shared_dictionary() {
+    let mut dictionary = TrainedDictionary::new();
+    dictionary.insert(1, b"timestamp".to_vec(), 10).unwrap();
+    let hex: String = dictionary.serialize().iter().map(|byte| format!("{:02x}", byte)).collect();
+    let profiles = Profiles::parse(&format!("[json]\ndictionary = {}\n", hex)).unwrap();
+
+    let data = b"{\"timestamp\": 1, \"timestamp\": 2}".repeat(10);
+    let frame = profiles.compress("json", &data).unwrap();
+    assert_eq!(profiles.decompress("json", &frame).unwrap(), data);
+}
+
+#[test]
+fn test_profiles_built_in_code() {
+    let mut profiles = Profiles::new();
+    profiles.insert("default", Profile::new(Compressor::new(), Decompressor::new()));
+    let frame = profiles.compress("default", b"hello").unwrap();
+    assert_eq!(profiles.decompress("default", &frame).unwrap(), b"hello");
+    assert!(matches!(profiles.compress("missing", b"hello"), Err(QuantumPackError::InvalidInput(_))));
+}
+
+#[test]
+fn test_invalid_profiles_report_the_line() {
+    let cases = [
+        ("level = 3\n", 1),
+        ("[a]\n\nunknown = 1\n", 3),
+        ("[a]\ntokenization = fastest\n", 2),
+        ("[a]\nhuffman = dynamic\n", 2),
+        ("[a]\nstage = integer bit_packing 24\n", 2),
+        ("[a]\nstage = float 32 signed\n", 2),
+        ("[a]\nmax_output_size = -1\n", 2),
+        ("[a]\ndictionary = zz\n", 2),
+        ("[a]\n[a]\n", 2),
+        ("[a]\njust text\n", 2),
+    ];
+    for (text, expected_line) in cases {
+        match Profiles::parse(text) {
+            Err(QuantumPackError::InvalidProfile { line, .. }) => assert_eq!(line, expected_line, "{:?}", text),
+            _ => panic!("{:?} should be rejected", text),
+        }
+    }
+}
+
+#[test]
+fn test_load_profiles() {
+    let path = std::env::temp_dir().join("quantum_pack_profiles.conf");
+    std::fs::write(&path, PROFILES).unwrap();
+    let profiles = quantum_pack::profile::load_profiles(path.to_str().unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(profiles.get("metrics").is_some());
+    assert!(matches!(quantum_pack::profile::load_profiles("/nonexistent/profiles.conf"), Err(QuantumPackError::Io(_))));
+}
@@ -0,0 +1,60 @@
+use std::sync::{Arc, Mutex};
+
+use quantum_pack::{Compressor, Decompressor, ProgressHandler};
+
+type Reports = Arc<Mutex<Vec<(u64, u64)>>>;
+
+fn collect() -> (ProgressHandler, Reports) {
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let sink = reports.clone();
+    (Arc::new(move |bytes_in, bytes_out| sink.lock().unwrap().push((bytes_in, bytes_out))), reports)
+}
+
+#[test]
+fn test_progress_is_reported_per_block() {
+    let data = b"progress of a long running job\n".repeat(100);
+    let (handler, reports) = collect();
+    let mut compressed = Vec::new();
+    Compressor::new().block_size(1000).on_progress(handler).compress_to(&data[..], &mut compressed).unwrap();
+    let reports = reports.lock().unwrap().clone();
+
+    // One report per block, growing, ending at the whole input and output
+    assert_eq!(reports.len(), data.len().div_ceil(1000));
+    assert_eq!(reports[0].0, 1000);
+    assert!(reports.windows(2).all(|pair| pair[0].0 < pair[1].0 && pair[0].1 < pair[1].1));
+    assert_eq!(*reports.last().unwrap(), (data.len() as u64, compressed.len() as u64));
+
+    let (handler, decoded_reports) = collect();
+    let mut decoded = Vec::new();
+    Decompressor::new().on_progress(handler).decompress_to(&compressed[..], &mut decoded).unwrap();
+    let decoded_reports = decoded_reports.lock().unwrap().clone();
+    assert_eq!(decoded_reports.len(), reports.len());
+    assert_eq!(decoded_reports[0].1, 1000);
+    assert_eq!(*decoded_reports.last().unwrap(), (compressed.len() as u64, data.len() as u64));
+}
+
+#[test]
+fn test_progress_for_files() -> std::io::Result<()> {
+    let dir = std::env::temp_dir().join("quantum_pack_progress");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    let input_path = dir.join("input.txt");
+    let compressed_path = dir.join("input.qp");
+    let output_path = dir.join("output.txt");
+    std::fs::write(&input_path, "one frame, one report. ".repeat(50))?;
+
+    let (handler, reports) = collect();
+    Compressor::new().on_progress(handler).compress_file(input_path.to_str().unwrap(), compressed_path.to_str().unwrap()).unwrap();
+    let compressed_len = std::fs::metadata(&compressed_path)?.len();
+    assert_eq!(*reports.lock().unwrap(), vec![(std::fs::metadata(&input_path)?.len(), compressed_len)]);
+
+    let (handler, reports) = collect();
+    Decompressor::new().on_progress(handler).decompress_file(compressed_path.to_str().unwrap(), output_path.to_str().unwrap()).unwrap();
+    assert_eq!(*reports.lock().unwrap(), vec![(compressed_len, std::fs::metadata(&output_path)?.len())]);
+
+    // The in-memory helpers do not report
+    let (handler, reports) = collect();
+    Compressor::new().on_progress(handler).compress_shared(b"in memory", &mut Vec::new()).unwrap();
+    assert!(reports.lock().unwrap().is_empty());
+    std::fs::remove_dir_all(&dir)
+}
@@ -0,0 +1,18 @@
+#![cfg(feature = "arbitrary")]
+
+use arbitrary::{Arbitrary, Unstructured};
+use quantum_pack::roundtrip::{roundtrip_check, RoundtripCase};
+
+#[test]
+fn test_roundtrip_check_passes_for_repetitive_text() {
+    assert_eq!(roundtrip_check(b"aaaaaaaabbbbbbbbaaaaaaaabbbbbbbb"), Ok(()));
+}
+
+#[test]
+fn test_roundtrip_case_from_unstructured_bytes() {
+    let raw = [1u8, b'x', b'y', b'x', b'y', b'x', b'y', b'x', b'y', b'x', b'y', b'x', b'y', b'x', b'y', b'x', b'y'];
+    let case = RoundtripCase::arbitrary(&mut Unstructured::new(&raw)).unwrap();
+
+    assert_eq!(case.data.len(), 16);
+    assert_eq!(case.check(), Ok(()));
+}
@@ -0,0 +1,60 @@
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+use quantum_pack::sniff::{sniff, CompressedFormat};
+use quantum_pack::{Compressor, Decompressor, Warning};
+
+mod common;
+use common::noise;
+
+fn with_magic(magic: &[u8], body: &[u8]) -> Vec<u8> {
+    let mut data = magic.to_vec();
+    data.extend_from_slice(body);
+    data
+}
+
+#[test]
+fn test_sniff_recognizes_compressed_formats() {
+    let body = noise(8192);
+    let cases: [(&[u8], CompressedFormat); 5] = [
+        (&[0x1f, 0x8b, 0x08], CompressedFormat::Gzip),
+        (b"PK\x03\x04", CompressedFormat::Zip),
+        (b"\x89PNG\r\n\x1a\n", CompressedFormat::Png),
+        (&[0xff, 0xd8, 0xff, 0xe0], CompressedFormat::Jpeg),
+        (b"\x00\x00\x00\x20ftypisom", CompressedFormat::Mp4),
+    ];
+    for (magic, format) in cases.iter() {
+        assert_eq!(CompressedFormat::from_magic(magic), Some(*format));
+        assert_eq!(sniff(&with_magic(magic, &body)), Some(*format));
+    }
+
+    // A zip of stored text is not compressed, and random bytes are no known format
+    assert_eq!(sniff(&with_magic(b"PK\x03\x04", &b"plain text ".repeat(500))), None);
+    assert_eq!(sniff(&body[1..]), None);
+    assert_eq!(sniff(b""), None);
+}
+
+#[test]
+fn test_compress_file_stores_compressed_inputs() {
+    let dir = std::env::temp_dir().join("quantum_pack_sniff");
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("photo.jpg");
+    let output = dir.join("photo.jpg.qp");
+    let photo = with_magic(&[0xff, 0xd8, 0xff, 0xe0], &noise(100_000));
+    fs::write(&input, &photo).unwrap();
+
+    let warnings = Arc::new(Mutex::new(Vec::new()));
+    let sink = warnings.clone();
+    let compressor = Compressor::new().on_warning(Arc::new(move |warning| sink.lock().unwrap().push(warning)));
+    compressor.compress_file(input.to_str().unwrap(), output.to_str().unwrap()).unwrap();
+    assert_eq!(*warnings.lock().unwrap(), vec![Warning::AlreadyCompressed { format: CompressedFormat::Jpeg }]);
+    let frame = fs::read(&output).unwrap();
+    assert_eq!(frame[5] & 0x80, 0x80, "stored block");
+    assert_eq!(Decompressor::new().decompress(&frame).unwrap().0, photo);
+
+    warnings.lock().unwrap().clear();
+    compressor.clone().detect_compressed(false).compress_file(input.to_str().unwrap(), output.to_str().unwrap()).unwrap();
+    assert!(!warnings.lock().unwrap().iter().any(|warning| matches!(warning, Warning::AlreadyCompressed { .. })));
+    assert_eq!(Decompressor::new().decompress(&fs::read(&output).unwrap()).unwrap().0, photo);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,118 @@
+use quantum_pack::preprocessor::{DictionaryMode, Preprocessor};
+use quantum_pack::stage::{ByteOrder, FloatStage, FloatWidth, IntegerCodec, IntegerStage, IntegerWidth, Stage};
+use quantum_pack::{Compressor, Decompressor, QuantumPackError};
+
+const CODECS: [IntegerCodec; 3] = [IntegerCodec::BitPacking, IntegerCodec::FrameOfReference, IntegerCodec::ZigzagVarint];
+
+fn timestamps() -> Vec<u8> {
+    (0..500u64).flat_map(|n| (1_700_000_000 + n * 15).to_le_bytes().to_vec()).collect()
+}
+
+#[test]
+fn test_integer_codecs_round_trip() {
+    let signed: Vec<u8> = [-3i32, 7, -120_000, 0, i32::MIN, i32::MAX].iter().flat_map(|v| v.to_be_bytes().to_vec()).collect();
+    for &codec in CODECS.iter() {
+        let stage = IntegerStage::new(codec, IntegerWidth::Bits32).signed(true).byte_order(ByteOrder::BigEndian);
+        assert_eq!(stage.decode(&stage.encode(&signed)).unwrap(), signed);
+
+        let stage = IntegerStage::new(codec, IntegerWidth::Bits64);
+        let data = timestamps();
+        assert_eq!(stage.decode(&stage.encode(&data)).unwrap(), data);
+    }
+}
+
+#[test]
+fn test_trailing_bytes_are_kept() {
+    let data = [1u8, 0, 2, 0, 3];
+    for &codec in CODECS.iter() {
+        let stage = IntegerStage::new(codec, IntegerWidth::Bits16);
+        assert_eq!(stage.decode(&stage.encode(&data)).unwrap(), data);
+    }
+}
+
+#[test]
+fn test_frame_of_reference_shrinks_timestamps() {
+    let data = timestamps();
+    let encoded = IntegerStage::new(IntegerCodec::FrameOfReference, IntegerWidth::Bits64).encode(&data);
+    // 13 bits per value instead of 64
+    assert!(encoded.len() * 4 < data.len());
+}
+
+#[test]
+fn test_malformed_stage_data_is_rejected() {
+    let stage = IntegerStage::new(IntegerCodec::BitPacking, IntegerWidth::Bits8);
+    let encoded = stage.encode(&[1, 2, 3, 200]);
+
+    assert!(stage.decode(&encoded[..encoded.len() - 1]).is_err());
+    assert!(stage.decode(&[1, 0, 9]).is_err());
+    // A 16 bit value does not fit an 8 bit column
+    assert!(stage.decode(&[1, 0, 16, 0xFF, 0xFF]).is_err());
+}
+
+#[test]
+fn test_stage_count_is_bounded_by_the_footer() {
+    // A bit-packed column of 2^62 values of 0 bits: no data, so only the count is wrong
+    let column = [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x40, 0, 0];
+    let mut frame = Vec::new();
+    Compressor::new().compress_shared(&column, &mut frame).unwrap();
+    // Turn on the stage flag and insert an integer stage descriptor after it
+    frame[5] |= 0x02;
+    frame.splice(6..6, [5, 1, 0, 1, 0, 0].iter().copied());
+
+    assert!(matches!(Decompressor::new().decompress(&frame), Err(QuantumPackError::ChecksumMismatch { .. })));
+}
+
+#[test]
+fn test_stage_is_recorded_in_the_frame() {
+    let data: Vec<u8> = (0..1000u64).flat_map(|n| (n % 100).to_le_bytes().to_vec()).collect();
+    let stage = Stage::Integer(IntegerStage::new(IntegerCodec::ZigzagVarint, IntegerWidth::Bits64));
+    let compressor = Compressor::new()
+        .preprocessor(Preprocessor::builder().dictionary_mode(DictionaryMode::Replace).build())
+        .stage(stage);
+
+    let mut frame = Vec::new();
+    compressor.compress_shared(&data, &mut frame).unwrap();
+    let (decoded, _) = Decompressor::new().decompress(&frame).unwrap();
+
+    assert_eq!(decoded, data);
+    assert!(frame.len() * 4 < data.len());
+}
+
+fn readings() -> Vec<f64> {
+    (0..1000).map(|n| 20.0 + ((n / 50) as f64) * 0.25).collect()
+}
+
+#[test]
+fn test_float_stage_round_trip() {
+    let values: Vec<f64> = vec![0.0, -0.0, 1.5, 1.5, f64::NAN, f64::INFINITY, -3.25e-300, f64::MAX, 42.0];
+    let data: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect();
+    let stage = FloatStage::new(FloatWidth::F64).byte_order(ByteOrder::BigEndian);
+    assert_eq!(stage.decode(&stage.encode(&data)).unwrap(), data);
+
+    let mut data: Vec<u8> = [1.0f32, 1.25, 1.25, -7.5, 1e-20].iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+    data.push(9);
+    let stage = FloatStage::new(FloatWidth::F32);
+    assert_eq!(stage.decode(&stage.encode(&data)).unwrap(), data);
+}
+
+#[test]
+fn test_float_stage_shrinks_slow_series() {
+    let data: Vec<u8> = readings().iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+    let encoded = FloatStage::new(FloatWidth::F64).encode(&data);
+
+    assert!(encoded.len() * 20 < data.len());
+    assert!(FloatStage::new(FloatWidth::F64).decode(&encoded[..encoded.len() / 2]).is_err());
+}
+
+#[test]
+fn test_float_stage_parameters_in_frame() {
+    let data: Vec<u8> = readings().iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+    let compressor = Compressor::new()
+        .preprocessor(Preprocessor::builder().dictionary_mode(DictionaryMode::Replace).build())
+        .stage(Stage::Float(FloatStage::new(FloatWidth::F64)));
+
+    let mut frame = Vec::new();
+    compressor.compress_shared(&data, &mut frame).unwrap();
+    let (decoded, _) = Decompressor::new().decompress(&frame).unwrap();
+    assert_eq!(decoded, data);
+}
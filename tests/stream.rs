@@ -0,0 +1,473 @@
+use std::io::{Read, Write};
+
+use quantum_pack::preprocessor::{DictionaryMode, Preprocessor};
+use quantum_pack::stream::{QpDecoder, QpEncoder};
+use quantum_pack::Compressor;
+
+fn compressor() -> Compressor {
+    Compressor::new().preprocessor(Preprocessor::builder().dictionary_mode(DictionaryMode::Replace).build())
+}
+
+fn text() -> Vec<u8> {
+    (0..300).flat_map(|n| format!("line {} of the streamed text\n", n % 17).into_bytes()).collect()
+}
+
+#[test]
+fn test_stream_round_trip_in_blocks() {
+    let data = text();
+    let mut encoder = QpEncoder::with_compressor(Vec::new(), compressor()).block_size(1000);
+    for piece in data.chunks(333) {
+        encoder.write_all(piece).unwrap();
+    }
+    let compressed = encoder.finish().unwrap();
+    assert!(compressed.len() < data.len());
+
+    let mut decoded = Vec::new();
+    QpDecoder::new(&compressed[..]).read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, data);
+}
+
+#[test]
+fn test_flush_ends_a_block() {
+    let mut encoder = QpEncoder::with_compressor(Vec::new(), compressor());
+    encoder.write_all(b"first part, first part").unwrap();
+    encoder.flush().unwrap();
+    let after_flush = encoder.get_ref().len();
+    assert!(after_flush > 0);
+    encoder.write_all(b" and the rest").unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let mut first = Vec::new();
+    QpDecoder::new(&compressed[..after_flush]).read_to_end(&mut first).unwrap();
+    assert_eq!(first, b"first part, first part");
+
+    let mut all = String::new();
+    QpDecoder::new(&compressed[..]).read_to_string(&mut all).unwrap();
+    assert_eq!(all, "first part, first part and the rest");
+}
+
+#[test]
+fn test_empty_and_truncated_streams() {
+    let compressed = QpEncoder::new(Vec::new()).finish().unwrap();
+    assert!(compressed.is_empty());
+    let mut decoded = Vec::new();
+    QpDecoder::new(&compressed[..]).read_to_end(&mut decoded).unwrap();
+    assert!(decoded.is_empty());
+
+    let mut encoder = QpEncoder::with_compressor(Vec::new(), compressor());
+    encoder.write_all(&text()).unwrap();
+    let compressed = encoder.finish().unwrap();
+    let result = QpDecoder::new(&compressed[..compressed.len() - 3]).read_to_end(&mut decoded);
+    assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_dictionary_swap_during_a_stream() {
+    use quantum_pack::preprocessor::{SharedDictionary, TrainedDictionary};
+    use std::sync::Arc;
+
+    let mut first = TrainedDictionary::new();
+    first.insert(1, b"line ".to_vec(), 0).unwrap();
+    let mut second = TrainedDictionary::new();
+    second.insert(2, b" of the streamed text\n".to_vec(), 0).unwrap();
+    let second_id = second.id();
+
+    let shared = Arc::new(SharedDictionary::new(first));
+    let data = text();
+    let mut encoder = QpEncoder::with_compressor(Vec::new(), Compressor::new().shared_dictionary(Arc::clone(&shared)));
+    encoder.write_all(&data[..2000]).unwrap();
+    encoder.flush().unwrap();
+    let previous = shared.swap(second);
+    assert_eq!(previous.pattern(1), Some(&b"line "[..]));
+    assert_eq!(shared.id(), second_id);
+    encoder.write_all(&data[2000..]).unwrap();
+    let compressed = encoder.finish().unwrap();
+    assert!(compressed.len() < data.len());
+
+    let mut decoded = Vec::new();
+    QpDecoder::new(&compressed[..]).read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, data);
+}
+
+#[test]
+fn test_decompress_filtered_returns_the_range() {
+    use quantum_pack::stream::decompress_filtered;
+
+    let data = text();
+    let mut encoder = QpEncoder::with_compressor(Vec::new(), compressor()).block_size(1000);
+    encoder.write_all(&data).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let len = data.len() as u64;
+    for range in [0..10, 990..1010, 2500..4100, len - 5..len + 100, 0..len, 20..20] {
+        let expected = &data[range.start.min(len) as usize..range.end.min(len) as usize];
+        assert_eq!(decompress_filtered(&compressed, range.clone()).unwrap(), expected, "{:?}", range);
+    }
+}
+
+#[test]
+fn test_decompress_filtered_skips_frames_outside_the_range() {
+    use quantum_pack::stream::decompress_filtered;
+
+    let data = text();
+    let mut encoder = QpEncoder::with_compressor(Vec::new(), compressor()).block_size(1000);
+    encoder.write_all(&data).unwrap();
+    let mut compressed = encoder.finish().unwrap();
+
+    // Corrupt the Huffman data of the last frame; it is never decoded
+    let last = compressed.len() - 17;
+    compressed[last] ^= 0xFF;
+    assert_eq!(decompress_filtered(&compressed, 0..1500).unwrap(), &data[..1500]);
+    assert!(decompress_filtered(&compressed, 0..data.len() as u64).is_err());
+}
+
+#[test]
+fn test_concat_joins_frame_streams() {
+    use quantum_pack::stream::concat;
+    use quantum_pack::{Decompressor, QuantumPackError};
+
+    let first = quantum_pack::compress_shared(b"first file, first file").unwrap();
+    let mut encoder = QpEncoder::with_compressor(Vec::new(), compressor()).block_size(1000);
+    encoder.write_all(&text()).unwrap();
+    let second = encoder.finish().unwrap();
+
+    let mut joined = Vec::new();
+    concat(&[&first, &second], &mut joined).unwrap();
+    assert_eq!(joined.len(), first.len() + second.len());
+
+    let mut expected = b"first file, first file".to_vec();
+    expected.extend(text());
+    let mut decoded = Vec::new();
+    QpDecoder::new(&joined[..]).read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, expected);
+    let (decoded, trailing) = Decompressor::new().concatenated(true).decompress(&joined).unwrap();
+    assert_eq!((decoded, trailing), (expected, None));
+
+    // Inputs must end at a frame boundary
+    let mut padded = first.clone();
+    padded.extend_from_slice(b"junk");
+    assert!(matches!(concat(&[&padded], &mut Vec::new()), Err(QuantumPackError::TrailingData(_))));
+    assert!(matches!(concat(&[&first[..first.len() - 1]], &mut Vec::new()), Err(QuantumPackError::Truncated { .. })));
+}
+
+#[test]
+fn test_recompress_keeps_frames_and_stages() {
+    use quantum_pack::stage::{IntegerCodec, IntegerStage, IntegerWidth, Stage};
+    use quantum_pack::stream::recompress;
+    use quantum_pack::CompressionLevel;
+
+    let data = text();
+    let fast = Compressor::new().level(CompressionLevel::Fast);
+    let mut encoder = QpEncoder::with_compressor(Vec::new(), fast).block_size(1000);
+    encoder.write_all(&data).unwrap();
+    let original = encoder.finish().unwrap();
+
+    let mut recompressed = Vec::new();
+    recompress(&original[..], &mut recompressed, &compressor()).unwrap();
+    let mut decoded = Vec::new();
+    QpDecoder::new(&recompressed[..]).read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, data);
+    // Block boundaries are unchanged
+    assert_eq!(quantum_pack::stream::decompress_filtered(&recompressed, 1000..1010).unwrap(), &data[1000..1010]);
+    assert_eq!(recompressed.windows(4).filter(|w| w == b"QPND").count(), data.len().div_ceil(1000));
+
+    let values: Vec<u8> = (0..200u32).flat_map(|n| (5000 + n).to_le_bytes().to_vec()).collect();
+    let stage = Stage::Integer(IntegerStage::new(IntegerCodec::FrameOfReference, IntegerWidth::Bits32));
+    let mut staged = Vec::new();
+    Compressor::new().stage(stage).compress_shared(&values, &mut staged).unwrap();
+    let mut recompressed = Vec::new();
+    recompress(&staged[..], &mut recompressed, &Compressor::new().level(CompressionLevel::Best)).unwrap();
+    assert_eq!(recompressed[5] & 0x02, 0x02);
+    assert_eq!(quantum_pack::Decompressor::new().decompress(&recompressed).unwrap().0, values);
+
+    // Symbol frames keep their width
+    let tokens: Vec<u16> = (0..400).map(|n| n % 3 * 1000).collect();
+    let mut symbols = Vec::new();
+    Compressor::new().compress_symbols(&tokens, &mut symbols).unwrap();
+    let mut recompressed = Vec::new();
+    recompress(&symbols[..], &mut recompressed, &compressor()).unwrap();
+    assert_eq!(quantum_pack::Decompressor::new().decompress_symbols::<u16>(&recompressed).unwrap().0, tokens);
+}
+
+#[test]
+fn test_blocks_decode_as_one_input() {
+    use quantum_pack::{Decompressor, QuantumPackError};
+
+    let data = text();
+    let mut frames = Vec::new();
+    compressor().block_size(1000).compress_shared(&data, &mut frames).unwrap();
+    let mut single = Vec::new();
+    compressor().compress_shared(&data, &mut single).unwrap();
+    assert!(frames.len() > single.len());
+
+    // No need to ask for concatenated frames, the blocks belong together
+    assert_eq!(Decompressor::new().decompress(&frames).unwrap().0, data);
+    let mut decoded = Vec::new();
+    QpDecoder::new(&frames[..]).read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, data);
+
+    // Losing the last block is noticed
+    let mut first = Vec::new();
+    compressor().block_size(1000).compress_shared(&data[..1500], &mut first).unwrap();
+    let mut block = Vec::new();
+    compressor().compress_shared(&data[..1000], &mut block).unwrap();
+    assert!(matches!(Decompressor::new().decompress(&first[..block.len()]), Err(QuantumPackError::Truncated { .. })));
+}
+
+#[test]
+fn test_salvage_skips_damaged_blocks() {
+    use quantum_pack::stream::salvage;
+
+    let data = text();
+    let mut frames = Vec::new();
+    compressor().block_size(1000).compress_shared(&data, &mut frames).unwrap();
+    let clean = salvage(&frames);
+    assert_eq!(clean.data, data);
+    assert!(clean.damaged.is_empty());
+
+    // Blocks are framed like separate inputs, only a flag differs
+    let frame_len = |block: &[u8]| {
+        let mut frame = Vec::new();
+        compressor().compress_shared(block, &mut frame).unwrap();
+        frame.len()
+    };
+    let second = frame_len(&data[..1000]);
+    let second_len = frame_len(&data[1000..2000]);
+
+    // Damage the Huffman data of the second block
+    let mut damaged = frames.clone();
+    damaged[second + second_len - 40] ^= 0xff;
+    let salvaged = salvage(&damaged);
+    assert_eq!(salvaged.damaged.len(), 1);
+    let block = &salvaged.damaged[0];
+    assert_eq!(block.input, second as u64..(second + second_len) as u64);
+    assert_eq!((block.output_offset, block.len), (1000, Some(1000)));
+    let mut expected = data[..1000].to_vec();
+    expected.extend_from_slice(&data[2000..]);
+    assert_eq!(salvaged.data, expected);
+
+    // Damaged sizes lose the frame boundary, decoding resumes at the next frame
+    let mut header = frames.clone();
+    header[second + 6] ^= 0xff;
+    let salvaged = salvage(&header);
+    assert_eq!(salvaged.damaged.len(), 1);
+    assert_eq!(salvaged.damaged[0].len, None);
+    assert_eq!(salvaged.data, expected);
+}
+
+#[test]
+fn test_salvage_file_uses_the_output_policy() -> std::io::Result<()> {
+    use std::sync::Arc;
+    use quantum_pack::{Decompressor, OutputPolicy};
+
+    let data = text();
+    let mut frames = Vec::new();
+    compressor().block_size(1000).compress_shared(&data, &mut frames).unwrap();
+    frames[100] ^= 0xff;
+    let dir = std::env::temp_dir().join("quantum_pack_salvage_file");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    let input_path = dir.join("damaged.qp");
+    let output_path = dir.join("salvaged.txt");
+    std::fs::write(&input_path, &frames)?;
+
+    let salvaged = Decompressor::new().salvage_file(input_path.to_str().unwrap(), output_path.to_str().unwrap())?;
+    assert_eq!(salvaged.damaged.len(), 1);
+    assert_eq!(std::fs::read(&output_path)?, salvaged.data);
+    assert_eq!(salvaged.data, &data[1000..]);
+
+    let refuse = OutputPolicy::Custom(Arc::new(|_: &std::path::Path| Err(std::io::Error::other("read-only store"))));
+    let result = Decompressor::new().output_policy(refuse).salvage_file(input_path.to_str().unwrap(), "objects/salvaged.txt");
+    assert!(result.is_err());
+    std::fs::remove_dir_all(&dir)
+}
+
+#[test]
+fn test_seekable_reader_reads_any_range() {
+    use std::io::{Cursor, Seek, SeekFrom};
+    use quantum_pack::stream::{block_index, SeekableReader};
+
+    let data = text();
+    let mut frames = Vec::new();
+    compressor().block_size(1000).compress_shared(&data, &mut frames).unwrap();
+
+    let index = block_index(Cursor::new(&frames)).unwrap();
+    assert_eq!(index.len(), data.len().div_ceil(1000));
+    assert_eq!(index[1].decoded_offset, 1000);
+    assert_eq!(index.last().unwrap().offset + index.last().unwrap().frame_len, frames.len() as u64);
+
+    let mut reader = SeekableReader::new(Cursor::new(&frames)).unwrap().cache_blocks(2);
+    assert_eq!(reader.len(), data.len() as u64);
+
+    // A range spanning a block boundary
+    reader.seek(SeekFrom::Start(990)).unwrap();
+    let mut buf = [0u8; 40];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf[..], &data[990..1030]);
+
+    // Backwards, relative and from the end
+    reader.seek(SeekFrom::Current(-100)).unwrap();
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf[..], &data[930..970]);
+    reader.seek(SeekFrom::End(-10)).unwrap();
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, &data[data.len() - 10..]);
+
+    // Past the end reads nothing, before the start is an error
+    assert_eq!(reader.seek(SeekFrom::End(5)).unwrap(), data.len() as u64 + 5);
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    assert!(reader.seek(SeekFrom::Current(-(data.len() as i64) - 6)).is_err());
+
+    reader.seek(SeekFrom::Start(0)).unwrap();
+    let mut all = Vec::new();
+    reader.read_to_end(&mut all).unwrap();
+    assert_eq!(all, data);
+}
+
+// Counts the bytes read through it
+struct CountingReader<R> {
+    inner: R,
+    read: std::rc::Rc<std::cell::Cell<usize>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.read.set(self.read.get() + count);
+        Ok(count)
+    }
+}
+
+impl<R: std::io::Seek> std::io::Seek for CountingReader<R> {
+    fn seek(&mut self, position: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(position)
+    }
+}
+
+#[test]
+fn test_shrinking_the_block_cache_keeps_recent_blocks() {
+    use std::io::{Cursor, Seek, SeekFrom};
+    use quantum_pack::stream::SeekableReader;
+
+    let data = text();
+    let mut frames = Vec::new();
+    compressor().block_size(1000).compress_shared(&data, &mut frames).unwrap();
+    let read = std::rc::Rc::new(std::cell::Cell::new(0));
+    let mut reader = SeekableReader::new(CountingReader { inner: Cursor::new(&frames), read: read.clone() }).unwrap();
+
+    // Visit blocks 0, 1 and 2, then keep only one of them
+    let mut buf = [0u8; 10];
+    for block in 0..3 {
+        reader.seek(SeekFrom::Start(block * 1000)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+    }
+    let mut reader = reader.cache_blocks(1);
+    let before = read.get();
+    reader.seek(SeekFrom::Start(2500)).unwrap();
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf[..], &data[2500..2510]);
+    assert_eq!(read.get(), before);
+}
+
+#[test]
+fn test_stream_decoder_output_limit() {
+    use quantum_pack::{Decompressor, QuantumPackError};
+
+    let data = text();
+    let mut frames = Vec::new();
+    compressor().block_size(1000).compress_shared(&data, &mut frames).unwrap();
+
+    let mut decoded = Vec::new();
+    QpDecoder::with_decompressor(&frames[..], Decompressor::new().max_output_size(data.len())).read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, data);
+
+    // Every frame is within the limit, all of them together are not
+    let mut decoder = QpDecoder::with_decompressor(&frames[..], Decompressor::new().max_output_size(data.len() - 1));
+    let error = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+    assert!(matches!(error.into_inner().unwrap().downcast::<QuantumPackError>().map(|error| *error), Ok(QuantumPackError::OutputLimitExceeded { .. })));
+}
+
+#[test]
+fn test_decompress_filtered_output_limit() {
+    use quantum_pack::stream::decompress_filtered_with;
+    use quantum_pack::{Decompressor, QuantumPackError};
+
+    let data = text();
+    let mut frames = Vec::new();
+    compressor().block_size(1000).compress_shared(&data, &mut frames).unwrap();
+
+    let decompressor = Decompressor::new().max_output_size(1000);
+    assert_eq!(decompress_filtered_with(&frames, 0..10, &decompressor).unwrap(), &data[..10]);
+    // Only the frames the range needs count
+    assert_eq!(decompress_filtered_with(&frames, 2000..2010, &decompressor).unwrap(), &data[2000..2010]);
+    let result = decompress_filtered_with(&frames, 990..1010, &decompressor);
+    assert!(matches!(result, Err(QuantumPackError::OutputLimitExceeded { .. })));
+    let result = decompress_filtered_with(&frames, 0..10, &Decompressor::new().max_output_size(999));
+    assert!(matches!(result, Err(QuantumPackError::OutputLimitExceeded { limit: 999 })));
+}
+
+#[test]
+fn test_recompress_output_limit() {
+    use quantum_pack::profile::Profiles;
+    use quantum_pack::stream::recompress_with;
+    use quantum_pack::QuantumPackError;
+
+    let data = text();
+    let mut frames = Vec::new();
+    compressor().block_size(1000).compress_shared(&data, &mut frames).unwrap();
+
+    let profiles = Profiles::parse(&format!("[fits]\nmax_output_size = {}\n[short]\nmax_output_size = {}\n", data.len(), data.len() - 1)).unwrap();
+    let fits = profiles.get("fits").unwrap();
+    let mut recompressed = Vec::new();
+    recompress_with(&frames[..], &mut recompressed, fits.compressor(), fits.decompressor()).unwrap();
+    assert_eq!(fits.decompressor().clone().concatenated(true).decompress(&recompressed).unwrap().0, data);
+
+    let short = profiles.get("short").unwrap();
+    let result = recompress_with(&frames[..], Vec::new(), short.compressor(), short.decompressor());
+    assert!(matches!(result, Err(QuantumPackError::OutputLimitExceeded { .. })));
+}
+
+#[test]
+fn test_streams_with_a_preset_dictionary() {
+    use std::io::Cursor;
+    use quantum_pack::preprocessor::TrainedDictionary;
+    use quantum_pack::stream::{decompress_filtered, decompress_filtered_with, recompress, salvage, salvage_with, SeekableReader};
+    use quantum_pack::{Decompressor, QuantumPackError};
+
+    let mut dictionary = TrainedDictionary::new();
+    dictionary.insert(1, b" of the streamed text".to_vec(), 10).unwrap();
+    let data = text();
+    let compressor = Compressor::new().preset_dictionary(dictionary.clone()).block_size(1000);
+    let mut encoder = QpEncoder::with_compressor(Vec::new(), compressor.clone());
+    encoder.write_all(&data).unwrap();
+    let frames = encoder.finish().unwrap();
+    let decompressor = Decompressor::new().preset_dictionary(dictionary);
+
+    let mut decoded = Vec::new();
+    QpDecoder::with_decompressor(&frames[..], decompressor.clone()).read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, data);
+    let error = QpDecoder::new(&frames[..]).read_to_end(&mut Vec::new()).unwrap_err();
+    assert!(matches!(error.into_inner().unwrap().downcast::<QuantumPackError>().map(|error| *error), Ok(QuantumPackError::DictionaryMismatch { found: None, .. })));
+
+    let mut reader = SeekableReader::with_decompressor(Cursor::new(&frames), decompressor.clone()).unwrap();
+    let mut all = Vec::new();
+    reader.read_to_end(&mut all).unwrap();
+    assert_eq!(all, data);
+    assert!(SeekableReader::new(Cursor::new(&frames)).unwrap().read_to_end(&mut Vec::new()).is_err());
+
+    assert_eq!(decompress_filtered_with(&frames, 990..1030, &decompressor).unwrap(), &data[990..1030]);
+    assert!(matches!(decompress_filtered(&frames, 990..1030), Err(QuantumPackError::DictionaryMismatch { .. })));
+
+    let salvaged = salvage_with(&frames, &decompressor);
+    assert_eq!(salvaged.data, data);
+    assert!(salvaged.damaged.is_empty());
+    assert_eq!(salvage(&frames).damaged.len(), data.len().div_ceil(1000));
+
+    let mut recompressed = Vec::new();
+    recompress(&frames[..], &mut recompressed, &compressor).unwrap();
+    let mut decoded = Vec::new();
+    QpDecoder::with_decompressor(&recompressed[..], decompressor).read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, data);
+    assert!(matches!(recompress(&frames[..], Vec::new(), &Compressor::new()), Err(QuantumPackError::DictionaryMismatch { .. })));
+}
@@ -0,0 +1,52 @@
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use quantum_pack::throttle::Throttled;
+
+#[test]
+fn test_throttled_read_preserves_data() {
+    let data: Vec<u8> = (0..=255u8).cycle().take(3000).collect();
+    let mut reader = Throttled::new(&data[..], 1_000_000);
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, data);
+}
+
+#[test]
+fn test_throttled_read_respects_limit() {
+    let data = vec![7u8; 6000];
+    let mut reader = Throttled::new(&data[..], 4000);
+    let mut out = Vec::new();
+
+    // The first 4000 bytes come out of the initial burst, the remaining 2000 need half a second
+    let start = Instant::now();
+    reader.read_to_end(&mut out).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(450));
+    assert_eq!(out.len(), data.len());
+}
+
+#[test]
+fn test_throttled_write_respects_limit() {
+    let mut writer = Throttled::new(Vec::new(), 4000);
+
+    let start = Instant::now();
+    writer.write_all(&[1u8; 6000]).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(450));
+    assert_eq!(writer.into_inner().len(), 6000);
+}
+
+#[test]
+fn test_archive_inputs_are_throttled() -> std::io::Result<()> {
+    let dir = std::env::temp_dir().join("quantum_pack_throttle_archive");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    let input = dir.join("input.txt");
+    let archive = dir.join("input.qpa");
+    std::fs::write(&input, vec![b'a'; 6000])?;
+
+    // The archive itself is small enough for the initial burst, reading the input is not
+    let start = Instant::now();
+    quantum_pack::create_archive(&[input.to_str().unwrap()], archive.to_str().unwrap(), &quantum_pack::Compressor::new().bwlimit(4000))?;
+    assert!(start.elapsed() >= Duration::from_millis(450));
+    std::fs::remove_dir_all(&dir)
+}
@@ -0,0 +1,86 @@
+#![cfg(feature = "trace")]
+
+use quantum_pack::trace::{record, replay, Divergence, Trace};
+use quantum_pack::{serialize_code_length_table, Compressor, QuantumPackError};
+
+fn input() -> Vec<u8> {
+    (0..300).flat_map(|n| format!("GET /index.html {} {}\n", n % 7, n % 13).into_bytes()).collect()
+}
+
+#[test]
+fn test_replay_follows_recorded_path() {
+    let (frame, trace) = record(&Compressor::new(), &input()).unwrap();
+    assert_eq!(replay(&frame, &trace).unwrap(), None);
+    assert_eq!(Trace::parse(&trace.to_bytes()).unwrap(), trace);
+
+    let (empty, empty_trace) = record(&Compressor::new(), b"").unwrap();
+    assert!(empty_trace.tokens.is_empty());
+    assert_eq!(replay(&empty, &empty_trace).unwrap(), None);
+}
+
+#[test]
+fn test_replay_reports_first_divergence() {
+    let (frame, trace) = record(&Compressor::new(), &input()).unwrap();
+
+    let mut tokens = trace.clone();
+    let index = tokens.tokens.len() / 2;
+    let original = tokens.tokens[index];
+    tokens.tokens[index] = *trace.tokens.iter().find(|&&token| token != original).unwrap();
+    match replay(&frame, &tokens).unwrap() {
+        Some(Divergence::Token { index: at, decoded, .. }) => {
+            assert_eq!(at, index);
+            assert_eq!(decoded, Some(original));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let mut lengths = trace.clone();
+    let symbol = trace.tokens[0];
+    *lengths.code_lengths.get_mut(&symbol).unwrap() += 1;
+    assert!(matches!(replay(&frame, &lengths).unwrap(), Some(Divergence::CodeLength { symbol: s, .. }) if s == symbol));
+
+    let mut dictionary = trace.clone();
+    dictionary.dictionary.push(0);
+    assert_eq!(replay(&frame, &dictionary).unwrap(), Some(Divergence::Dictionary));
+}
+
+#[test]
+fn test_replay_locates_corrupted_frame_data() {
+    let (mut frame, trace) = record(&Compressor::new(), &input()).unwrap();
+    // Header and padding bits, then the size-prefixed table, dictionary and Huffman data
+    let table_len = serialize_code_length_table(&trace.code_lengths).len();
+    let data = 6 + 1 + 4 + table_len + 4 + trace.dictionary.len() + 4;
+    frame[data] ^= 0x80;
+    match replay(&frame, &trace).unwrap() {
+        Some(Divergence::Token { index, bit_offset, .. }) => assert_eq!((index, bit_offset), (0, 0)),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_trace_parse_rejects_other_data() {
+    assert!(matches!(Trace::parse(b"QPK1\x02"), Err(QuantumPackError::InvalidInput(_))));
+    assert!(matches!(Trace::parse(b"QPTR\x01\x00"), Err(QuantumPackError::Truncated { what: "trace", .. })));
+    assert!(matches!(Trace::parse(b"QPTR\x07"), Err(QuantumPackError::UnsupportedVersion(7))));
+}
+
+#[test]
+fn test_replay_command_exit_status() {
+    use std::process::Command;
+
+    let dir = std::env::temp_dir().join("quantum_pack_replay");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let (frame, trace) = record(&Compressor::new(), &input()).unwrap();
+    let mut diverging = trace.clone();
+    diverging.dictionary.push(0);
+    std::fs::write(dir.join("frame.qp"), frame).unwrap();
+    std::fs::write(dir.join("same.qpt"), trace.to_bytes()).unwrap();
+    std::fs::write(dir.join("other.qpt"), diverging.to_bytes()).unwrap();
+
+    let replay_status = |trace: &str| Command::new(env!("CARGO_BIN_EXE_quantum_pack")).arg("replay").arg(dir.join("frame.qp")).arg(dir.join(trace)).output().unwrap().status.code();
+    assert_eq!(replay_status("same.qpt"), Some(0));
+    // Apart from every error status, and from a partial batch
+    assert_eq!(replay_status("other.qpt"), Some(6));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+use quantum_pack::tuning::{pick_level, LevelController, LevelTrial, SpeedOrRatio};
+use quantum_pack::{CompressionLevel, Compressor, Decompressor};
+
+fn trial(level: CompressionLevel, compressed_len: usize, millis: u64) -> LevelTrial {
+    LevelTrial { level, input_len: 1000, compressed_len, elapsed: Duration::from_millis(millis) }
+}
+
+#[test]
+fn test_choose_level_for_target() {
+    let trials = [
+        trial(CompressionLevel::Fast, 700, 1),
+        trial(CompressionLevel::Default, 400, 10),
+        trial(CompressionLevel::Best, 390, 100),
+    ];
+    assert_eq!(SpeedOrRatio::Ratio.choose(&trials), Some(CompressionLevel::Best));
+    // 1000 bytes in 10ms is 100 KB/s
+    assert_eq!(SpeedOrRatio::MinSpeed(50_000).choose(&trials), Some(CompressionLevel::Default));
+    assert_eq!(SpeedOrRatio::MinSpeed(10_000_000).choose(&trials), Some(CompressionLevel::Fast));
+    assert_eq!(SpeedOrRatio::MaxRatio(0.5).choose(&trials), Some(CompressionLevel::Default));
+    assert_eq!(SpeedOrRatio::MaxRatio(0.1).choose(&trials), Some(CompressionLevel::Best));
+    assert_eq!(SpeedOrRatio::Ratio.choose(&[]), None);
+
+    // Equal output goes to the faster level
+    let tied = [trial(CompressionLevel::Default, 400, 10), trial(CompressionLevel::Best, 400, 100)];
+    assert_eq!(SpeedOrRatio::Ratio.choose(&tied), Some(CompressionLevel::Default));
+}
+
+#[test]
+fn test_pick_level_on_a_sample() {
+    let sample: Vec<u8> = (0..400).flat_map(|n| format!("GET /api/v1/items/{} HTTP/1.1 200\n", n % 23).into_bytes()).collect();
+    let trials = Compressor::new().try_levels(&sample);
+    assert_eq!(trials.iter().map(|trial| trial.level).collect::<Vec<_>>(), CompressionLevel::ALL);
+    for trial in &trials {
+        let mut frame = Vec::new();
+        Compressor::new().level(trial.level).compress_shared(&sample, &mut frame).unwrap();
+        assert_eq!(trial.compressed_len, frame.len());
+    }
+
+    let smallest = trials.iter().map(|trial| trial.compressed_len).min().unwrap();
+    let picked = pick_level(&sample, SpeedOrRatio::Ratio);
+    assert_eq!(trials.iter().find(|trial| trial.level == picked).unwrap().compressed_len, smallest);
+    // Padding would not fit the sample and is ignored for the trials
+    assert_eq!(Compressor::new().pad_to(1).try_levels(&sample).len(), 3);
+}
+
+#[test]
+fn test_level_controller_follows_latency() {
+    let controller = LevelController::new(Compressor::new(), Duration::from_millis(10)).window(4);
+    let window = |millis: u64| (0..4).for_each(|_| controller.record(Duration::from_millis(millis)));
+    assert_eq!(controller.level(), CompressionLevel::Best);
+
+    // One slow compression in a window is enough to step down
+    (0..3).for_each(|_| controller.record(Duration::from_millis(1)));
+    assert_eq!(controller.level(), CompressionLevel::Best);
+    controller.record(Duration::from_millis(20));
+    assert_eq!(controller.level(), CompressionLevel::Default);
+    window(20);
+    assert_eq!(controller.level(), CompressionLevel::Fast);
+    window(20);
+    assert_eq!(controller.level(), CompressionLevel::Fast);
+
+    // Within budget but without headroom it stays put
+    window(7);
+    assert_eq!(controller.level(), CompressionLevel::Fast);
+    window(2);
+    assert_eq!(controller.level(), CompressionLevel::Default);
+    window(2);
+    window(2);
+    assert_eq!(controller.level(), CompressionLevel::Best);
+
+    let capped = LevelController::new(Compressor::new(), Duration::from_millis(10)).window(1).max_level(CompressionLevel::Default);
+    assert_eq!(capped.level(), CompressionLevel::Default);
+    capped.record(Duration::ZERO);
+    assert_eq!(capped.level(), CompressionLevel::Default);
+}
+
+#[test]
+fn test_level_controller_compresses() {
+    let data = b"GET /api/v1/items HTTP/1.1 200\n".repeat(100);
+    // No compression fits in a nanosecond, so every one steps down
+    let controller = LevelController::new(Compressor::new(), Duration::from_nanos(1)).window(1);
+    for level in [CompressionLevel::Default, CompressionLevel::Fast, CompressionLevel::Fast] {
+        let mut frame = Vec::new();
+        controller.compress(&data, &mut frame).unwrap();
+        assert_eq!(Decompressor::new().decompress(&frame).unwrap().0, data);
+        assert_eq!(controller.level(), level);
+    }
+}
@@ -0,0 +1,62 @@
+use std::sync::{Arc, Mutex};
+
+use quantum_pack::preprocessor::{DictionaryMode, Preprocessor};
+use quantum_pack::{Compressor, Warning, WarningHandler};
+
+fn collector() -> (WarningHandler, Arc<Mutex<Vec<Warning>>>) {
+    let warnings = Arc::new(Mutex::new(Vec::new()));
+    let sink = warnings.clone();
+    (Arc::new(move |warning| sink.lock().unwrap().push(warning)), warnings)
+}
+
+#[test]
+fn test_expanded_blocks_are_reported() {
+    let (handler, warnings) = collector();
+    let text: Vec<u8> = (0..200).flat_map(|n| format!("row {}\n", n % 5).into_bytes()).collect();
+    let mut data = text.clone();
+    data.extend_from_slice(b"x");
+    let mut frames = Vec::new();
+    Compressor::new().on_warning(handler).block_size(text.len()).compress_shared(&data, &mut frames).unwrap();
+
+    // The one byte block cannot pay for its frame header
+    let warnings = warnings.lock().unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert!(matches!(warnings[0], Warning::BlockExpanded { index: 1, input_len: 1, frame_len } if frame_len > 1));
+}
+
+#[test]
+fn test_unused_dictionary_is_reported() {
+    let (handler, warnings) = collector();
+    let preprocessor = Preprocessor::builder().patterns(vec![b"never appears".to_vec()]).dictionary_mode(DictionaryMode::Replace).build();
+    let compressor = Compressor::new().preprocessor(preprocessor).on_warning(handler);
+    compressor.compress(b"completely different input, completely different input").unwrap();
+    assert_eq!(*warnings.lock().unwrap(), [Warning::DictionaryUnused]);
+
+    warnings.lock().unwrap().clear();
+    compressor.compress(b"never appears, and never appears again").unwrap();
+    assert!(warnings.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_metadata_restore_failure_is_a_warning() -> std::io::Result<()> {
+    use quantum_pack::{Decompressor, OutputPolicy};
+
+    let (handler, warnings) = collector();
+    let dir = std::env::temp_dir();
+    let input_path = dir.join("quantum_pack_warning.txt");
+    let compressed_path = dir.join("quantum_pack_warning.qp");
+    std::fs::write(&input_path, "kept somewhere that is not a file")?;
+    Compressor::new().preserve_metadata(true).compress_file(input_path.to_str().unwrap(), compressed_path.to_str().unwrap())?;
+
+    let custom = OutputPolicy::Custom(Arc::new(|_: &std::path::Path| Ok(Box::new(std::io::sink()) as Box<dyn std::io::Write + Send>)));
+    Decompressor::new()
+        .output_policy(custom)
+        .on_warning(handler)
+        .decompress_file(compressed_path.to_str().unwrap(), "objects/warning.txt")?;
+    assert!(matches!(&warnings.lock().unwrap()[..], [Warning::MetadataNotRestored { path, .. }] if path == "objects/warning.txt"));
+
+    for path in [input_path, compressed_path].iter() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use quantum_pack::checksum::crc32;
+use quantum_pack::preprocessor::TrainedDictionary;
+use quantum_pack::stage::{ByteOrder, FloatStage, FloatWidth, IntegerCodec, IntegerStage, IntegerWidth};
+use quantum_pack::wire::{self, Reader};
+
+#[test]
+fn test_integers_are_written_big_endian() {
+    let mut out = Vec::new();
+    wire::write_u16(&mut out, 0x0102);
+    wire::write_u32(&mut out, 0x0304_0506);
+    wire::write_u64(&mut out, 0x0708_090A_0B0C_0D0E);
+    assert_eq!(out, [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14]);
+
+    let mut reader = Reader::new(&out, "test data");
+    assert_eq!(reader.u16().unwrap(), 0x0102);
+    assert_eq!(reader.u32().unwrap(), 0x0304_0506);
+    assert_eq!(reader.u64().unwrap(), 0x0708_090A_0B0C_0D0E);
+    assert!(reader.is_empty());
+    assert!(reader.u8().is_err());
+}
+
+#[test]
+fn test_varint_round_trip() {
+    for &value in &[0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, u32::MAX as u64, u64::MAX] {
+        let mut out = Vec::new();
+        wire::write_varint(&mut out, value);
+        assert_eq!(out.len(), wire::varint_len(value));
+        assert_eq!(Reader::new(&out, "test data").varint().unwrap(), value);
+    }
+    assert_eq!(Reader::new(&[0x80, 0x01], "test data").varint().unwrap(), 0x80);
+    assert!(Reader::new(&[0x80], "test data").varint().is_err());
+    assert!(Reader::new(&[0xFF; 10], "test data").varint().is_err());
+}
+
+#[test]
+fn test_truncation_names_the_structure() {
+    let error = Reader::new(&[1, 2, 3], "page model").u32().unwrap_err();
+    assert_eq!(error.to_string(), "page model is truncated");
+}
+
+#[test]
+fn test_frame_header_layout() {
+    use quantum_pack::Compressor;
+    use quantum_pack::stage::{FloatStage, FloatWidth, Stage};
+
+    let input = b"header ".repeat(40);
+    let frame = quantum_pack::compress_shared(&input).unwrap();
+    assert_eq!(&frame[..6], b"QPK1\x03\x00");
+
+    let floats: Vec<u8> = (0..500).flat_map(|i| (i as f32 * 0.5).to_le_bytes().to_vec()).collect();
+    let mut frame = Vec::new();
+    Compressor::new().stage(Stage::Float(FloatStage::new(FloatWidth::F32))).compress_shared(&floats, &mut frame).unwrap();
+    // The staged bytes use few symbols, so the block type announces a sparse table
+    assert_eq!(&frame[..6], b"QPK1\x03\x82");
+
+    // Too short to gain from coding: a stored block with empty table and dictionary
+    let frame = quantum_pack::compress_shared(b"header").unwrap();
+    assert_eq!(&frame[..8], b"QPK1\x03\x80\x01\x00");
+    assert_eq!(&frame[8..20], [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 6]);
+    assert_eq!(&frame[20..26], b"header");
+}
+
+#[test]
+fn test_frame_footer_layout() {
+    let data = b"footer fields are big-endian";
+    let frame = quantum_pack::compress_shared(data).unwrap();
+    let footer = &frame[frame.len() - 16..];
+    assert_eq!(&footer[..4], b"QPND");
+    assert_eq!(footer[4..12], (data.len() as u64).to_be_bytes());
+    assert_eq!(footer[12..], crc32(data).to_be_bytes());
+}
+
+#[test]
+fn test_code_length_table_layout() {
+    use std::collections::BTreeMap;
+    use quantum_pack::{deserialize_code_length_table, serialize_code_length_table};
+
+    // One byte per symbol value up to the highest one used, 0 for unused symbols
+    let lengths = BTreeMap::from([(b'a', 1), (b'c', 2), (b'd', 2)]);
+    let table = serialize_code_length_table(&lengths);
+    assert_eq!(table.len(), b'd' as usize + 1);
+    assert_eq!(&table[b'a' as usize..], [1, 0, 2, 2]);
+    assert_eq!(deserialize_code_length_table(&table).unwrap(), lengths);
+
+    // Three one bit codes cannot exist
+    assert!(deserialize_code_length_table(&[1, 1, 1]).is_err());
+    assert!(deserialize_code_length_table(&[1; 257]).is_err());
+}
+
+#[test]
+fn test_symbol_length_table_layout() {
+    use std::collections::BTreeMap;
+    use quantum_pack::{deserialize_symbol_length_table, serialize_symbol_length_table};
+
+    // Symbol count, then the gap to the previous symbol and the length per symbol
+    let lengths = BTreeMap::from([(2, 1), (3, 2), (300, 2)]);
+    let table = serialize_symbol_length_table(&lengths);
+    assert_eq!(table, [3, 2, 1, 0, 2, 0xA8, 0x02, 2]);
+    assert_eq!(deserialize_symbol_length_table(&table).unwrap(), lengths);
+
+    assert!(deserialize_symbol_length_table(&[3, 0, 1, 0, 1, 0, 1]).is_err());
+    assert!(deserialize_symbol_length_table(&[1, 0, 0]).is_err());
+    assert!(deserialize_symbol_length_table(&[1, 0xFF, 0xFF, 0xFF, 0xFF, 0x10, 1]).is_err());
+}
+
+#[test]
+fn test_dictionary_layout() {
+    let mut dictionary = TrainedDictionary::new();
+    dictionary.insert(0x0102, b"ab".to_vec(), 3).unwrap();
+    assert_eq!(dictionary.serialize(), [2, 0x01, 0x02, 2, b'a', b'b']);
+}
+
+#[test]
+fn test_stages_decode_data_from_either_byte_order() {
+    let values: Vec<u64> = (0..100).map(|n| 1_000_000 + n * 37).collect();
+    let little: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+    let big: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect();
+
+    for &codec in &[IntegerCodec::BitPacking, IntegerCodec::FrameOfReference, IntegerCodec::ZigzagVarint] {
+        let stage = IntegerStage::new(codec, IntegerWidth::Bits64);
+        let from_little = stage.byte_order(ByteOrder::LittleEndian).encode(&little);
+        let from_big = stage.byte_order(ByteOrder::BigEndian).encode(&big);
+        // The staged form depends only on the values
+        assert_eq!(from_little, from_big);
+        assert_eq!(stage.byte_order(ByteOrder::BigEndian).decode(&from_little).unwrap(), big);
+        assert_eq!(stage.byte_order(ByteOrder::LittleEndian).decode(&from_big).unwrap(), little);
+    }
+
+    let readings: Vec<f32> = (0..100).map(|n| 0.5 * n as f32).collect();
+    let little: Vec<u8> = readings.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+    let big: Vec<u8> = readings.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect();
+    let stage = FloatStage::new(FloatWidth::F32);
+    let from_little = stage.byte_order(ByteOrder::LittleEndian).encode(&little);
+    assert_eq!(from_little, stage.byte_order(ByteOrder::BigEndian).encode(&big));
+    assert_eq!(stage.byte_order(ByteOrder::BigEndian).decode(&from_little).unwrap(), big);
+}